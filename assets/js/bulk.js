@@ -0,0 +1,39 @@
+// Uploads the CSV with the admin token and offers the streamed results as a download.
+(function () {
+  document.addEventListener("DOMContentLoaded", function () {
+    var form = document.getElementById("bulk");
+    var result = document.getElementById("bulk_result");
+    form.addEventListener("submit", function (event) {
+      event.preventDefault();
+      var file = document.getElementById("file").files[0];
+      var token = document.getElementById("token").value.trim();
+      result.textContent = "Creating the accounts, this may take a while...";
+      fetch("/bulk", {
+        method: "POST",
+        headers: { "Content-Type": "text/csv", "Authorization": "Bearer " + token },
+        body: file,
+      })
+        .then(function (response) {
+          if (response.ok) {
+            return response.blob().then(function (blob) {
+              var link = document.createElement("a");
+              link.href = URL.createObjectURL(blob);
+              link.download = "results.csv";
+              link.textContent = "Download the results";
+              result.textContent = "";
+              result.appendChild(link);
+            });
+          }
+          return response.json().then(function (data) {
+            var lines = (data.error.rows || []).map(function (row) {
+              return "line " + row.line + ": " + row.message;
+            });
+            result.textContent = [data.error.message].concat(lines).join("\n");
+          });
+        })
+        .catch(function (err) {
+          result.textContent = "Failed to upload the file: " + err;
+        });
+    });
+  });
+})();
@@ -0,0 +1,27 @@
+// Fetches a challenge for the public key of the form, the user signs its SHA-256 hash with the key.
+(function () {
+  document.addEventListener("DOMContentLoaded", function () {
+    var button = document.getElementById("key_challenge_button");
+    button.addEventListener("click", function () {
+      var publicKey = document.getElementById("public_key").value.trim();
+      var text = document.getElementById("key_challenge_text");
+      fetch("/api/v1/key-challenge", {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({ public_key: publicKey }),
+      })
+        .then(function (response) { return response.json(); })
+        .then(function (data) {
+          if (data.challenge) {
+            document.getElementById("key_challenge").value = data.challenge;
+            text.textContent = data.challenge;
+          } else {
+            text.textContent = data.error ? data.error.message : "Failed to get a challenge";
+          }
+        })
+        .catch(function (err) {
+          text.textContent = "Failed to get a challenge: " + err;
+        });
+    });
+  });
+})();
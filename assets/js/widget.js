@@ -0,0 +1,111 @@
+// Submits the widget form and reports the result to the embedding page via postMessage.
+// Messages are only posted to the origins allowed to embed the widget.
+(function () {
+  var MESSAGE_TYPE = "sw4-account-creator:result";
+
+  function allowedOrigins() {
+    var container = document.getElementById("widget");
+    var origins = (container.getAttribute("data-allowed-origins") || "").split(" ");
+    return origins.filter(function (origin) { return origin.length > 0; });
+  }
+
+  function notifyParent(message) {
+    if (window.parent === window) {
+      return;
+    }
+    allowedOrigins().forEach(function (origin) {
+      window.parent.postMessage(message, origin);
+    });
+  }
+
+  function showResult(text, success) {
+    var result = document.getElementById("result");
+    result.className = "response " + (success ? "success" : "fail");
+    result.textContent = text;
+  }
+
+  // Keeps the submit button disabled until the client may retry, counting the seconds down on it
+  function countDown(submit, seconds) {
+    var label = submit.value;
+    var tick = function () {
+      if (seconds <= 0) {
+        submit.value = label;
+        submit.disabled = false;
+        return;
+      }
+      submit.value = "Try again in " + seconds + "s";
+      seconds -= 1;
+      setTimeout(tick, 1000);
+    };
+    submit.disabled = true;
+    tick();
+  }
+
+  // Fetches a fresh reCAPTCHA token into the form before submitting it, the tokens expire after two minutes
+  function withRecaptcha(form, submit) {
+    var field = document.getElementById("g-recaptcha-response");
+    if (!field || typeof grecaptcha === "undefined") {
+      submit();
+      return;
+    }
+    grecaptcha.ready(function () {
+      grecaptcha.execute(field.getAttribute("data-sitekey"), { action: "create_account" }).then(function (token) {
+        field.value = token;
+        submit();
+      });
+    });
+  }
+
+  document.addEventListener("DOMContentLoaded", function () {
+    var form = document.getElementById("create_account");
+    // No form while the faucet is paused
+    if (!form) {
+      return;
+    }
+    form.addEventListener("submit", function (event) {
+      event.preventDefault();
+      var submit = form.querySelector("input[type=submit]");
+      submit.disabled = true;
+      var retryAfter = 0;
+
+      withRecaptcha(form, function () {
+        fetch(form.action, {
+          method: "POST",
+          headers: { "Content-Type": "application/x-www-form-urlencoded" },
+          body: new URLSearchParams(new FormData(form)).toString(),
+        })
+          .then(function (response) { return response.json(); })
+          .then(function (data) {
+            if (data.success) {
+              showResult("Account " + data.account_id + " has been created.", true);
+            } else {
+              showResult("Failed to create the account: " + data.error, false);
+              retryAfter = data.retry_after_secs || 0;
+            }
+            notifyParent({
+              type: MESSAGE_TYPE,
+              success: data.success,
+              account_id: data.account_id,
+              public_key: data.public_key,
+              code: data.code,
+              error: data.error,
+              retry_after_secs: data.retry_after_secs,
+              tx_hash: data.tx_hash,
+              explorer_url: data.explorer_url,
+            });
+          })
+          .catch(function (err) {
+            showResult("Failed to create the account: " + err, false);
+            notifyParent({ type: MESSAGE_TYPE, success: false, error: String(err) });
+          })
+          .finally(function () {
+            // A Turnstile token is single-use, the next submission needs a new one
+            if (typeof turnstile !== "undefined") {
+              turnstile.reset();
+            }
+            countDown(submit, retryAfter);
+          });
+      });
+    });
+  });
+})();
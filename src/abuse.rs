@@ -0,0 +1,632 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use anyhow::Context as _;
+use near_account_id::AccountId;
+use near_jsonrpc_client::methods;
+use near_jsonrpc_primitives::types::query::{QueryResponseKind, RpcQueryError};
+use near_primitives::types::{BlockReference, Finality};
+use near_primitives::views::QueryRequest;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{CodedError, ErrorCode};
+use crate::middleware::api_tokens::AdminToken;
+use crate::middleware::client_ip::client_ip;
+use crate::middleware::replay_guard::AuthenticatedClient;
+use crate::quota::Identity;
+use crate::utils::rpc_pool::RpcPool;
+
+/// Reports waiting for a review at once, further reports are rejected until some are reviewed
+const MAX_OPEN_REPORTS: usize = 10_000;
+/// Reports of a single reporter waiting for a review at once, so one client can't fill the queue
+const MAX_OPEN_REPORTS_PER_REPORTER: usize = 10;
+const MAX_REASON_LENGTH: usize = 1024;
+const MAX_EVIDENCE_ITEMS: usize = 10;
+const MAX_EVIDENCE_LENGTH: usize = 512;
+/// Derived name patterns keep at least this many characters before the wildcard
+const MIN_PATTERN_PREFIX: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ReportStatus {
+    Open,
+    Abusive,
+    Dismissed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct AbuseReport {
+    id: u64,
+    account_id: String,
+    reason: String,
+    /// Transaction hashes, links or anything else backing the report
+    evidence: Vec<String>,
+    /// API key of the reporter, `public` for anonymous reports
+    reporter: String,
+    /// Identity or address of the reporter, its open reports are capped by `MAX_OPEN_REPORTS_PER_REPORTER`
+    #[serde(skip)]
+    source: String,
+    /// Unix timestamp
+    reported_at: u64,
+    status: ReportStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reviewed_by: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reviewed_at: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ReportRequest {
+    account_id: String,
+    reason: String,
+    #[serde(default)]
+    evidence: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ReviewRequest {
+    verdict: ReportStatus,
+    /// Pattern of the names to deny, derived from the account name if not set
+    name_pattern: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ReportsQuery {
+    status: Option<ReportStatus>,
+}
+
+/// Public keys and name patterns the faucet refuses to create accounts for
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub(crate) struct Denylist {
+    public_keys: BTreeSet<String>,
+    /// Patterns of the names without the suffix, `*` matches any characters
+    name_patterns: BTreeSet<String>,
+}
+
+impl Denylist {
+    fn denies_name(&self, name: &str) -> Option<&str> {
+        self.name_patterns
+            .iter()
+            .find(|pattern| matches_pattern(pattern, name))
+            .map(String::as_str)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: u64,
+    reports: BTreeMap<u64, AbuseReport>,
+    denylist: Denylist,
+}
+
+/// Abuse reports of the created accounts and the denylists filled by reviewing them
+/// The reports are kept in memory, like the quotas, the denylist in `denylist_file` if set
+#[derive(Debug)]
+pub(crate) struct AbuseDesk {
+    /// Parent account of the created accounts, only its direct sub-accounts can be reported
+    suffix: AccountId,
+    /// API keys allowed to use the `/admin` endpoints
+    admin_api_keys: HashSet<String>,
+    /// Looks up the access keys of the accounts marked as abusive
+    rpc: RpcPool,
+    state: Mutex<State>,
+    /// JSON file the denylist is read from on startup and written to after each review denying more
+    denylist_file: Option<PathBuf>,
+    /// Keeps the writes of the file in the order of the reviews
+    saving: tokio::sync::Mutex<()>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn coded(code: ErrorCode, message: impl Into<String>) -> anyhow::Error {
+    CodedError {
+        code,
+        message: message.into(),
+    }
+    .into()
+}
+
+/// Matches the name against a pattern where `*` stands for any characters
+pub(crate) fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // No wildcard at all
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+/// Pattern catching the numbered variants of a name, e.g. `spam*` for `spam123`, or the name itself
+fn derive_pattern(name: &str) -> String {
+    let prefix = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '-' || c == '_');
+    if prefix.len() < name.len() && prefix.len() >= MIN_PATTERN_PREFIX {
+        format!("{}*", prefix)
+    } else {
+        name.to_string()
+    }
+}
+
+impl AbuseDesk {
+    /// Reads the denylist file if set, a missing file is an empty denylist
+    pub(crate) fn new(
+        suffix: AccountId,
+        admin_api_keys: &[String],
+        rpc: RpcPool,
+        denylist_file: Option<PathBuf>,
+    ) -> anyhow::Result<Self> {
+        let denylist = match &denylist_file {
+            Some(path) => match std::fs::read(path) {
+                Ok(data) => serde_json::from_slice(&data).with_context(|| {
+                    format!("failed parsing the denylist file {}", path.display())
+                })?,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Denylist::default(),
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        format!("failed reading the denylist file {}", path.display())
+                    })
+                }
+            },
+            None => Denylist::default(),
+        };
+        Ok(Self {
+            suffix,
+            admin_api_keys: admin_api_keys.iter().cloned().collect(),
+            rpc,
+            state: Mutex::new(State {
+                denylist,
+                ..State::default()
+            }),
+            denylist_file,
+            saving: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Writes the current denylist to the file, replaced by a rename so a crash never leaves it half-written
+    async fn save_denylist(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.denylist_file else {
+            return Ok(());
+        };
+        let _saving = self.saving.lock().await;
+        let data = serde_json::to_vec_pretty(&self.state.lock().unwrap().denylist)?;
+        let tmp_path = path.with_extension("tmp");
+        tokio::fs::write(&tmp_path, data).await?;
+        tokio::fs::rename(&tmp_path, path)
+            .await
+            .with_context(|| format!("failed writing the denylist file {}", path.display()))
+    }
+
+    /// Name of a direct sub-account of the suffix
+    fn name_of<'a>(&self, account_id: &'a str) -> Option<&'a str> {
+        match account_id.split_once('.') {
+            Some((name, parent)) if parent == self.suffix.as_str() => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Fails with `DENYLISTED` if the account name or the public key was denied after a review
+    pub(crate) fn check(&self, account_id: &str, public_key: &str) -> anyhow::Result<()> {
+        let state = self.state.lock().unwrap();
+        if state.denylist.public_keys.contains(public_key) {
+            return Err(coded(
+                ErrorCode::Denylisted,
+                "the public key belongs to an account marked as abusive",
+            ));
+        }
+        let name = self.name_of(account_id).unwrap_or(account_id);
+        if let Some(pattern) = state.denylist.denies_name(name) {
+            return Err(coded(
+                ErrorCode::Denylisted,
+                format!("account names matching {} are not allowed", pattern),
+            ));
+        }
+        Ok(())
+    }
+
+    fn report(
+        &self,
+        request: ReportRequest,
+        reporter: String,
+        source: String,
+    ) -> anyhow::Result<AbuseReport> {
+        let account_id = request.account_id.trim().to_lowercase();
+        if self.name_of(&account_id).is_none() {
+            return Err(coded(
+                ErrorCode::InvalidRequest,
+                format!("only the sub-accounts of {} can be reported", self.suffix),
+            ));
+        }
+        let reason = request.reason.trim();
+        if reason.is_empty() || reason.len() > MAX_REASON_LENGTH {
+            return Err(coded(
+                ErrorCode::InvalidRequest,
+                format!(
+                    "the reason must be between 1 and {} characters long",
+                    MAX_REASON_LENGTH
+                ),
+            ));
+        }
+        if request.evidence.len() > MAX_EVIDENCE_ITEMS
+            || request
+                .evidence
+                .iter()
+                .any(|item| item.len() > MAX_EVIDENCE_LENGTH)
+        {
+            return Err(coded(
+                ErrorCode::InvalidRequest,
+                format!(
+                    "at most {} evidence items of up to {} characters are accepted",
+                    MAX_EVIDENCE_ITEMS, MAX_EVIDENCE_LENGTH
+                ),
+            ));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let (open, open_of_source) = state
+            .reports
+            .values()
+            .filter(|report| report.status == ReportStatus::Open)
+            .fold((0, 0), |(open, of_source), report| {
+                (open + 1, of_source + usize::from(report.source == source))
+            });
+        if open >= MAX_OPEN_REPORTS {
+            return Err(coded(
+                ErrorCode::RateLimited,
+                "too many reports are waiting for a review, try again later",
+            ));
+        }
+        if open_of_source >= MAX_OPEN_REPORTS_PER_REPORTER {
+            return Err(coded(
+                ErrorCode::RateLimited,
+                "too many of your reports are waiting for a review, try again once they're reviewed",
+            ));
+        }
+        state.next_id += 1;
+        let report = AbuseReport {
+            id: state.next_id,
+            account_id,
+            reason: reason.to_string(),
+            evidence: request.evidence,
+            reporter,
+            source,
+            reported_at: now(),
+            status: ReportStatus::Open,
+            reviewed_by: None,
+            reviewed_at: None,
+        };
+        state.reports.insert(report.id, report.clone());
+        Ok(report)
+    }
+
+    fn reports(&self, status: Option<ReportStatus>) -> Vec<AbuseReport> {
+        self.state
+            .lock()
+            .unwrap()
+            .reports
+            .values()
+            .filter(|report| status.map_or(true, |status| report.status == status))
+            .cloned()
+            .collect()
+    }
+
+    /// Records the verdict of an admin; marking an account as abusive denies its current access keys
+    /// and its name pattern, and closes the other open reports of the account too
+    async fn review(
+        &self,
+        id: u64,
+        review: ReviewRequest,
+        reviewer: String,
+    ) -> anyhow::Result<serde_json::Value> {
+        let account_id = match self.state.lock().unwrap().reports.get(&id) {
+            Some(report) if report.status == ReportStatus::Open => report.account_id.clone(),
+            Some(_) => {
+                return Err(coded(
+                    ErrorCode::InvalidRequest,
+                    format!("report {} was reviewed already", id),
+                ))
+            }
+            None => {
+                return Err(coded(
+                    ErrorCode::NotFound,
+                    format!("report {} doesn't exist", id),
+                ))
+            }
+        };
+
+        let mut denied = Denylist::default();
+        let closed_status = match review.verdict {
+            ReportStatus::Open => {
+                return Err(coded(
+                    ErrorCode::InvalidRequest,
+                    "the verdict must be abusive or dismissed",
+                ))
+            }
+            ReportStatus::Dismissed => ReportStatus::Dismissed,
+            ReportStatus::Abusive => {
+                // Looked up before taking the lock, the account may have added or replaced keys since its creation
+                denied.public_keys = self.access_keys(&account_id).await?;
+                let name = self.name_of(&account_id).unwrap_or(&account_id);
+                let pattern = review
+                    .name_pattern
+                    .as_deref()
+                    .map(|pattern| pattern.trim().to_lowercase())
+                    .unwrap_or_else(|| derive_pattern(name));
+                if pattern.is_empty() || pattern.chars().all(|c| c == '*') {
+                    return Err(coded(
+                        ErrorCode::InvalidRequest,
+                        "the name pattern would deny every name",
+                    ));
+                }
+                denied.name_patterns.insert(pattern);
+                ReportStatus::Abusive
+            }
+        };
+
+        let closed = {
+            let mut state = self.state.lock().unwrap();
+            let reviewed_at = now();
+            let mut closed = vec![];
+            for report in state.reports.values_mut() {
+                let affected = report.status == ReportStatus::Open
+                    && (report.id == id
+                        || (closed_status == ReportStatus::Abusive
+                            && report.account_id == account_id));
+                if affected {
+                    report.status = closed_status;
+                    report.reviewed_by = Some(reviewer.clone());
+                    report.reviewed_at = Some(reviewed_at);
+                    closed.push(report.id);
+                }
+            }
+            state
+                .denylist
+                .public_keys
+                .extend(denied.public_keys.iter().cloned());
+            state
+                .denylist
+                .name_patterns
+                .extend(denied.name_patterns.iter().cloned());
+            closed
+        };
+        tracing::info!(
+            "{} marked {} as {:?} (reports {:?}), denied {:?}",
+            reviewer,
+            account_id,
+            closed_status,
+            closed,
+            denied
+        );
+        if closed_status == ReportStatus::Abusive {
+            self.save_denylist().await?;
+        }
+        Ok(serde_json::json!({
+            "account_id": account_id,
+            "status": closed_status,
+            "closed_reports": closed,
+            "denied": denied,
+        }))
+    }
+
+    /// Current access keys of the account, none if it was deleted
+    async fn access_keys(&self, account_id: &str) -> anyhow::Result<BTreeSet<String>> {
+        let response = self
+            .rpc
+            .call(methods::query::RpcQueryRequest {
+                block_reference: BlockReference::Finality(Finality::Final),
+                request: QueryRequest::ViewAccessKeyList {
+                    account_id: account_id.parse()?,
+                },
+            })
+            .await?;
+        match response {
+            Ok(response) => match response.kind {
+                QueryResponseKind::AccessKeyList(list) => Ok(list
+                    .keys
+                    .into_iter()
+                    .map(|key| key.public_key.to_string())
+                    .collect()),
+                kind => anyhow::bail!("unexpected query response: {:?}", kind),
+            },
+            Err(err) => match err.handler_error() {
+                Some(RpcQueryError::UnknownAccount { .. }) => Ok(BTreeSet::new()),
+                _ => Err(coded(
+                    ErrorCode::RpcUnavailable,
+                    format!(
+                        "failed looking up the access keys of {}: {}",
+                        account_id, err
+                    ),
+                )),
+            },
+        }
+    }
+
+    /// API key of the admin making the request, fails unless it's a signed request of an admin key
+    /// or carries an API token marked as `admin`, which is named after the token
+    pub(crate) fn admin_of(&self, req: &HttpRequest) -> anyhow::Result<String> {
+        if let Some(token) = req.extensions().get::<AdminToken>() {
+            return Ok(format!("token:{}", token.0));
+        }
+        match req.extensions().get::<AuthenticatedClient>() {
+            Some(client) if self.admin_api_keys.contains(&client.0) => Ok(client.0.clone()),
+            Some(_) => Err(coded(
+                ErrorCode::Forbidden,
+                "the API key is not allowed to use the admin endpoints",
+            )),
+            None => Err(coded(
+                ErrorCode::Unauthorized,
+                "the admin endpoints require a signed request",
+            )),
+        }
+    }
+}
+
+/// JSON error of the admin and the other auxiliary endpoints, with the status of its code
+pub(crate) fn error_response(err: &anyhow::Error) -> HttpResponse {
+    let code = ErrorCode::classify(err);
+    HttpResponse::build(code.http_status()).json(serde_json::json!({
+        "result": null,
+        "error": { "code": code, "message": err.to_string() },
+    }))
+}
+
+/// Endpoint: /report
+/// Stores a report of an abusive account created by the faucet for the admins to review (JSON)
+pub(crate) async fn report_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+    request: web::Json<ReportRequest>,
+) -> impl Responder {
+    tracing::debug!("POST /report");
+    let reporter = req
+        .extensions()
+        .get::<AuthenticatedClient>()
+        .map(|client| client.0.clone())
+        .unwrap_or_else(|| crate::create_account::RequestOrigin::PUBLIC_TENANT.to_string());
+    let source = Identity::of(&req)
+        .map(|identity| identity.to_string())
+        .or_else(|| client_ip(&req).map(|ip| ip.to_string()))
+        .unwrap_or_default();
+    match near.abuse.report(request.into_inner(), reporter, source) {
+        Ok(report) => {
+            tracing::info!("Abuse report {} for {}", report.id, report.account_id);
+            HttpResponse::Ok().json(report)
+        }
+        Err(err) => error_response(&err),
+    }
+}
+
+/// Endpoint: /admin/reports
+/// Lists the abuse reports, optionally only those with the given `?status=` (JSON)
+pub(crate) async fn reports_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+    query: web::Query<ReportsQuery>,
+) -> impl Responder {
+    tracing::debug!("GET /admin/reports");
+    if let Err(err) = near.abuse.admin_of(&req) {
+        return error_response(&err);
+    }
+    HttpResponse::Ok().json(near.abuse.reports(query.status))
+}
+
+/// Endpoint: /admin/reports/{id}/review
+/// Marks the reported account as abusive, denying its keys and name pattern, or dismisses the report (JSON)
+pub(crate) async fn review_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+    id: web::Path<u64>,
+    review: web::Json<ReviewRequest>,
+) -> impl Responder {
+    tracing::debug!("POST /admin/reports/{}/review", id);
+    let result = match near.abuse.admin_of(&req) {
+        Ok(reviewer) => {
+            near.abuse
+                .review(id.into_inner(), review.into_inner(), reviewer)
+                .await
+        }
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(result) => HttpResponse::Ok().json(result),
+        Err(err) => error_response(&err),
+    }
+}
+
+/// Endpoint: /admin/denylist
+/// Responds with the denied public keys and name patterns (JSON)
+pub(crate) async fn denylist_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+) -> impl Responder {
+    tracing::debug!("GET /admin/denylist");
+    if let Err(err) = near.abuse.admin_of(&req) {
+        return error_response(&err);
+    }
+    let denylist = near.abuse.state.lock().unwrap().denylist.clone();
+    HttpResponse::Ok().json(denylist)
+}
+
+pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/report", web::post().to(report_handler))
+        .route("/admin/reports", web::get().to(reports_handler))
+        .route("/admin/reports/{id}/review", web::post().to(review_handler))
+        .route("/admin/denylist", web::get().to(denylist_handler))
+        .route(
+            "/admin/invites",
+            web::post().to(crate::invites::issue_handler),
+        );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn desk(denylist_file: Option<PathBuf>) -> AbuseDesk {
+        let rpc = RpcPool::new(
+            &["http://localhost:3030".to_string()],
+            Default::default(),
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        AbuseDesk::new("testnet".parse().unwrap(), &[], rpc, denylist_file).unwrap()
+    }
+
+    fn report(desk: &AbuseDesk, source: &str) -> anyhow::Result<AbuseReport> {
+        let request = ReportRequest {
+            account_id: "spam42.testnet".to_string(),
+            reason: "drains the faucet".to_string(),
+            evidence: vec![],
+        };
+        desk.report(request, "public".to_string(), source.to_string())
+    }
+
+    #[test]
+    fn caps_the_open_reports_of_each_reporter() {
+        let desk = desk(None);
+        for _ in 0..MAX_OPEN_REPORTS_PER_REPORTER {
+            report(&desk, "1.2.3.4").unwrap();
+        }
+        let err = report(&desk, "1.2.3.4").unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::RateLimited);
+        report(&desk, "5.6.7.8").unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_the_denylist_across_restarts() {
+        let path = std::env::temp_dir().join(format!("denylist-{}.json", rand::random::<u64>()));
+        let desk = self::desk(Some(path.clone()));
+        desk.state
+            .lock()
+            .unwrap()
+            .denylist
+            .name_patterns
+            .insert("spam*".to_string());
+        desk.save_denylist().await.unwrap();
+
+        let desk = self::desk(Some(path.clone()));
+        let err = desk.check("spam42.testnet", "ed25519:a").unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::Denylisted);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::Context as _;
+use near_crypto::PublicKey;
+
+use crate::abuse::matches_pattern;
+
+/// Account names and public keys the operators allow or refuse, as loaded from the file
+///
+/// One rule per line: `allow-name <pattern>`, `reserve-name <pattern>`, `allow-key <public_key>` or `block-key <public_key>`,
+/// where `*` in a name pattern matches any characters; empty lines and lines starting with `#` are ignored
+/// An empty allowlist allows everything not reserved or blocked
+#[derive(Debug, Clone, Default)]
+pub(crate) struct AccountLists {
+    allowed_names: Vec<String>,
+    reserved_names: Vec<String>,
+    /// Keys in their canonical `<type>:<base58>` form
+    allowed_keys: HashSet<String>,
+    blocked_keys: HashSet<String>,
+}
+
+impl AccountLists {
+    pub(crate) fn parse(rules: &str) -> anyhow::Result<Self> {
+        let mut lists = Self::default();
+        for (number, line) in rules.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (kind, value) = line
+                .split_once(char::is_whitespace)
+                .with_context(|| format!("line {}: expected `<kind> <value>`", number + 1))?;
+            let value = value.trim();
+            match kind {
+                "allow-name" => lists.allowed_names.push(value.to_lowercase()),
+                "reserve-name" => lists.reserved_names.push(value.to_lowercase()),
+                "allow-key" | "block-key" => {
+                    let key: PublicKey = value.parse().with_context(|| {
+                        format!("line {}: invalid public key {}", number + 1, value)
+                    })?;
+                    let keys = match kind {
+                        "allow-key" => &mut lists.allowed_keys,
+                        _ => &mut lists.blocked_keys,
+                    };
+                    keys.insert(key.to_string());
+                }
+                _ => anyhow::bail!("line {}: unknown rule kind {}", number + 1, kind),
+            }
+        }
+        Ok(lists)
+    }
+
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+        let rules = std::fs::read_to_string(path)
+            .with_context(|| format!("failed reading account lists file {}", path.display()))?;
+        Self::parse(&rules).with_context(|| format!("failed parsing {}", path.display()))
+    }
+
+    /// Why the account name (without the suffix) can't be used, if it can't
+    pub(crate) fn refuses_name(&self, name: &str) -> Option<String> {
+        if let Some(pattern) = self
+            .reserved_names
+            .iter()
+            .find(|pattern| matches_pattern(pattern, name))
+        {
+            return Some(match pattern.contains('*') {
+                true => format!("account names matching {} are reserved", pattern),
+                false => format!("the account name {} is reserved", name),
+            });
+        }
+        if !self.allowed_names.is_empty()
+            && !self
+                .allowed_names
+                .iter()
+                .any(|pattern| matches_pattern(pattern, name))
+        {
+            return Some(format!(
+                "the account name {} is not among the names the faucet creates",
+                name
+            ));
+        }
+        None
+    }
+
+    /// Why the public key can't be used, if it can't
+    pub(crate) fn refuses_key(&self, public_key: &PublicKey) -> Option<String> {
+        let key = public_key.to_string();
+        if self.blocked_keys.contains(&key) {
+            return Some(format!("the public key {} is blocked", key));
+        }
+        if !self.allowed_keys.is_empty() && !self.allowed_keys.contains(&key) {
+            return Some(format!(
+                "the public key {} is not among the keys the faucet creates accounts for",
+                key
+            ));
+        }
+        None
+    }
+}
+
+/// Account lists that can be swapped at runtime by reloading their file
+#[derive(Debug, Clone)]
+pub(crate) struct ReloadableAccountLists {
+    path: PathBuf,
+    lists: Arc<RwLock<AccountLists>>,
+}
+
+impl ReloadableAccountLists {
+    pub(crate) fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let lists = AccountLists::load(&path)?;
+        Ok(Self {
+            path,
+            lists: Arc::new(RwLock::new(lists)),
+        })
+    }
+
+    pub(crate) fn current(&self) -> std::sync::RwLockReadGuard<'_, AccountLists> {
+        self.lists.read().unwrap()
+    }
+
+    /// Re-reads the file, the current lists stay in effect if it's invalid
+    pub(crate) fn reload(&self) -> anyhow::Result<()> {
+        let lists = AccountLists::load(&self.path)?;
+        *self.lists.write().unwrap() = lists;
+        Ok(())
+    }
+
+    /// Reloads the lists on every SIGHUP
+    pub(crate) fn spawn_reloader(&self) -> anyhow::Result<()> {
+        let mut hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .context("failed listening for SIGHUP")?;
+        let lists = self.clone();
+        tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                match lists.reload() {
+                    Ok(()) => {
+                        tracing::info!("Reloaded account lists from {}", lists.path.display())
+                    }
+                    Err(err) => tracing::warn!("Failed to reload account lists: {:?}", err),
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_crypto::{KeyType, SecretKey};
+
+    use super::*;
+
+    #[test]
+    fn refuses_reserved_names_and_blocked_keys() {
+        let blocked = SecretKey::from_random(KeyType::ED25519).public_key();
+        let lists = AccountLists::parse(&format!(
+            "# Names of the ecosystem\nreserve-name near\nreserve-name wallet*\n\nblock-key {}\n",
+            blocked
+        ))
+        .unwrap();
+        assert!(lists.refuses_name("near").is_some());
+        assert!(lists.refuses_name("wallet-2").is_some());
+        assert!(lists.refuses_name("nearby").is_none());
+        assert!(lists.refuses_key(&blocked).is_some());
+        let other = SecretKey::from_random(KeyType::ED25519).public_key();
+        assert!(lists.refuses_key(&other).is_none());
+
+        let lists = AccountLists::parse("allow-name team-*\nreserve-name team-admin").unwrap();
+        assert!(lists.refuses_name("team-alice").is_none());
+        assert!(lists.refuses_name("team-admin").is_some());
+        assert!(lists.refuses_name("alice").is_some());
+        assert!(AccountLists::parse("block-key ed25519:nope").is_err());
+    }
+}
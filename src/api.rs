@@ -0,0 +1,88 @@
+use actix_web::web;
+
+/// Prefix of the current version of the programmatic endpoints
+/// A response-shape change ships under a new prefix, the existing integrations keep this one
+pub(crate) const V1_PREFIX: &str = "/api/v1";
+
+/// Former paths that are served under another name in `/api/v1`
+pub(crate) const RENAMED: &[(&str, &str)] = &[("/v1/events/stream", "/events/stream")];
+
+/// Path of the request without the version prefix, so the path-based middlewares treat both paths of an endpoint alike
+pub(crate) fn unversioned(path: &str) -> &str {
+    match path.strip_prefix(V1_PREFIX) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => path,
+    }
+}
+
+/// Programmatic endpoints, served under `/api/v1` and at their former paths
+/// The pages, the form and the widget submissions, the sign-in redirects, `/metrics` and `/.well-known` stay where they are
+fn endpoints(cfg: &mut web::ServiceConfig) {
+    cfg.route("/quota", web::get().to(crate::quota::quota_handler))
+        .route(
+            "/admin/tokens",
+            web::get().to(crate::middleware::api_tokens::tokens_handler),
+        )
+        .route("/stats", web::get().to(crate::stats::stats_handler))
+        .route("/status", web::get().to(crate::status::status_handler))
+        .route("/jobs", web::post().to(crate::jobs::create_job_handler))
+        .route("/jobs/{id}", web::get().to(crate::jobs::job_handler))
+        .route(
+            "/key-challenge",
+            web::post().to(crate::key_proof::challenge_handler),
+        )
+        .route(
+            "/tx/{tx_hash}",
+            web::get().to(crate::tx_tracker::tx_status_handler),
+        )
+        .configure(crate::info::configure)
+        .configure(crate::passkey::configure)
+        .configure(crate::abuse::configure)
+        .configure(crate::maintenance::configure)
+        .configure(crate::bans::configure);
+
+    #[cfg(feature = "contract-helper")]
+    cfg.service(crate::contract_helper::account_scope());
+}
+
+/// Registers the endpoints in the `/api/v1` scope
+pub(crate) fn configure_v1(cfg: &mut web::ServiceConfig) {
+    cfg.route(
+        "/events/stream",
+        web::get().to(crate::events::stream_handler),
+    );
+    // New in `/api/v1`, without a former path
+    cfg.route(
+        "/creations",
+        web::get().to(crate::history::creations_handler),
+    );
+    #[cfg(feature = "contract-helper")]
+    cfg.route(
+        "/graphql",
+        web::post().to(crate::contract_helper::graphql::graphql_handler),
+    );
+    endpoints(cfg);
+}
+
+/// Registers the endpoints at their former paths, kept for the existing integrations
+/// Their responses carry a `Deprecation` header and a `Link` to the `/api/v1` path
+pub(crate) fn configure_legacy(cfg: &mut web::ServiceConfig) {
+    cfg.route(
+        "/v1/events/stream",
+        web::get().to(crate::events::stream_handler),
+    );
+    endpoints(cfg);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_the_version_prefix() {
+        assert_eq!(unversioned("/api/v1/jobs/abc"), "/jobs/abc");
+        assert_eq!(unversioned("/api/v1/admin/pause"), "/admin/pause");
+        assert_eq!(unversioned("/jobs"), "/jobs");
+        assert_eq!(unversioned("/api/v10/jobs"), "/api/v10/jobs");
+    }
+}
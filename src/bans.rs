@@ -0,0 +1,347 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::ErrorCode;
+
+/// When the failed requests of an address or a public key get it banned and for how long
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BanConfig {
+    /// Failed requests within the window that get the subject banned
+    pub(crate) strikes: u32,
+    pub(crate) window: Duration,
+    /// Length of the first ban, doubled on every further ban of the same subject
+    pub(crate) ban: Duration,
+    pub(crate) max_ban: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Subject {
+    PublicKey(String),
+    Ip(IpAddr),
+}
+
+impl fmt::Display for Subject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Subject::PublicKey(public_key) => write!(f, "public_key:{}", public_key),
+            Subject::Ip(ip) => write!(f, "ip:{}", ip),
+        }
+    }
+}
+
+/// The requester is banned for a while after too many failed requests
+/// Classified as `BANNED`, the remaining time is returned to the clients along with the error
+#[derive(Debug)]
+pub(crate) struct Banned {
+    pub(crate) message: String,
+    pub(crate) retry_after: Duration,
+}
+
+impl fmt::Display for Banned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Banned {}
+
+#[derive(Debug, Default)]
+struct Offender {
+    /// Failed requests within the window, oldest first
+    strikes: VecDeque<Instant>,
+    /// Bans so far, the next one lasts twice as long as the last
+    bans: u32,
+    banned_until: Option<Instant>,
+    /// Code of the failure that got the subject banned last
+    reason: Option<ErrorCode>,
+}
+
+/// Ban as listed by `GET /admin/bans`
+#[derive(Debug, Serialize)]
+struct BanEntry {
+    subject: String,
+    /// Unix timestamp
+    banned_until: u64,
+    /// Bans of the subject so far, including this one
+    bans: u32,
+    reason: Option<ErrorCode>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct UnbanRequest {
+    ip: Option<IpAddr>,
+    public_key: Option<String>,
+}
+
+/// Failures that are a sign of scripted abuse rather than of a busy faucet, e.g. random keys or taken names tried in a loop
+fn is_offense(code: ErrorCode) -> bool {
+    matches!(
+        code,
+        ErrorCode::AccountExists
+            | ErrorCode::InvalidAccountId
+            | ErrorCode::InvalidPublicKey
+            | ErrorCode::Denylisted
+            | ErrorCode::InvalidInvite
+            | ErrorCode::ReservedName
+            | ErrorCode::BlockedKey
+    )
+}
+
+/// Temporary bans of the addresses and public keys piling up failed requests, each ban of the same subject
+/// lasting twice as long as the previous one; the admins review them and lift them by hand
+/// Kept in memory of the process serving the requests
+pub(crate) struct AutoBans {
+    config: BanConfig,
+    offenders: Mutex<HashMap<Subject, Offender>>,
+}
+
+impl AutoBans {
+    pub(crate) fn new(config: BanConfig) -> Self {
+        Self {
+            config: BanConfig {
+                strikes: config.strikes.max(1),
+                ..config
+            },
+            offenders: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn subjects(public_key: Option<&str>, ip: Option<IpAddr>) -> Vec<Subject> {
+        public_key
+            .map(|public_key| Subject::PublicKey(public_key.to_string()))
+            .into_iter()
+            .chain(ip.map(Subject::Ip))
+            .collect()
+    }
+
+    fn ban_length(&self, bans: u32) -> Duration {
+        self.config
+            .ban
+            .saturating_mul(2u32.saturating_pow(bans.saturating_sub(1)))
+            .min(self.config.max_ban)
+    }
+
+    /// Fails with `BANNED` and the remaining time if the key or the address is banned
+    /// The address is `None` for the authenticated clients, which share addresses
+    pub(crate) fn check(&self, public_key: &str, ip: Option<IpAddr>) -> anyhow::Result<()> {
+        let now = Instant::now();
+        let offenders = self.offenders.lock().unwrap();
+        for subject in Self::subjects(Some(public_key), ip) {
+            let Some(until) = offenders
+                .get(&subject)
+                .and_then(|offender| offender.banned_until)
+                .filter(|until| *until > now)
+            else {
+                continue;
+            };
+            let seconds = until.duration_since(now).as_secs().max(1);
+            let message = match subject {
+                Subject::PublicKey(_) => format!(
+                    "the public key is banned after too many failed requests, try again in {} seconds",
+                    seconds
+                ),
+                Subject::Ip(_) => format!(
+                    "your address is banned after too many failed requests, try again in {} seconds",
+                    seconds
+                ),
+            };
+            return Err(Banned {
+                message,
+                retry_after: Duration::from_secs(seconds),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Counts the failed request against the key and the address, banning those reaching the strikes within the window
+    /// The public key is `None` if it couldn't be parsed, only the failures in `is_offense` count
+    pub(crate) fn strike(&self, public_key: Option<&str>, ip: Option<IpAddr>, code: ErrorCode) {
+        if !is_offense(code) {
+            return;
+        }
+        let now = Instant::now();
+        let mut offenders = self.offenders.lock().unwrap();
+        for subject in Self::subjects(public_key, ip) {
+            let offender = offenders.entry(subject.clone()).or_default();
+            while offender
+                .strikes
+                .front()
+                .is_some_and(|at| now.duration_since(*at) >= self.config.window)
+            {
+                offender.strikes.pop_front();
+            }
+            offender.strikes.push_back(now);
+            if offender.strikes.len() < self.config.strikes as usize {
+                continue;
+            }
+            offender.strikes.clear();
+            offender.bans += 1;
+            let length = self.ban_length(offender.bans);
+            offender.banned_until = Some(now + length);
+            offender.reason = Some(code);
+            tracing::warn!(
+                "Banned {} for {} seconds after repeated {} failures (ban #{})",
+                subject,
+                length.as_secs(),
+                code,
+                offender.bans
+            );
+        }
+    }
+
+    /// Current bans, the longest first
+    fn bans(&self) -> Vec<BanEntry> {
+        let now = Instant::now();
+        let unix_now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let mut bans: Vec<BanEntry> = self
+            .offenders
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(subject, offender)| {
+                let until = offender.banned_until.filter(|until| *until > now)?;
+                Some(BanEntry {
+                    subject: subject.to_string(),
+                    banned_until: unix_now + until.duration_since(now).as_secs(),
+                    bans: offender.bans,
+                    reason: offender.reason,
+                })
+            })
+            .collect();
+        bans.sort_by(|a, b| b.banned_until.cmp(&a.banned_until));
+        bans
+    }
+
+    /// Lifts the ban of the subject and forgets its history, returns whether it was known
+    fn unban(&self, subject: &Subject) -> bool {
+        self.offenders.lock().unwrap().remove(subject).is_some()
+    }
+
+    /// Forgets the subjects without recent failures whose last ban ended over `max_ban` ago,
+    /// so a ban is only doubled for the repeated offenders, returns how many were forgotten
+    pub(crate) fn purge_expired(&self) -> usize {
+        let now = Instant::now();
+        let mut offenders = self.offenders.lock().unwrap();
+        let before = offenders.len();
+        offenders.retain(|_, offender| {
+            let recent_strike = offender
+                .strikes
+                .back()
+                .is_some_and(|at| now.duration_since(*at) < self.config.window);
+            let remembered_ban = offender
+                .banned_until
+                .is_some_and(|until| until + self.config.max_ban > now);
+            recent_strike || remembered_ban
+        });
+        before - offenders.len()
+    }
+}
+
+/// Endpoint: /admin/bans
+/// Responds with the current automatic bans (JSON)
+pub(crate) async fn bans_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+) -> impl Responder {
+    tracing::debug!("GET /admin/bans");
+    if let Err(err) = near.abuse.admin_of(&req) {
+        return crate::abuse::error_response(&err);
+    }
+    let bans = near
+        .bans
+        .as_ref()
+        .map(|bans| bans.bans())
+        .unwrap_or_default();
+    HttpResponse::Ok().json(bans)
+}
+
+/// Endpoint: /admin/bans/unban
+/// Lifts the ban of `{ip}` or `{public_key}` and forgets its failures (JSON)
+pub(crate) async fn unban_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+    request: web::Json<UnbanRequest>,
+) -> impl Responder {
+    tracing::debug!("POST /admin/bans/unban");
+    let admin = match near.abuse.admin_of(&req) {
+        Ok(admin) => admin,
+        Err(err) => return crate::abuse::error_response(&err),
+    };
+    let subject = match request.into_inner() {
+        UnbanRequest {
+            ip: Some(ip),
+            public_key: None,
+        } => Subject::Ip(ip),
+        UnbanRequest {
+            ip: None,
+            public_key: Some(public_key),
+        } => Subject::PublicKey(public_key.trim().to_string()),
+        _ => {
+            return crate::abuse::error_response(
+                &crate::errors::CodedError {
+                    code: ErrorCode::InvalidRequest,
+                    message: "either ip or public_key is required".to_string(),
+                }
+                .into(),
+            )
+        }
+    };
+    let unbanned = near.bans.as_ref().is_some_and(|bans| bans.unban(&subject));
+    if unbanned {
+        tracing::info!("{} lifted the ban of {}", admin, subject);
+    }
+    HttpResponse::Ok().json(serde_json::json!({
+        "subject": subject.to_string(),
+        "unbanned": unbanned,
+    }))
+}
+
+pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/admin/bans", web::get().to(bans_handler))
+        .route("/admin/bans/unban", web::post().to(unban_handler));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::retry_after;
+
+    #[test]
+    fn bans_the_repeated_offenders_for_longer() {
+        let bans = AutoBans::new(BanConfig {
+            strikes: 2,
+            window: Duration::from_secs(60),
+            ban: Duration::from_secs(100),
+            max_ban: Duration::from_secs(300),
+        });
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        // Only the failures hinting at abuse count
+        bans.strike(Some("ed25519:a"), Some(ip), ErrorCode::RpcUnavailable);
+        bans.strike(Some("ed25519:a"), Some(ip), ErrorCode::RpcUnavailable);
+        bans.check("ed25519:a", Some(ip)).unwrap();
+
+        bans.strike(None, Some(ip), ErrorCode::InvalidPublicKey);
+        bans.strike(Some("ed25519:b"), Some(ip), ErrorCode::AccountExists);
+        let err = bans.check("ed25519:c", Some(ip)).unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::Banned);
+        assert!(retry_after(&err).unwrap() <= Duration::from_secs(100));
+        // The key failed only once, and the authenticated clients don't share the ban of the address
+        bans.check("ed25519:b", None).unwrap();
+
+        assert_eq!(bans.ban_length(2), Duration::from_secs(200));
+        assert_eq!(bans.ban_length(5), Duration::from_secs(300));
+        assert_eq!(bans.bans().len(), 1);
+        assert!(bans.unban(&Subject::Ip(ip)));
+        bans.check("ed25519:c", Some(ip)).unwrap();
+    }
+}
@@ -0,0 +1,207 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use actix_web::dev::Payload;
+use actix_web::error::{InternalError, JsonPayloadError, UrlencodedError};
+use actix_web::http::StatusCode;
+use actix_web::{web, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use serde::de::DeserializeOwned;
+
+use crate::errors::ErrorCode;
+
+/// Largest bodies the JSON and the form endpoints accept, in bytes
+/// The bodies over them are rejected with `413`, those of another content type with `415`,
+/// and the malformed ones with `400` and the parser's message, all in the JSON error envelope
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BodyLimits {
+    pub(crate) json: usize,
+    pub(crate) form: usize,
+}
+
+fn rejected(status: StatusCode, message: String) -> actix_web::Error {
+    tracing::debug!("Rejected the request body: {}", message);
+    let response = HttpResponse::build(status).json(serde_json::json!({
+        "result": null,
+        "error": { "code": ErrorCode::InvalidRequest, "message": message },
+    }));
+    InternalError::from_response(message, response).into()
+}
+
+fn too_large(limit: usize) -> actix_web::Error {
+    rejected(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!(
+            "the request body is larger than the limit of {} bytes",
+            limit
+        ),
+    )
+}
+
+impl BodyLimits {
+    /// Extractor config of the JSON bodies, which must be sent as `application/json`
+    pub(crate) fn json_config(&self) -> web::JsonConfig {
+        let limit = self.json;
+        web::JsonConfig::default()
+            .limit(limit)
+            .error_handler(move |err, _req| match err {
+                JsonPayloadError::OverflowKnownLength { .. }
+                | JsonPayloadError::Overflow { .. } => too_large(limit),
+                JsonPayloadError::ContentType => rejected(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "the request body must be sent as application/json".to_string(),
+                ),
+                JsonPayloadError::Deserialize(err) => rejected(
+                    StatusCode::BAD_REQUEST,
+                    format!("invalid JSON body: {}", err),
+                ),
+                err => rejected(err.status_code(), err.to_string()),
+            })
+    }
+
+    /// Extractor config of the form bodies, which must be sent as `application/x-www-form-urlencoded`
+    pub(crate) fn form_config(&self) -> web::FormConfig {
+        let limit = self.form;
+        web::FormConfig::default()
+            .limit(limit)
+            .error_handler(move |err, _req| match err {
+                UrlencodedError::Overflow { .. } => too_large(limit),
+                UrlencodedError::ContentType => rejected(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "the form must be sent as application/x-www-form-urlencoded".to_string(),
+                ),
+                UrlencodedError::Parse(err) => rejected(
+                    StatusCode::BAD_REQUEST,
+                    format!("invalid form body: {}", err),
+                ),
+                err => rejected(err.status_code(), err.to_string()),
+            })
+    }
+
+    /// Config of the raw bodies read by the middlewares, e.g. to verify the signed requests, the larger of the limits
+    pub(crate) fn payload_config(&self) -> web::PayloadConfig {
+        web::PayloadConfig::new(self.json.max(self.form))
+    }
+}
+
+/// Body of the endpoints taking both the forms and JSON, parsed as JSON if sent as `application/json`
+/// and as a form otherwise, with the limits and the errors of the respective extractor
+pub(crate) struct FormOrJson<T> {
+    pub(crate) body: T,
+    /// Whether the body was sent as JSON, e.g. by a programmatic client rather than a browser form
+    pub(crate) json: bool,
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for FormOrJson<T> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        if req.content_type() == "application/json" {
+            let body = web::Json::<T>::from_request(req, payload);
+            Box::pin(async move {
+                Ok(Self {
+                    body: body.await?.into_inner(),
+                    json: true,
+                })
+            })
+        } else {
+            let body = web::Form::<T>::from_request(req, payload);
+            Box::pin(async move {
+                Ok(Self {
+                    body: body.await?.into_inner(),
+                    json: false,
+                })
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, App};
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Body {
+        account_id: String,
+    }
+
+    #[actix_web::test]
+    async fn rejects_oversized_and_mistyped_bodies() {
+        let limits = BodyLimits { json: 64, form: 64 };
+        let app = test::init_service(
+            App::new()
+                .app_data(limits.json_config())
+                .app_data(limits.form_config())
+                .route(
+                    "/json",
+                    web::post()
+                        .to(|body: web::Json<Body>| async move { body.into_inner().account_id }),
+                )
+                .route(
+                    "/form",
+                    web::post()
+                        .to(|body: web::Form<Body>| async move { body.into_inner().account_id }),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/json")
+            .set_json(serde_json::json!({ "account_id": "alice" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let req = test::TestRequest::post()
+            .uri("/json")
+            .set_json(serde_json::json!({ "account_id": "a".repeat(100) }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 413);
+
+        let req = test::TestRequest::post()
+            .uri("/json")
+            .set_form([("account_id", "alice")])
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 415);
+
+        let req = test::TestRequest::post()
+            .uri("/form")
+            .set_json(serde_json::json!({ "account_id": "alice" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 415);
+
+        let req = test::TestRequest::post()
+            .uri("/json")
+            .set_json(serde_json::json!({ "name": "alice" }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["error"]["code"], "INVALID_REQUEST");
+    }
+
+    #[actix_web::test]
+    async fn parses_forms_and_json_alike() {
+        let app = test::init_service(App::new().route(
+            "/either",
+            web::post().to(|body: FormOrJson<Body>| async move {
+                format!("{} {}", body.body.account_id, body.json)
+            }),
+        ))
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/either")
+            .set_json(serde_json::json!({ "account_id": "alice" }))
+            .to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "alice true");
+
+        let req = test::TestRequest::post()
+            .uri("/either")
+            .set_form([("account_id", "bob")])
+            .to_request();
+        assert_eq!(test::call_and_read_body(&app, req).await, "bob false");
+    }
+}
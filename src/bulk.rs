@@ -0,0 +1,318 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use actix_web::{error, web, HttpRequest, HttpResponse, Responder};
+use futures_util::StreamExt;
+use serde::Serialize;
+use tera::Context;
+use tokio::sync::oneshot;
+
+use crate::create_account::{EntryPoint, RequestOrigin};
+use crate::errors::{pending_tx_hash, user_message, ErrorCode};
+use crate::utils::nonce::NonceBlock;
+
+/// Bytes allowed per row of the uploads, far above the longest account ID and public key
+const BYTES_PER_ROW: usize = 512;
+const RESULT_HEADER: &str = "account_id,public_key,status,code,tx_hash,error\n";
+
+/// Rows an upload may have
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BulkConfig {
+    pub(crate) max_rows: usize,
+}
+
+impl BulkConfig {
+    /// Config of the upload bodies, sized for the allowed rows
+    pub(crate) fn payload_config(&self) -> web::PayloadConfig {
+        web::PayloadConfig::new(self.max_rows.max(1) * BYTES_PER_ROW)
+    }
+}
+
+/// Row of an upload, `account_id,public_key[,invite_code]`
+#[derive(Debug, PartialEq, Eq)]
+struct Row {
+    /// 1-based line of the upload, reported with the problems of the row
+    line: usize,
+    account_id: String,
+    public_key: String,
+    invite_code: Option<String>,
+}
+
+/// Problem of a row refusing the whole upload
+#[derive(Debug, Serialize)]
+struct RowError {
+    line: usize,
+    message: String,
+}
+
+/// Splits the upload into its rows, skipping the blank lines and the `account_id,public_key` header
+fn parse(csv: &str) -> Result<Vec<Row>, Vec<RowError>> {
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for (index, line) in csv.lines().enumerate() {
+        let line_number = index + 1;
+        let fields: Vec<_> = line.split(',').map(str::trim).collect();
+        match fields.as_slice() {
+            [""] => {}
+            [account_id, _, ..] if index == 0 && account_id.eq_ignore_ascii_case("account_id") => {}
+            [account_id, public_key] | [account_id, public_key, ""] => rows.push(Row {
+                line: line_number,
+                account_id: account_id.to_string(),
+                public_key: public_key.to_string(),
+                invite_code: None,
+            }),
+            [account_id, public_key, invite_code] => rows.push(Row {
+                line: line_number,
+                account_id: account_id.to_string(),
+                public_key: public_key.to_string(),
+                invite_code: Some(invite_code.to_string()),
+            }),
+            _ => errors.push(RowError {
+                line: line_number,
+                message: "expected account_id,public_key[,invite_code]".to_string(),
+            }),
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(rows),
+        false => Err(errors),
+    }
+}
+
+/// Quotes the field if it has a comma, a quote or a line break
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
+
+fn result_line(
+    account_id: &str,
+    public_key: &str,
+    status: &str,
+    code: &str,
+    tx_hash: &str,
+    error: &str,
+) -> String {
+    format!(
+        "{},{},{},{},{},{}\n",
+        account_id,
+        public_key,
+        status,
+        code,
+        tx_hash,
+        csv_field(error)
+    )
+}
+
+fn refused(message: String, rows: Vec<RowError>) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "result": null,
+        "error": { "code": ErrorCode::InvalidRequest, "message": message, "rows": rows },
+    }))
+}
+
+/// Endpoint: /bulk
+/// Page uploading a CSV of accounts to create with an admin API token (HTML)
+pub(crate) async fn bulk_page(
+    templates: web::Data<crate::templates::Templates>,
+    config: web::Data<BulkConfig>,
+) -> actix_web::Result<impl Responder> {
+    tracing::debug!("GET /bulk");
+    let mut context = Context::new();
+    context.insert("max_rows", &config.max_rows);
+    let rendered = templates
+        .render("bulk.html.tera", &context)
+        .map_err(|err| {
+            error::ErrorInternalServerError(format!("Failed to render template: {:?}", err))
+        })?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
+
+/// Endpoint: /bulk
+/// Creates the accounts of a CSV upload of `account_id,public_key[,invite_code]` rows, for the admins
+/// All the rows are validated first, any invalid one refuses the upload with `400` listing the problems (JSON),
+/// then the creations run in the slots of the creation jobs and a result row per account is streamed back (CSV)
+pub(crate) async fn bulk_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+    config: web::Data<BulkConfig>,
+    body: web::Bytes,
+) -> HttpResponse {
+    tracing::debug!("POST /bulk");
+    let admin = match near.abuse.admin_of(&req) {
+        Ok(admin) => admin,
+        Err(err) => return crate::abuse::error_response(&err),
+    };
+    let Ok(csv) = std::str::from_utf8(&body) else {
+        return refused("the upload is not valid UTF-8".to_string(), Vec::new());
+    };
+    let rows = match parse(csv) {
+        Ok(rows) => rows,
+        Err(errors) => return refused("the upload has malformed rows".to_string(), errors),
+    };
+    if rows.is_empty() || rows.len() > config.max_rows {
+        return refused(
+            format!(
+                "the upload has {} rows, it must have 1 to {}",
+                rows.len(),
+                config.max_rows
+            ),
+            Vec::new(),
+        );
+    }
+    let mut seen = HashSet::new();
+    let mut errors = Vec::new();
+    let mut accounts = Vec::new();
+    for row in rows {
+        match near.validation.validate(&row.account_id, &row.public_key) {
+            Ok(input) if !seen.insert(input.account_id.clone()) => errors.push(RowError {
+                line: row.line,
+                message: format!("{} is listed more than once", input.account_id),
+            }),
+            Ok(input) => accounts.push((input, row.invite_code)),
+            Err(invalid) => errors.push(RowError {
+                line: row.line,
+                message: invalid.to_string(),
+            }),
+        }
+    }
+    if !errors.is_empty() {
+        return refused("the upload has invalid rows".to_string(), errors);
+    }
+    tracing::info!("{} uploaded {} accounts to create", admin, accounts.len());
+
+    let nonces = reserve_nonces(&near, accounts.len()).await;
+    // Each creation runs in its own task, finishing even if the client stops reading the results
+    let mut results = Vec::new();
+    for (input, invite_code) in accounts {
+        let (sender, result) = oneshot::channel();
+        let near = near.get_ref().clone();
+        let mut origin = RequestOrigin::new(EntryPoint::Api, &req);
+        origin.invite_code = invite_code;
+        origin.nonces = nonces.clone();
+        let fallback = result_line(
+            &input.account_id,
+            &input.public_key,
+            "failed",
+            ErrorCode::InternalError.as_str(),
+            "",
+            "the creation task failed",
+        );
+        tokio::spawn(async move {
+            let created = near
+                .jobs
+                .run(crate::create_account::create_account(
+                    &near,
+                    &input.account_id,
+                    &input.public_key,
+                    near.default_wait,
+                    &origin,
+                ))
+                .await;
+            let line = match created {
+                Ok(submitted) => {
+                    let tx_hash = submitted
+                        .tx_hash
+                        .map(|hash| hash.to_string())
+                        .unwrap_or_default();
+                    result_line(
+                        &input.account_id,
+                        &input.public_key,
+                        "created",
+                        "",
+                        &tx_hash,
+                        "",
+                    )
+                }
+                Err(err) => {
+                    let code = ErrorCode::classify(&err);
+                    // A creation pending past the deadline may still succeed
+                    let status = match code {
+                        ErrorCode::Pending => "pending",
+                        _ => "failed",
+                    };
+                    // The transaction of a pending creation can be followed at `/tx/{tx_hash}`
+                    let tx_hash = pending_tx_hash(&err)
+                        .map(|hash| hash.to_string())
+                        .unwrap_or_default();
+                    result_line(
+                        &input.account_id,
+                        &input.public_key,
+                        status,
+                        code.as_str(),
+                        &tx_hash,
+                        &user_message(&err),
+                    )
+                }
+            };
+            let _ = sender.send(line);
+        });
+        results.push((result, fallback));
+    }
+    let stream = futures_util::stream::once(async { RESULT_HEADER.to_string() })
+        .chain(
+            futures_util::stream::iter(results)
+                .then(|(result, fallback)| async move { result.await.unwrap_or(fallback) }),
+        )
+        .map(|line| Ok::<_, actix_web::Error>(web::Bytes::from(line)));
+    HttpResponse::Ok()
+        .content_type("text/csv")
+        .insert_header((
+            "content-disposition",
+            "attachment; filename=\"results.csv\"",
+        ))
+        .streaming(stream)
+}
+
+/// Reserves a nonce for each row at once, rather than bumping the counter shared with the other requests for every row
+/// Only done when this process signs the transactions, the workers sign the queued ones
+async fn reserve_nonces(near: &crate::NearData, rows: usize) -> Option<Arc<NonceBlock>> {
+    #[cfg(feature = "queue")]
+    if near.queue.is_some() {
+        return None;
+    }
+    let submitter = near.submitter.as_ref()?;
+    match submitter.reserve_nonces(rows as u64).await {
+        Ok(block) => Some(Arc::new(block)),
+        Err(err) => {
+            tracing::warn!("failed reserving the nonces of the upload: {:?}", err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_rows() {
+        let rows =
+            parse("account_id,public_key\nalice,ed25519:a\n\nbob, ed25519:b ,code\n").unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                Row {
+                    line: 2,
+                    account_id: "alice".to_string(),
+                    public_key: "ed25519:a".to_string(),
+                    invite_code: None,
+                },
+                Row {
+                    line: 4,
+                    account_id: "bob".to_string(),
+                    public_key: "ed25519:b".to_string(),
+                    invite_code: Some("code".to_string()),
+                },
+            ]
+        );
+        let errors = parse("alice\nbob,ed25519:b,code,extra").unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.line).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(csv_field("a, \"b\""), "\"a, \"\"b\"\"\"");
+    }
+}
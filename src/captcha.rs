@@ -0,0 +1,175 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use anyhow::Context;
+use near_primitives::types::Balance;
+use serde::Deserialize;
+
+use crate::errors::{CodedError, ErrorCode};
+
+const TURNSTILE_VERIFY_URL: &str = "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+#[cfg(feature = "contract-helper")]
+const HCAPTCHA_VERIFY_URL: &str = "https://api.hcaptcha.com/siteverify";
+const RECAPTCHA_VERIFY_URL: &str = "https://www.google.com/recaptcha/api/siteverify";
+/// The verification is on the path of every creation, a slow provider shouldn't hold the requests for long
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Answer of the `siteverify` endpoint
+#[derive(Debug, Deserialize)]
+struct SiteVerifyResponse {
+    success: bool,
+    /// Only reCAPTCHA v3 scores the requests, from 0 for a bot to 1 for a human
+    score: Option<f64>,
+    #[serde(default, rename = "error-codes")]
+    error_codes: Vec<String>,
+}
+
+/// Verifies the captcha tokens solved by the clients with the provider, server-side
+#[derive(Debug, Clone)]
+pub(crate) struct CaptchaVerifier {
+    /// Name shown in the messages, e.g. `Turnstile`
+    provider: &'static str,
+    verify_url: &'static str,
+    secret: String,
+    http: reqwest::Client,
+}
+
+impl CaptchaVerifier {
+    /// Cloudflare Turnstile, the widget puts its token into the `cf-turnstile-response` form field
+    pub(crate) fn turnstile(secret: String) -> anyhow::Result<Self> {
+        Self::new("Turnstile", TURNSTILE_VERIFY_URL, secret)
+    }
+
+    #[cfg(feature = "contract-helper")]
+    /// hCaptcha, the integrators pass its token in the `h-captcha-response` field
+    pub(crate) fn hcaptcha(secret: String) -> anyhow::Result<Self> {
+        Self::new("hCaptcha", HCAPTCHA_VERIFY_URL, secret)
+    }
+
+    fn new(
+        provider: &'static str,
+        verify_url: &'static str,
+        secret: String,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            provider,
+            verify_url,
+            secret,
+            http: reqwest::Client::builder()
+                .timeout(VERIFY_TIMEOUT)
+                .build()
+                .context("failed building the captcha HTTP client")?,
+        })
+    }
+
+    /// Fails with `CAPTCHA_FAILED` if the token is missing or the provider doesn't accept it
+    pub(crate) async fn verify(
+        &self,
+        token: Option<&str>,
+        remote_ip: Option<IpAddr>,
+    ) -> anyhow::Result<()> {
+        self.siteverify(token, remote_ip).await.map(|_| ())
+    }
+
+    async fn siteverify(
+        &self,
+        token: Option<&str>,
+        remote_ip: Option<IpAddr>,
+    ) -> anyhow::Result<SiteVerifyResponse> {
+        let token = token
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| CodedError {
+                code: ErrorCode::CaptchaFailed,
+                message: format!("solve the {} captcha first", self.provider),
+            })?;
+        let mut params = vec![
+            ("secret", self.secret.clone()),
+            ("response", token.to_string()),
+        ];
+        if let Some(ip) = remote_ip {
+            params.push(("remoteip", ip.to_string()));
+        }
+        let response: SiteVerifyResponse = self
+            .http
+            .post(self.verify_url)
+            .form(&params)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("failed reaching {} to verify the captcha", self.provider))?
+            .json()
+            .await
+            .with_context(|| format!("failed parsing the {} verification", self.provider))?;
+        if !response.success {
+            tracing::debug!(
+                "{} rejected the captcha: {:?}",
+                self.provider,
+                response.error_codes
+            );
+            return Err(CodedError {
+                code: ErrorCode::CaptchaFailed,
+                message: format!(
+                    "the {} captcha wasn't solved or has expired, try again",
+                    self.provider
+                ),
+            }
+            .into());
+        }
+        Ok(response)
+    }
+}
+
+/// Funding of the accounts by the reCAPTCHA v3 score of their request
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FundingTiers {
+    /// Scores from this one up get the configured funding
+    pub(crate) full_score: f64,
+    /// Scores from this one up get `reduced_amount`, the lower ones are rejected
+    pub(crate) min_score: f64,
+    /// Funding of the scores between `min_score` and `full_score`, rejected as well if not set
+    pub(crate) reduced_amount: Option<Balance>,
+}
+
+/// reCAPTCHA v3, invisible to the users, its score picks the funding of the account
+#[derive(Debug, Clone)]
+pub(crate) struct ScoredCaptcha {
+    verifier: CaptchaVerifier,
+    tiers: FundingTiers,
+}
+
+impl ScoredCaptcha {
+    /// The page puts the token into the `g-recaptcha-response` field
+    pub(crate) fn recaptcha(secret: String, tiers: FundingTiers) -> anyhow::Result<Self> {
+        Ok(Self {
+            verifier: CaptchaVerifier::new("reCAPTCHA", RECAPTCHA_VERIFY_URL, secret)?,
+            tiers,
+        })
+    }
+
+    /// Verifies the token and returns the funding of its score, `None` for the configured one
+    /// Fails with `CAPTCHA_FAILED` if the score is too low for any funding
+    pub(crate) async fn funding_amount(
+        &self,
+        token: Option<&str>,
+        remote_ip: Option<IpAddr>,
+    ) -> anyhow::Result<Option<Balance>> {
+        let score = self
+            .verifier
+            .siteverify(token, remote_ip)
+            .await?
+            .score
+            .context("reCAPTCHA returned no score, is the secret of a v3 key?")?;
+        tracing::debug!("reCAPTCHA score {}", score);
+        match self.tiers.reduced_amount {
+            _ if score >= self.tiers.full_score => Ok(None),
+            Some(reduced_amount) if score >= self.tiers.min_score => Ok(Some(reduced_amount)),
+            _ => Err(CodedError {
+                code: ErrorCode::CaptchaFailed,
+                message: "the request looks automated, try again later or from another browser"
+                    .to_string(),
+            }
+            .into()),
+        }
+    }
+}
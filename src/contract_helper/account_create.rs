@@ -1,10 +1,44 @@
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder};
+use near_primitives::views::{FinalExecutionOutcomeView, TxExecutionStatus};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize)]
+use crate::create_account::{EntryPoint, RequestOrigin};
+use crate::errors::{pending_tx_hash, retry_after, user_message, ErrorCode};
+use crate::tx_tracker::CreationTx;
+use crate::utils::send_tx::WaitLevel;
+use crate::validation::FieldError;
+
+#[derive(Debug, Serialize)]
 struct AccountCreateResponse {
     result: Option<AccountInfo>,
     error: Option<AccountCreateError>,
+    /// Raw transaction outcome in the `FinalExecutionOutcome` shape, only with `?response=outcome`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    outcome: Option<FinalExecutionOutcomeView>,
+    /// Hash of the creation transaction, the gas and tokens it burnt once executed and its explorer page
+    /// In the asynchronous broadcast mode its outcome is served by `/tx/{tx_hash}`
+    #[serde(flatten)]
+    transaction: Option<CreationTx>,
+    /// Level the creation transaction reached before responding, at least the requested `wait` one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    final_execution_status: Option<TxExecutionStatus>,
+}
+
+/// Shape of the creation response
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ResponseFormat {
+    /// Only the simplified envelope
+    #[default]
+    Envelope,
+    /// The envelope along with the raw transaction outcome, as expected by near-api-js based tooling
+    Outcome,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct AccountCreateQuery {
+    wait: Option<WaitLevel>,
+    response: Option<ResponseFormat>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -13,72 +47,153 @@ pub(crate) struct AccountInfo {
     public_key: String,
 }
 
-impl AccountInfo {
-    /// Normalizes the form data by trimming whitespace from the strings
-    fn normalize(self, base_signer_account_id: &str) -> Self {
-        let account_id = self.account_id.trim().to_string();
-        // If account_id provided by the user does not end with .statelessnet, we add it
-        let account_id =
-            if account_id.ends_with(format!(".{}", base_signer_account_id).to_string().as_str()) {
-                account_id
-            } else {
-                format!("{}.{}", account_id, base_signer_account_id)
-            };
-        AccountInfo {
-            account_id,
-            public_key: self.public_key.trim().to_string(),
-        }
-    }
+#[derive(Debug, Deserialize)]
+pub(crate) struct AccountCreateRequest {
+    #[serde(flatten)]
+    account: AccountInfo,
+    /// Token of the solved hCaptcha, only checked if hCaptcha is enabled
+    #[serde(rename = "h-captcha-response")]
+    hcaptcha_response: Option<String>,
+    /// Token of the reCAPTCHA v3 score, only checked if reCAPTCHA is enabled
+    #[serde(rename = "g-recaptcha-response")]
+    recaptcha_response: Option<String>,
+    /// One-time code of the gated faucet, only checked if the invites are enabled
+    invite_code: Option<String>,
+    /// Challenge from `/key-challenge` and its signature by the public key, only checked if the proofs are required
+    key_challenge: Option<String>,
+    key_signature: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Serialize)]
 struct AccountCreateError {
+    /// Stable code to branch on, the message is meant for humans and may change
+    code: ErrorCode,
     message: String,
+    /// Per-field problems of an invalid request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fields: Option<Vec<FieldError>>,
+    /// Seconds until the client may retry, e.g. the remaining cooldown of the public key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after_secs: Option<u64>,
 }
 
 pub(crate) async fn account_create_handler(
+    req: HttpRequest,
     data: web::Data<crate::NearData>,
-    account_info: web::Json<AccountInfo>,
+    query: web::Query<AccountCreateQuery>,
+    body: web::Json<AccountCreateRequest>,
 ) -> impl Responder {
+    let account_info = &body.account;
     // Extract the account_id and public_key from the request body
-    let normalized_account_info = account_info
-        .clone()
-        .normalize(&data.base_signer.account_id.as_str());
-    let account_id = normalized_account_info.account_id.clone();
-    let public_key = normalized_account_info.public_key.clone();
+    let (account_id, public_key) = match data
+        .validation
+        .validate(&account_info.account_id, &account_info.public_key)
+    {
+        Ok(input) => (input.account_id, input.public_key),
+        Err(errors) => {
+            crate::create_account::record_rejection(
+                &data,
+                &req,
+                &account_info.public_key,
+                errors.code(),
+            );
+            return HttpResponse::BadRequest().json(AccountCreateResponse {
+                result: None,
+                error: Some(AccountCreateError {
+                    code: errors.code(),
+                    message: errors.to_string(),
+                    fields: Some(errors.0),
+                    retry_after_secs: None,
+                }),
+                outcome: None,
+                transaction: None,
+                final_execution_status: None,
+            });
+        }
+    };
 
-    // Call the send_account_create function from crate::create_account
-    let result = crate::create_account::send_create_account(
-        &data.rpc,
-        &data.base_signer,
-        &account_id,
-        &public_key,
-        data.nonce.as_ref(),
-        *data.block_hash.read().unwrap(),
-        data.funding_amount,
-    )
-    .await;
+    let remote_ip = crate::middleware::client_ip::client_ip(&req);
+    let checks = async {
+        if let Some(key_proofs) = &data.key_proofs {
+            key_proofs.verify(
+                &public_key,
+                body.key_challenge.as_deref(),
+                body.key_signature.as_deref(),
+            )?;
+        }
+        if let Some(hcaptcha) = &data.hcaptcha {
+            hcaptcha
+                .verify(body.hcaptcha_response.as_deref(), remote_ip)
+                .await?;
+        }
+        // The reCAPTCHA score may reduce the funding of the account
+        match &data.recaptcha {
+            Some(recaptcha) => {
+                recaptcha
+                    .funding_amount(body.recaptcha_response.as_deref(), remote_ip)
+                    .await
+            }
+            None => Ok(None),
+        }
+    };
+    // Call the create_account function from crate::create_account
+    let result = match checks.await {
+        Ok(funding_amount) => {
+            let mut origin = RequestOrigin::new(EntryPoint::Api, &req);
+            origin.reduce_funding(funding_amount);
+            origin.captcha_verified = data.hcaptcha.is_some() || data.recaptcha.is_some();
+            origin.invite_code = body.invite_code.clone();
+            crate::create_account::create_account(
+                &data,
+                &account_id,
+                &public_key,
+                query.wait.unwrap_or(data.default_wait),
+                &origin,
+            )
+            .await
+        }
+        Err(err) => Err(err),
+    };
 
     // Return an appropriate response based on the result
     match result {
-        Ok(_) => {
+        Ok(submitted) => {
             let response = AccountCreateResponse {
                 result: Some(AccountInfo {
                     account_id: account_id.clone(),
                     public_key: public_key.clone(),
                 }),
                 error: None,
+                transaction: CreationTx::of(&submitted, data.explorer_tx_url.as_deref()),
+                outcome: submitted
+                    .outcome
+                    .filter(|_| query.response.unwrap_or_default() == ResponseFormat::Outcome),
+                final_execution_status: Some(submitted.reached),
             };
             HttpResponse::Ok().json(response)
         }
         Err(err) => {
+            let code = ErrorCode::classify(&err);
+            crate::middleware::idempotency::report_failure(&req, code);
+            let retry_after = retry_after(&err).map(|retry_after| retry_after.as_secs().max(1));
             let response = AccountCreateResponse {
                 result: None,
                 error: Some(AccountCreateError {
-                    message: err.to_string(),
+                    code,
+                    message: user_message(&err),
+                    fields: None,
+                    retry_after_secs: retry_after,
                 }),
+                outcome: None,
+                transaction: pending_tx_hash(&err)
+                    .map(|tx_hash| CreationTx::pending(tx_hash, data.explorer_tx_url.as_deref())),
+                final_execution_status: None,
             };
-            HttpResponse::InternalServerError().json(response)
+            let mut builder = HttpResponse::build(code.http_status());
+            if let Some(retry_after) = retry_after {
+                builder.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+            }
+            builder.json(response)
         }
     }
 }
@@ -0,0 +1,72 @@
+use actix_web::{web, HttpResponse, Responder};
+use sqlx::PgPool;
+
+use crate::errors::ErrorCode;
+
+/// Endpoint: /account/{account_id}/info
+/// Responds with whether the faucet created the account, when, with which key, how much it was funded with
+/// and the creation transaction (JSON)
+/// The creation records of the workers are checked first, the ExplorerDB `accounts` table is the fallback
+pub(crate) async fn account_info_handler(
+    pool: web::Data<PgPool>,
+    near: web::Data<crate::NearData>,
+    account_id: web::Path<String>,
+) -> impl Responder {
+    tracing::debug!("account_info_handler called. account_id: {:?}", account_id);
+    let account_id = account_id.into_inner();
+
+    #[cfg(feature = "queue")]
+    if let Some(queue) = &near.queue {
+        match queue.creation_record(&account_id).await {
+            Ok(Some(record)) => return HttpResponse::Ok().json(record),
+            Ok(None) => {}
+            Err(err) => tracing::warn!("Failed to look up the creation record: {:?}", err),
+        }
+    }
+
+    let result: Result<Option<serde_json::Value>, _> = sqlx::query_scalar(
+        r#"
+        SELECT json_build_object(
+            'account_id', acc.account_id,
+            'created_by_faucet', COALESCE(t.signer_account_id = $2, false),
+            'created_at', TO_CHAR(TO_TIMESTAMP(t.block_timestamp / 1000000000)::timestamp at time zone 'UTC', 'YYYY-MM-DD HH24:MI:SS.US'),
+            'public_key', (SELECT ak.public_key FROM access_keys ak
+                           WHERE ak.account_id = acc.account_id AND ak.created_by_receipt_id = acc.created_by_receipt_id
+                           LIMIT 1),
+            'funding_amount', (SELECT a.args->>'deposit' FROM transaction_actions a
+                               WHERE a.transaction_hash = t.transaction_hash AND a.action_kind = 'TRANSFER'
+                               LIMIT 1),
+            'transaction_hash', t.transaction_hash,
+            'source', 'explorer'
+        )
+        FROM accounts acc
+        LEFT JOIN transactions t ON t.converted_into_receipt_id = acc.created_by_receipt_id
+        WHERE acc.account_id = $1
+        "#,
+    )
+    .bind(&account_id)
+    .bind(near.validation.suffix.as_str())
+    .fetch_optional(&**pool)
+    .await;
+
+    match result {
+        Ok(Some(info)) => HttpResponse::Ok().json(info),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
+            "result": null,
+            "error": {
+                "code": ErrorCode::NotFound,
+                "message": format!("{} is unknown to the faucet and the explorer", account_id),
+            },
+        })),
+        Err(err) => {
+            tracing::warn!("Failed to execute query: {:?}", err);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "result": null,
+                "error": {
+                    "code": ErrorCode::InternalError,
+                    "message": "failed looking up the account",
+                },
+            }))
+        }
+    }
+}
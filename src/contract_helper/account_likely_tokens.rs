@@ -8,23 +8,15 @@ pub struct AccountLikelyTokensQuery {
     from_block_timestamp: u64,
 }
 
-pub(crate) async fn account_likely_tokens_handler(
-    pool: web::Data<PgPool>,
-    account_id: web::Path<String>,
-    query_params: web::Query<AccountLikelyTokensQuery>,
-) -> Result<impl Responder> {
-    let account_id = account_id.into_inner();
-    let from_block_timestamp = query_params.into_inner().from_block_timestamp;
-    tracing::debug!(
-        "account_likely_tokens_handler called. account_id: {:?}, from_block_timestamp: {:?}",
-        account_id,
-        from_block_timestamp
-    );
-
-    // convert from_block_timestamp to BigDecimal
+/// Contracts the account likely holds fungible tokens of, as `{lastBlockTimestamp, list, version}`
+/// `None` while the ExplorerDB has no blocks
+pub(super) async fn likely_tokens(
+    pool: &PgPool,
+    account_id: &str,
+    from_block_timestamp: u64,
+) -> sqlx::Result<Option<serde_json::Value>> {
     let from_block_timestamp = sqlx::types::BigDecimal::from(from_block_timestamp);
-
-    let result: Option<serde_json::Value> = sqlx::query_scalar!(
+    let result: Option<Option<serde_json::Value>> = sqlx::query_scalar!(
         r#"
         WITH last_block AS (
             SELECT block_timestamp
@@ -64,14 +56,30 @@ pub(crate) async fn account_likely_tokens_handler(
         account_id,
         from_block_timestamp,
     )
-    .fetch_optional(&**pool)
-    .await
-    .map_err(|e| {
-        tracing::warn!("Failed to execute query: {:?}", e);
-        HttpResponse::InternalServerError().finish()
-    })
-    .unwrap_or_default()
-    .unwrap_or_default();
+    .fetch_optional(pool)
+    .await?;
+    Ok(result.flatten())
+}
+
+pub(crate) async fn account_likely_tokens_handler(
+    pool: web::Data<PgPool>,
+    account_id: web::Path<String>,
+    query_params: web::Query<AccountLikelyTokensQuery>,
+) -> Result<impl Responder> {
+    let account_id = account_id.into_inner();
+    let from_block_timestamp = query_params.into_inner().from_block_timestamp;
+    tracing::debug!(
+        "account_likely_tokens_handler called. account_id: {:?}, from_block_timestamp: {:?}",
+        account_id,
+        from_block_timestamp
+    );
+
+    let result = likely_tokens(&pool, &account_id, from_block_timestamp)
+        .await
+        .unwrap_or_else(|e| {
+            tracing::warn!("Failed to execute query: {:?}", e);
+            None
+        });
 
     match result {
         Some(json) => Ok(HttpResponse::Ok().json(json)),
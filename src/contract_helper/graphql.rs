@@ -0,0 +1,373 @@
+use actix_web::{web, HttpResponse, Responder};
+use async_graphql::connection::{Connection, Edge};
+use async_graphql::{
+    ComplexObject, Context, EmptyMutation, EmptySubscription, Enum, ErrorExtensions, Object,
+    Schema, SimpleObject,
+};
+use serde::Deserialize;
+use sqlx::types::Json;
+use sqlx::PgPool;
+
+use super::account_likely_nfts::likely_nfts;
+use super::account_likely_tokens::likely_tokens;
+use crate::errors::ErrorCode;
+
+/// Page size of the connections when `first` is omitted
+const DEFAULT_PAGE_SIZE: usize = 10;
+/// Largest `first` of the connections
+const MAX_PAGE_SIZE: usize = 100;
+/// Nesting allowed in the queries, the schema is at most 5 levels deep
+const MAX_DEPTH: usize = 8;
+/// Fields allowed in a query, counting the nodes of the connections `first` times
+const MAX_COMPLEXITY: usize = 2_000;
+
+pub(crate) type ExplorerSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Schema over the ExplorerDB data of the `/account` endpoints, the pool is queried only for the requested fields
+pub(crate) fn schema(pool: PgPool) -> ExplorerSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(pool)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// Endpoint: /api/v1/graphql
+/// Runs a GraphQL query over the account activity, keys, likely tokens and likely NFTs (JSON)
+/// Errors are reported in `errors` with their `extensions.code`, like the other responses of the schema
+pub(crate) async fn graphql_handler(
+    schema: web::Data<ExplorerSchema>,
+    request: web::Json<async_graphql::Request>,
+) -> impl Responder {
+    tracing::debug!("POST /api/v1/graphql");
+    HttpResponse::Ok().json(schema.execute(request.into_inner()).await)
+}
+
+fn error(code: ErrorCode, message: impl Into<String>) -> async_graphql::Error {
+    async_graphql::Error::new(message).extend_with(|_, e| e.set("code", code.as_str()))
+}
+
+fn query_failed(err: sqlx::Error) -> async_graphql::Error {
+    tracing::warn!("Failed to execute query: {:?}", err);
+    error(
+        ErrorCode::InternalError,
+        "failed querying the explorer data",
+    )
+}
+
+fn parse_timestamp(value: &str) -> async_graphql::Result<u64> {
+    value.parse().map_err(|_| {
+        error(
+            ErrorCode::InvalidRequest,
+            "fromBlockTimestamp must be a block timestamp in nanoseconds",
+        )
+    })
+}
+
+pub(crate) struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Account to query the data of, the account isn't looked up until one of its fields needs it
+    async fn account(&self, account_id: String) -> Account {
+        Account { account_id }
+    }
+
+    /// Access keys with this public key, on any account
+    async fn keys(
+        &self,
+        ctx: &Context<'_>,
+        public_key: String,
+    ) -> async_graphql::Result<Vec<AccessKey>> {
+        let pool = ctx.data::<PgPool>()?;
+        sqlx::query_as(&format!("{} WHERE ak.public_key = $1", ACCESS_KEYS_QUERY))
+            .bind(public_key)
+            .fetch_all(pool)
+            .await
+            .map_err(query_failed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Enum)]
+enum Order {
+    Asc,
+    #[default]
+    Desc,
+}
+
+pub(crate) struct Account {
+    account_id: String,
+}
+
+#[Object]
+impl Account {
+    async fn account_id(&self) -> &str {
+        &self.account_id
+    }
+
+    /// Transactions sent by or to the account, by block height, `first` at a time after the `after` cursor
+    #[graphql(
+        complexity = "first.unwrap_or(DEFAULT_PAGE_SIZE as i32).max(0) as usize * child_complexity"
+    )]
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] order: Order,
+        first: Option<i32>,
+        after: Option<String>,
+    ) -> async_graphql::Result<Connection<usize, Transaction>> {
+        let limit = match first {
+            None => DEFAULT_PAGE_SIZE,
+            Some(first) if (0..=MAX_PAGE_SIZE as i32).contains(&first) => first as usize,
+            Some(_) => {
+                return Err(error(
+                    ErrorCode::InvalidRequest,
+                    format!("first must be between 0 and {}", MAX_PAGE_SIZE),
+                ))
+            }
+        };
+        let offset = match after {
+            Some(cursor) => cursor
+                .parse::<usize>()
+                .map(|index| index + 1)
+                .map_err(|_| error(ErrorCode::InvalidRequest, "invalid after cursor"))?,
+            None => 0,
+        };
+        let pool = ctx.data::<PgPool>()?;
+        let order = match order {
+            Order::Asc => "ASC",
+            Order::Desc => "DESC",
+        };
+        // One more than the page tells whether there is a next one
+        let mut transactions: Vec<Transaction> = sqlx::query_as(&format!(
+            "{} ORDER BY b.block_height {} OFFSET $2 LIMIT $3",
+            TRANSACTIONS_QUERY, order
+        ))
+        .bind(&self.account_id)
+        .bind(offset as i64)
+        .bind(limit as i64 + 1)
+        .fetch_all(pool)
+        .await
+        .map_err(query_failed)?;
+
+        let has_next_page = transactions.len() > limit;
+        transactions.truncate(limit);
+        let mut connection = Connection::new(offset > 0, has_next_page);
+        connection.edges.extend(
+            transactions
+                .into_iter()
+                .enumerate()
+                .map(|(index, transaction)| Edge::new(offset + index, transaction)),
+        );
+        Ok(connection)
+    }
+
+    /// Access keys of the account, including the deleted ones
+    async fn keys(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<AccessKey>> {
+        let pool = ctx.data::<PgPool>()?;
+        sqlx::query_as(&format!("{} WHERE ak.account_id = $1", ACCESS_KEYS_QUERY))
+            .bind(&self.account_id)
+            .fetch_all(pool)
+            .await
+            .map_err(query_failed)
+    }
+
+    /// Contracts the account likely holds fungible tokens of, from the actions since `fromBlockTimestamp` (nanoseconds)
+    async fn likely_tokens(
+        &self,
+        ctx: &Context<'_>,
+        from_block_timestamp: String,
+    ) -> async_graphql::Result<Option<LikelyContracts>> {
+        let from_block_timestamp = parse_timestamp(&from_block_timestamp)?;
+        let pool = ctx.data::<PgPool>()?;
+        let json = likely_tokens(pool, &self.account_id, from_block_timestamp)
+            .await
+            .map_err(query_failed)?;
+        Ok(json.and_then(|json| serde_json::from_value(json).ok()))
+    }
+
+    /// Contracts the account likely holds NFTs of, from the actions and events since `fromBlockTimestamp` (nanoseconds)
+    async fn likely_nfts(
+        &self,
+        ctx: &Context<'_>,
+        from_block_timestamp: String,
+    ) -> async_graphql::Result<Option<LikelyContracts>> {
+        let from_block_timestamp = parse_timestamp(&from_block_timestamp)?;
+        let from_block_timestamp = i64::try_from(from_block_timestamp).map_err(|_| {
+            error(
+                ErrorCode::InvalidRequest,
+                "fromBlockTimestamp is out of range",
+            )
+        })?;
+        let pool = ctx.data::<PgPool>()?;
+        let json = likely_nfts(pool, &self.account_id, from_block_timestamp)
+            .await
+            .map_err(query_failed)?;
+        Ok(json.and_then(|json| serde_json::from_value(json).ok()))
+    }
+}
+
+/// Transactions of the account, as in `/account/{account_id}/txns`
+/// The numeric columns are read as text, the yoctoNEAR amounts and the nanosecond timestamps don't fit the GraphQL numbers
+const TRANSACTIONS_QUERY: &str = r#"
+    SELECT
+        r.receipt_id,
+        r.predecessor_account_id,
+        r.receiver_account_id,
+        t.transaction_hash,
+        b.block_hash AS included_in_block_hash,
+        r.included_in_block_timestamp::text AS block_timestamp,
+        b.block_height::bigint AS block_height,
+        COALESCE((SELECT json_agg(json_build_object(
+                      'kind', a.action_kind::text,
+                      'method', a.args->>'method_name',
+                      'deposit', a.args->>'deposit'))
+                  FROM transaction_actions a
+                  WHERE a.transaction_hash = t.transaction_hash), '[]'::json) AS actions,
+        (SELECT o.status::text FROM execution_outcomes o
+         WHERE o.receipt_id = t.converted_into_receipt_id) AS status,
+        (SELECT SUM(o.tokens_burnt)::text FROM execution_outcomes o
+         WHERE o.receipt_id = t.converted_into_receipt_id) AS transaction_fee
+    FROM transactions t
+    JOIN receipts r ON t.converted_into_receipt_id = r.receipt_id
+    JOIN blocks b ON t.included_in_block_hash = b.block_hash
+    WHERE r.predecessor_account_id = $1 OR r.receiver_account_id = $1
+"#;
+
+#[derive(Debug, SimpleObject, sqlx::FromRow)]
+#[graphql(complex)]
+pub(crate) struct Transaction {
+    receipt_id: String,
+    predecessor_account_id: String,
+    receiver_account_id: String,
+    transaction_hash: String,
+    included_in_block_hash: String,
+    /// Nanoseconds
+    block_timestamp: String,
+    block_height: i64,
+    #[graphql(skip)]
+    actions: Json<Vec<Action>>,
+    status: Option<String>,
+    /// yoctoNEAR burnt by the transaction
+    transaction_fee: Option<String>,
+}
+
+#[ComplexObject]
+impl Transaction {
+    async fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, SimpleObject)]
+pub(crate) struct Action {
+    kind: String,
+    /// Called method of the function calls
+    method: Option<String>,
+    /// Attached yoctoNEAR
+    deposit: Option<String>,
+}
+
+/// Access keys, as in `/account/keys/{public_key}`
+const ACCESS_KEYS_QUERY: &str = r#"
+    SELECT
+        ak.public_key,
+        ak.account_id,
+        ak.permission_kind::text AS permission_kind,
+        cr.transaction_hash AS created_transaction_hash,
+        cr.block_timestamp::text AS created_block_timestamp,
+        dl.transaction_hash AS deleted_transaction_hash,
+        dl.block_timestamp::text AS deleted_block_timestamp
+    FROM access_keys ak
+    LEFT JOIN transactions cr ON ak.created_by_receipt_id = cr.converted_into_receipt_id
+    LEFT JOIN transactions dl ON ak.deleted_by_receipt_id = dl.converted_into_receipt_id
+"#;
+
+#[derive(Debug, SimpleObject, sqlx::FromRow)]
+#[graphql(complex)]
+pub(crate) struct AccessKey {
+    public_key: String,
+    account_id: String,
+    permission_kind: String,
+    #[graphql(skip)]
+    created_transaction_hash: Option<String>,
+    #[graphql(skip)]
+    created_block_timestamp: Option<String>,
+    #[graphql(skip)]
+    deleted_transaction_hash: Option<String>,
+    #[graphql(skip)]
+    deleted_block_timestamp: Option<String>,
+}
+
+#[ComplexObject]
+impl AccessKey {
+    /// Transaction adding the key, `null` for the keys of the genesis
+    async fn created(&self) -> Option<KeyChange> {
+        KeyChange::of(
+            &self.created_transaction_hash,
+            &self.created_block_timestamp,
+        )
+    }
+
+    /// Transaction deleting the key, `null` while the key exists
+    async fn deleted(&self) -> Option<KeyChange> {
+        KeyChange::of(
+            &self.deleted_transaction_hash,
+            &self.deleted_block_timestamp,
+        )
+    }
+}
+
+#[derive(Debug, SimpleObject)]
+pub(crate) struct KeyChange {
+    transaction_hash: String,
+    /// Nanoseconds
+    block_timestamp: String,
+}
+
+impl KeyChange {
+    fn of(transaction_hash: &Option<String>, block_timestamp: &Option<String>) -> Option<Self> {
+        Some(Self {
+            transaction_hash: transaction_hash.clone()?,
+            block_timestamp: block_timestamp.clone()?,
+        })
+    }
+}
+
+/// Likely tokens or NFTs, as in `/account/{account_id}/likelyTokensFromBlock` and `likelyNFTsFromBlock`
+#[derive(Debug, Deserialize, SimpleObject)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct LikelyContracts {
+    /// Nanoseconds, the `fromBlockTimestamp` of the next query
+    last_block_timestamp: String,
+    /// Contract account IDs
+    #[serde(default, deserialize_with = "null_as_empty")]
+    list: Vec<String>,
+}
+
+fn null_as_empty<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    Ok(Option::<Vec<String>>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn checks_the_page_size_before_querying() {
+        // No pool: the arguments must be refused before the resolver needs it
+        let schema = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .limit_depth(MAX_DEPTH)
+            .finish();
+        let response = schema
+            .execute(r#"{ account(accountId: "alice.near") { transactions(first: 1000) { edges { cursor } } } }"#)
+            .await;
+        assert_eq!(response.errors.len(), 1);
+        assert!(response.errors[0].message.contains("first must be between"));
+
+        let sdl = schema.sdl();
+        assert!(sdl.contains("likelyTokens(fromBlockTimestamp: String!): LikelyContracts"));
+    }
+}
@@ -3,14 +3,17 @@ use actix_web::web;
 use account_activity::account_activity_handler;
 use account_by_public_key::account_by_public_key_handler;
 use account_create::account_create_handler;
+use account_info::account_info_handler;
 use account_likely_nfts::account_likely_nfts_handler;
 use account_likely_tokens::account_likely_tokens_handler;
 
 mod account_activity;
 mod account_by_public_key;
 mod account_create;
+mod account_info;
 mod account_likely_nfts;
 mod account_likely_tokens;
+pub(crate) mod graphql;
 
 // Function to create and return the accounts scope
 pub fn account_scope() -> actix_web::Scope {
@@ -24,6 +27,7 @@ pub fn account_scope() -> actix_web::Scope {
             "/{account_id}/likelyTokensFromBlock",
             web::get().to(account_likely_tokens_handler),
         )
+        .route("/{account_id}/info", web::get().to(account_info_handler))
         .route(
             "/{account_id}/txns",
             web::get().to(account_activity_handler),
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+#[cfg(feature = "shared-limits")]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::errors::RetryLater;
+#[cfg(feature = "shared-limits")]
+use crate::shared_limits::{Counter, SharedLimits};
+
+/// Time between two creations with the same public key or from the same client address, no cooldown if not set
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct CooldownConfig {
+    pub(crate) public_key: Option<Duration>,
+    pub(crate) ip: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Subject {
+    PublicKey(String),
+    Ip(IpAddr),
+}
+
+impl Subject {
+    #[cfg(feature = "shared-limits")]
+    fn key(&self) -> String {
+        match self {
+            Subject::PublicKey(public_key) => format!("cooldown:public_key:{}", public_key),
+            Subject::Ip(ip) => format!("cooldown:ip:{}", ip),
+        }
+    }
+
+    fn cooled_down(&self, remaining: Duration) -> anyhow::Error {
+        let seconds = remaining.as_secs().max(1);
+        let message = match self {
+            Subject::PublicKey(public_key) => format!(
+                "an account was just created with the public key {}, try again in {} seconds",
+                public_key, seconds
+            ),
+            Subject::Ip(_) => format!(
+                "an account was just created from your address, try again in {} seconds",
+                seconds
+            ),
+        };
+        RetryLater {
+            message,
+            retry_after: Duration::from_secs(seconds),
+        }
+        .into()
+    }
+}
+
+/// Short cooldowns after each creation, so the scripted loops can't drain the faucet in bursts within their quotas
+/// Kept in memory of the process serving the requests unless they are shared in Redis
+pub(crate) struct Cooldowns {
+    config: CooldownConfig,
+    /// When the cooldown of every subject ends
+    until: Mutex<HashMap<Subject, Instant>>,
+    #[cfg(feature = "shared-limits")]
+    shared: Option<Arc<SharedLimits>>,
+}
+
+impl Cooldowns {
+    pub(crate) fn new(config: CooldownConfig) -> Self {
+        Self {
+            config,
+            until: Mutex::new(HashMap::new()),
+            #[cfg(feature = "shared-limits")]
+            shared: None,
+        }
+    }
+
+    #[cfg(feature = "shared-limits")]
+    /// Keeps the cooldowns in Redis, shared by all the replicas
+    pub(crate) fn with_shared(mut self, shared: Arc<SharedLimits>) -> Self {
+        self.shared = Some(shared);
+        self
+    }
+
+    fn subjects(&self, public_key: &str, ip: Option<IpAddr>) -> Vec<(Subject, Duration)> {
+        let public_key = self
+            .config
+            .public_key
+            .map(|cooldown| (Subject::PublicKey(public_key.to_string()), cooldown));
+        let ip = self
+            .config
+            .ip
+            .zip(ip)
+            .map(|(cooldown, ip)| (Subject::Ip(ip), cooldown));
+        public_key.into_iter().chain(ip).collect()
+    }
+
+    /// Starts the cooldowns of the key and the address, fails with `RATE_LIMITED` and the remaining time
+    /// if any of them is still cooling down
+    /// The address is `None` for the authenticated clients, which share addresses and have their own quotas
+    pub(crate) async fn acquire(&self, public_key: &str, ip: Option<IpAddr>) -> anyhow::Result<()> {
+        let subjects = self.subjects(public_key, ip);
+        #[cfg(feature = "shared-limits")]
+        if let Some(shared) = &self.shared {
+            let counters = shared_counters(&subjects);
+            match shared.acquire(&counters).await {
+                Some(Ok(())) => return Ok(()),
+                Some(Err(exhausted)) => {
+                    let (subject, cooldown) = &subjects[exhausted];
+                    let remaining = shared.remaining(&subject.key()).await;
+                    return Err(subject.cooled_down(remaining.unwrap_or(*cooldown)));
+                }
+                None => {}
+            }
+        }
+        let now = Instant::now();
+        let mut until = self.until.lock().unwrap();
+        for (subject, _) in &subjects {
+            if let Some(end) = until.get(subject).filter(|end| **end > now) {
+                return Err(subject.cooled_down(end.duration_since(now)));
+            }
+        }
+        for (subject, cooldown) in subjects {
+            until.insert(subject, now + cooldown);
+        }
+        Ok(())
+    }
+
+    /// Ends the cooldowns started by `acquire`, used when the creation failed
+    pub(crate) async fn release(&self, public_key: &str, ip: Option<IpAddr>) {
+        let subjects = self.subjects(public_key, ip);
+        #[cfg(feature = "shared-limits")]
+        if let Some(shared) = &self.shared {
+            let counters = shared_counters(&subjects);
+            if shared.release(&counters).await.is_some() {
+                return;
+            }
+        }
+        let mut until = self.until.lock().unwrap();
+        for (subject, _) in subjects {
+            until.remove(&subject);
+        }
+    }
+
+    /// Forgets the cooldowns that have ended, returns how many were forgotten
+    pub(crate) fn purge_expired(&self) -> usize {
+        let now = Instant::now();
+        let mut until = self.until.lock().unwrap();
+        let before = until.len();
+        until.retain(|_, end| *end > now);
+        before - until.len()
+    }
+}
+
+#[cfg(feature = "shared-limits")]
+/// Redis counters allowing one creation per subject until they expire
+fn shared_counters(subjects: &[(Subject, Duration)]) -> Vec<Counter> {
+    subjects
+        .iter()
+        .map(|(subject, cooldown)| Counter {
+            key: subject.key(),
+            limit: Some(1),
+            ttl: *cooldown,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{retry_after, ErrorCode};
+
+    #[tokio::test]
+    async fn reports_the_remaining_cooldown() {
+        let cooldowns = Cooldowns::new(CooldownConfig {
+            public_key: Some(Duration::from_secs(600)),
+            ip: Some(Duration::from_secs(60)),
+        });
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        cooldowns.acquire("ed25519:a", Some(ip)).await.unwrap();
+
+        let err = cooldowns.acquire("ed25519:a", None).await.unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::RateLimited);
+        let remaining = retry_after(&err).unwrap();
+        assert!(remaining > Duration::from_secs(590) && remaining <= Duration::from_secs(600));
+
+        let err = cooldowns.acquire("ed25519:b", Some(ip)).await.unwrap_err();
+        assert!(retry_after(&err).unwrap() <= Duration::from_secs(60));
+        // Clients authenticated on their own don't share the cooldown of the address
+        cooldowns.acquire("ed25519:b", None).await.unwrap();
+
+        cooldowns.release("ed25519:a", Some(ip)).await;
+        cooldowns.acquire("ed25519:a", Some(ip)).await.unwrap();
+    }
+}
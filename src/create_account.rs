@@ -1,134 +1,440 @@
-use std::{
-    str::FromStr,
-    sync::atomic::{AtomicU64, Ordering},
-};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
 
+use actix_web::{HttpMessage, HttpRequest};
 use anyhow::Context;
-use near_account_id::AccountId;
-use near_crypto::{InMemorySigner, PublicKey, Signer};
-use near_jsonrpc_client::{
-    errors::{JsonRpcError, JsonRpcServerError},
-    methods::{self, tx::RpcTransactionError},
-    JsonRpcClient,
-};
-use near_primitives::{
-    account::AccessKey,
-    action::{Action, AddKeyAction, CreateAccountAction, TransferAction},
-    errors::{InvalidTxError, TxExecutionError},
-    hash::CryptoHash,
-    transaction::{SignedTransaction, Transaction},
-    types::Balance,
-    views::FinalExecutionStatus,
-};
-
-use crate::utils::nonce::retry_nonce;
-
-// TODO: rate limit or somehow gate this faucet
-
-/// Creates a Transaction with actions:
-/// - CreateAccount
-/// - AddKey
-/// - Transfer (funding the account)
-/// Signs the transaction by the base signer and sends it to the NEAR RPC node
-pub(crate) async fn send_create_account(
-    near_rpc: &JsonRpcClient,
-    base_signer: &InMemorySigner,
+use near_primitives::types::Balance;
+use tracing::Instrument;
+
+use crate::errors::{CodedError, ErrorCode, StillPending};
+use crate::escalation::PROOF_OF_WORK_HEADER;
+use crate::metrics;
+use crate::middleware::api_tokens::{AuthenticatedToken, TokenFundingAmount};
+use crate::middleware::replay_guard::AuthenticatedClient;
+use crate::quota::Identity;
+use crate::utils::nonce::NonceBlock;
+use crate::utils::send_tx::{Submitted, WaitLevel};
+
+/// Channel a creation request came through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EntryPoint {
+    /// The HTML form on the index page
+    Form,
+    /// The embeddable widget
+    Widget,
+    /// The JSON API of the contract-helper feature
+    Api,
+    /// The `/create` command of the Telegram bot
+    Telegram,
+}
+
+impl EntryPoint {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            EntryPoint::Form => "form",
+            EntryPoint::Widget => "widget",
+            EntryPoint::Api => "api",
+            EntryPoint::Telegram => "telegram",
+        }
+    }
+}
+
+impl FromStr for EntryPoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "form" => Ok(EntryPoint::Form),
+            "widget" => Ok(EntryPoint::Widget),
+            "api" => Ok(EntryPoint::Api),
+            "telegram" => Ok(EntryPoint::Telegram),
+            _ => anyhow::bail!("unknown entry point: {}", s),
+        }
+    }
+}
+
+/// Where a creation request came from, used to attribute the load and the abuse in the metrics
+#[derive(Debug, Clone)]
+pub(crate) struct RequestOrigin {
+    pub(crate) entry_point: EntryPoint,
+    /// API key or token name of the authenticated client, `public` for anonymous requests
+    pub(crate) tenant: String,
+    /// Identity the creation is charged to, only set for requests authenticated by this process
+    pub(crate) identity: Option<Identity>,
+    /// Nonce solving the proof of work challenge, required when the service is under attack
+    pub(crate) proof_of_work: Option<String>,
+    /// Amount to fund the account with instead of the configured one, set by the API token or the reCAPTCHA score tiers
+    pub(crate) funding_amount: Option<Balance>,
+    /// One-time code required by the gated faucet, taken from the body by the handlers
+    pub(crate) invite_code: Option<String>,
+    /// Address of the client past the trusted proxies, only set for requests received by this process
+    pub(crate) client_ip: Option<IpAddr>,
+    /// Whether the handler verified a Turnstile, reCAPTCHA or hCaptcha token, which passes the captcha level of the escalation
+    pub(crate) captcha_verified: bool,
+    /// Nonces reserved for the batch the request belongs to, only set by the bulk uploads
+    pub(crate) nonces: Option<Arc<NonceBlock>>,
+}
+
+impl RequestOrigin {
+    pub(crate) const PUBLIC_TENANT: &'static str = "public";
+
+    pub(crate) fn new(entry_point: EntryPoint, req: &HttpRequest) -> Self {
+        let extensions = req.extensions();
+        let tenant = extensions
+            .get::<AuthenticatedClient>()
+            .map(|client| client.0.clone())
+            .or_else(|| {
+                extensions
+                    .get::<AuthenticatedToken>()
+                    .map(|token| token.0.clone())
+            })
+            .unwrap_or_else(|| Self::PUBLIC_TENANT.to_string());
+        Self {
+            entry_point,
+            tenant,
+            identity: Identity::of(req),
+            proof_of_work: req
+                .headers()
+                .get(PROOF_OF_WORK_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            funding_amount: extensions
+                .get::<TokenFundingAmount>()
+                .map(|amount| amount.0),
+            invite_code: None,
+            client_ip: crate::middleware::client_ip::client_ip(req),
+            captcha_verified: false,
+            nonces: None,
+        }
+    }
+
+    /// Funds the account with the reduced amount of the reCAPTCHA score tier, unless the token's own amount is lower
+    pub(crate) fn reduce_funding(&mut self, reduced: Option<Balance>) {
+        if let Some(reduced) = reduced {
+            self.funding_amount = Some(
+                self.funding_amount
+                    .map_or(reduced, |amount| amount.min(reduced)),
+            );
+        }
+    }
+
+    /// Address the cooldown and the bans of the client apply to
+    fn cooldown_ip(&self) -> Option<IpAddr> {
+        anonymous_ip(self.identity.as_ref(), self.client_ip)
+    }
+}
+
+/// The address of the anonymous requests, the authenticated clients share addresses and have their own quotas
+fn anonymous_ip(identity: Option<&Identity>, client_ip: Option<IpAddr>) -> Option<IpAddr> {
+    match identity {
+        Some(identity) if identity.is_client() => None,
+        _ => client_ip,
+    }
+}
+
+/// Counts a request refused by the validation of the handlers towards the automatic bans of its key and address
+pub(crate) fn record_rejection(
+    near: &crate::NearData,
+    req: &HttpRequest,
+    public_key: &str,
+    code: ErrorCode,
+) {
+    if let Some(bans) = &near.bans {
+        // A malformed key is only held against the address
+        let public_key = near_crypto::PublicKey::from_str(public_key.trim())
+            .ok()
+            .map(|key| key.to_string());
+        let ip = anonymous_ip(
+            Identity::of(req).as_ref(),
+            crate::middleware::client_ip::client_ip(req),
+        );
+        bans.strike(public_key.as_deref(), ip, code);
+    }
+}
+
+/// Creates the account requested by any of the entry points
+/// Neither the public key nor the address may be banned, and the request has to pass the denylists, the challenge of the current escalation level, the limit of its name prefix,
+/// the cooldowns of its public key and address, the quotas of its identity and public key, the Discord and email gates, the invite code of the gated faucet and the daily cap first
+/// In the frontend mode the request is handed over to the workers through the job queue,
+/// otherwise the transaction is signed and sent right away by the `TxSubmitter` of this process
+/// Every attempt is recorded in the creation history, whatever its outcome
+pub(crate) async fn create_account(
+    near: &crate::NearData,
     account_id: &str,
     public_key: &str,
-    nonce: &AtomicU64,
-    block_hash: CryptoHash,
-    funding_amount: Balance,
-) -> anyhow::Result<()> {
-    tracing::debug!(
-        "Creating account {} with public key {}",
+    wait: WaitLevel,
+    origin: &RequestOrigin,
+) -> anyhow::Result<Submitted> {
+    let started = std::time::Instant::now();
+    let result = attempt(near, account_id, public_key, wait, origin).await;
+    let funding_amount = origin.funding_amount.unwrap_or(near.funding_amount);
+    near.history.record(crate::history::Attempt::of(
         account_id,
-        public_key
-    );
-    let new_account = AccountId::from_str(account_id)
-        .with_context(|| format!("failed parsing account ID: {}", account_id))?;
-    let pkey = PublicKey::from_str(public_key)
-        .with_context(|| format!("failed parsing public key: {}", public_key))?;
-
-    let actions = vec![
-        Action::CreateAccount(CreateAccountAction {}),
-        Action::AddKey(Box::new(AddKeyAction {
-            public_key: pkey,
-            access_key: AccessKey::full_access(),
-        })),
-        Action::Transfer(TransferAction {
-            deposit: funding_amount,
-        }),
-    ];
-    let mut next_nonce = nonce.fetch_add(1, Ordering::SeqCst) + 1;
-
-    loop {
-        let tx = Transaction {
-            signer_id: base_signer.account_id.clone(),
-            public_key: base_signer.public_key.clone(),
-            nonce: next_nonce,
-            receiver_id: new_account.clone(),
-            block_hash,
-            actions: actions.clone(),
-        };
-        let (hash, _size) = tx.get_hash_and_size();
-        let sig = base_signer.sign(hash.as_ref());
-        let signed_transaction = SignedTransaction::new(sig, tx.clone());
-
-        tracing::debug!(
-            "Sending transaction creating {} with nonce {} to NEAR RPC node...",
+        public_key,
+        origin,
+        funding_amount,
+        &result,
+        started.elapsed(),
+    ));
+    result
+}
+
+/// The checks and the submission of `create_account`
+async fn attempt(
+    near: &crate::NearData,
+    account_id: &str,
+    public_key: &str,
+    wait: WaitLevel,
+    origin: &RequestOrigin,
+) -> anyhow::Result<Submitted> {
+    if let Some(recorder) = &near.recorder {
+        recorder.record(origin, wait);
+    }
+    near.maintenance.check()?;
+    if let Some(bans) = &near.bans {
+        bans.check(public_key, origin.cooldown_ip())?;
+    }
+    // A double submission waits for the first request, it isn't charged nor admitted again
+    let result = near
+        .inflight
+        .run(
             account_id,
-            next_nonce
+            public_key,
+            admit_and_submit(near, account_id, public_key, wait, origin),
+        )
+        .await;
+    if let (Some(bans), Err(err)) = (&near.bans, &result) {
+        bans.strike(
+            Some(public_key),
+            origin.cooldown_ip(),
+            ErrorCode::classify(err),
         );
-        match near_rpc
-            .call(methods::broadcast_tx_commit::RpcBroadcastTxCommitRequest { signed_transaction })
-            .await
+    }
+    result
+}
+
+async fn admit_and_submit(
+    near: &crate::NearData,
+    account_id: &str,
+    public_key: &str,
+    wait: WaitLevel,
+    origin: &RequestOrigin,
+) -> anyhow::Result<Submitted> {
+    near.abuse.check(account_id, public_key)?;
+    near.escalation.admit(
+        account_id,
+        public_key,
+        origin.proof_of_work.as_deref(),
+        origin.captcha_verified || origin.identity.as_ref().is_some_and(Identity::is_passkey),
+    )?;
+    if let Some(limiter) = &near.prefix_limit {
+        limiter.acquire(account_id)?;
+    }
+    if let Some(cooldowns) = &near.cooldowns {
+        if let Err(err) = cooldowns.acquire(public_key, origin.cooldown_ip()).await {
+            release_prefix(near, account_id);
+            return Err(err);
+        }
+    }
+    let charged = near
+        .quotas
+        .charged_identities(origin.identity.as_ref(), public_key);
+    if let Err(err) = near.quotas.acquire_all(&charged).await {
+        release_limits(near, account_id, public_key, origin).await;
+        return Err(err);
+    }
+    // Looked up after the local limits, so the throttled requests never reach the RPC node
+    if let Err(err) = crate::utils::preflight::ensure_account_available(&near.rpc, account_id).await
+    {
+        near.quotas.release_all(&charged).await;
+        release_limits(near, account_id, public_key, origin).await;
+        return Err(err);
+    }
+    #[cfg(feature = "discord")]
+    if let Some(discord) = &near.discord {
+        if let Err(err) = discord.admit(origin.identity.as_ref()) {
+            near.quotas.release_all(&charged).await;
+            release_limits(near, account_id, public_key, origin).await;
+            return Err(err);
+        }
+    }
+    #[cfg(feature = "email")]
+    if let Some(email) = &near.email {
+        if let Err(err) = email.admit(origin.identity.as_ref()) {
+            near.quotas.release_all(&charged).await;
+            release_limits(near, account_id, public_key, origin).await;
+            #[cfg(feature = "discord")]
+            if let Some(discord) = &near.discord {
+                discord.release(origin.identity.as_ref());
+            }
+            return Err(err);
+        }
+    }
+    // Redeemed last, the requests rejected by the other limits keep their code
+    if let Some(invites) = &near.invites {
+        if let Err(err) = invites.redeem(origin.invite_code.as_deref()).await {
+            near.quotas.release_all(&charged).await;
+            release_limits(near, account_id, public_key, origin).await;
+            #[cfg(feature = "discord")]
+            if let Some(discord) = &near.discord {
+                discord.release(origin.identity.as_ref());
+            }
+            return Err(err);
+        }
+    }
+    // Deferred requests stay charged until they are processed, the rejected ones aren't a sign of abuse
+    if let Some(cap) = &near.daily_cap {
+        if let Err(err) = cap.admit(account_id, public_key, wait, origin) {
+            if ErrorCode::classify(&err) == ErrorCode::RateLimited {
+                release_admission(near, account_id, public_key, origin).await;
+            }
+            return Err(err);
+        }
+    }
+    let result = submit(near, account_id, public_key, wait, origin).await;
+    if result.is_ok() {
+        near.events.publish_creation(account_id);
+    }
+    // A queued request that is still processing may succeed yet, so it stays charged
+    if let Err(err) = &result {
+        if ErrorCode::classify(err) != ErrorCode::Pending {
+            near.escalation.record_failure();
+            release_admission(near, account_id, public_key, origin).await;
+            if let Some(cap) = &near.daily_cap {
+                cap.release();
+            }
+        }
+    }
+    result
+}
+
+fn release_prefix(near: &crate::NearData, account_id: &str) {
+    if let Some(limiter) = &near.prefix_limit {
+        limiter.release(account_id);
+    }
+}
+
+/// Gives back the name prefix and ends the cooldowns
+async fn release_limits(
+    near: &crate::NearData,
+    account_id: &str,
+    public_key: &str,
+    origin: &RequestOrigin,
+) {
+    release_prefix(near, account_id);
+    if let Some(cooldowns) = &near.cooldowns {
+        cooldowns.release(public_key, origin.cooldown_ip()).await;
+    }
+}
+
+/// Gives back the name prefix, the cooldowns, the quotas, the Discord cooldown and the invite code taken by the admission,
+/// used when the creation failed
+pub(crate) async fn release_admission(
+    near: &crate::NearData,
+    account_id: &str,
+    public_key: &str,
+    origin: &RequestOrigin,
+) {
+    release_limits(near, account_id, public_key, origin).await;
+    near.quotas
+        .release_all(
+            &near
+                .quotas
+                .charged_identities(origin.identity.as_ref(), public_key),
+        )
+        .await;
+    #[cfg(feature = "discord")]
+    if let Some(discord) = &near.discord {
+        discord.release(origin.identity.as_ref());
+    }
+    if let (Some(invites), Some(code)) = (&near.invites, &origin.invite_code) {
+        invites.release(code).await;
+    }
+}
+
+/// Same as `create_account`, but without the admission checks
+/// Used by the workers for the queued requests, the frontend already checked them
+pub(crate) async fn submit(
+    near: &crate::NearData,
+    account_id: &str,
+    public_key: &str,
+    wait: WaitLevel,
+    origin: &RequestOrigin,
+) -> anyhow::Result<Submitted> {
+    // The workers record the metrics of the queued requests, they are the ones broadcasting the transactions
+    #[cfg(feature = "queue")]
+    if let Some(queue) = &near.queue {
+        return queue
+            .enqueue_and_wait(account_id, public_key, wait, origin)
+            .await;
+    }
+
+    let submitter = near
+        .submitter
+        .as_ref()
+        .context("no base signer configured to sign the transaction")?;
+    if let Some(disbursement) = &near.disbursement {
+        disbursement.check()?;
+    }
+    // Held until the transaction reaches the wait level, so the spikes don't pile up requests on the RPC node
+    let permit = match &near.broadcasts {
+        Some(broadcasts) => Some(broadcasts.clone().try_acquire_owned().map_err(|_| {
+            CodedError {
+                code: ErrorCode::Overloaded,
+                message:
+                    "too many accounts are being created right now, try again in a few seconds"
+                        .to_string(),
+            }
+        })?),
+        None => None,
+    };
+    // The creation goes on in the background past the deadline, the transaction may still land
+    let creation = tokio::spawn(
         {
-            Ok(r) => match r.status {
-                FinalExecutionStatus::SuccessValue(_) => {
-                    tracing::info!(
-                        "transaction execution succeeded for {}: {:?}",
-                        account_id,
-                        &r.status
-                    );
-                    return Ok(());
-                }
-                // looks like this one doesn't show up, and instead we get an Err(JsonRpcError) in this case,
-                // but might as well handle this case here too
-                FinalExecutionStatus::Failure(TxExecutionError::InvalidTxError(
-                    InvalidTxError::InvalidNonce { tx_nonce, ak_nonce },
-                )) => {
-                    next_nonce = retry_nonce(nonce, next_nonce, tx_nonce, ak_nonce);
-                    tracing::debug!(
-                        "retrying creating {} with nonce {} after nonce {} was rejected with current access key nonce {}",
-                        account_id,
-                        next_nonce,
-                        tx_nonce,
-                        ak_nonce,
-                    );
+            let submitter = submitter.clone();
+            let disbursement = near.disbursement.clone();
+            let (account_id, public_key, origin) = (
+                account_id.to_string(),
+                public_key.to_string(),
+                origin.clone(),
+            );
+            async move {
+                let _permit = permit;
+                let funding_amount = origin
+                    .funding_amount
+                    .unwrap_or_else(|| submitter.funding_amount());
+                let result = submitter
+                    .create_account(
+                        &account_id,
+                        &public_key,
+                        wait,
+                        funding_amount,
+                        origin.nonces.clone(),
+                    )
+                    .await;
+                metrics::record_creation(&origin, &account_id, funding_amount, result.is_ok());
+                if let (Some(disbursement), Ok(_)) = (&disbursement, &result) {
+                    disbursement.record(funding_amount);
                 }
-                _ => {
-                    tracing::warn!("transaction execution failed: {:?}", &r.status);
-                    return Err(anyhow::anyhow!(
-                        "transaction execution failed: {:?}",
-                        &r.status
-                    ));
-                }
-            },
-            Err(JsonRpcError::ServerError(JsonRpcServerError::HandlerError(
-                RpcTransactionError::InvalidTransaction {
-                    context: InvalidTxError::InvalidNonce { tx_nonce, ak_nonce },
-                },
-            ))) => {
-                next_nonce = retry_nonce(nonce, next_nonce, tx_nonce, ak_nonce);
-                tracing::debug!(
-                    "retrying creating {} with nonce {} after nonce {} was rejected with current access key nonce {}",
-                    account_id,
-                    next_nonce,
-                    tx_nonce,
-                    ak_nonce,
-                );
+                result
             }
-            Err(e) => return Err(e.into()),
-        };
+        }
+        .instrument(tracing::Span::current()),
+    );
+    match tokio::time::timeout(near.creation_deadline, creation).await {
+        Ok(result) => result.context("account creation task failed")?,
+        Err(_) => {
+            let tx_hash = submitter.sent_hash(account_id);
+            let message = match tx_hash {
+                Some(hash) => format!(
+                    "account creation is still processing (transaction {}), check back later",
+                    hash
+                ),
+                None => "account creation is still processing, check back later".to_string(),
+            };
+            tracing::warn!("Creation of {} exceeded the deadline", account_id);
+            Err(StillPending { message, tx_hash }.into())
+        }
     }
 }
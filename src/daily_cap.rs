@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::create_account::RequestOrigin;
+use crate::errors::{CodedError, ErrorCode, RetryLater};
+use crate::utils::send_tx::WaitLevel;
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+/// Deferred requests are accepted for at most this many days ahead
+const MAX_DEFERRED_DAYS: usize = 7;
+
+/// Request over the cap, created once the day resets
+#[derive(Debug)]
+pub(crate) struct DeferredRequest {
+    pub(crate) account_id: String,
+    pub(crate) public_key: String,
+    pub(crate) wait: WaitLevel,
+    pub(crate) origin: RequestOrigin,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    day: u64,
+    created: u32,
+    deferred: VecDeque<DeferredRequest>,
+}
+
+impl State {
+    fn roll(&mut self, now: u64) {
+        let day = now / DAY_SECS;
+        if self.day != day {
+            self.day = day;
+            self.created = 0;
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct DailyCapStatus {
+    limit: u32,
+    created: u32,
+    deferred: usize,
+    /// Unix timestamp the count starts over at
+    resets_at: u64,
+}
+
+/// Caps the accounts created per UTC day by this process, regardless of who requests them
+/// Requests over the cap are either rejected, or deferred in memory until the next day
+#[derive(Debug)]
+pub(crate) struct DailyCap {
+    limit: u32,
+    defer: bool,
+    state: Mutex<State>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl DailyCap {
+    pub(crate) fn new(limit: u32, defer: bool) -> Self {
+        Self {
+            limit: limit.max(1),
+            defer,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Counts the request against today's cap
+    /// Over the cap it fails with `PENDING` and the position in the line if the request was deferred,
+    /// with `RATE_LIMITED` otherwise
+    pub(crate) fn admit(
+        &self,
+        account_id: &str,
+        public_key: &str,
+        wait: WaitLevel,
+        origin: &RequestOrigin,
+    ) -> anyhow::Result<()> {
+        let now = now();
+        let mut state = self.state.lock().unwrap();
+        state.roll(now);
+        if state.created < self.limit {
+            state.created += 1;
+            return Ok(());
+        }
+        let until_reset = (state.day + 1) * DAY_SECS - now;
+        if !self.defer || state.deferred.len() >= self.limit as usize * MAX_DEFERRED_DAYS {
+            return Err(RetryLater {
+                message: format!(
+                    "the daily cap of {} accounts is reached, try again in {}",
+                    self.limit,
+                    format_duration(until_reset)
+                ),
+                retry_after: Duration::from_secs(until_reset),
+            }
+            .into());
+        }
+        state.deferred.push_back(DeferredRequest {
+            account_id: account_id.to_string(),
+            public_key: public_key.to_string(),
+            wait,
+            origin: origin.clone(),
+        });
+        let position = state.deferred.len();
+        let expected_in = until_reset + (position as u64 - 1) / self.limit as u64 * DAY_SECS;
+        Err(CodedError {
+            code: ErrorCode::Pending,
+            message: format!(
+                "the daily cap of {} accounts is reached, the request is queued at position {} and will be processed in about {}",
+                self.limit,
+                position,
+                format_duration(expected_in)
+            ),
+        }
+        .into())
+    }
+
+    /// Gives back the slot taken by `admit`, used when the creation failed
+    pub(crate) fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.roll(now());
+        state.created = state.created.saturating_sub(1);
+    }
+
+    /// Takes the deferred requests fitting into today's cap, counting them against it
+    fn take_due(&self) -> Vec<DeferredRequest> {
+        let mut state = self.state.lock().unwrap();
+        state.roll(now());
+        let count = (self.limit - state.created.min(self.limit)) as usize;
+        let due: Vec<_> = (0..count)
+            .map_while(|_| state.deferred.pop_front())
+            .collect();
+        state.created += due.len() as u32;
+        due
+    }
+
+    /// Whether today's requests over the cap are rejected right away, deferring ones still get in line
+    pub(crate) fn is_exhausted(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.roll(now());
+        !self.defer && state.created >= self.limit
+    }
+
+    pub(crate) fn status(&self) -> DailyCapStatus {
+        let now = now();
+        let mut state = self.state.lock().unwrap();
+        state.roll(now);
+        DailyCapStatus {
+            limit: self.limit,
+            created: state.created,
+            deferred: state.deferred.len(),
+            resets_at: (state.day + 1) * DAY_SECS,
+        }
+    }
+}
+
+fn format_duration(secs: u64) -> String {
+    format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+}
+
+/// Creates the deferred requests each time the day resets
+/// Nobody waits for the results anymore, they are logged and counted in the metrics
+pub(crate) async fn run_deferred(near: crate::NearData, shutdown: crate::shutdown::Shutdown) {
+    let Some(cap) = near.daily_cap.clone() else {
+        return;
+    };
+    loop {
+        let until_reset = DAY_SECS - now() % DAY_SECS;
+        tokio::time::sleep(Duration::from_secs(until_reset + 1)).await;
+        let due = cap.take_due();
+        if !due.is_empty() {
+            tracing::info!("Processing {} deferred creation requests", due.len());
+        }
+        for (i, request) in due.iter().enumerate() {
+            // The deferred requests are kept in memory only, the rest are lost with the process
+            if shutdown.is_requested() {
+                tracing::warn!(
+                    "Dropping {} deferred creation requests on shutdown",
+                    due.len() - i
+                );
+                break;
+            }
+            let result = crate::create_account::submit(
+                &near,
+                &request.account_id,
+                &request.public_key,
+                request.wait,
+                &request.origin,
+            )
+            .await;
+            match result {
+                Ok(_) => {
+                    tracing::info!("Created deferred account {}", request.account_id);
+                    near.events.publish_creation(&request.account_id);
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        "Failed to create deferred account {}: {:?}",
+                        request.account_id,
+                        err
+                    );
+                    cap.release();
+                    crate::create_account::release_admission(
+                        &near,
+                        &request.account_id,
+                        &request.public_key,
+                        &request.origin,
+                    )
+                    .await;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use near_primitives::types::Balance;
+
+use crate::maintenance::Maintenance;
+use crate::metrics::{NEAR_DISBURSED_LAST_DAY, YOCTO_PER_NEAR};
+use crate::templates::format_near;
+
+const WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+const ALERT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Shown as the admin who paused the faucet
+pub(crate) const PAUSED_BY: &str = "disbursement cap";
+
+/// Caps the NEAR transferred out by this process in any 24 hours, pausing the creations once it's exceeded
+/// A last resort against the abuse the other limits miss, the admins resume the faucet once they looked into it
+pub(crate) struct DisbursementCap {
+    cap: Balance,
+    maintenance: Maintenance,
+    /// Webhook the operators are alerted through when the cap pauses the faucet
+    alert_url: Option<String>,
+    http: reqwest::Client,
+    /// Time and amount of the transfers within the window
+    transfers: Mutex<VecDeque<(Instant, Balance)>>,
+}
+
+impl DisbursementCap {
+    pub(crate) fn new(
+        cap: Balance,
+        maintenance: Maintenance,
+        alert_url: Option<String>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            cap,
+            maintenance,
+            alert_url,
+            http: reqwest::Client::builder().timeout(ALERT_TIMEOUT).build()?,
+            transfers: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    /// Total of the transfers within the window, forgetting the older ones
+    fn disbursed(transfers: &mut VecDeque<(Instant, Balance)>, now: Instant) -> Balance {
+        while let Some((at, _)) = transfers.front() {
+            if now.duration_since(*at) < WINDOW {
+                break;
+            }
+            transfers.pop_front();
+        }
+        transfers.iter().map(|(_, amount)| amount).sum()
+    }
+
+    /// Counts a successful transfer and pauses the creations if the window total goes over the cap
+    /// The creations in progress still finish, so the total may end up a little past the cap
+    pub(crate) fn record(&self, amount: Balance) {
+        let now = Instant::now();
+        let disbursed = {
+            let mut transfers = self.transfers.lock().unwrap();
+            transfers.push_back((now, amount));
+            Self::disbursed(&mut transfers, now)
+        };
+        NEAR_DISBURSED_LAST_DAY.set(disbursed as f64 / YOCTO_PER_NEAR);
+        if disbursed <= self.cap || self.maintenance.current().is_some() {
+            return;
+        }
+        let message = format!(
+            "{} were disbursed in the last 24 hours, over the cap of {}; the creations are paused until an admin resumes them",
+            format_near(disbursed),
+            format_near(self.cap)
+        );
+        self.maintenance.pause(
+            PAUSED_BY.to_string(),
+            Some("the daily disbursement cap is reached".to_string()),
+        );
+        tracing::error!("{}", message);
+        if let Some(url) = self.alert_url.clone() {
+            let request = self.http.post(url).json(&serde_json::json!({
+                "text": message,
+                "disbursed": disbursed.to_string(),
+                "cap": self.cap.to_string(),
+            }));
+            tokio::spawn(async move {
+                let sent = request
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status);
+                if let Err(err) = sent {
+                    tracing::error!("Failed to alert the operators: {:?}", err);
+                }
+            });
+        }
+    }
+
+    /// Fails with `FAUCET_PAUSED` while the cap pauses the creations, whereas the admins' pauses are left to the admission
+    /// Checked by the submission, as the workers of the job queue take neither the admin requests nor the admission
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        match self.maintenance.current() {
+            Some(pause) if pause.paused_by == PAUSED_BY => self.maintenance.check(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Total transferred out in the last 24 hours
+    pub(crate) fn current(&self) -> Balance {
+        Self::disbursed(&mut self.transfers.lock().unwrap(), Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pauses_the_creations_over_the_cap() {
+        let maintenance = Maintenance::default();
+        let cap = DisbursementCap::new(250, maintenance.clone(), None).unwrap();
+        cap.record(100);
+        cap.record(150);
+        assert!(maintenance.check().is_ok());
+        assert_eq!(cap.current(), 250);
+
+        cap.record(100);
+        assert_eq!(maintenance.current().unwrap().paused_by, PAUSED_BY);
+        assert!(cap.check().is_err());
+        maintenance.resume();
+        assert!(cap.check().is_ok());
+    }
+}
@@ -0,0 +1,384 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use anyhow::Context as _;
+use serde::Deserialize;
+
+use crate::errors::{CodedError, ErrorCode};
+use crate::quota::Identity;
+
+const AUTHORIZE_URL: &str = "https://discord.com/oauth2/authorize";
+const TOKEN_URL: &str = "https://discord.com/api/oauth2/token";
+const API_URL: &str = "https://discord.com/api/v10";
+/// Cookie carrying the session token issued after the OAuth flow
+pub(crate) const SESSION_COOKIE: &str = "discord_session";
+/// How long the user has to authorize the faucet on Discord
+const STATE_TTL: Duration = Duration::from_secs(600);
+const API_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Discord application and the guild whose members may create accounts
+#[derive(Debug, Clone)]
+pub(crate) struct DiscordConfig {
+    pub(crate) client_id: String,
+    pub(crate) client_secret: String,
+    /// URL of `/auth/discord/callback` as registered with the application
+    pub(crate) redirect_url: String,
+    pub(crate) guild_id: String,
+    /// Time between the creations of the same Discord user
+    pub(crate) cooldown: Duration,
+    pub(crate) session_ttl: Duration,
+}
+
+#[derive(Debug)]
+struct Session {
+    user_id: String,
+    expires_at: Instant,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    id: String,
+    username: String,
+}
+
+/// Discord sign-in restricting the creations to the members of a guild, kept in memory of the process
+/// The membership is checked once at sign-in, the session outlives the user leaving the guild
+#[derive(Debug)]
+pub(crate) struct DiscordGate {
+    config: DiscordConfig,
+    http: reqwest::Client,
+    /// OAuth `state` values of the flows in progress
+    states: Mutex<HashMap<String, Instant>>,
+    sessions: Mutex<HashMap<String, Session>>,
+    /// Last creation of each Discord user
+    creations: Mutex<HashMap<String, Instant>>,
+}
+
+fn unauthorized(message: impl Into<String>) -> anyhow::Error {
+    CodedError {
+        code: ErrorCode::Unauthorized,
+        message: message.into(),
+    }
+    .into()
+}
+
+impl DiscordGate {
+    pub(crate) fn new(config: DiscordConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            config,
+            http: reqwest::Client::builder()
+                .timeout(API_TIMEOUT)
+                .build()
+                .context("failed building the Discord HTTP client")?,
+            states: Mutex::new(HashMap::new()),
+            sessions: Mutex::new(HashMap::new()),
+            creations: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// URL of the Discord consent screen the user is sent to
+    fn authorize_url(&self) -> anyhow::Result<reqwest::Url> {
+        let state = hex::encode(rand::random::<[u8; 16]>());
+        self.states
+            .lock()
+            .unwrap()
+            .insert(state.clone(), Instant::now() + STATE_TTL);
+        reqwest::Url::parse_with_params(
+            AUTHORIZE_URL,
+            [
+                ("response_type", "code"),
+                ("client_id", &self.config.client_id),
+                ("redirect_uri", &self.config.redirect_url),
+                ("scope", "identify guilds.members.read"),
+                ("state", &state),
+            ],
+        )
+        .context("failed building the Discord authorize URL")
+    }
+
+    /// Exchanges the code of the callback, checks the guild membership and starts a session
+    async fn sign_in(&self, code: &str, state: &str) -> anyhow::Result<(String, User)> {
+        let started = self.states.lock().unwrap().remove(state);
+        if !started.is_some_and(|expires_at| expires_at > Instant::now()) {
+            return Err(unauthorized("the Discord sign-in has expired, try again"));
+        }
+        let token: TokenResponse = self
+            .http
+            .post(TOKEN_URL)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.config.redirect_url),
+                ("client_id", &self.config.client_id),
+                ("client_secret", &self.config.client_secret),
+            ])
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context("failed exchanging the Discord authorization code")?
+            .json()
+            .await
+            .context("failed parsing the Discord token")?;
+        let user: User = self
+            .http
+            .get(format!("{}/users/@me", API_URL))
+            .bearer_auth(&token.access_token)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .context("failed fetching the Discord user")?
+            .json()
+            .await
+            .context("failed parsing the Discord user")?;
+        let member = self
+            .http
+            .get(format!(
+                "{}/users/@me/guilds/{}/member",
+                API_URL, self.config.guild_id
+            ))
+            .bearer_auth(&token.access_token)
+            .send()
+            .await
+            .context("failed fetching the Discord guild membership")?;
+        match member.status() {
+            status if status.is_success() => {}
+            reqwest::StatusCode::NOT_FOUND => {
+                return Err(CodedError {
+                    code: ErrorCode::Forbidden,
+                    message: "only the members of the Discord server may create accounts"
+                        .to_string(),
+                }
+                .into())
+            }
+            status => anyhow::bail!("Discord responded {} to the membership check", status),
+        }
+
+        let session = hex::encode(rand::random::<[u8; 32]>());
+        self.sessions.lock().unwrap().insert(
+            session.clone(),
+            Session {
+                user_id: user.id.clone(),
+                expires_at: Instant::now() + self.config.session_ttl,
+            },
+        );
+        Ok((session, user))
+    }
+
+    /// Discord identity of a live session
+    pub(crate) fn identity(&self, session: &str) -> Option<Identity> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(session)
+            .filter(|session| session.expires_at > Instant::now())
+            .map(|session| Identity::discord(&session.user_id))
+    }
+
+    /// Requires a signed-in guild member and starts its cooldown
+    /// The clients authenticated with an API key or token aren't gated
+    pub(crate) fn admit(&self, identity: Option<&Identity>) -> anyhow::Result<()> {
+        let user_id = match identity {
+            Some(identity) if identity.is_client() => return Ok(()),
+            Some(identity) => identity.discord_user(),
+            None => None,
+        }
+        .ok_or_else(|| unauthorized("sign in with Discord to create an account"))?;
+        let now = Instant::now();
+        let mut creations = self.creations.lock().unwrap();
+        if let Some(last) = creations.get(user_id) {
+            let elapsed = now.duration_since(*last);
+            if elapsed < self.config.cooldown {
+                return Err(CodedError {
+                    code: ErrorCode::RateLimited,
+                    message: format!(
+                        "this Discord account created an account recently, try again in {} seconds",
+                        (self.config.cooldown - elapsed).as_secs().max(1)
+                    ),
+                }
+                .into());
+            }
+        }
+        creations.insert(user_id.to_string(), now);
+        Ok(())
+    }
+
+    /// Ends the cooldown started by `admit`, used when the creation failed
+    pub(crate) fn release(&self, identity: Option<&Identity>) {
+        if let Some(user_id) = identity.and_then(Identity::discord_user) {
+            self.creations.lock().unwrap().remove(user_id);
+        }
+    }
+
+    /// Forgets the expired flows, sessions and cooldowns, returns how many were forgotten
+    pub(crate) fn purge_expired(&self) -> usize {
+        let now = Instant::now();
+        let mut purged = 0;
+        let mut states = self.states.lock().unwrap();
+        let before = states.len();
+        states.retain(|_, expires_at| *expires_at > now);
+        purged += before - states.len();
+        drop(states);
+        let mut sessions = self.sessions.lock().unwrap();
+        let before = sessions.len();
+        sessions.retain(|_, session| session.expires_at > now);
+        purged += before - sessions.len();
+        drop(sessions);
+        let mut creations = self.creations.lock().unwrap();
+        let before = creations.len();
+        creations.retain(|_, last| now.duration_since(*last) < self.config.cooldown);
+        purged + before - creations.len()
+    }
+}
+
+/// Discord identity of the request, if it carries the cookie of a live session
+pub(crate) fn identity_of(req: &HttpRequest) -> Option<Identity> {
+    let cookie = req.cookie(SESSION_COOKIE)?;
+    req.app_data::<web::Data<crate::NearData>>()?
+        .discord
+        .as_ref()?
+        .identity(cookie.value())
+}
+
+/// Whether the request carries the cookie of a live session, shown on the index page
+pub(crate) fn signed_in(req: &HttpRequest) -> bool {
+    identity_of(req).is_some_and(|identity| identity.discord_user().is_some())
+}
+
+fn error_page(status: actix_web::http::StatusCode, message: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type("text/plain")
+        .body(message.to_string())
+}
+
+/// Endpoint: /auth/discord
+/// Redirects to the Discord consent screen
+pub(crate) async fn login_handler(near: web::Data<crate::NearData>) -> impl Responder {
+    tracing::debug!("GET /auth/discord");
+    let Some(discord) = &near.discord else {
+        return error_page(
+            actix_web::http::StatusCode::NOT_FOUND,
+            "Discord sign-in is not enabled",
+        );
+    };
+    match discord.authorize_url() {
+        Ok(url) => HttpResponse::Found()
+            .insert_header((header::LOCATION, url.to_string()))
+            .finish(),
+        Err(err) => {
+            tracing::error!("Failed to start the Discord sign-in: {:?}", err);
+            error_page(
+                actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to start the Discord sign-in",
+            )
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+}
+
+/// Endpoint: /auth/discord/callback
+/// Signs the guild member in with a session cookie and redirects to the index page
+pub(crate) async fn callback_handler(
+    near: web::Data<crate::NearData>,
+    query: web::Query<CallbackQuery>,
+) -> impl Responder {
+    tracing::debug!("GET /auth/discord/callback");
+    let Some(discord) = &near.discord else {
+        return error_page(
+            actix_web::http::StatusCode::NOT_FOUND,
+            "Discord sign-in is not enabled",
+        );
+    };
+    // The user denied the consent, or the query was tampered with
+    let (Some(code), Some(state)) = (&query.code, &query.state) else {
+        return error_page(
+            actix_web::http::StatusCode::BAD_REQUEST,
+            "The Discord sign-in was cancelled",
+        );
+    };
+    match discord.sign_in(code, state).await {
+        Ok((session, user)) => {
+            tracing::info!("Discord user {} ({}) signed in", user.username, user.id);
+            let cookie = Cookie::build(SESSION_COOKIE, session)
+                .path("/")
+                .http_only(true)
+                .secure(discord.config.redirect_url.starts_with("https://"))
+                .same_site(SameSite::Lax)
+                .max_age(actix_web::cookie::time::Duration::seconds(
+                    discord.config.session_ttl.as_secs() as i64,
+                ))
+                .finish();
+            HttpResponse::Found()
+                .insert_header((header::LOCATION, "/"))
+                .cookie(cookie)
+                .finish()
+        }
+        Err(err) => {
+            tracing::debug!("Rejected Discord sign-in: {:?}", err);
+            match ErrorCode::classify(&err) {
+                ErrorCode::Forbidden => {
+                    error_page(actix_web::http::StatusCode::FORBIDDEN, &err.to_string())
+                }
+                ErrorCode::Unauthorized => {
+                    error_page(actix_web::http::StatusCode::UNAUTHORIZED, &err.to_string())
+                }
+                _ => error_page(
+                    actix_web::http::StatusCode::BAD_GATEWAY,
+                    "Failed to reach Discord, try again",
+                ),
+            }
+        }
+    }
+}
+
+pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/auth/discord", web::get().to(login_handler))
+        .route("/auth/discord/callback", web::get().to(callback_handler));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gate(cooldown: Duration) -> DiscordGate {
+        DiscordGate::new(DiscordConfig {
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            redirect_url: "https://faucet.example.com/auth/discord/callback".to_string(),
+            guild_id: "guild".to_string(),
+            cooldown,
+            session_ttl: Duration::from_secs(60),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn gates_the_creations_per_discord_user() {
+        let gate = gate(Duration::from_secs(60));
+        let user = Identity::discord("42");
+        assert_eq!(
+            ErrorCode::classify(&gate.admit(None).unwrap_err()),
+            ErrorCode::Unauthorized
+        );
+        assert!(gate.admit(Some(&Identity::api_key("partner"))).is_ok());
+
+        gate.admit(Some(&user)).unwrap();
+        let err = gate.admit(Some(&user)).unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::RateLimited);
+        gate.release(Some(&user));
+        gate.admit(Some(&user)).unwrap();
+    }
+}
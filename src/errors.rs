@@ -0,0 +1,612 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use actix_web::http::StatusCode;
+use near_jsonrpc_client::{
+    errors::{JsonRpcError, JsonRpcServerError},
+    methods::tx::RpcTransactionError,
+};
+use near_primitives::errors::{ActionErrorKind, InvalidTxError, TxExecutionError};
+use near_primitives::hash::CryptoHash;
+use serde::{Deserialize, Serialize};
+
+use crate::bans::Banned;
+use crate::utils::rpc_pool::RpcTimeout;
+use crate::validation::ValidationErrors;
+
+/// Other transactions are usually done with the signer's nonces by then
+const NONCE_CONFLICT_RETRY_AFTER: Duration = Duration::from_secs(5);
+/// The broadcasts in progress take a few seconds to settle
+const OVERLOADED_RETRY_AFTER: Duration = Duration::from_secs(2);
+
+/// Stable machine-readable error codes returned in the `code` field of the JSON errors
+///
+/// API clients branch on these instead of the messages, so the existing codes must never change,
+/// new ones may only be added
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub(crate) enum ErrorCode {
+    /// The requested account already exists
+    AccountExists,
+    /// The requested account ID is not a valid NEAR account ID
+    InvalidAccountId,
+    /// The public key is not a valid NEAR public key
+    InvalidPublicKey,
+    /// The client made too many requests
+    RateLimited,
+    /// The faucet account can't cover the funding of the new account
+    FaucetEmpty,
+    /// The NEAR RPC node couldn't be reached or didn't answer in time
+    RpcUnavailable,
+    /// The transaction was rejected or its execution failed for another reason
+    TransactionFailed,
+    /// The request was accepted but is still being processed
+    Pending,
+    /// The faucet is outside of its availability windows, see the message for the next opening
+    FaucetClosed,
+    /// The service is under heavy load and requires solving a challenge, see the message
+    ChallengeRequired,
+    /// The request is malformed
+    InvalidRequest,
+    /// The request signature was missing or invalid
+    Unauthorized,
+    /// The client's address is not allowed to use the service
+    Forbidden,
+    /// There is nothing at the requested path
+    NotFound,
+    /// The account name or public key was denied after an abuse report
+    Denylisted,
+    /// The signer's nonces kept being taken by other transactions, retry after a while
+    NonceConflict,
+    /// Too many transactions are being broadcast at once, retry after a while
+    Overloaded,
+    /// The captcha token was missing or rejected by the captcha provider
+    CaptchaFailed,
+    /// The invite code required by the gated faucet was missing, invalid, expired or already used
+    InvalidInvite,
+    /// The signature proving the ownership of the public key was missing or didn't match the key
+    KeyProofFailed,
+    /// The account name is reserved by the operators or not among the names they allow
+    ReservedName,
+    /// The public key is blocked by the operators or not among the keys they allow
+    BlockedKey,
+    /// The admins paused the creations, e.g. during an incident, see the message
+    FaucetPaused,
+    /// The address or the public key is banned for a while after too many failed requests
+    Banned,
+    /// The `Idempotency-Key` belongs to a request still in progress, or to a different request
+    IdempotencyConflict,
+    /// Anything else, see the message for details
+    InternalError,
+}
+
+impl ErrorCode {
+    pub(crate) const ALL: [ErrorCode; 26] = [
+        ErrorCode::AccountExists,
+        ErrorCode::InvalidAccountId,
+        ErrorCode::InvalidPublicKey,
+        ErrorCode::RateLimited,
+        ErrorCode::FaucetEmpty,
+        ErrorCode::RpcUnavailable,
+        ErrorCode::TransactionFailed,
+        ErrorCode::Pending,
+        ErrorCode::FaucetClosed,
+        ErrorCode::ChallengeRequired,
+        ErrorCode::InvalidRequest,
+        ErrorCode::Unauthorized,
+        ErrorCode::Forbidden,
+        ErrorCode::NotFound,
+        ErrorCode::Denylisted,
+        ErrorCode::NonceConflict,
+        ErrorCode::Overloaded,
+        ErrorCode::CaptchaFailed,
+        ErrorCode::InvalidInvite,
+        ErrorCode::KeyProofFailed,
+        ErrorCode::ReservedName,
+        ErrorCode::BlockedKey,
+        ErrorCode::FaucetPaused,
+        ErrorCode::Banned,
+        ErrorCode::IdempotencyConflict,
+        ErrorCode::InternalError,
+    ];
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::AccountExists => "ACCOUNT_EXISTS",
+            ErrorCode::InvalidAccountId => "INVALID_ACCOUNT_ID",
+            ErrorCode::InvalidPublicKey => "INVALID_PUBLIC_KEY",
+            ErrorCode::RateLimited => "RATE_LIMITED",
+            ErrorCode::FaucetEmpty => "FAUCET_EMPTY",
+            ErrorCode::RpcUnavailable => "RPC_UNAVAILABLE",
+            ErrorCode::TransactionFailed => "TRANSACTION_FAILED",
+            ErrorCode::Pending => "PENDING",
+            ErrorCode::FaucetClosed => "FAUCET_CLOSED",
+            ErrorCode::ChallengeRequired => "CHALLENGE_REQUIRED",
+            ErrorCode::InvalidRequest => "INVALID_REQUEST",
+            ErrorCode::Unauthorized => "UNAUTHORIZED",
+            ErrorCode::Forbidden => "FORBIDDEN",
+            ErrorCode::NotFound => "NOT_FOUND",
+            ErrorCode::Denylisted => "DENYLISTED",
+            ErrorCode::NonceConflict => "NONCE_CONFLICT",
+            ErrorCode::Overloaded => "OVERLOADED",
+            ErrorCode::CaptchaFailed => "CAPTCHA_FAILED",
+            ErrorCode::InvalidInvite => "INVALID_INVITE",
+            ErrorCode::KeyProofFailed => "KEY_PROOF_FAILED",
+            ErrorCode::ReservedName => "RESERVED_NAME",
+            ErrorCode::BlockedKey => "BLOCKED_KEY",
+            ErrorCode::FaucetPaused => "FAUCET_PAUSED",
+            ErrorCode::Banned => "BANNED",
+            ErrorCode::IdempotencyConflict => "IDEMPOTENCY_CONFLICT",
+            ErrorCode::InternalError => "INTERNAL_ERROR",
+        }
+    }
+
+    /// Status of the JSON creation responses failing with the code
+    /// Still pending creations get `202`, malformed or refused requests `400`, unsigned ones `401`, unknown paths `404`,
+    /// taken names and reused idempotency keys `409`, throttled clients, those over the broadcast limit and those to solve a challenge `429` to back off,
+    /// denied or banned ones and those failing the captcha, the invite code or the key proof `403`,
+    /// those arriving while the RPC is down, the nonces are contended or the faucet is paused or closed `503`, the rest of the failures keep `500`
+    pub(crate) fn http_status(&self) -> StatusCode {
+        match self {
+            ErrorCode::Pending => StatusCode::ACCEPTED,
+            ErrorCode::InvalidRequest
+            | ErrorCode::InvalidAccountId
+            | ErrorCode::InvalidPublicKey
+            | ErrorCode::ReservedName
+            | ErrorCode::BlockedKey => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::AccountExists | ErrorCode::IdempotencyConflict => StatusCode::CONFLICT,
+            ErrorCode::RateLimited | ErrorCode::Overloaded | ErrorCode::ChallengeRequired => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            ErrorCode::RpcUnavailable
+            | ErrorCode::NonceConflict
+            | ErrorCode::FaucetPaused
+            | ErrorCode::FaucetClosed => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::Forbidden
+            | ErrorCode::Denylisted
+            | ErrorCode::Banned
+            | ErrorCode::CaptchaFailed
+            | ErrorCode::InvalidInvite
+            | ErrorCode::KeyProofFailed => StatusCode::FORBIDDEN,
+            ErrorCode::FaucetEmpty | ErrorCode::TransactionFailed | ErrorCode::InternalError => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// How long the client should wait before retrying, sent as `Retry-After` along with the error
+    pub(crate) fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ErrorCode::NonceConflict => Some(NONCE_CONFLICT_RETRY_AFTER),
+            ErrorCode::Overloaded => Some(OVERLOADED_RETRY_AFTER),
+            _ => None,
+        }
+    }
+
+    /// Finds the code of the first recognized error in the chain, `INTERNAL_ERROR` if there is none
+    pub(crate) fn classify(err: &anyhow::Error) -> Self {
+        err.chain()
+            .find_map(|cause| {
+                if let Some(coded) = cause.downcast_ref::<CodedError>() {
+                    Some(coded.code)
+                } else if cause.is::<near_account_id::ParseAccountError>() {
+                    Some(ErrorCode::InvalidAccountId)
+                } else if cause.is::<near_crypto::ParseKeyError>() {
+                    Some(ErrorCode::InvalidPublicKey)
+                } else if let Some(errors) = cause.downcast_ref::<ValidationErrors>() {
+                    Some(errors.code())
+                } else if cause.is::<RetryLater>() {
+                    Some(ErrorCode::RateLimited)
+                } else if cause.is::<StillPending>() {
+                    Some(ErrorCode::Pending)
+                } else if cause.is::<Banned>() {
+                    Some(ErrorCode::Banned)
+                } else if cause.is::<NonceRetriesExhausted>() {
+                    Some(ErrorCode::NonceConflict)
+                } else if cause.is::<RpcTimeout>() {
+                    Some(ErrorCode::RpcUnavailable)
+                } else if let Some(TransactionFailed(err)) = cause.downcast_ref() {
+                    Some(Self::of_execution_error(err))
+                } else {
+                    cause
+                        .downcast_ref::<JsonRpcError<RpcTransactionError>>()
+                        .map(Self::of_rpc_error)
+                }
+            })
+            .unwrap_or(ErrorCode::InternalError)
+    }
+
+    fn of_rpc_error(err: &JsonRpcError<RpcTransactionError>) -> Self {
+        match err {
+            JsonRpcError::TransportError(_) => ErrorCode::RpcUnavailable,
+            JsonRpcError::ServerError(JsonRpcServerError::HandlerError(err)) => match err {
+                RpcTransactionError::InvalidTransaction { context } => {
+                    Self::of_invalid_tx_error(context)
+                }
+                RpcTransactionError::InternalError { .. } | RpcTransactionError::TimeoutError => {
+                    ErrorCode::RpcUnavailable
+                }
+                _ => ErrorCode::TransactionFailed,
+            },
+            JsonRpcError::ServerError(
+                JsonRpcServerError::InternalError { .. }
+                | JsonRpcServerError::ResponseStatusError(_),
+            ) => ErrorCode::RpcUnavailable,
+            JsonRpcError::ServerError(_) => ErrorCode::InternalError,
+        }
+    }
+
+    fn of_execution_error(err: &TxExecutionError) -> Self {
+        match err {
+            TxExecutionError::ActionError(err) => match err.kind {
+                ActionErrorKind::AccountAlreadyExists { .. } => ErrorCode::AccountExists,
+                _ => ErrorCode::TransactionFailed,
+            },
+            TxExecutionError::InvalidTxError(err) => Self::of_invalid_tx_error(err),
+        }
+    }
+
+    fn of_invalid_tx_error(err: &InvalidTxError) -> Self {
+        match err {
+            InvalidTxError::NotEnoughBalance { .. }
+            | InvalidTxError::LackBalanceForState { .. } => ErrorCode::FaucetEmpty,
+            InvalidTxError::InvalidReceiverId { .. } => ErrorCode::InvalidAccountId,
+            _ => ErrorCode::TransactionFailed,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ErrorCode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|code| code.as_str() == s)
+            .ok_or_else(|| anyhow::anyhow!("unknown error code: {}", s))
+    }
+}
+
+/// How long the client should wait before retrying, the time the error itself tells if it's a `RetryLater`
+/// or a `Banned`, the one of its code otherwise
+pub(crate) fn retry_after(err: &anyhow::Error) -> Option<Duration> {
+    err.chain()
+        .find_map(|cause| {
+            cause
+                .downcast_ref::<RetryLater>()
+                .map(|later| later.retry_after)
+                .or_else(|| {
+                    cause
+                        .downcast_ref::<Banned>()
+                        .map(|banned| banned.retry_after)
+                })
+        })
+        .or_else(|| ErrorCode::classify(err).retry_after())
+}
+
+/// Hash of the transaction of a creation still processing past its deadline, `None` if none was sent
+pub(crate) fn pending_tx_hash(err: &anyhow::Error) -> Option<CryptoHash> {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<StillPending>())
+        .and_then(|pending| pending.tx_hash)
+}
+
+/// Message for the user explaining the first recognized transaction error in the chain,
+/// the error's own message if there is none
+pub(crate) fn user_message(err: &anyhow::Error) -> String {
+    err.chain()
+        .find_map(|cause| {
+            if let Some(TransactionFailed(err)) = cause.downcast_ref() {
+                describe_execution_error(err)
+            } else if let Some(JsonRpcError::ServerError(JsonRpcServerError::HandlerError(
+                RpcTransactionError::InvalidTransaction { context },
+            ))) = cause.downcast_ref::<JsonRpcError<RpcTransactionError>>()
+            {
+                describe_invalid_tx_error(context)
+            } else {
+                None
+            }
+        })
+        .unwrap_or_else(|| err.to_string())
+}
+
+fn describe_execution_error(err: &TxExecutionError) -> Option<String> {
+    match err {
+        TxExecutionError::ActionError(err) => match &err.kind {
+            ActionErrorKind::AccountAlreadyExists { account_id } => Some(format!(
+                "the account {} already exists, please choose another name",
+                account_id
+            )),
+            ActionErrorKind::CreateAccountNotAllowed {
+                account_id,
+                predecessor_id,
+            } => Some(format!(
+                "{} can't be created by the faucet, only sub-accounts of {} can",
+                account_id, predecessor_id
+            )),
+            _ => None,
+        },
+        TxExecutionError::InvalidTxError(err) => describe_invalid_tx_error(err),
+    }
+}
+
+fn describe_invalid_tx_error(err: &InvalidTxError) -> Option<String> {
+    match err {
+        InvalidTxError::NotEnoughBalance { .. } | InvalidTxError::LackBalanceForState { .. } => {
+            Some("the faucet ran out of funds, please try again later".to_string())
+        }
+        InvalidTxError::InvalidReceiverId { receiver_id } => {
+            Some(format!("{} is not a valid account name", receiver_id))
+        }
+        _ => None,
+    }
+}
+
+/// The transaction was executed, but failed
+#[derive(Debug)]
+pub(crate) struct TransactionFailed(pub(crate) TxExecutionError);
+
+impl fmt::Display for TransactionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "transaction execution failed: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for TransactionFailed {}
+
+/// Every nonce tried for the transaction was rejected as already used, e.g. by another replica racing us
+#[derive(Debug)]
+pub(crate) struct NonceRetriesExhausted {
+    pub(crate) attempts: u32,
+}
+
+impl fmt::Display for NonceRetriesExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the faucet is busy, the transaction was rejected {} times for its nonce, please try again shortly",
+            self.attempts
+        )
+    }
+}
+
+impl std::error::Error for NonceRetriesExhausted {}
+
+/// Error whose code was already determined, e.g. by the worker that processed a queued request
+#[derive(Debug)]
+pub(crate) struct CodedError {
+    pub(crate) code: ErrorCode,
+    pub(crate) message: String,
+}
+
+impl fmt::Display for CodedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CodedError {}
+
+/// The requester has to wait before creating another account, e.g. until the cooldown of its public key ends
+/// Classified as `RATE_LIMITED`, the time is returned to the clients so they can count it down
+#[derive(Debug)]
+pub(crate) struct RetryLater {
+    pub(crate) message: String,
+    pub(crate) retry_after: Duration,
+}
+
+impl fmt::Display for RetryLater {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for RetryLater {}
+
+/// The creation took longer than its deadline and goes on in the background
+/// Classified as `PENDING`, the hash of its transaction lets the clients follow it at `/tx/{tx_hash}`
+#[derive(Debug)]
+pub(crate) struct StillPending {
+    pub(crate) message: String,
+    pub(crate) tx_hash: Option<CryptoHash>,
+}
+
+impl fmt::Display for StillPending {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for StillPending {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context as _;
+
+    /// The codes are part of the public API, changing any of these strings breaks the clients
+    #[test]
+    fn codes_are_stable() {
+        let expected = [
+            "ACCOUNT_EXISTS",
+            "INVALID_ACCOUNT_ID",
+            "INVALID_PUBLIC_KEY",
+            "RATE_LIMITED",
+            "FAUCET_EMPTY",
+            "RPC_UNAVAILABLE",
+            "TRANSACTION_FAILED",
+            "PENDING",
+            "FAUCET_CLOSED",
+            "CHALLENGE_REQUIRED",
+            "INVALID_REQUEST",
+            "UNAUTHORIZED",
+            "FORBIDDEN",
+            "NOT_FOUND",
+            "DENYLISTED",
+            "NONCE_CONFLICT",
+            "OVERLOADED",
+            "CAPTCHA_FAILED",
+            "INVALID_INVITE",
+            "KEY_PROOF_FAILED",
+            "RESERVED_NAME",
+            "BLOCKED_KEY",
+            "FAUCET_PAUSED",
+            "BANNED",
+            "IDEMPOTENCY_CONFLICT",
+            "INTERNAL_ERROR",
+        ];
+        assert_eq!(ErrorCode::ALL.len(), expected.len());
+        for (code, expected) in ErrorCode::ALL.iter().zip(expected) {
+            assert_eq!(code.as_str(), expected);
+            assert_eq!(
+                serde_json::to_value(code).unwrap(),
+                serde_json::json!(expected)
+            );
+            assert_eq!(expected.parse::<ErrorCode>().unwrap(), *code);
+        }
+    }
+
+    #[test]
+    fn classifies_parse_errors() {
+        let err = "NOT VALID"
+            .parse::<near_account_id::AccountId>()
+            .context("failed parsing account ID")
+            .unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::InvalidAccountId);
+
+        let err = "ed25519:nope"
+            .parse::<near_crypto::PublicKey>()
+            .context("failed parsing public key")
+            .unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::InvalidPublicKey);
+    }
+
+    #[test]
+    fn carries_the_pending_transaction() {
+        let tx_hash = CryptoHash::hash_bytes(b"creation");
+        let err = anyhow::Error::new(StillPending {
+            message: "account creation is still processing".to_string(),
+            tx_hash: Some(tx_hash),
+        })
+        .context("account creation failed");
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::Pending);
+        assert_eq!(pending_tx_hash(&err), Some(tx_hash));
+        assert_eq!(ErrorCode::Pending.http_status(), StatusCode::ACCEPTED);
+    }
+
+    #[test]
+    fn classifies_execution_errors() {
+        let err = anyhow::Error::new(TransactionFailed(TxExecutionError::ActionError(
+            near_primitives::errors::ActionError {
+                index: Some(0),
+                kind: ActionErrorKind::AccountAlreadyExists {
+                    account_id: "alice.test.near".parse().unwrap(),
+                },
+            },
+        )));
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::AccountExists);
+
+        let err = anyhow::Error::new(TransactionFailed(TxExecutionError::InvalidTxError(
+            InvalidTxError::NotEnoughBalance {
+                signer_id: "test.near".parse().unwrap(),
+                balance: 0,
+                cost: 1,
+            },
+        )));
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::FaucetEmpty);
+    }
+
+    #[test]
+    fn describes_execution_errors() {
+        let err = anyhow::Error::new(TransactionFailed(TxExecutionError::ActionError(
+            near_primitives::errors::ActionError {
+                index: Some(0),
+                kind: ActionErrorKind::AccountAlreadyExists {
+                    account_id: "alice.test.near".parse().unwrap(),
+                },
+            },
+        )))
+        .context("failed creating the account");
+        assert_eq!(
+            user_message(&err),
+            "the account alice.test.near already exists, please choose another name"
+        );
+
+        let err: JsonRpcError<RpcTransactionError> = JsonRpcError::ServerError(
+            JsonRpcServerError::HandlerError(RpcTransactionError::InvalidTransaction {
+                context: InvalidTxError::InvalidReceiverId {
+                    receiver_id: "NOT VALID".to_string(),
+                },
+            }),
+        );
+        assert_eq!(
+            user_message(&anyhow::Error::new(err)),
+            "NOT VALID is not a valid account name"
+        );
+
+        let err = anyhow::anyhow!("something else");
+        assert_eq!(user_message(&err), "something else");
+    }
+
+    #[test]
+    fn classifies_rpc_errors() {
+        let err: JsonRpcError<RpcTransactionError> = JsonRpcError::ServerError(
+            JsonRpcServerError::HandlerError(RpcTransactionError::TimeoutError),
+        );
+        assert_eq!(
+            ErrorCode::classify(&anyhow::Error::new(err)),
+            ErrorCode::RpcUnavailable
+        );
+
+        let err: JsonRpcError<RpcTransactionError> = JsonRpcError::ServerError(
+            JsonRpcServerError::HandlerError(RpcTransactionError::InvalidTransaction {
+                context: InvalidTxError::NotEnoughBalance {
+                    signer_id: "test.near".parse().unwrap(),
+                    balance: 0,
+                    cost: 1,
+                },
+            }),
+        );
+        assert_eq!(
+            ErrorCode::classify(&anyhow::Error::new(err)),
+            ErrorCode::FaucetEmpty
+        );
+
+        let err = anyhow::Error::new(RpcTimeout {
+            method: "send_tx".to_string(),
+            after: std::time::Duration::from_secs(60),
+        })
+        .context("failed sending the transaction");
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::RpcUnavailable);
+    }
+
+    #[test]
+    fn classifies_nonce_conflicts() {
+        let err = anyhow::Error::new(NonceRetriesExhausted { attempts: 5 })
+            .context("failed creating the account");
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::NonceConflict);
+        assert!(ErrorCode::NonceConflict.retry_after().is_some());
+    }
+
+    #[test]
+    fn keeps_predetermined_codes() {
+        let err = anyhow::Error::new(CodedError {
+            code: ErrorCode::AccountExists,
+            message: "transaction execution failed".to_string(),
+        });
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::AccountExists);
+        assert_eq!(
+            ErrorCode::classify(&anyhow::anyhow!("something else")),
+            ErrorCode::InternalError
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::errors::{CodedError, ErrorCode};
+
+/// Header carrying the proof of work solved by the client
+pub(crate) const PROOF_OF_WORK_HEADER: &str = "x-proof-of-work";
+
+/// Window the request rate is measured over, the rates are per this minute
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+/// Extra weight of a failed creation attempt, failures are typical for scripted abuse
+const FAILURE_RISK: f64 = 1.0;
+
+/// Friction the creation requests are subjected to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ChallengeLevel {
+    None,
+    Captcha,
+    ProofOfWork,
+    Deny,
+}
+
+/// Weighted requests per minute at which each level kicks in, levels without a threshold are skipped
+#[derive(Debug, Clone)]
+pub(crate) struct EscalationConfig {
+    pub(crate) captcha_rate: Option<f64>,
+    pub(crate) proof_of_work_rate: Option<f64>,
+    pub(crate) deny_rate: Option<f64>,
+    /// How long the rate must stay below the threshold before stepping one level down
+    pub(crate) cooldown: Duration,
+    /// Leading zero bits required from the proof of work hash
+    pub(crate) proof_of_work_difficulty: u32,
+}
+
+#[derive(Debug)]
+struct State {
+    events: VecDeque<(Instant, f64)>,
+    level: ChallengeLevel,
+    calm_since: Option<Instant>,
+}
+
+impl State {
+    fn rate(&mut self, now: Instant) -> f64 {
+        while let Some((at, _)) = self.events.front() {
+            if now.duration_since(*at) <= RATE_WINDOW {
+                break;
+            }
+            self.events.pop_front();
+        }
+        self.events.iter().map(|(_, weight)| weight).sum()
+    }
+}
+
+/// Raises the challenge level as soon as the global request rate crosses a threshold,
+/// and lowers it one level at a time once the rate stays below for the cooldown
+#[derive(Debug)]
+pub(crate) struct Escalation {
+    config: EscalationConfig,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct EscalationStatus {
+    level: ChallengeLevel,
+    /// Weighted creation requests per minute
+    request_rate: f64,
+    proof_of_work_difficulty: u32,
+}
+
+impl Escalation {
+    pub(crate) fn new(config: EscalationConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State {
+                events: VecDeque::new(),
+                level: ChallengeLevel::None,
+                calm_since: None,
+            }),
+        }
+    }
+
+    fn target_level(&self, rate: f64) -> ChallengeLevel {
+        [
+            (self.config.deny_rate, ChallengeLevel::Deny),
+            (self.config.proof_of_work_rate, ChallengeLevel::ProofOfWork),
+            (self.config.captcha_rate, ChallengeLevel::Captcha),
+        ]
+        .into_iter()
+        .find(|(threshold, _)| threshold.is_some_and(|threshold| rate >= threshold))
+        .map(|(_, level)| level)
+        .unwrap_or(ChallengeLevel::None)
+    }
+
+    /// Adds a weighted event to the window and moves the level accordingly
+    fn record(&self, weight: f64) -> ChallengeLevel {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.events.push_back((now, weight));
+        let target = self.target_level(state.rate(now));
+        if target >= state.level {
+            if target > state.level {
+                tracing::warn!("Escalating the challenge level to {:?}", target);
+            }
+            state.level = target;
+            state.calm_since = None;
+        } else {
+            let calm_since = *state.calm_since.get_or_insert(now);
+            if now.duration_since(calm_since) >= self.config.cooldown {
+                state.level = match state.level {
+                    ChallengeLevel::Deny => ChallengeLevel::ProofOfWork,
+                    ChallengeLevel::ProofOfWork => ChallengeLevel::Captcha,
+                    _ => ChallengeLevel::None,
+                }
+                .max(target);
+                state.calm_since = None;
+                tracing::info!("De-escalating the challenge level to {:?}", state.level);
+            }
+        }
+        state.level
+    }
+
+    /// Counts the creation request and checks it against the current level
+    /// The requests with a verified captcha token or passkey pass the captcha level, the others solve the proof of work
+    pub(crate) fn admit(
+        &self,
+        account_id: &str,
+        public_key: &str,
+        proof_of_work: Option<&str>,
+        human_verified: bool,
+    ) -> anyhow::Result<()> {
+        match self.record(1.0) {
+            ChallengeLevel::None => Ok(()),
+            ChallengeLevel::Captcha if human_verified => Ok(()),
+            ChallengeLevel::Captcha | ChallengeLevel::ProofOfWork => {
+                let solved = proof_of_work.is_some_and(|nonce| {
+                    verify_proof_of_work(
+                        account_id,
+                        public_key,
+                        nonce,
+                        self.config.proof_of_work_difficulty,
+                    )
+                });
+                if solved {
+                    Ok(())
+                } else {
+                    Err(CodedError {
+                        code: ErrorCode::ChallengeRequired,
+                        message: format!(
+                            "the faucet is under heavy load, solve a proof of work of difficulty {} and send it in the X-Proof-Of-Work header",
+                            self.config.proof_of_work_difficulty
+                        ),
+                    }
+                    .into())
+                }
+            }
+            ChallengeLevel::Deny => Err(CodedError {
+                code: ErrorCode::RateLimited,
+                message: "the faucet is under heavy load, try again later".to_string(),
+            }
+            .into()),
+        }
+    }
+
+    /// Failed creation attempts weigh in on top of the request itself
+    pub(crate) fn record_failure(&self) {
+        self.record(FAILURE_RISK);
+    }
+
+    pub(crate) fn level(&self) -> ChallengeLevel {
+        self.state.lock().unwrap().level
+    }
+
+    pub(crate) fn status(&self) -> EscalationStatus {
+        let mut state = self.state.lock().unwrap();
+        EscalationStatus {
+            level: state.level,
+            request_rate: state.rate(Instant::now()),
+            proof_of_work_difficulty: self.config.proof_of_work_difficulty,
+        }
+    }
+}
+
+/// Checks that `sha256("{account_id}:{public_key}:{nonce}")` starts with `difficulty` zero bits
+/// Binding the work to the requested account keeps a solution from being reused for other accounts
+fn verify_proof_of_work(account_id: &str, public_key: &str, nonce: &str, difficulty: u32) -> bool {
+    let hash = Sha256::digest(format!("{}:{}:{}", account_id, public_key, nonce));
+    let mut remaining = difficulty;
+    for byte in hash {
+        if remaining == 0 {
+            return true;
+        }
+        let zeros = byte.leading_zeros().min(remaining);
+        if zeros < remaining.min(8) {
+            return false;
+        }
+        remaining -= zeros;
+    }
+    remaining == 0
+}
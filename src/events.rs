@@ -0,0 +1,98 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::errors::ErrorCode;
+use crate::middleware::replay_guard::AuthenticatedClient;
+
+/// How many events a slow subscriber may fall behind before it starts missing them
+const CAPACITY: usize = 1024;
+/// Comment sent to idle streams so the proxies in between don't close them
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Account successfully created by this process
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct CreationEvent {
+    account_id: String,
+    /// Unix timestamp of the creation
+    timestamp: u64,
+}
+
+/// In-process bus the creations are published to as they happen
+#[derive(Debug, Clone)]
+pub(crate) struct EventBus {
+    sender: broadcast::Sender<CreationEvent>,
+}
+
+impl EventBus {
+    pub(crate) fn new() -> Self {
+        Self {
+            sender: broadcast::channel(CAPACITY).0,
+        }
+    }
+
+    pub(crate) fn publish_creation(&self, account_id: &str) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        // Nobody listening is fine, the events aren't kept
+        let _ = self.sender.send(CreationEvent {
+            account_id: account_id.to_string(),
+            timestamp,
+        });
+    }
+}
+
+/// Who may subscribe to `GET /v1/events/stream`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum StreamAccess {
+    Disabled,
+    Public,
+    /// Only the clients authenticated with a signed request
+    Authenticated,
+}
+
+/// Endpoint: /v1/events/stream
+/// Streams an `account_created` server-sent event with `{account_id, timestamp}` per successful creation
+pub(crate) async fn stream_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+    access: web::Data<StreamAccess>,
+) -> HttpResponse {
+    tracing::debug!("GET /v1/events/stream");
+    let error = |code: ErrorCode, message: &str| serde_json::json!({ "result": null, "error": { "code": code, "message": message } });
+    match **access {
+        StreamAccess::Disabled => {
+            return HttpResponse::NotFound()
+                .json(error(ErrorCode::NotFound, "the events stream is disabled"))
+        }
+        StreamAccess::Authenticated if req.extensions().get::<AuthenticatedClient>().is_none() => {
+            return HttpResponse::Unauthorized().json(error(
+                ErrorCode::Unauthorized,
+                "the events stream is available to authenticated clients only",
+            ))
+        }
+        _ => {}
+    }
+
+    let stream =
+        futures_util::stream::unfold(near.events.sender.subscribe(), |mut events| async move {
+            let chunk = match tokio::time::timeout(KEEP_ALIVE, events.recv()).await {
+                Ok(Ok(event)) => format!(
+                    "event: account_created\ndata: {}\n\n",
+                    serde_json::to_string(&event).expect("event is serializable")
+                ),
+                Ok(Err(RecvError::Lagged(missed))) => format!(": missed {} events\n\n", missed),
+                Ok(Err(RecvError::Closed)) => return None,
+                Err(_) => ": keep-alive\n\n".to_string(),
+            };
+            Some((Ok::<_, actix_web::Error>(web::Bytes::from(chunk)), events))
+        });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("cache-control", "no-cache"))
+        .streaming(stream)
+}
@@ -0,0 +1,117 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::errors::{CodedError, ErrorCode};
+
+/// Told to the bots and the humans whose page is broken alike, without hinting at what failed
+const UNVERIFIED: &str = "the form could not be verified, please reload the page and try again";
+/// Stamps older than this are refused, the page has to be reloaded
+const MAX_STAMP_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn rejected(message: &str) -> anyhow::Error {
+    CodedError {
+        code: ErrorCode::InvalidRequest,
+        message: message.to_string(),
+    }
+    .into()
+}
+
+/// Cheap bot detection of the index form, no third party involved
+/// The form carries a honeypot field hidden from the humans and the signed time it was rendered at,
+/// the submissions filling the honeypot or arriving faster than a human could fill the form are refused
+pub(crate) struct FormGuard {
+    secret: Vec<u8>,
+    min_fill_time: Duration,
+}
+
+impl FormGuard {
+    /// Signs the stamps with the secret, a random one if not set, which only works with a single replica
+    pub(crate) fn new(secret: Option<&str>, min_fill_time: Duration) -> Self {
+        let secret = match secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => rand::random::<[u8; 32]>().to_vec(),
+        };
+        Self {
+            secret,
+            min_fill_time,
+        }
+    }
+
+    fn mac(&self, issued_at: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(issued_at.as_bytes());
+        mac
+    }
+
+    /// Stamp of a form rendered now, `<unix millis>.<signature>`
+    pub(crate) fn stamp(&self) -> String {
+        let issued_at = now_millis().to_string();
+        let signature = hex::encode(self.mac(&issued_at).finalize().into_bytes());
+        format!("{}.{}", issued_at, signature)
+    }
+
+    /// Fails with `INVALID_REQUEST` if the honeypot is filled, the stamp is missing or forged,
+    /// or the form was submitted too quickly or too late
+    pub(crate) fn check(&self, honeypot: Option<&str>, stamp: Option<&str>) -> anyhow::Result<()> {
+        if honeypot.is_some_and(|value| !value.trim().is_empty()) {
+            return Err(rejected(UNVERIFIED));
+        }
+        let Some((issued_at, signature)) = stamp.and_then(|stamp| stamp.trim().split_once('.'))
+        else {
+            return Err(rejected(UNVERIFIED));
+        };
+        let genuine = hex::decode(signature)
+            .is_ok_and(|signature| self.mac(issued_at).verify_slice(&signature).is_ok());
+        let issued_at: u64 = match issued_at.parse() {
+            Ok(issued_at) if genuine => issued_at,
+            _ => return Err(rejected(UNVERIFIED)),
+        };
+        let elapsed = Duration::from_millis(now_millis().saturating_sub(issued_at));
+        if elapsed < self.min_fill_time {
+            return Err(rejected(
+                "the form was submitted too quickly, please wait a moment and try again",
+            ));
+        }
+        if elapsed > MAX_STAMP_AGE {
+            return Err(rejected(
+                "the form has expired, please reload the page and try again",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_the_bots() {
+        let guard = FormGuard::new(Some("secret"), Duration::ZERO);
+        let stamp = guard.stamp();
+        guard.check(None, Some(&stamp)).unwrap();
+        guard.check(Some(""), Some(&stamp)).unwrap();
+        assert!(guard
+            .check(Some("https://spam.example"), Some(&stamp))
+            .is_err());
+        assert!(guard.check(None, None).is_err());
+
+        let (issued_at, _) = stamp.split_once('.').unwrap();
+        let forged = format!("{}.{}", issued_at.parse::<u64>().unwrap() - 60_000, "00");
+        assert!(guard.check(None, Some(&forged)).is_err());
+
+        let slow_humans_only = FormGuard::new(Some("secret"), Duration::from_secs(3));
+        let err = slow_humans_only.check(None, Some(&stamp)).unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::InvalidRequest);
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::{http::header, web, HttpResponse, Responder};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use near_crypto::{KeyType, SecretKey};
+
+use crate::throughput::{ThroughputLimiter, MINUTE};
+
+/// Key pair waiting to be picked up by the user it was generated for
+struct Claim {
+    account_id: String,
+    secret_key: SecretKey,
+    expires_at: Instant,
+}
+
+/// Key pairs generated for the form requests without a public key
+/// Each is handed over once through a claim link, and forgotten after the first download or the TTL
+pub(crate) struct GeneratedKeys {
+    /// Over the rate the requests are rejected right away instead of waiting
+    limiter: ThroughputLimiter,
+    ttl: Duration,
+    claims: Mutex<HashMap<String, Claim>>,
+}
+
+impl GeneratedKeys {
+    pub(crate) fn new(per_minute: u32, ttl: Duration) -> Self {
+        Self {
+            limiter: ThroughputLimiter::new(0).with_limit(MINUTE, per_minute),
+            ttl,
+            claims: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Generates a key pair for a new account, fails with `RATE_LIMITED` over the rate
+    pub(crate) async fn generate(&self) -> anyhow::Result<SecretKey> {
+        self.limiter.acquire().await?;
+        Ok(SecretKey::from_random(KeyType::ED25519))
+    }
+
+    /// Keeps the secret key of the created account until it's claimed, returns the claim token
+    pub(crate) fn issue_claim(&self, account_id: &str, secret_key: SecretKey) -> String {
+        let token = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+        self.claims.lock().unwrap().insert(
+            token.clone(),
+            Claim {
+                account_id: account_id.to_string(),
+                secret_key,
+                expires_at: Instant::now() + self.ttl,
+            },
+        );
+        token
+    }
+
+    /// Forgets the key of an account that won't be created
+    pub(crate) fn release_claim(&self, token: &str) {
+        self.claims.lock().unwrap().remove(token);
+    }
+
+    fn claim(&self, token: &str) -> Option<Claim> {
+        self.claims
+            .lock()
+            .unwrap()
+            .remove(token)
+            .filter(|claim| claim.expires_at > Instant::now())
+    }
+
+    /// Forgets the keys nobody claimed in time, returns how many were forgotten
+    pub(crate) fn purge_expired(&self) -> usize {
+        let now = Instant::now();
+        let mut claims = self.claims.lock().unwrap();
+        let before = claims.len();
+        claims.retain(|_, claim| claim.expires_at > now);
+        before - claims.len()
+    }
+}
+
+/// Endpoint: /claim/{token}
+/// Downloads the generated key of the account as a near-cli credentials file, once
+pub(crate) async fn claim_handler(
+    near: web::Data<crate::NearData>,
+    token: web::Path<String>,
+) -> impl Responder {
+    tracing::debug!("GET /claim");
+    let Some(claim) = near
+        .generated_keys
+        .as_ref()
+        .and_then(|keys| keys.claim(&token))
+    else {
+        return HttpResponse::NotFound().finish();
+    };
+    tracing::info!("Generated key of {} was claimed", claim.account_id);
+    HttpResponse::Ok()
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}.json\"", claim.account_id),
+        ))
+        .json(serde_json::json!({
+            "account_id": claim.account_id,
+            "public_key": claim.secret_key.public_key(),
+            "private_key": claim.secret_key,
+        }))
+}
@@ -0,0 +1,542 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use anyhow::Context as _;
+use near_primitives::types::Balance;
+use serde::{Deserialize, Serialize};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+
+use crate::create_account::RequestOrigin;
+use crate::errors::{pending_tx_hash, ErrorCode};
+use crate::utils::send_tx::Submitted;
+
+/// Attempts per page of `GET /api/v1/creations`
+const PAGE_SIZE: usize = 50;
+/// The totals are cached for this long, the all-time ones scan the whole table
+const TOTALS_TTL: Duration = Duration::from_secs(60);
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// How a creation attempt ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Outcome {
+    Created,
+    /// Still pending past the deadline, the account may be created yet
+    Pending,
+    Failed,
+}
+
+impl Outcome {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Created => "created",
+            Outcome::Pending => "pending",
+            Outcome::Failed => "failed",
+        }
+    }
+}
+
+/// Creation attempt as served by `GET /api/v1/creations`
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub(crate) struct Attempt {
+    pub(crate) id: i64,
+    /// Unix timestamp
+    pub(crate) created_at: i64,
+    pub(crate) entry_point: String,
+    /// API key or token name of the client, `public` for anonymous requests
+    pub(crate) tenant: String,
+    /// Passkey, Discord or email identity the creation was charged to
+    pub(crate) identity: Option<String>,
+    pub(crate) client_ip: Option<String>,
+    pub(crate) account_id: String,
+    pub(crate) public_key: String,
+    /// `created`, `pending` or `failed`
+    pub(crate) outcome: String,
+    pub(crate) error_code: Option<String>,
+    pub(crate) tx_hash: Option<String>,
+    /// yoctoNEAR the account was funded with, only set for the created ones
+    pub(crate) funding_amount: Option<String>,
+    /// Time from the request to the outcome
+    pub(crate) duration_ms: i64,
+}
+
+impl Attempt {
+    /// Attempt ending with `result`, the ID is assigned by the database
+    pub(crate) fn of(
+        account_id: &str,
+        public_key: &str,
+        origin: &RequestOrigin,
+        funding_amount: Balance,
+        result: &anyhow::Result<Submitted>,
+        duration: Duration,
+    ) -> Self {
+        let (outcome, error_code) = match result {
+            Ok(_) => (Outcome::Created, None),
+            Err(err) => match ErrorCode::classify(err) {
+                ErrorCode::Pending => (Outcome::Pending, Some(ErrorCode::Pending)),
+                code => (Outcome::Failed, Some(code)),
+            },
+        };
+        let submitted = result.as_ref().ok();
+        Self {
+            id: 0,
+            created_at: now().saturating_sub(duration.as_secs()) as i64,
+            entry_point: origin.entry_point.as_str().to_string(),
+            tenant: origin.tenant.clone(),
+            identity: origin
+                .identity
+                .as_ref()
+                .map(|identity| identity.to_string()),
+            client_ip: origin.client_ip.map(|ip| ip.to_string()),
+            account_id: account_id.to_string(),
+            public_key: public_key.to_string(),
+            outcome: outcome.as_str().to_string(),
+            error_code: error_code.map(|code| code.as_str().to_string()),
+            tx_hash: match result {
+                Ok(submitted) => submitted.tx_hash,
+                Err(err) => pending_tx_hash(err),
+            }
+            .map(|hash| hash.to_string()),
+            funding_amount: submitted.map(|_| funding_amount.to_string()),
+            duration_ms: duration.as_millis() as i64,
+        }
+    }
+}
+
+/// Where the attempts are kept
+#[derive(Clone)]
+enum Store {
+    /// Local to the process, in a file next to it by default
+    Sqlite(SqlitePool),
+    #[cfg(feature = "contract-helper")]
+    /// Shared by the frontends and the replicas
+    Postgres(sqlx::PgPool),
+}
+
+const INSERT: &str = r#"
+    INSERT INTO creation_attempts (
+        created_at, entry_point, tenant, identity, client_ip, account_id, public_key,
+        outcome, error_code, tx_hash, funding_amount, duration_ms
+    ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+"#;
+
+const SELECT: &str = r#"
+    SELECT * FROM creation_attempts
+    WHERE created_at >= $1 AND ($2 IS NULL OR outcome = $2)
+    ORDER BY id DESC
+    LIMIT $3 OFFSET $4
+"#;
+
+const PURGE: &str = "DELETE FROM creation_attempts WHERE created_at < $1";
+
+const COUNTS: &str = r#"
+    SELECT
+        COALESCE(SUM(CASE WHEN outcome = 'created' THEN 1 ELSE 0 END), 0) AS created,
+        COALESCE(SUM(CASE WHEN outcome = 'created' AND created_at >= $1 THEN 1 ELSE 0 END), 0) AS created_today,
+        COALESCE(SUM(CASE WHEN outcome = 'failed' THEN 1 ELSE 0 END), 0) AS failed,
+        CAST(AVG(CASE WHEN outcome = 'created' THEN duration_ms END) AS DOUBLE PRECISION) AS average_duration_ms
+    FROM creation_attempts
+"#;
+
+/// The funding amounts are text, yoctoNEAR don't fit the integers of the databases, so they are added up by amount
+const DISBURSED: &str = r#"
+    SELECT funding_amount, COUNT(*) AS accounts FROM creation_attempts
+    WHERE outcome = 'created' AND funding_amount IS NOT NULL
+    GROUP BY funding_amount
+"#;
+
+#[derive(Debug, sqlx::FromRow)]
+struct Counts {
+    created: i64,
+    created_today: i64,
+    failed: i64,
+    average_duration_ms: Option<f64>,
+}
+
+/// Aggregates of the creation history, served by `/stats`
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Totals {
+    /// Accounts created since midnight UTC
+    pub(crate) created_today: u64,
+    pub(crate) created_all_time: u64,
+    /// Share of the finished attempts that created the account, the pending ones aside, `None` before the first one
+    pub(crate) success_rate: Option<f64>,
+    /// NEAR the created accounts were funded with, in yoctoNEAR
+    pub(crate) disbursed_yocto: String,
+    /// Average time an account took to be created, `None` before the first one
+    pub(crate) average_latency_ms: Option<u64>,
+}
+
+impl Totals {
+    fn of(counts: Counts, disbursed: &[(String, i64)]) -> Self {
+        let disbursed: Balance = disbursed
+            .iter()
+            .filter_map(|(amount, accounts)| {
+                Some(amount.parse::<Balance>().ok()? * *accounts as Balance)
+            })
+            .sum();
+        let finished = counts.created + counts.failed;
+        Self {
+            created_today: counts.created_today as u64,
+            created_all_time: counts.created as u64,
+            success_rate: (finished > 0).then_some(counts.created as f64 / finished as f64),
+            disbursed_yocto: disbursed.to_string(),
+            average_latency_ms: counts.average_duration_ms.map(|ms| ms.round() as u64),
+        }
+    }
+}
+
+/// Binds the columns of an attempt to the `INSERT` query, in its order
+macro_rules! bind_insert {
+    ($query:expr, $attempt:expr) => {
+        $query
+            .bind($attempt.created_at)
+            .bind(&$attempt.entry_point)
+            .bind(&$attempt.tenant)
+            .bind(&$attempt.identity)
+            .bind(&$attempt.client_ip)
+            .bind(&$attempt.account_id)
+            .bind(&$attempt.public_key)
+            .bind(&$attempt.outcome)
+            .bind(&$attempt.error_code)
+            .bind(&$attempt.tx_hash)
+            .bind(&$attempt.funding_amount)
+            .bind($attempt.duration_ms)
+    };
+}
+
+/// Every creation attempt of the entry points handled by this process, for the operators and the support staff
+#[derive(Clone)]
+pub(crate) struct CreationHistory {
+    store: Store,
+    totals: Arc<tokio::sync::Mutex<Option<(Instant, Totals)>>>,
+}
+
+impl CreationHistory {
+    /// Keeps the attempts in the `creation_attempts` table of a SQLite database, both created if missing
+    pub(crate) async fn sqlite(url: &str) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::from_str(url)
+            .context("invalid SQLite database of the creation history")?
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(options)
+            .await
+            .context("failed opening the creation history")?;
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS creation_attempts (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                created_at BIGINT NOT NULL,
+                entry_point TEXT NOT NULL,
+                tenant TEXT NOT NULL,
+                identity TEXT,
+                client_ip TEXT,
+                account_id TEXT NOT NULL,
+                public_key TEXT NOT NULL,
+                outcome TEXT NOT NULL,
+                error_code TEXT,
+                tx_hash TEXT,
+                funding_amount TEXT,
+                duration_ms BIGINT NOT NULL
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .context("failed creating the creation_attempts table")?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS creation_attempts_created_at ON creation_attempts (created_at)",
+        )
+        .execute(&pool)
+        .await
+        .context("failed indexing the creation_attempts table")?;
+        Ok(Self {
+            store: Store::Sqlite(pool),
+            totals: Default::default(),
+        })
+    }
+
+    #[cfg(feature = "contract-helper")]
+    /// Keeps the attempts in the `creation_attempts` table of a Postgres database, created if missing
+    pub(crate) async fn postgres(pool: sqlx::PgPool) -> anyhow::Result<Self> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS creation_attempts (
+                id BIGSERIAL PRIMARY KEY,
+                created_at BIGINT NOT NULL,
+                entry_point TEXT NOT NULL,
+                tenant TEXT NOT NULL,
+                identity TEXT,
+                client_ip TEXT,
+                account_id TEXT NOT NULL,
+                public_key TEXT NOT NULL,
+                outcome TEXT NOT NULL,
+                error_code TEXT,
+                tx_hash TEXT,
+                funding_amount TEXT,
+                duration_ms BIGINT NOT NULL
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .context("failed creating the creation_attempts table")?;
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS creation_attempts_created_at ON creation_attempts (created_at)",
+        )
+        .execute(&pool)
+        .await
+        .context("failed indexing the creation_attempts table")?;
+        Ok(Self {
+            store: Store::Postgres(pool),
+            totals: Default::default(),
+        })
+    }
+
+    /// Stores the attempt in the background, the response of the creation doesn't wait for it
+    pub(crate) fn record(&self, attempt: Attempt) {
+        let store = self.store.clone();
+        tokio::spawn(async move {
+            let result = match &store {
+                Store::Sqlite(pool) => bind_insert!(sqlx::query(INSERT), attempt)
+                    .execute(pool)
+                    .await
+                    .map(|_| ()),
+                #[cfg(feature = "contract-helper")]
+                Store::Postgres(pool) => bind_insert!(sqlx::query(INSERT), attempt)
+                    .execute(pool)
+                    .await
+                    .map(|_| ()),
+            };
+            if let Err(err) = result {
+                tracing::warn!(
+                    "Failed to record the creation attempt of {}: {:?}",
+                    attempt.account_id,
+                    err
+                );
+            }
+        });
+    }
+
+    /// Attempts since the Unix timestamp, optionally only those ending with `outcome`, latest first
+    pub(crate) async fn list(
+        &self,
+        since: u64,
+        outcome: Option<Outcome>,
+        limit: usize,
+        offset: usize,
+    ) -> anyhow::Result<Vec<Attempt>> {
+        let outcome = outcome.map(|outcome| outcome.as_str());
+        let attempts = match &self.store {
+            Store::Sqlite(pool) => {
+                sqlx::query_as(SELECT)
+                    .bind(since as i64)
+                    .bind(outcome)
+                    .bind(limit as i64)
+                    .bind(offset as i64)
+                    .fetch_all(pool)
+                    .await
+            }
+            #[cfg(feature = "contract-helper")]
+            Store::Postgres(pool) => {
+                sqlx::query_as(SELECT)
+                    .bind(since as i64)
+                    .bind(outcome)
+                    .bind(limit as i64)
+                    .bind(offset as i64)
+                    .fetch_all(pool)
+                    .await
+            }
+        };
+        attempts.context("failed listing the creation attempts")
+    }
+
+    /// Totals of the attempts, refreshed at most every minute
+    pub(crate) async fn totals(&self) -> anyhow::Result<Totals> {
+        let mut cached = self.totals.lock().await;
+        match &*cached {
+            Some((at, totals)) if at.elapsed() < TOTALS_TTL => Ok(totals.clone()),
+            _ => {
+                let totals = self.aggregate().await?;
+                *cached = Some((Instant::now(), totals.clone()));
+                Ok(totals)
+            }
+        }
+    }
+
+    async fn aggregate(&self) -> anyhow::Result<Totals> {
+        let now = now();
+        let today = (now - now % SECS_PER_DAY) as i64;
+        let (counts, disbursed): (sqlx::Result<Counts>, sqlx::Result<Vec<(String, i64)>>) =
+            match &self.store {
+                Store::Sqlite(pool) => (
+                    sqlx::query_as(COUNTS).bind(today).fetch_one(pool).await,
+                    sqlx::query_as(DISBURSED).fetch_all(pool).await,
+                ),
+                #[cfg(feature = "contract-helper")]
+                Store::Postgres(pool) => (
+                    sqlx::query_as(COUNTS).bind(today).fetch_one(pool).await,
+                    sqlx::query_as(DISBURSED).fetch_all(pool).await,
+                ),
+            };
+        let counts = counts.context("failed counting the creation attempts")?;
+        let disbursed = disbursed.context("failed adding up the disbursed NEAR")?;
+        Ok(Totals::of(counts, &disbursed))
+    }
+
+    /// Forgets the attempts older than `retention`, returns how many were forgotten
+    pub(crate) async fn purge_older_than(&self, retention: Duration) -> anyhow::Result<u64> {
+        let before = now().saturating_sub(retention.as_secs()) as i64;
+        let purged = match &self.store {
+            Store::Sqlite(pool) => sqlx::query(PURGE)
+                .bind(before)
+                .execute(pool)
+                .await
+                .map(|done| done.rows_affected()),
+            #[cfg(feature = "contract-helper")]
+            Store::Postgres(pool) => sqlx::query(PURGE)
+                .bind(before)
+                .execute(pool)
+                .await
+                .map(|done| done.rows_affected()),
+        };
+        purged.context("failed purging the creation attempts")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CreationsQuery {
+    /// Unix timestamp, all the attempts if not set
+    #[serde(default)]
+    since: u64,
+    status: Option<Outcome>,
+    /// 1-based
+    #[serde(default = "first_page")]
+    page: usize,
+}
+
+fn first_page() -> usize {
+    1
+}
+
+#[derive(Debug, Serialize)]
+struct CreationsPage {
+    creations: Vec<Attempt>,
+    page: usize,
+    per_page: usize,
+    has_more: bool,
+}
+
+/// Endpoint: /api/v1/creations
+/// Lists the creation attempts, latest first, optionally since a Unix timestamp and with a `?status=` (JSON)
+/// For the admins, 50 attempts per `?page=`
+pub(crate) async fn creations_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+    query: web::Query<CreationsQuery>,
+) -> impl Responder {
+    tracing::debug!("GET /api/v1/creations");
+    if let Err(err) = near.abuse.admin_of(&req) {
+        return crate::abuse::error_response(&err);
+    }
+    let page = query.page.max(1);
+    // One more than the page tells whether there is a next one
+    let attempts = near
+        .history
+        .list(
+            query.since,
+            query.status,
+            PAGE_SIZE + 1,
+            (page - 1).saturating_mul(PAGE_SIZE),
+        )
+        .await;
+    match attempts {
+        Ok(mut creations) => {
+            let has_more = creations.len() > PAGE_SIZE;
+            creations.truncate(PAGE_SIZE);
+            HttpResponse::Ok().json(CreationsPage {
+                creations,
+                page,
+                per_page: PAGE_SIZE,
+                has_more,
+            })
+        }
+        Err(err) => {
+            tracing::warn!("{:?}", err);
+            crate::abuse::error_response(&err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lists_and_totals_the_attempts() {
+        let path = std::env::temp_dir().join(format!("creations-{}.db", rand::random::<u64>()));
+        let history = CreationHistory::sqlite(&format!("sqlite://{}", path.display()))
+            .await
+            .unwrap();
+        // Only the Postgres store of the contract helper makes the pattern refutable
+        #[allow(clippy::infallible_destructuring_match)]
+        let pool = match &history.store {
+            Store::Sqlite(pool) => pool,
+            #[cfg(feature = "contract-helper")]
+            Store::Postgres(_) => unreachable!(),
+        };
+        for (account_id, outcome, created_at) in [
+            ("alice.testnet", "created", 100),
+            ("bob.testnet", "failed", 200),
+            ("carol.testnet", "created", 300),
+        ] {
+            let attempt = Attempt {
+                id: 0,
+                created_at,
+                entry_point: "api".to_string(),
+                tenant: "public".to_string(),
+                identity: None,
+                client_ip: None,
+                account_id: account_id.to_string(),
+                public_key: "ed25519:a".to_string(),
+                outcome: outcome.to_string(),
+                error_code: None,
+                tx_hash: None,
+                funding_amount: (outcome == "created").then(|| "1000".to_string()),
+                duration_ms: created_at,
+            };
+            bind_insert!(sqlx::query(INSERT), attempt)
+                .execute(pool)
+                .await
+                .unwrap();
+        }
+
+        let created = history
+            .list(0, Some(Outcome::Created), 10, 0)
+            .await
+            .unwrap();
+        let accounts: Vec<_> = created.iter().map(|a| a.account_id.as_str()).collect();
+        assert_eq!(accounts, vec!["carol.testnet", "alice.testnet"]);
+        assert_eq!(history.list(150, None, 10, 0).await.unwrap().len(), 2);
+        assert_eq!(
+            history.list(0, None, 1, 1).await.unwrap()[0].account_id,
+            "bob.testnet"
+        );
+
+        let totals = history.aggregate().await.unwrap();
+        assert_eq!(totals.created_all_time, 2);
+        assert_eq!(totals.created_today, 0);
+        assert_eq!(totals.success_rate, Some(2.0 / 3.0));
+        assert_eq!(totals.disbursed_yocto, "2000");
+        assert_eq!(totals.average_latency_ms, Some(200));
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::watch;
+
+use crate::errors::{user_message, CodedError, ErrorCode};
+use crate::utils::send_tx::Submitted;
+
+/// Result of a creation as handed to the identical requests waiting for it, the error reduced to its code and message
+type SharedResult = Result<Submitted, (ErrorCode, String)>;
+
+/// The account ID and the public key of a creation
+type Key = (String, String);
+
+/// Creations in progress, so a double-submitted request waits for the first one instead of sending a duplicate transaction
+#[derive(Debug, Clone, Default)]
+pub(crate) struct InFlight {
+    requests: Arc<Mutex<HashMap<Key, watch::Receiver<Option<SharedResult>>>>>,
+}
+
+enum Role {
+    Leader(watch::Sender<Option<SharedResult>>),
+    Follower(watch::Receiver<Option<SharedResult>>),
+}
+
+/// Forgets the creation once its leader is done, including when the leader's request is dropped
+struct Registration<'a> {
+    inflight: &'a InFlight,
+    key: Key,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.inflight.requests.lock().unwrap().remove(&self.key);
+    }
+}
+
+impl InFlight {
+    /// Runs the creation unless an identical one is in progress, in which case its result is awaited instead
+    pub(crate) async fn run<F>(
+        &self,
+        account_id: &str,
+        public_key: &str,
+        create: F,
+    ) -> anyhow::Result<Submitted>
+    where
+        F: Future<Output = anyhow::Result<Submitted>>,
+    {
+        let key = (account_id.to_string(), public_key.to_string());
+        let role = {
+            let mut requests = self.requests.lock().unwrap();
+            match requests.get(&key) {
+                Some(result) => Role::Follower(result.clone()),
+                None => {
+                    let (sender, receiver) = watch::channel(None);
+                    requests.insert(key.clone(), receiver);
+                    Role::Leader(sender)
+                }
+            }
+        };
+        match role {
+            Role::Leader(sender) => {
+                let _registration = Registration {
+                    inflight: self,
+                    key,
+                };
+                let result = create.await;
+                sender.send_replace(Some(match &result {
+                    Ok(submitted) => Ok(submitted.clone()),
+                    Err(err) => Err((ErrorCode::classify(err), user_message(err))),
+                }));
+                result
+            }
+            Role::Follower(mut receiver) => {
+                tracing::info!(
+                    "Waiting for the creation of {} already in progress",
+                    account_id
+                );
+                let shared = match receiver.wait_for(Option::is_some).await {
+                    Ok(result) => result.clone(),
+                    Err(_) => None,
+                };
+                match shared {
+                    Some(Ok(submitted)) => Ok(submitted),
+                    Some(Err((code, message))) => Err(CodedError { code, message }.into()),
+                    None => anyhow::bail!(
+                        "the identical request creating {} was cancelled, try again",
+                        account_id
+                    ),
+                }
+            }
+        }
+    }
+}
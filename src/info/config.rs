@@ -0,0 +1,70 @@
+use actix_web::{web, HttpResponse, Responder};
+use near_primitives_core::types::Balance;
+use serde::Serialize;
+
+use crate::validation::{NameCharset, ValidationRules};
+
+/// Settings the frontends need to configure themselves
+/// Built once at startup and served as-is by `GET /config`
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct PublicConfig {
+    account_suffix: String,
+    /// yoctoNEAR, serialized as a string since it doesn't fit into a JS number
+    funding_amount: String,
+    min_name_length: usize,
+    max_name_length: usize,
+    name_charset: NameCharset,
+    /// Whether the creation requests must carry a captcha token
+    captcha_required: bool,
+    /// `turnstile` or `recaptcha`, Turnstile if both are enabled
+    captcha_provider: Option<&'static str>,
+    /// Site key the captcha of `captcha_provider` is rendered with
+    captcha_site_key: Option<String>,
+    explorer_url: Option<String>,
+    /// Explorer page of a transaction, `{tx_hash}` being replaced with its hash
+    explorer_tx_url: Option<String>,
+}
+
+impl PublicConfig {
+    pub(crate) fn new(
+        validation: &ValidationRules,
+        funding_amount: Balance,
+        explorer_url: Option<String>,
+        explorer_tx_url: Option<String>,
+    ) -> Self {
+        Self {
+            account_suffix: validation.suffix.to_string(),
+            funding_amount: funding_amount.to_string(),
+            min_name_length: validation.min_name_length,
+            max_name_length: validation.max_name_length,
+            name_charset: validation.charset,
+            captcha_required: false,
+            captcha_provider: None,
+            captcha_site_key: None,
+            explorer_url,
+            explorer_tx_url,
+        }
+    }
+
+    /// Reports the site key of the enabled captcha, Turnstile's if both are enabled
+    pub(crate) fn with_captcha(
+        mut self,
+        turnstile_site_key: Option<String>,
+        recaptcha_site_key: Option<String>,
+    ) -> Self {
+        let captcha = turnstile_site_key
+            .map(|site_key| ("turnstile", site_key))
+            .or_else(|| recaptcha_site_key.map(|site_key| ("recaptcha", site_key)));
+        self.captcha_required = captcha.is_some();
+        self.captcha_provider = captcha.as_ref().map(|(provider, _)| *provider);
+        self.captcha_site_key = captcha.map(|(_, site_key)| site_key);
+        self
+    }
+}
+
+/// Endpoint: /config
+/// Responds with the public configuration of the faucet (JSON)
+pub(crate) async fn config_handler(config: web::Data<PublicConfig>) -> impl Responder {
+    tracing::debug!("GET /config");
+    HttpResponse::Ok().json(config.get_ref())
+}
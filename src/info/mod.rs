@@ -0,0 +1,14 @@
+use actix_web::web;
+
+use config::config_handler;
+pub(crate) use config::PublicConfig;
+use version::version_handler;
+
+mod config;
+mod version;
+
+// Registers the public, unauthenticated informational endpoints on the root of the app
+pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/config", web::get().to(config_handler))
+        .route("/version", web::get().to(version_handler));
+}
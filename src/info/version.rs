@@ -0,0 +1,42 @@
+use actix_web::{HttpResponse, Responder};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct VersionResponse {
+    name: &'static str,
+    version: &'static str,
+    git_commit: &'static str,
+    /// Unix timestamp (seconds) of the build
+    build_timestamp: &'static str,
+    features: Vec<&'static str>,
+}
+
+/// Returns the list of cargo features the binary was built with
+fn enabled_features() -> Vec<&'static str> {
+    [
+        ("chaos", cfg!(feature = "chaos")),
+        ("contract-helper", cfg!(feature = "contract-helper")),
+        ("discord", cfg!(feature = "discord")),
+        ("email", cfg!(feature = "email")),
+        ("queue", cfg!(feature = "queue")),
+        ("shared-nonce", cfg!(feature = "shared-nonce")),
+        ("shared-limits", cfg!(feature = "shared-limits")),
+        ("telegram", cfg!(feature = "telegram")),
+    ]
+    .into_iter()
+    .filter_map(|(name, enabled)| enabled.then_some(name))
+    .collect()
+}
+
+/// Endpoint: /version
+/// Responds with the build information of the running binary (JSON)
+pub(crate) async fn version_handler() -> impl Responder {
+    tracing::debug!("GET /version");
+    HttpResponse::Ok().json(VersionResponse {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("GIT_COMMIT"),
+        build_timestamp: env!("BUILD_TIMESTAMP"),
+        features: enabled_features(),
+    })
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+#[cfg(feature = "queue")]
+use anyhow::Context as _;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::errors::{CodedError, ErrorCode};
+
+/// Most codes issued by a single admin request or command
+const MAX_ISSUED: usize = 1000;
+const DEFAULT_TTL_SECS: u64 = 7 * 24 * 60 * 60;
+/// Bytes of the HMAC kept in the codes, enough to rule out guessing while keeping them short to type
+const SIGNATURE_BYTES: usize = 16;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn sign(secret: &str, payload: &str) -> Vec<u8> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(payload.as_bytes());
+    mac.finalize().into_bytes()[..SIGNATURE_BYTES].to_vec()
+}
+
+/// Issues a one-time invite code valid for `ttl`, `<id>.<expires_at>.<signature>`
+/// The codes are verified by their signature, only the redeemed ones are stored
+pub(crate) fn issue(secret: &str, ttl: Duration) -> String {
+    let payload = format!(
+        "{}.{}",
+        hex::encode(rand::random::<[u8; 8]>()),
+        now() + ttl.as_secs()
+    );
+    format!("{}.{}", payload, hex::encode(sign(secret, &payload)))
+}
+
+fn invalid(message: &str) -> anyhow::Error {
+    CodedError {
+        code: ErrorCode::InvalidInvite,
+        message: message.to_string(),
+    }
+    .into()
+}
+
+/// Where the redeemed codes are kept until they expire
+enum Redemptions {
+    /// Lost on restart, the codes redeemed before can be used once more then
+    Memory(Mutex<HashMap<String, u64>>),
+    #[cfg(feature = "queue")]
+    /// Shared by all the frontends of the job queue database
+    Postgres(sqlx::PgPool),
+}
+
+/// Invite codes required by the gated faucet, each one creates a single account
+pub(crate) struct Invites {
+    secret: String,
+    redemptions: Redemptions,
+}
+
+impl Invites {
+    pub(crate) fn new(secret: String) -> Self {
+        Self {
+            secret,
+            redemptions: Redemptions::Memory(Mutex::new(HashMap::new())),
+        }
+    }
+
+    #[cfg(feature = "queue")]
+    /// Keeps the redeemed codes in the `redeemed_invites` table, created if missing
+    pub(crate) async fn with_postgres(mut self, pool: sqlx::PgPool) -> anyhow::Result<Self> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS redeemed_invites (
+                id TEXT PRIMARY KEY,
+                expires_at BIGINT NOT NULL,
+                redeemed_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .context("failed creating the redeemed_invites table")?;
+        self.redemptions = Redemptions::Postgres(pool);
+        Ok(self)
+    }
+
+    pub(crate) fn issue(&self, ttl: Duration) -> String {
+        issue(&self.secret, ttl)
+    }
+
+    /// ID and expiry of a genuine code
+    fn verify(&self, code: &str) -> anyhow::Result<(String, u64)> {
+        let mut parts = code.trim().splitn(3, '.');
+        let (Some(id), Some(expires_at), Some(signature)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid("the invite code is malformed"));
+        };
+        let payload = format!("{}.{}", id, expires_at);
+        let genuine = hex::decode(signature).is_ok_and(|signature| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(self.secret.as_bytes())
+                .expect("HMAC accepts keys of any length");
+            mac.update(payload.as_bytes());
+            signature.len() == SIGNATURE_BYTES && mac.verify_truncated_left(&signature).is_ok()
+        });
+        let expires_at: u64 = match expires_at.parse() {
+            Ok(expires_at) if genuine => expires_at,
+            _ => return Err(invalid("the invite code is not valid")),
+        };
+        if expires_at <= now() {
+            return Err(invalid("the invite code has expired"));
+        }
+        Ok((id.to_string(), expires_at))
+    }
+
+    /// Marks the code as used, fails with `INVALID_INVITE` if it's missing, forged, expired or already used
+    pub(crate) async fn redeem(&self, code: Option<&str>) -> anyhow::Result<()> {
+        let code = code
+            .filter(|code| !code.trim().is_empty())
+            .ok_or_else(|| invalid("an invite code is required to create an account"))?;
+        let (id, expires_at) = self.verify(code)?;
+        let fresh = match &self.redemptions {
+            Redemptions::Memory(redeemed) => {
+                redeemed.lock().unwrap().insert(id, expires_at).is_none()
+            }
+            #[cfg(feature = "queue")]
+            Redemptions::Postgres(pool) => {
+                sqlx::query(
+                    "INSERT INTO redeemed_invites (id, expires_at) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                )
+                .bind(&id)
+                .bind(expires_at as i64)
+                .execute(pool)
+                .await
+                .context("failed redeeming the invite code")?
+                .rows_affected()
+                    == 1
+            }
+        };
+        match fresh {
+            true => Ok(()),
+            false => Err(invalid("the invite code was already used")),
+        }
+    }
+
+    /// Makes the redeemed code usable again, used when the creation failed
+    pub(crate) async fn release(&self, code: &str) {
+        let Ok((id, _)) = self.verify(code) else {
+            return;
+        };
+        match &self.redemptions {
+            Redemptions::Memory(redeemed) => {
+                redeemed.lock().unwrap().remove(&id);
+            }
+            #[cfg(feature = "queue")]
+            Redemptions::Postgres(pool) => {
+                if let Err(err) = sqlx::query("DELETE FROM redeemed_invites WHERE id = $1")
+                    .bind(&id)
+                    .execute(pool)
+                    .await
+                {
+                    tracing::warn!("Failed to release the invite code {}: {:?}", id, err);
+                }
+            }
+        }
+    }
+
+    /// Forgets the redeemed codes that have expired since, returns how many were forgotten
+    pub(crate) async fn purge_expired(&self) -> anyhow::Result<u64> {
+        let now = now();
+        match &self.redemptions {
+            Redemptions::Memory(redeemed) => {
+                let mut redeemed = redeemed.lock().unwrap();
+                let before = redeemed.len();
+                redeemed.retain(|_, expires_at| *expires_at > now);
+                Ok((before - redeemed.len()) as u64)
+            }
+            #[cfg(feature = "queue")]
+            Redemptions::Postgres(pool) => Ok(sqlx::query(
+                "DELETE FROM redeemed_invites WHERE expires_at <= $1",
+            )
+            .bind(now as i64)
+            .execute(pool)
+            .await
+            .context("failed purging the expired invite codes")?
+            .rows_affected()),
+        }
+    }
+}
+
+/// Issues invite codes from the command line, without a running service
+#[derive(Debug, clap::Args)]
+pub(crate) struct InviteArgs {
+    /// Secret the codes are signed with, the same as the service's `--invite-secret`
+    #[clap(long, env)]
+    invite_secret: String,
+    /// How many codes to issue
+    #[clap(long, default_value_t = 1)]
+    count: usize,
+    /// How long the codes stay valid in seconds, default 7 days
+    #[clap(long, default_value_t = DEFAULT_TTL_SECS)]
+    ttl_secs: u64,
+}
+
+/// Prints the issued codes, one per line
+pub(crate) fn run(args: InviteArgs) -> anyhow::Result<()> {
+    if args.count > MAX_ISSUED {
+        anyhow::bail!("at most {} codes can be issued at once", MAX_ISSUED);
+    }
+    for _ in 0..args.count {
+        println!(
+            "{}",
+            issue(&args.invite_secret, Duration::from_secs(args.ttl_secs))
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct IssueRequest {
+    #[serde(default = "default_count")]
+    count: usize,
+    #[serde(default = "default_ttl_secs")]
+    ttl_secs: u64,
+}
+
+fn default_count() -> usize {
+    1
+}
+
+fn default_ttl_secs() -> u64 {
+    DEFAULT_TTL_SECS
+}
+
+#[derive(Debug, Serialize)]
+struct IssueResponse {
+    codes: Vec<String>,
+    /// Unix timestamp the codes expire at
+    expires_at: u64,
+}
+
+/// Endpoint: /admin/invites
+/// Issues `count` one-time invite codes valid for `ttl_secs` (JSON)
+pub(crate) async fn issue_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+    request: web::Json<IssueRequest>,
+) -> impl Responder {
+    tracing::debug!("POST /admin/invites");
+    let error = |code: ErrorCode, message: String| {
+        crate::abuse::error_response(&CodedError { code, message }.into())
+    };
+    let admin = match near.abuse.admin_of(&req) {
+        Ok(admin) => admin,
+        Err(err) => return crate::abuse::error_response(&err),
+    };
+    let Some(invites) = &near.invites else {
+        return error(
+            ErrorCode::NotFound,
+            "the invite codes are not enabled".to_string(),
+        );
+    };
+    if request.count == 0 || request.count > MAX_ISSUED {
+        return error(
+            ErrorCode::InvalidRequest,
+            format!("count must be between 1 and {}", MAX_ISSUED),
+        );
+    }
+    let ttl = Duration::from_secs(request.ttl_secs);
+    let codes: Vec<_> = (0..request.count).map(|_| invites.issue(ttl)).collect();
+    tracing::info!("{} issued {} invite codes", admin, codes.len());
+    HttpResponse::Ok().json(IssueResponse {
+        codes,
+        expires_at: now() + ttl.as_secs(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn codes_are_redeemed_once() {
+        let invites = Invites::new("secret".to_string());
+        let code = invites.issue(Duration::from_secs(60));
+        invites.redeem(Some(&code)).await.unwrap();
+        let err = invites.redeem(Some(&code)).await.unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::InvalidInvite);
+
+        invites.release(&code).await;
+        invites.redeem(Some(&code)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejects_forged_and_expired_codes() {
+        let invites = Invites::new("secret".to_string());
+        let forged = issue("another secret", Duration::from_secs(60));
+        let expired = invites.issue(Duration::ZERO);
+        for code in [None, Some(""), Some("abc"), Some(&forged), Some(&expired)] {
+            let err = invites.redeem(code).await.unwrap_err();
+            assert_eq!(ErrorCode::classify(&err), ErrorCode::InvalidInvite);
+        }
+    }
+}
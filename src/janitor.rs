@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use crate::metrics::JANITOR_CLEANED;
+use crate::NearData;
+
+/// What the janitor cleans up and how often
+#[derive(Debug, Clone)]
+pub(crate) struct JanitorConfig {
+    pub(crate) interval: Duration,
+    #[cfg(feature = "queue")]
+    /// Jobs not finished within this time are expired
+    pub(crate) stale_job_after: Duration,
+    #[cfg(feature = "queue")]
+    /// Finished jobs older than this are deleted, kept forever if not set
+    pub(crate) job_retention: Option<Duration>,
+    /// Creation attempts older than this are deleted, kept forever if not set
+    pub(crate) history_retention: Option<Duration>,
+}
+
+/// Periodically expires and purges what would otherwise accumulate forever,
+/// reporting the cleaned up items in `sw4_janitor_cleaned_total`
+pub(crate) async fn run_janitor(
+    near: NearData,
+    #[cfg(feature = "queue")] queue: Option<crate::queue::Queue>,
+    config: JanitorConfig,
+) {
+    let mut interval = tokio::time::interval(config.interval);
+    loop {
+        interval.tick().await;
+
+        record("quota_usage", near.quotas.purge_expired() as u64);
+        if let Some(cooldowns) = &near.cooldowns {
+            record("cooldown", cooldowns.purge_expired() as u64);
+        }
+        if let Some(bans) = &near.bans {
+            record("ban", bans.purge_expired() as u64);
+        }
+        if let Some(limiter) = &near.prefix_limit {
+            record("account_prefix", limiter.purge_expired() as u64);
+        }
+        if let Some(limiter) = &near.ip_rate_limit {
+            record("ip_rate_limit", limiter.purge_full() as u64);
+        }
+        if let Some(invites) = &near.invites {
+            match invites.purge_expired().await {
+                Ok(purged) => record("redeemed_invite", purged),
+                Err(err) => tracing::warn!("Failed to purge the redeemed invites: {:?}", err),
+            }
+        }
+        #[cfg(feature = "discord")]
+        if let Some(discord) = &near.discord {
+            record("discord_session", discord.purge_expired() as u64);
+        }
+        #[cfg(feature = "email")]
+        if let Some(email) = &near.email {
+            record("email_session", email.purge_expired() as u64);
+        }
+        if let Some(key_proofs) = &near.key_proofs {
+            record("key_challenge", key_proofs.purge_expired() as u64);
+        }
+        if let Some(keys) = &near.generated_keys {
+            record("key_claim", keys.purge_expired() as u64);
+        }
+        if let Some(tracker) = near.submitter.as_ref().and_then(|s| s.tracker()) {
+            record("tracked_tx", tracker.purge_finished() as u64);
+        }
+        record("creation_job", near.jobs.purge_finished() as u64);
+        record(
+            "idempotency_key",
+            near.idempotency_keys.purge_expired() as u64,
+        );
+        if let Some(retention) = config.history_retention {
+            match near.history.purge_older_than(retention).await {
+                Ok(purged) => record("creation_attempt", purged),
+                Err(err) => tracing::warn!("Failed to purge the creation history: {:?}", err),
+            }
+        }
+
+        #[cfg(feature = "queue")]
+        if let Some(queue) = &queue {
+            match queue.expire_stale(config.stale_job_after).await {
+                Ok((queued, processing)) => {
+                    record("stale_queued_job", queued);
+                    record("stale_processing_job", processing);
+                }
+                Err(err) => tracing::warn!("Failed to expire the stale jobs: {:?}", err),
+            }
+            if let Some(retention) = config.job_retention {
+                match queue.purge_finished(retention).await {
+                    Ok(purged) => record("finished_job", purged),
+                    Err(err) => tracing::warn!("Failed to purge the finished jobs: {:?}", err),
+                }
+            }
+        }
+    }
+}
+
+fn record(kind: &str, count: u64) {
+    if count > 0 {
+        tracing::info!("Janitor cleaned up {} {} item(s)", count, kind);
+        JANITOR_CLEANED.with_label_values(&[kind]).inc_by(count);
+    }
+}
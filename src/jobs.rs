@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::Serialize;
+use tokio::sync::{broadcast, Semaphore};
+
+use crate::errors::{CodedError, ErrorCode};
+use crate::utils::send_tx::WaitQuery;
+use crate::{FormCreated, FormError};
+
+/// Finished jobs are kept for this long for the clients to poll them
+const RETENTION: Duration = Duration::from_secs(60 * 60);
+/// Jobs waiting for a running slot, the ones over it are refused with `OVERLOADED`
+const MAX_QUEUED: usize = 1000;
+/// How many state changes a slow `/ws` client may fall behind before it starts missing them
+const UPDATES_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum JobStatus {
+    /// Accepted, waiting for a running slot
+    Queued,
+    /// Being admitted and created, or still pending past the creation deadline
+    Submitted,
+    Succeeded,
+    Failed,
+}
+
+/// Creation job as served by `GET /jobs/{id}`
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Job {
+    id: String,
+    status: JobStatus,
+    account_id: String,
+    /// The account, its transaction hash and claim URL once created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<FormCreated>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<FormError>,
+    /// Unix timestamp
+    created_at: u64,
+    /// Unix timestamp the result became known at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finished_at: Option<u64>,
+}
+
+impl Job {
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Creation requests accepted by `POST /jobs` and created in the background, kept in memory of the process
+/// A limited number of them run at once, so a burst of jobs doesn't fail with `OVERLOADED` like the waiting requests
+pub(crate) struct Jobs {
+    jobs: Mutex<HashMap<String, Job>>,
+    running: Semaphore,
+    /// The jobs as they start and finish, pushed to the `/ws` clients following them
+    updates: broadcast::Sender<Job>,
+}
+
+impl Jobs {
+    pub(crate) fn new(concurrency: usize) -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            running: Semaphore::new(concurrency.max(1)),
+            updates: broadcast::channel(UPDATES_CAPACITY).0,
+        }
+    }
+
+    /// Runs the creation once one of the running slots is free, shared by the jobs and the bulk uploads
+    pub(crate) async fn run<F: Future>(&self, creation: F) -> F::Output {
+        // The semaphore is never closed
+        let _permit = self.running.acquire().await;
+        creation.await
+    }
+
+    /// Registers a queued job of the account, fails with `OVERLOADED` if too many are queued already
+    fn enqueue(&self, account_id: &str) -> anyhow::Result<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let queued = jobs
+            .values()
+            .filter(|job| job.status == JobStatus::Queued)
+            .count();
+        if queued >= MAX_QUEUED {
+            return Err(CodedError {
+                code: ErrorCode::Overloaded,
+                message: "too many creation jobs are queued, try again in a few seconds"
+                    .to_string(),
+            }
+            .into());
+        }
+        let job = Job {
+            id: hex::encode(rand::random::<[u8; 16]>()),
+            status: JobStatus::Queued,
+            account_id: account_id.to_string(),
+            result: None,
+            error: None,
+            created_at: now(),
+            finished_at: None,
+        };
+        jobs.insert(job.id.clone(), job.clone());
+        Ok(job)
+    }
+
+    fn start(&self, id: &str) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            job.status = JobStatus::Submitted;
+            self.publish(job);
+        }
+    }
+
+    /// Nobody following the job is fine, the changes aren't kept
+    fn publish(&self, job: &Job) {
+        let _ = self.updates.send(job.clone());
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<Job> {
+        self.updates.subscribe()
+    }
+
+    /// Stores the result of the job, a creation still pending past the deadline stays `submitted` with the `PENDING` error
+    fn finish(&self, id: &str, result: Result<FormCreated, FormError>) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            match result {
+                Ok(created) => {
+                    job.status = JobStatus::Succeeded;
+                    job.result = Some(created);
+                }
+                Err(error) => {
+                    if error.code != ErrorCode::Pending {
+                        job.status = JobStatus::Failed;
+                    }
+                    job.error = Some(error);
+                }
+            }
+            job.finished_at = Some(now());
+            self.publish(job);
+        }
+    }
+
+    pub(crate) fn get(&self, id: &str) -> Option<Job> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+
+    /// Forgets the jobs finished longer than the retention ago, returns how many were forgotten
+    pub(crate) fn purge_finished(&self) -> usize {
+        let cutoff = now().saturating_sub(RETENTION.as_secs());
+        let mut jobs = self.jobs.lock().unwrap();
+        let before = jobs.len();
+        jobs.retain(|_, job| {
+            job.finished_at
+                .map_or(true, |finished_at| finished_at > cutoff)
+        });
+        before - jobs.len()
+    }
+}
+
+/// Endpoint: /jobs
+/// Checks the JSON creation request like `/create_account` does, then admits and creates the account in the background
+/// Responds with `202` and the queued job, to be polled at the `Location` (JSON)
+pub(crate) async fn create_job_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+    query: web::Query<WaitQuery>,
+    body: web::Json<crate::FormData>,
+) -> impl Responder {
+    tracing::debug!("POST /jobs");
+    let checked = match crate::check_form(&req, &near, &body, crate::CheckMode::Json).await {
+        Ok(checked) => checked,
+        Err(failure) => return crate::form_json(Err(failure)),
+    };
+    let job = match near.jobs.enqueue(&checked.data.account_id) {
+        Ok(job) => job,
+        Err(err) => return crate::form_json(Err(err.into())),
+    };
+    tracing::debug!("Queued job {} creating {}", job.id, job.account_id);
+
+    let wait = query.wait.unwrap_or(near.default_wait);
+    let (near, id) = (near.get_ref().clone(), job.id.clone());
+    tokio::spawn(async move {
+        let result = near
+            .jobs
+            .run(async {
+                near.jobs.start(&id);
+                crate::create_checked(&near, checked, wait).await
+            })
+            .await
+            .map_err(crate::FormFailure::into_error);
+        near.jobs.finish(&id, result);
+    });
+    HttpResponse::Accepted()
+        .insert_header((header::LOCATION, format!("{}/{}", req.path(), job.id)))
+        .json(job)
+}
+
+/// Endpoint: /jobs/{id}
+/// Responds with the state of a creation job: `queued`, `submitted`, `succeeded` or `failed` (JSON)
+pub(crate) async fn job_handler(
+    near: web::Data<crate::NearData>,
+    id: web::Path<String>,
+) -> impl Responder {
+    tracing::debug!("GET /jobs/{}", id);
+    match near.jobs.get(&id) {
+        Some(job) => HttpResponse::Ok().json(job),
+        None => HttpResponse::NotFound().json(serde_json::json!({
+            "result": null,
+            "error": {
+                "code": ErrorCode::NotFound,
+                "message": format!("job {} is not known to this faucet", id),
+            },
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_the_job_states() {
+        let jobs = Jobs::new(1);
+        let job = jobs.enqueue("alice.testnet").unwrap();
+        assert_eq!(jobs.get(&job.id).unwrap().status, JobStatus::Queued);
+        jobs.start(&job.id);
+        assert_eq!(jobs.get(&job.id).unwrap().status, JobStatus::Submitted);
+
+        jobs.finish(
+            &job.id,
+            Err(FormError {
+                code: ErrorCode::AccountExists,
+                message: "taken".to_string(),
+                fields: None,
+                retry_after_secs: None,
+                tx_hash: None,
+                claim_url: None,
+            }),
+        );
+        let finished = jobs.get(&job.id).unwrap();
+        assert_eq!(finished.status, JobStatus::Failed);
+        assert_eq!(finished.error.unwrap().code, ErrorCode::AccountExists);
+        assert_eq!(jobs.purge_finished(), 0);
+    }
+}
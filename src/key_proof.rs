@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::{web, HttpResponse, Responder};
+use near_crypto::{PublicKey, Signature};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::errors::{CodedError, ErrorCode};
+
+/// How long the client has to sign the challenge
+const CHALLENGE_TTL: Duration = Duration::from_secs(300);
+/// Most challenges outstanding at once, so the endpoint can't be used to fill the memory
+const MAX_CHALLENGES: usize = 100_000;
+
+fn failed(message: impl Into<String>) -> anyhow::Error {
+    CodedError {
+        code: ErrorCode::KeyProofFailed,
+        message: message.into(),
+    }
+    .into()
+}
+
+/// Challenges proving the requester holds the secret key of the public key given to the account
+/// Catches the typos in the keys and the requests squatting names with someone else's key
+#[derive(Debug, Default)]
+pub(crate) struct KeyProofs {
+    /// Outstanding challenges, each bound to the key it was issued for
+    challenges: Mutex<HashMap<String, (PublicKey, Instant)>>,
+}
+
+impl KeyProofs {
+    /// Issues a random challenge for the key, to be signed and sent back with the creation request
+    fn issue(&self, public_key: PublicKey) -> anyhow::Result<String> {
+        let challenge = hex::encode(rand::random::<[u8; 32]>());
+        let now = Instant::now();
+        let mut challenges = self.challenges.lock().unwrap();
+        if challenges.len() >= MAX_CHALLENGES {
+            challenges.retain(|_, (_, issued_at)| now.duration_since(*issued_at) < CHALLENGE_TTL);
+            if challenges.len() >= MAX_CHALLENGES {
+                return Err(CodedError {
+                    code: ErrorCode::Overloaded,
+                    message: "too many key challenges are outstanding, try again in a few minutes"
+                        .to_string(),
+                }
+                .into());
+            }
+        }
+        challenges.insert(challenge.clone(), (public_key, now));
+        Ok(challenge)
+    }
+
+    /// Checks the signature of the challenge issued for the key, the challenge is used up either way
+    /// The signed message is the SHA-256 hash of the challenge string, as secp256k1 keys only sign hashes
+    pub(crate) fn verify(
+        &self,
+        public_key: &str,
+        challenge: Option<&str>,
+        signature: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let (Some(challenge), Some(signature)) = (
+            challenge.map(str::trim).filter(|c| !c.is_empty()),
+            signature.map(str::trim).filter(|s| !s.is_empty()),
+        ) else {
+            return Err(failed(
+                "sign a key challenge from /key-challenge to prove you hold the key",
+            ));
+        };
+        let issued = self.challenges.lock().unwrap().remove(challenge);
+        let issued_key = match issued {
+            Some((issued_key, issued_at)) if issued_at.elapsed() < CHALLENGE_TTL => issued_key,
+            _ => return Err(failed("the key challenge is unknown or has expired")),
+        };
+        let public_key: PublicKey = public_key
+            .parse()
+            .map_err(|_| failed("the public key is invalid"))?;
+        if issued_key != public_key {
+            return Err(failed("the key challenge was issued for another key"));
+        }
+        let signature: Signature = signature
+            .parse()
+            .map_err(|_| failed("the signature is malformed"))?;
+        match signature.verify(&Sha256::digest(challenge.as_bytes()), &public_key) {
+            true => Ok(()),
+            false => Err(failed(
+                "the signature of the key challenge doesn't match the public key",
+            )),
+        }
+    }
+
+    /// Forgets the challenges that have expired, returns how many were forgotten
+    pub(crate) fn purge_expired(&self) -> usize {
+        let mut challenges = self.challenges.lock().unwrap();
+        let before = challenges.len();
+        challenges.retain(|_, (_, issued_at)| issued_at.elapsed() < CHALLENGE_TTL);
+        before - challenges.len()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ChallengeRequest {
+    public_key: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ChallengeResponse {
+    challenge: String,
+    expires_in_secs: u64,
+}
+
+/// Endpoint: /key-challenge
+/// Issues a challenge for `{public_key}`, whose SHA-256 hash the client signs with the key (JSON)
+pub(crate) async fn challenge_handler(
+    near: web::Data<crate::NearData>,
+    request: web::Json<ChallengeRequest>,
+) -> impl Responder {
+    tracing::debug!("POST /key-challenge");
+    let error = |code: ErrorCode, message: String| {
+        serde_json::json!({
+            "result": null,
+            "error": { "code": code, "message": message },
+        })
+    };
+    let Some(key_proofs) = &near.key_proofs else {
+        return HttpResponse::NotFound().json(error(
+            ErrorCode::NotFound,
+            "the key ownership proofs are not enabled".to_string(),
+        ));
+    };
+    let Ok(public_key) = request.public_key.trim().parse::<PublicKey>() else {
+        return HttpResponse::BadRequest().json(error(
+            ErrorCode::InvalidPublicKey,
+            "the public key is invalid".to_string(),
+        ));
+    };
+    match key_proofs.issue(public_key) {
+        Ok(challenge) => HttpResponse::Ok().json(ChallengeResponse {
+            challenge,
+            expires_in_secs: CHALLENGE_TTL.as_secs(),
+        }),
+        Err(err) => HttpResponse::ServiceUnavailable()
+            .json(error(ErrorCode::classify(&err), err.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use near_crypto::{KeyType, SecretKey};
+
+    use super::*;
+
+    #[test]
+    fn verifies_the_signature_of_the_issued_challenge() {
+        let proofs = KeyProofs::default();
+        for key_type in [KeyType::ED25519, KeyType::SECP256K1] {
+            let secret_key = SecretKey::from_random(key_type);
+            let public_key = secret_key.public_key();
+            let challenge = proofs.issue(public_key.clone()).unwrap();
+            let signature = secret_key
+                .sign(&Sha256::digest(challenge.as_bytes()))
+                .to_string();
+            proofs
+                .verify(&public_key.to_string(), Some(&challenge), Some(&signature))
+                .unwrap();
+            // Used up
+            let err = proofs
+                .verify(&public_key.to_string(), Some(&challenge), Some(&signature))
+                .unwrap_err();
+            assert_eq!(ErrorCode::classify(&err), ErrorCode::KeyProofFailed);
+        }
+    }
+
+    #[test]
+    fn rejects_the_signatures_of_other_keys() {
+        let proofs = KeyProofs::default();
+        let secret_key = SecretKey::from_random(KeyType::ED25519);
+        let other_key = SecretKey::from_random(KeyType::ED25519);
+        let challenge = proofs.issue(secret_key.public_key()).unwrap();
+        let signature = other_key
+            .sign(&Sha256::digest(challenge.as_bytes()))
+            .to_string();
+        let err = proofs
+            .verify(
+                &secret_key.public_key().to_string(),
+                Some(&challenge),
+                Some(&signature),
+            )
+            .unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::KeyProofFailed);
+    }
+}
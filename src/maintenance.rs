@@ -0,0 +1,165 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::errors::{CodedError, ErrorCode};
+
+/// Why and since when the creations are paused
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Pause {
+    /// Shown to the users along with the error, if set
+    pub(crate) reason: Option<String>,
+    /// API key or token name of the admin who paused the faucet
+    pub(crate) paused_by: String,
+    /// Unix timestamp
+    pub(crate) paused_at: u64,
+}
+
+/// Maintenance mode the admins switch on during incidents, refusing the creations without stopping the process
+/// The flag is kept in memory, every replica has to be paused on its own and is running again after a restart
+#[derive(Debug, Clone)]
+pub(crate) struct Maintenance {
+    pause: Arc<watch::Sender<Option<Pause>>>,
+}
+
+impl Default for Maintenance {
+    fn default() -> Self {
+        Self {
+            pause: Arc::new(watch::channel(None).0),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct PauseRequest {
+    reason: Option<String>,
+}
+
+impl Maintenance {
+    /// The current pause, `None` while the faucet is running
+    pub(crate) fn current(&self) -> Option<Pause> {
+        self.pause.borrow().clone()
+    }
+
+    /// Pauses the creations, replacing the reason of an earlier pause
+    pub(crate) fn pause(&self, paused_by: String, reason: Option<String>) -> Pause {
+        let pause = Pause {
+            reason: reason.filter(|reason| !reason.trim().is_empty()),
+            paused_by,
+            paused_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        };
+        self.pause.send_replace(Some(pause.clone()));
+        pause
+    }
+
+    /// Resumes the creations, returns the pause that ended if there was one
+    pub(crate) fn resume(&self) -> Option<Pause> {
+        let mut ended = None;
+        // Resuming a running faucet announces nothing
+        self.pause.send_if_modified(|pause| {
+            ended = pause.take();
+            ended.is_some()
+        });
+        ended
+    }
+
+    /// Follows the pauses and the resumptions, announced to the `/ws` clients
+    pub(crate) fn subscribe(&self) -> watch::Receiver<Option<Pause>> {
+        self.pause.subscribe()
+    }
+
+    /// Fails with `FAUCET_PAUSED` while the faucet is paused
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        match self.current() {
+            None => Ok(()),
+            Some(pause) => Err(CodedError {
+                code: ErrorCode::FaucetPaused,
+                message: match pause.reason {
+                    Some(reason) => format!(
+                        "the faucet is temporarily disabled ({}), please try again later",
+                        reason
+                    ),
+                    None => {
+                        "the faucet is temporarily disabled, please try again later".to_string()
+                    }
+                },
+            }
+            .into()),
+        }
+    }
+}
+
+/// Endpoint: /admin/pause
+/// Pauses the creations of this process with an optional `{reason}` shown to the users (JSON)
+pub(crate) async fn pause_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+    body: Option<web::Json<PauseRequest>>,
+) -> impl Responder {
+    tracing::debug!("POST /admin/pause");
+    let admin = match near.abuse.admin_of(&req) {
+        Ok(admin) => admin,
+        Err(err) => return crate::abuse::error_response(&err),
+    };
+    let reason = body
+        .map(|body| body.into_inner())
+        .unwrap_or_default()
+        .reason;
+    let pause = near.maintenance.pause(admin, reason);
+    tracing::warn!(
+        "Creations paused by {}: {}",
+        pause.paused_by,
+        pause.reason.as_deref().unwrap_or("no reason given")
+    );
+    HttpResponse::Ok().json(serde_json::json!({ "paused": true, "pause": pause }))
+}
+
+/// Endpoint: /admin/resume
+/// Resumes the creations of this process (JSON)
+pub(crate) async fn resume_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+) -> impl Responder {
+    tracing::debug!("POST /admin/resume");
+    let admin = match near.abuse.admin_of(&req) {
+        Ok(admin) => admin,
+        Err(err) => return crate::abuse::error_response(&err),
+    };
+    let ended = near.maintenance.resume();
+    if ended.is_some() {
+        tracing::warn!("Creations resumed by {}", admin);
+    }
+    HttpResponse::Ok().json(serde_json::json!({ "paused": false, "pause": ended }))
+}
+
+pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/admin/pause", web::post().to(pause_handler))
+        .route("/admin/resume", web::post().to(resume_handler));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_the_creations_while_paused() {
+        let maintenance = Maintenance::default();
+        maintenance.check().unwrap();
+
+        let shared = maintenance.clone();
+        shared.pause("admin".to_string(), Some("RPC incident".to_string()));
+        let err = maintenance.check().unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::FaucetPaused);
+        assert!(err.to_string().contains("RPC incident"));
+
+        assert_eq!(maintenance.resume().unwrap().paused_by, "admin");
+        maintenance.check().unwrap();
+        assert!(maintenance.resume().is_none());
+    }
+}
@@ -0,0 +1,194 @@
+use actix_web::{HttpResponse, Responder};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_gauge, register_gauge_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, register_int_gauge_vec, Encoder, Gauge, GaugeVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+
+use crate::create_account::RequestOrigin;
+use crate::errors::ErrorCode;
+use crate::utils::send_tx::Submitted;
+
+/// Labels attributing the creations to the channel they came through
+const CREATION_LABELS: &[&str] = &["entry_point", "tenant", "suffix"];
+
+/// yoctoNEAR in one NEAR, the disbursement gauge is in NEAR to stay readable as a float
+pub(crate) const YOCTO_PER_NEAR: f64 = 1e24;
+
+pub(crate) static ACCOUNTS_CREATED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "sw4_accounts_created_total",
+        "Number of successfully created accounts",
+        CREATION_LABELS
+    )
+    .unwrap()
+});
+
+pub(crate) static ACCOUNT_CREATION_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "sw4_account_creation_failures_total",
+        "Number of failed account creations",
+        CREATION_LABELS
+    )
+    .unwrap()
+});
+
+pub(crate) static NEAR_DISBURSED: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "sw4_near_disbursed",
+        "Amount of NEAR transferred to the created accounts since the start",
+        CREATION_LABELS
+    )
+    .unwrap()
+});
+
+/// Compared with `--daily-disbursement-cap`, which pauses the creations once it's exceeded
+pub(crate) static NEAR_DISBURSED_LAST_DAY: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "sw4_near_disbursed_last_day",
+        "Amount of NEAR transferred to the created accounts by this process in the last 24 hours"
+    )
+    .unwrap()
+});
+
+pub(crate) static JANITOR_CLEANED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "sw4_janitor_cleaned_total",
+        "Number of stale or expired items cleaned up by the janitor",
+        &["kind"]
+    )
+    .unwrap()
+});
+
+pub(crate) static LATEST_BLOCK_HEIGHT: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "sw4_latest_block_height",
+        "Height of the latest block the transactions reference"
+    )
+    .unwrap()
+});
+
+/// The block hash age is `time() - sw4_block_fetched_timestamp_seconds`
+pub(crate) static BLOCK_FETCHED_AT: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "sw4_block_fetched_timestamp_seconds",
+        "Unix timestamp the latest block was fetched at"
+    )
+    .unwrap()
+});
+
+/// 0 while the endpoint is out of the rotation after repeated failures
+pub(crate) static RPC_ENDPOINT_UP: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "sw4_rpc_endpoint_up",
+        "Whether the NEAR RPC endpoint is in the rotation",
+        &["endpoint"]
+    )
+    .unwrap()
+});
+
+/// 1 while the calls to the NEAR RPC fail fast
+pub(crate) static RPC_CIRCUIT_OPEN: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "sw4_rpc_circuit_open",
+        "Whether the circuit breaker of the NEAR RPC is open"
+    )
+    .unwrap()
+});
+
+/// 1 while the cached block hash is older than `--block-hash-stale-secs`, the updater is stuck
+pub(crate) static BLOCK_HASH_STALE: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "sw4_block_hash_stale",
+        "Whether the block hash the transactions reference is stale"
+    )
+    .unwrap()
+});
+
+/// `result` is `success` or the error code of the failure
+pub(crate) static TRANSACTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "sw4_creation_transactions_total",
+        "Number of account creations submitted by this process, by their result",
+        &["result"]
+    )
+    .unwrap()
+});
+
+pub(crate) static NONCE_RETRIES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "sw4_nonce_retries_total",
+        "Number of transactions signed again after their nonce was rejected"
+    )
+    .unwrap()
+});
+
+pub(crate) static EXPIRED_RETRIES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "sw4_expired_retries_total",
+        "Number of transactions signed again with a fresh block hash after they expired"
+    )
+    .unwrap()
+});
+
+pub(crate) static SUBMISSION_QUEUE_DEPTH: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "sw4_submission_queue_depth",
+        "Number of creations waiting for a submission worker"
+    )
+    .unwrap()
+});
+
+/// Returns the parent account of the given account id, e.g. `statelessnet` for `alice.statelessnet`
+fn suffix(account_id: &str) -> &str {
+    account_id
+        .split_once('.')
+        .map(|(_, parent)| parent)
+        .unwrap_or_default()
+}
+
+/// Records the result of an account creation broadcast by this process
+pub(crate) fn record_creation(
+    origin: &RequestOrigin,
+    account_id: &str,
+    funding_amount: u128,
+    success: bool,
+) {
+    let labels = [
+        origin.entry_point.as_str(),
+        origin.tenant.as_str(),
+        suffix(account_id),
+    ];
+    if success {
+        ACCOUNTS_CREATED.with_label_values(&labels).inc();
+        NEAR_DISBURSED
+            .with_label_values(&labels)
+            .add(funding_amount as f64 / YOCTO_PER_NEAR);
+    } else {
+        ACCOUNT_CREATION_FAILURES.with_label_values(&labels).inc();
+    }
+}
+
+/// Records the result of a creation submitted by this process, whichever entry point it came through
+pub(crate) fn record_transaction(result: &anyhow::Result<Submitted>) {
+    let label = match result {
+        Ok(_) => "success",
+        Err(err) => ErrorCode::classify(err).as_str(),
+    };
+    TRANSACTIONS.with_label_values(&[label]).inc();
+}
+
+/// Endpoint: /metrics
+/// Responds with all the registered metrics in the Prometheus text format
+pub(crate) async fn metrics_handler() -> impl Responder {
+    let mut buffer = vec![];
+    let encoder = TextEncoder::new();
+    if let Err(err) = encoder.encode(&prometheus::gather(), &mut buffer) {
+        tracing::warn!("Failed to encode metrics: {:?}", err);
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(buffer)
+}
@@ -0,0 +1,96 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::time::Instant;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpMessage};
+use tracing::Instrument;
+
+use crate::middleware::client_ip::client_ip;
+use crate::middleware::request_id::RequestId;
+
+/// Name of the root span field holding the hashes of the transactions sent while serving the request
+/// Recorded by `TxSubmitter::create_account` for every attempt, including the retried ones
+pub(crate) const TX_HASHES_FIELD: &str = "tx_hashes";
+
+/// Middleware wrapping every request into a root `request` span and emitting an access log entry once it's served
+/// Must run inside the `RequestIdMiddleware` and the `ClientIpMiddleware` to pick up the request ID and the client address
+pub(crate) struct AccessLogMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for AccessLogMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = AccessLogService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AccessLogService { service }))
+    }
+}
+
+pub(crate) struct AccessLogService<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for AccessLogService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let request_id = req
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.0.clone())
+            .unwrap_or_default();
+        let client_ip = client_ip(req.request())
+            .map(|ip| ip.to_string())
+            .unwrap_or_default();
+        let method = req.method().to_string();
+        let path = req.path().to_string();
+        let span = tracing::info_span!(
+            "request",
+            request_id = %request_id,
+            client_ip = %client_ip,
+            method = %method,
+            path = %path,
+            tx_hashes = tracing::field::Empty,
+        );
+        let started = Instant::now();
+
+        let fut = {
+            let _entered = span.enter();
+            self.service.call(req)
+        };
+        Box::pin(
+            async move {
+                let res = fut.await;
+                let status = match &res {
+                    Ok(res) => res.status().as_u16(),
+                    Err(err) => err.as_response_error().status_code().as_u16(),
+                };
+                tracing::info!(
+                    target: "access_log",
+                    status,
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "{} {}",
+                    method,
+                    path,
+                );
+                res
+            }
+            .instrument(span),
+        )
+    }
+}
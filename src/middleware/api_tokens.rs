@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
+use std::path::Path;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header;
+use actix_web::{web, Error, HttpMessage, HttpRequest, HttpResponse, Responder};
+use anyhow::Context as _;
+use near_primitives::types::Balance;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::errors::ErrorCode;
+use crate::middleware::rate_limit::{self, RateLimit};
+use crate::quota::{Identity, QuotaLimits, QuotaStatus};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Token as listed in the tokens file
+#[derive(Debug, Deserialize)]
+struct TokenConfig {
+    /// Name the requests are attributed to, e.g. the wallet using the token
+    name: String,
+    token: String,
+    /// Requests per minute, unlimited if not set
+    per_minute: Option<u32>,
+    /// Accounts created per day (UTC), unlimited if not set
+    daily_limit: Option<u32>,
+    /// Whether the token may use the admin endpoints, e.g. from the incident runbooks
+    #[serde(default)]
+    admin: bool,
+    /// Funding of the accounts created with the token in yoctoNEAR, as a string, `FUNDING_AMOUNT` if not set
+    funding_amount: Option<String>,
+}
+
+#[derive(Debug)]
+struct Token {
+    name: String,
+    admin: bool,
+    funding_amount: Option<Balance>,
+    per_minute: Option<u32>,
+    daily_limit: Option<u32>,
+    /// Start of the current minute and the requests made in it
+    window: Mutex<(Instant, u32)>,
+    requests: AtomicU64,
+    rate_limited: AtomicU64,
+}
+
+/// Name of the token the request was authenticated with
+/// Inserted into the request extensions, so the handlers can attribute the request to the client
+#[derive(Debug, Clone)]
+pub(crate) struct AuthenticatedToken(pub(crate) String);
+
+/// Funding of the accounts created with the token the request was authenticated with, if it has its own
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TokenFundingAmount(pub(crate) Balance);
+
+/// Name of the admin token the request was authenticated with, allowing the admin endpoints
+#[derive(Debug, Clone)]
+pub(crate) struct AdminToken(pub(crate) String);
+
+/// Bearer tokens of the programmatic clients, each with its own rate limit and daily creation quota
+/// Looked up by the hash of the token, so the tokens aren't compared byte by byte
+pub(crate) struct ApiTokens {
+    tokens: HashMap<[u8; 32], Token>,
+    #[cfg(feature = "shared-limits")]
+    shared: Option<Arc<crate::shared_limits::SharedLimits>>,
+}
+
+/// Usage of a token as reported by `GET /admin/tokens`
+#[derive(Debug, Serialize)]
+struct TokenUsage {
+    name: String,
+    per_minute: Option<u32>,
+    /// In yoctoNEAR, `null` if the token funds the configured amount
+    funding_amount: Option<String>,
+    /// Requests since the start of the process
+    requests: u64,
+    rate_limited: u64,
+    quota: QuotaStatus,
+}
+
+impl ApiTokens {
+    /// Reads the JSON list of `{name, token, per_minute, daily_limit, admin, funding_amount}` tokens
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::read_to_string(path)
+            .with_context(|| format!("failed reading API tokens file {}", path.display()))?;
+        let configs: Vec<TokenConfig> = serde_json::from_str(&file)
+            .with_context(|| format!("failed parsing {}", path.display()))?;
+        let mut tokens = HashMap::new();
+        for config in configs {
+            let funding_amount = config
+                .funding_amount
+                .map(|amount| {
+                    amount.trim().parse::<Balance>().with_context(|| {
+                        format!(
+                            "{}: invalid funding amount {} of token {}",
+                            path.display(),
+                            amount,
+                            config.name
+                        )
+                    })
+                })
+                .transpose()?;
+            let token = Token {
+                name: config.name,
+                admin: config.admin,
+                funding_amount,
+                per_minute: config.per_minute,
+                daily_limit: config.daily_limit,
+                window: Mutex::new((Instant::now(), 0)),
+                requests: AtomicU64::new(0),
+                rate_limited: AtomicU64::new(0),
+            };
+            if tokens.insert(hash(&config.token), token).is_some() {
+                anyhow::bail!("{}: the same token is listed twice", path.display());
+            }
+        }
+        tracing::info!("Loaded {} API tokens", tokens.len());
+        Ok(Self {
+            tokens,
+            #[cfg(feature = "shared-limits")]
+            shared: None,
+        })
+    }
+
+    /// Lowers the funding of the tokens over the global maximum to it
+    pub(crate) fn with_max_funding_amount(mut self, max: Balance) -> Self {
+        for token in self.tokens.values_mut() {
+            match token.funding_amount {
+                Some(amount) if amount > max => {
+                    tracing::warn!(
+                        "Funding of API token {} lowered to the maximum of {} yoctoNEAR",
+                        token.name,
+                        max
+                    );
+                    token.funding_amount = Some(max);
+                }
+                _ => {}
+            }
+        }
+        self
+    }
+
+    #[cfg(feature = "shared-limits")]
+    /// Keeps the per-minute windows in Redis, shared by all the replicas
+    pub(crate) fn with_shared(mut self, shared: Arc<crate::shared_limits::SharedLimits>) -> Self {
+        self.shared = Some(shared);
+        self
+    }
+
+    /// Daily quotas of the tokens, enforced by the `QuotaStore` along with the other identities
+    pub(crate) fn quota_limits(&self) -> HashMap<Identity, QuotaLimits> {
+        self.tokens
+            .values()
+            .map(|token| {
+                (
+                    Identity::api_token(&token.name),
+                    QuotaLimits {
+                        daily: token.daily_limit,
+                        weekly: None,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Counts the request of the token, returns the state of its rate limit, as an error if over it
+    async fn admit(&self, token: &Token) -> Result<Option<RateLimit>, RateLimit> {
+        token.requests.fetch_add(1, Ordering::Relaxed);
+        let Some(per_minute) = token.per_minute else {
+            return Ok(None);
+        };
+        #[cfg(feature = "shared-limits")]
+        if let Some(shared) = &self.shared {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default();
+            let minute = now.as_secs() / RATE_WINDOW.as_secs();
+            let key = format!("api_token:{}:minute:{}", token.name, minute);
+            let counter = crate::shared_limits::Counter {
+                key: key.clone(),
+                limit: Some(per_minute),
+                ttl: RATE_WINDOW,
+            };
+            let reset = RATE_WINDOW
+                .saturating_sub(Duration::from_secs(now.as_secs() % RATE_WINDOW.as_secs()));
+            match shared.acquire(&[counter]).await {
+                Some(Ok(())) => {
+                    // The other replicas count in the same window, so the count is read back
+                    let used = shared.count(&key).await.unwrap_or(per_minute);
+                    return Ok(Some(Self::window_state(per_minute, used, reset, false)));
+                }
+                Some(Err(_)) => {
+                    token.rate_limited.fetch_add(1, Ordering::Relaxed);
+                    return Err(Self::window_state(per_minute, per_minute, reset, true));
+                }
+                None => {}
+            }
+        }
+        self.admit_local(token, per_minute).map(Some)
+    }
+
+    fn admit_local(&self, token: &Token, per_minute: u32) -> Result<RateLimit, RateLimit> {
+        let now = Instant::now();
+        let mut window = token.window.lock().unwrap();
+        if now.duration_since(window.0) >= RATE_WINDOW {
+            *window = (now, 0);
+        }
+        let reset = RATE_WINDOW.saturating_sub(now.duration_since(window.0));
+        if window.1 >= per_minute {
+            token.rate_limited.fetch_add(1, Ordering::Relaxed);
+            return Err(Self::window_state(per_minute, window.1, reset, true));
+        }
+        window.1 += 1;
+        Ok(Self::window_state(per_minute, window.1, reset, false))
+    }
+
+    /// State of a window with `used` of the `per_minute` requests
+    fn window_state(per_minute: u32, used: u32, reset: Duration, refused: bool) -> RateLimit {
+        RateLimit {
+            limit: per_minute,
+            remaining: per_minute.saturating_sub(used),
+            reset,
+            refused,
+        }
+    }
+}
+
+fn hash(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+/// Middleware authenticating the requests with an `Authorization: Bearer <token>` header
+/// Requests without the header are passed through, unknown tokens get `401` and those over their rate `429`
+pub(crate) struct ApiTokenMiddleware {
+    pub(crate) tokens: Option<Arc<ApiTokens>>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ApiTokenMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = ApiTokenService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiTokenService {
+            service: Rc::new(service),
+            tokens: self.tokens.clone(),
+        }))
+    }
+}
+
+pub(crate) struct ApiTokenService<S> {
+    service: Rc<S>,
+    tokens: Option<Arc<ApiTokens>>,
+}
+
+impl<S, B> Service<ServiceRequest> for ApiTokenService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let bearer = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim);
+        let (Some(tokens), Some(bearer)) = (&self.tokens, bearer) else {
+            let service = self.service.clone();
+            return Box::pin(async move { Ok(service.call(req).await?.map_into_boxed_body()) });
+        };
+
+        let key = hash(bearer);
+        if !tokens.tokens.contains_key(&key) {
+            let response = HttpResponse::Unauthorized().json(serde_json::json!({
+                "result": null,
+                "error": { "code": ErrorCode::Unauthorized, "message": "unknown API token" },
+            }));
+            return Box::pin(async move { Ok(req.into_response(response)) });
+        }
+
+        let service = self.service.clone();
+        let tokens = tokens.clone();
+        Box::pin(async move {
+            let token = &tokens.tokens[&key];
+            match tokens.admit(token).await {
+                Ok(Some(limit)) => rate_limit::report(&req, limit),
+                Ok(None) => {}
+                Err(limit) => {
+                    tracing::warn!("Rate limited request of API token {}", token.name);
+                    rate_limit::report(&req, limit);
+                    let retry_after = limit.reset.as_secs().max(1);
+                    let response = HttpResponse::TooManyRequests().json(serde_json::json!({
+                        "result": null,
+                        "error": {
+                            "code": ErrorCode::RateLimited,
+                            "message": format!("the API token is over its rate limit, try again in {} seconds", retry_after),
+                        },
+                    }));
+                    let mut res = req.into_response(response);
+                    rate_limit::insert_headers(&mut res);
+                    return Ok(res);
+                }
+            }
+            req.extensions_mut()
+                .insert(AuthenticatedToken(token.name.clone()));
+            if token.admin {
+                req.extensions_mut().insert(AdminToken(token.name.clone()));
+            }
+            if let Some(amount) = token.funding_amount {
+                req.extensions_mut().insert(TokenFundingAmount(amount));
+            }
+            let mut res = service.call(req).await?.map_into_boxed_body();
+            rate_limit::insert_headers(&mut res);
+            Ok(res)
+        })
+    }
+}
+
+/// Endpoint: /admin/tokens
+/// Responds with the requests and the creation quota usage of every API token (JSON)
+pub(crate) async fn tokens_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+) -> impl Responder {
+    tracing::debug!("GET /admin/tokens");
+    if let Err(err) = near.abuse.admin_of(&req) {
+        return crate::abuse::error_response(&err);
+    }
+    let mut usage = Vec::new();
+    for token in near
+        .api_tokens
+        .iter()
+        .flat_map(|tokens| tokens.tokens.values())
+    {
+        usage.push(TokenUsage {
+            name: token.name.clone(),
+            per_minute: token.per_minute,
+            funding_amount: token.funding_amount.map(|amount| amount.to_string()),
+            requests: token.requests.load(Ordering::Relaxed),
+            rate_limited: token.rate_limited.load(Ordering::Relaxed),
+            quota: near.quotas.status(&Identity::api_token(&token.name)).await,
+        });
+    }
+    usage.sort_by(|a, b| a.name.cmp(&b.name));
+    HttpResponse::Ok().json(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn limits_the_requests_per_minute() {
+        let token = Token {
+            name: "wallet".to_string(),
+            admin: false,
+            funding_amount: None,
+            per_minute: Some(2),
+            daily_limit: None,
+            window: Mutex::new((Instant::now(), 0)),
+            requests: AtomicU64::new(0),
+            rate_limited: AtomicU64::new(0),
+        };
+        let tokens = ApiTokens {
+            tokens: HashMap::new(),
+            #[cfg(feature = "shared-limits")]
+            shared: None,
+        };
+        let first = tokens.admit(&token).await.unwrap().unwrap();
+        assert_eq!((first.limit, first.remaining), (2, 1));
+        assert!(tokens.admit(&token).await.is_ok());
+        assert!(tokens.admit(&token).await.unwrap_err().refused);
+        assert_eq!(token.requests.load(Ordering::Relaxed), 3);
+        assert_eq!(token.rate_limited.load(Ordering::Relaxed), 1);
+    }
+}
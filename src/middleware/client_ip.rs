@@ -0,0 +1,187 @@
+use std::future::{ready, Ready};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderMap};
+use actix_web::{Error, HttpMessage, HttpRequest};
+use ipnet::IpNet;
+
+/// Address of the client the request came from, past the trusted proxies
+/// Inserted into the request extensions by the `ClientIpMiddleware`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ClientIp(pub(crate) IpAddr);
+
+/// Proxies in front of the service whose forwarding headers are believed, e.g. the load balancer
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TrustedProxies(Vec<IpNet>);
+
+impl TrustedProxies {
+    pub(crate) fn new(nets: Vec<IpNet>) -> Self {
+        Self(nets)
+    }
+
+    fn trusts(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|net| net.contains(ip))
+    }
+
+    /// Resolves the client address of a request made by the peer
+    /// The forwarded chain is walked from the nearest hop, the first untrusted address is the client,
+    /// so a client can't forge its address by sending the headers itself
+    pub(crate) fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        if !self.trusts(&peer) {
+            return peer;
+        }
+        let chain = forwarded_for(headers);
+        chain
+            .iter()
+            .rev()
+            .find(|ip| !self.trusts(ip))
+            // Every hop is a trusted proxy, the farthest one is as close to the client as it gets
+            .or(chain.first())
+            .copied()
+            .unwrap_or(peer)
+    }
+}
+
+/// Addresses of the `Forwarded` header if present, of `X-Forwarded-For` otherwise, from the farthest hop
+/// Obfuscated and unknown hops can't be resolved, they end the chain
+fn forwarded_for(headers: &HeaderMap) -> Vec<IpAddr> {
+    let mut chain = Vec::new();
+    if headers.contains_key(header::FORWARDED) {
+        let elements = headers
+            .get_all(header::FORWARDED)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for element in elements {
+            let node = element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for").then_some(value)
+            });
+            match node.and_then(parse_node) {
+                Some(ip) => chain.push(ip),
+                None => chain.clear(),
+            }
+        }
+    } else {
+        let hops = headers
+            .get_all("x-forwarded-for")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+        for hop in hops {
+            match parse_node(hop) {
+                Some(ip) => chain.push(ip),
+                None => chain.clear(),
+            }
+        }
+    }
+    chain
+}
+
+/// Parses a hop such as `203.0.113.7`, `"[2001:db8::1]:4711"` or `198.51.100.1:80`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            node.strip_prefix('[')
+                .and_then(|node| node.strip_suffix(']'))
+                .and_then(|node| node.parse().ok())
+        })
+}
+
+/// Client address of the request, the peer address if the `ClientIpMiddleware` didn't resolve one
+pub(crate) fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    req.extensions()
+        .get::<ClientIp>()
+        .map(|ip| ip.0)
+        .or_else(|| req.peer_addr().map(|addr| addr.ip()))
+}
+
+/// Middleware resolving the client address of every request for the IP filter, the rate limits and the logs
+/// Must run before all of them
+pub(crate) struct ClientIpMiddleware {
+    pub(crate) proxies: Arc<TrustedProxies>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ClientIpMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = ClientIpService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ClientIpService {
+            service,
+            proxies: self.proxies.clone(),
+        }))
+    }
+}
+
+pub(crate) struct ClientIpService<S> {
+    service: S,
+    proxies: Arc<TrustedProxies>,
+}
+
+impl<S, B> Service<ServiceRequest> for ClientIpService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = S::Future;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if let Some(peer) = req.peer_addr() {
+            let ip = self.proxies.resolve(peer.ip(), req.headers());
+            req.extensions_mut().insert(ClientIp(ip));
+        }
+        self.service.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn believes_only_the_trusted_proxies() {
+        let proxies = TrustedProxies::new(vec!["10.0.0.0/8".parse().unwrap()]);
+        let lb: IpAddr = "10.0.0.2".parse().unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+
+        // The spoofed hop sent by the client comes before the one appended by the load balancer
+        let forwarded = headers(&[("x-forwarded-for", "198.51.100.1, 203.0.113.7")]);
+        assert_eq!(proxies.resolve(lb, &forwarded), client);
+        assert_eq!(proxies.resolve(client, &forwarded), client);
+
+        let forwarded = headers(&[("forwarded", "for=\"[2001:db8::1]:4711\", for=10.0.0.3")]);
+        assert_eq!(
+            proxies.resolve(lb, &forwarded),
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+        assert_eq!(proxies.resolve(lb, &HeaderMap::new()), lb);
+    }
+}
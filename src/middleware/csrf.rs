@@ -0,0 +1,192 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::Method;
+use actix_web::{Error, HttpMessage, HttpRequest, HttpResponse};
+
+use crate::errors::{CodedError, ErrorCode};
+
+/// Cookie carrying the CSRF token of the browser session
+const CSRF_COOKIE: &str = "csrf_token";
+/// Pages rendering the protected forms, the token is issued when they are loaded
+const FORM_PAGES: [&str; 1] = ["/"];
+/// Form submissions checked against the token, the JSON API, the JSON bodies and the widget embedded on other sites are exempt
+const PROTECTED_PATHS: [&str; 2] = ["/create_account", "/auth/email"];
+
+/// CSRF token of the browser session, embedded in the forms and compared with the submitted one by the handlers
+/// Inserted into the request extensions by the `CsrfMiddleware`
+#[derive(Debug, Clone)]
+pub(crate) struct CsrfToken(pub(crate) String);
+
+fn is_token(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Token to embed in the form of the page, empty if the middleware didn't run
+pub(crate) fn token_of(req: &HttpRequest) -> String {
+    req.extensions()
+        .get::<CsrfToken>()
+        .map(|token| token.0.clone())
+        .unwrap_or_default()
+}
+
+/// Fails with `FORBIDDEN` unless the form carries the token of the session's cookie
+pub(crate) fn verify(req: &HttpRequest, submitted: Option<&str>) -> anyhow::Result<()> {
+    let expected = req
+        .cookie(CSRF_COOKIE)
+        .map(|cookie| cookie.value().to_string());
+    let matches = match (expected, submitted) {
+        // Compared in constant time, so the token can't be guessed byte by byte
+        (Some(expected), Some(submitted)) if is_token(&expected) => {
+            expected.len() == submitted.len()
+                && expected
+                    .bytes()
+                    .zip(submitted.bytes())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+        }
+        _ => false,
+    };
+    match matches {
+        true => Ok(()),
+        false => Err(CodedError {
+            code: ErrorCode::Forbidden,
+            message: "the form has expired, please reload the page and try again".to_string(),
+        }
+        .into()),
+    }
+}
+
+/// Middleware issuing the CSRF cookie of the browser sessions on the form pages, and rejecting the form submissions
+/// without it with `403` before they reach the handlers, which compare it with the token in the form
+pub(crate) struct CsrfMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for CsrfMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = CsrfService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CsrfService { service }))
+    }
+}
+
+pub(crate) struct CsrfService<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let existing = req
+            .cookie(CSRF_COOKIE)
+            .map(|cookie| cookie.value().to_string())
+            .filter(|value| is_token(value));
+
+        if req.method() == Method::POST
+            && PROTECTED_PATHS.contains(&req.path())
+            && req.content_type() != "application/json"
+            && existing.is_none()
+        {
+            tracing::debug!("Rejected a form submission without the CSRF cookie");
+            let response = HttpResponse::Forbidden().json(serde_json::json!({
+                "result": null,
+                "error": {
+                    "code": ErrorCode::Forbidden,
+                    "message": "the form has expired, please reload the page and try again",
+                },
+            }));
+            return Box::pin(async move { Ok(req.into_response(response)) });
+        }
+
+        let issued = match (&existing, req.method() == Method::GET) {
+            (None, true) if FORM_PAGES.contains(&req.path()) => {
+                Some(hex::encode(rand::random::<[u8; 32]>()))
+            }
+            _ => None,
+        };
+        if let Some(token) = existing.as_ref().or(issued.as_ref()) {
+            req.extensions_mut().insert(CsrfToken(token.clone()));
+        }
+        let secure = req.connection_info().scheme() == "https";
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?.map_into_boxed_body();
+            if let Some(token) = issued {
+                // A session cookie, a new one is issued once the browser is closed
+                let cookie = Cookie::build(CSRF_COOKIE, token)
+                    .path("/")
+                    .http_only(true)
+                    .secure(secure)
+                    .same_site(SameSite::Lax)
+                    .finish();
+                if let Err(err) = res.response_mut().add_cookie(&cookie) {
+                    tracing::warn!("Failed to set the CSRF cookie: {:?}", err);
+                }
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{test, web, App};
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn issues_the_cookie_and_requires_it_on_submission() {
+        let app = test::init_service(
+            App::new()
+                .wrap(CsrfMiddleware)
+                .route(
+                    "/",
+                    web::get().to(|req: HttpRequest| async move { token_of(&req) }),
+                )
+                .route("/create_account", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/").to_request()).await;
+        let cookie = res
+            .response()
+            .cookies()
+            .find(|cookie| cookie.name() == CSRF_COOKIE)
+            .unwrap()
+            .into_owned();
+        assert_eq!(test::read_body(res).await, cookie.value().as_bytes());
+
+        let req = test::TestRequest::post()
+            .uri("/create_account")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 403);
+
+        let req = test::TestRequest::post()
+            .uri("/create_account")
+            .cookie(cookie)
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), 200);
+    }
+}
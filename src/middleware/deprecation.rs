@@ -0,0 +1,83 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::Error;
+
+use crate::api::{RENAMED, V1_PREFIX};
+
+const DEPRECATION_HEADER: &str = "deprecation";
+
+/// `/api/v1` path of an endpoint requested at its former path, `None` for the other requests
+fn successor(req: &ServiceRequest) -> Option<String> {
+    let path = req.path();
+    if path.starts_with(V1_PREFIX) {
+        return None;
+    }
+    if let Some((_, renamed)) = RENAMED.iter().find(|(former, _)| *former == path) {
+        return Some(format!("{}{}", V1_PREFIX, renamed));
+    }
+    let versioned = format!("{}{}", V1_PREFIX, path);
+    req.resource_map()
+        .has_resource(&versioned)
+        .then_some(versioned)
+}
+
+/// Middleware marking the responses of the endpoints requested at their former paths as deprecated,
+/// with `Deprecation: true` and a `Link` header to the `/api/v1` path, which the integrations should move to
+pub(crate) struct DeprecationMiddleware;
+
+impl<S, B> Transform<S, ServiceRequest> for DeprecationMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = DeprecationService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(DeprecationService { service }))
+    }
+}
+
+pub(crate) struct DeprecationService<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for DeprecationService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let successor = successor(&req);
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            // The unknown paths are left to the 404 page
+            let matched = res.request().match_pattern().is_some();
+            let link = successor.filter(|_| matched).and_then(|successor| {
+                HeaderValue::from_str(&format!("<{}>; rel=\"successor-version\"", successor)).ok()
+            });
+            if let Some(link) = link {
+                let headers = res.headers_mut();
+                headers.insert(
+                    HeaderName::from_static(DEPRECATION_HEADER),
+                    HeaderValue::from_static("true"),
+                );
+                headers.insert(header::LINK, link);
+            }
+            Ok(res)
+        })
+    }
+}
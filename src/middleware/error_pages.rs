@@ -0,0 +1,124 @@
+use actix_web::body::BoxBody;
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::middleware::{ErrorHandlerResponse, ErrorHandlers};
+use actix_web::{web, HttpRequest, HttpResponse, Result};
+use tera::Context;
+
+use crate::errors::ErrorCode;
+use crate::middleware::request_id::RequestId;
+use crate::templates::Templates;
+
+/// Path prefixes of the endpoints consumed by programs rather than browsers
+/// Errors on these paths are responded with a JSON envelope instead of an HTML page
+const API_PATH_PREFIXES: &[&str] = &[
+    "/account/",
+    "/admin/",
+    "/api/",
+    "/bulk",
+    "/claim/",
+    "/config",
+    "/jobs",
+    "/key-challenge",
+    "/passkeys/",
+    "/quota",
+    "/report",
+    "/stats",
+    "/tx/",
+    "/v1/",
+    "/version",
+    "/widget/",
+];
+
+/// Whether the error is responded as JSON, on the API paths and to the clients asking for JSON rather than HTML anywhere
+fn wants_json(req: &HttpRequest) -> bool {
+    API_PATH_PREFIXES
+        .iter()
+        .any(|prefix| req.path().starts_with(prefix))
+        || crate::status::prefers_json(req)
+}
+
+/// Builds the middleware replacing actix's bare text 404 and 5xx responses with the branded pages
+pub(crate) fn error_handlers() -> ErrorHandlers<BoxBody> {
+    ErrorHandlers::new()
+        .handler(StatusCode::NOT_FOUND, |res| {
+            render_error(res, "404.html.tera", ErrorCode::NotFound, "Not Found")
+        })
+        .default_handler_server(|res| {
+            render_error(
+                res,
+                "500.html.tera",
+                ErrorCode::InternalError,
+                "Internal Server Error",
+            )
+        })
+}
+
+/// Responses that were already rendered by a handler (HTML page or JSON body) are passed through untouched
+fn is_already_rendered(res: &ServiceResponse<BoxBody>) -> bool {
+    res.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("text/html") || value.starts_with("application/json"))
+        .unwrap_or(false)
+}
+
+fn render_error(
+    res: ServiceResponse<BoxBody>,
+    template: &str,
+    code: ErrorCode,
+    message: &str,
+) -> Result<ErrorHandlerResponse<BoxBody>> {
+    if is_already_rendered(&res) {
+        return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+    }
+
+    let status = res.status();
+    if let Some(err) = res.response().error() {
+        tracing::warn!("{} {}: {:?}", status, res.request().path(), err);
+    }
+
+    let (req, _) = res.into_parts();
+    let request_id = RequestId::of(&req);
+    let response = if wants_json(&req) {
+        HttpResponse::build(status).json(serde_json::json!({
+            "result": null,
+            "error": {
+                "code": code,
+                "message": message,
+                "request_id": request_id,
+            },
+        }))
+    } else {
+        render_page(&req, status, template, &request_id)
+    };
+
+    Ok(ErrorHandlerResponse::Response(
+        ServiceResponse::new(req, response).map_into_right_body(),
+    ))
+}
+
+fn render_page(
+    req: &HttpRequest,
+    status: StatusCode,
+    template: &str,
+    request_id: &str,
+) -> HttpResponse {
+    let mut context = Context::new();
+    context.insert("request_id", request_id);
+
+    let rendered = req
+        .app_data::<web::Data<Templates>>()
+        .map(|templates| templates.render(template, &context));
+    match rendered {
+        Some(Ok(body)) => HttpResponse::build(status)
+            .insert_header((header::CONTENT_TYPE, HeaderValue::from_static("text/html")))
+            .body(body),
+        Some(Err(err)) => {
+            tracing::warn!("Failed to render template {}: {:?}", template, err);
+            HttpResponse::build(status).finish()
+        }
+        None => HttpResponse::build(status).finish(),
+    }
+}
@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{error, web, Error, HttpMessage, HttpRequest, HttpResponse};
+use sha2::{Digest, Sha256};
+
+use crate::errors::ErrorCode;
+use crate::middleware::client_ip::client_ip;
+use crate::quota::Identity;
+
+pub(crate) const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Set on the responses replayed for a repeated key
+pub(crate) const REPLAYED_HEADER: &str = "idempotent-replayed";
+const MAX_KEY_LENGTH: usize = 255;
+/// Keys remembered at once, the requests with a new key over it are refused with `OVERLOADED`
+const MAX_KEYS: usize = 100_000;
+/// Creation endpoints honoring the key
+const IDEMPOTENT_PATHS: &[&str] = &[
+    "/create_account",
+    "/widget/create_account",
+    "/account/create",
+    "/jobs",
+];
+
+/// Response of the first request with a key, replayed for the repeats
+#[derive(Debug, Clone)]
+struct StoredResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: web::Bytes,
+}
+
+impl StoredResponse {
+    fn replay(&self) -> HttpResponse {
+        let mut response = HttpResponse::with_body(self.status, self.body.clone());
+        for (name, value) in self.headers.iter() {
+            response.headers_mut().append(name.clone(), value.clone());
+        }
+        response.headers_mut().insert(
+            HeaderName::from_static(REPLAYED_HEADER),
+            HeaderValue::from_static("true"),
+        );
+        response.map_into_boxed_body()
+    }
+}
+
+/// Code of the failed creation a handler responded with, whatever the status of its response
+#[derive(Debug, Clone, Copy)]
+struct FailureCode(ErrorCode);
+
+/// Records the code of the failed creation the handler responds with, deciding whether the response is replayed
+/// Needed by the responses not carrying the status of their code, e.g. the `200` of the widget and of the HTML form
+pub(crate) fn report_failure(req: &HttpRequest, code: ErrorCode) {
+    req.extensions_mut().insert(FailureCode(code));
+}
+
+/// Only the final answers are stored, the pending, throttled and failed requests may be retried with the same key
+/// The code reported by the handler decides, else the status of the response
+fn is_final(status: StatusCode, code: Option<ErrorCode>) -> bool {
+    if code == Some(ErrorCode::Pending) {
+        return false;
+    }
+    let status = code.map_or(status, |code| code.http_status());
+    !status.is_server_error() && status != StatusCode::TOO_MANY_REQUESTS
+}
+
+#[derive(Debug)]
+enum Entry {
+    /// The first request with the key is still being handled
+    InProgress { fingerprint: [u8; 32] },
+    Done {
+        fingerprint: [u8; 32],
+        response: StoredResponse,
+        stored_at: Instant,
+    },
+}
+
+impl Entry {
+    fn fingerprint(&self) -> [u8; 32] {
+        match self {
+            Entry::InProgress { fingerprint } | Entry::Done { fingerprint, .. } => *fingerprint,
+        }
+    }
+}
+
+/// Who sent the key, e.g. `api_token:wallet` or the client address, and the key
+/// The same key sent by different clients never collides
+type Key = (String, String);
+
+#[derive(Debug)]
+enum Claim {
+    /// First request with the key, to be handled and stored
+    First,
+    Replay(StoredResponse),
+    InProgress,
+    /// The key was used for a request with another path or body
+    Mismatch,
+    Full,
+}
+
+/// Responses of the creation requests sent with an `Idempotency-Key`, replayed for the repeated requests within the TTL
+/// So a client retrying after a network failure gets the first answer instead of sending another transaction
+/// Kept in memory of the process, like the creation jobs
+#[derive(Debug)]
+pub(crate) struct IdempotencyKeys {
+    ttl: Duration,
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+impl IdempotencyKeys {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn claim(&self, key: &Key, fingerprint: [u8; 32]) -> Claim {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(Entry::Done { stored_at, .. }) if stored_at.elapsed() >= self.ttl => {}
+            Some(entry) if entry.fingerprint() != fingerprint => return Claim::Mismatch,
+            Some(Entry::InProgress { .. }) => return Claim::InProgress,
+            Some(Entry::Done { response, .. }) => return Claim::Replay(response.clone()),
+            None if entries.len() >= MAX_KEYS => return Claim::Full,
+            None => {}
+        }
+        entries.insert(key.clone(), Entry::InProgress { fingerprint });
+        Claim::First
+    }
+
+    /// Stores the response of the first request, or forgets the key if there is none to replay
+    fn finish(&self, key: &Key, response: Option<StoredResponse>) {
+        let mut entries = self.entries.lock().unwrap();
+        match response {
+            Some(response) => {
+                if let Some(entry) = entries.get_mut(key) {
+                    *entry = Entry::Done {
+                        fingerprint: entry.fingerprint(),
+                        response,
+                        stored_at: Instant::now(),
+                    };
+                }
+            }
+            None => {
+                entries.remove(key);
+            }
+        }
+    }
+
+    /// Forgets the responses stored longer than the TTL ago, returns how many were forgotten
+    pub(crate) fn purge_expired(&self) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|_, entry| match entry {
+            Entry::InProgress { .. } => true,
+            Entry::Done { stored_at, .. } => stored_at.elapsed() < self.ttl,
+        });
+        before - entries.len()
+    }
+}
+
+/// Forgets the claimed key unless the response was stored, e.g. if the request is dropped on the way
+struct Claimed {
+    keys: Arc<IdempotencyKeys>,
+    key: Option<Key>,
+}
+
+impl Claimed {
+    fn store(mut self, response: Option<StoredResponse>) {
+        if let Some(key) = self.key.take() {
+            self.keys.finish(&key, response);
+        }
+    }
+}
+
+impl Drop for Claimed {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.keys.finish(&key, None);
+        }
+    }
+}
+
+fn refused(status: StatusCode, code: ErrorCode, message: &str) -> HttpResponse {
+    HttpResponse::build(status).json(serde_json::json!({
+        "result": null,
+        "error": { "code": code, "message": message },
+    }))
+}
+
+/// Middleware honoring the `Idempotency-Key` header of the creation requests
+/// The first response of a key is stored and replayed for the repeats with the same path and body,
+/// a repeat arriving while the first is in progress, or with another body, is refused with `409 IDEMPOTENCY_CONFLICT`
+/// Must run after the authentication middlewares, the keys are scoped by the identity of the client
+pub(crate) struct IdempotencyMiddleware {
+    pub(crate) keys: Arc<IdempotencyKeys>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for IdempotencyMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = IdempotencyService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdempotencyService {
+            service: Rc::new(service),
+            keys: self.keys.clone(),
+        }))
+    }
+}
+
+pub(crate) struct IdempotencyService<S> {
+    service: Rc<S>,
+    keys: Arc<IdempotencyKeys>,
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotencyService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let keys = self.keys.clone();
+        Box::pin(async move {
+            let idempotency_key = req
+                .headers()
+                .get(IDEMPOTENCY_KEY_HEADER)
+                .map(|value| value.to_str().map(str::to_string));
+            let idempotency_key = match idempotency_key {
+                Some(key)
+                    if req.method() == Method::POST
+                        && IDEMPOTENT_PATHS.contains(&crate::api::unversioned(req.path())) =>
+                {
+                    key
+                }
+                _ => return Ok(service.call(req).await?.map_into_boxed_body()),
+            };
+            let idempotency_key = match idempotency_key {
+                Ok(key) if !key.trim().is_empty() && key.len() <= MAX_KEY_LENGTH => key,
+                _ => {
+                    let response = refused(
+                        StatusCode::BAD_REQUEST,
+                        ErrorCode::InvalidRequest,
+                        "the Idempotency-Key header must be 1 to 255 visible characters",
+                    );
+                    return Ok(req.into_response(response));
+                }
+            };
+
+            // The body tells the repeats from a different request reusing the key, we read it here and put it back for the handler
+            let body = req.extract::<web::Bytes>().await?;
+            let fingerprint: [u8; 32] = Sha256::new()
+                .chain_update(crate::api::unversioned(req.path()))
+                .chain_update(b"\n")
+                .chain_update(&body)
+                .finalize()
+                .into();
+            let scope = Identity::of(req.request())
+                .map(|identity| identity.to_string())
+                .or_else(|| client_ip(req.request()).map(|ip| ip.to_string()))
+                .unwrap_or_default();
+            let key = (scope, idempotency_key);
+            let response = match keys.claim(&key, fingerprint) {
+                Claim::First => None,
+                Claim::Replay(stored) => {
+                    tracing::debug!("Replaying the response of idempotency key {:?}", key);
+                    Some(stored.replay())
+                }
+                Claim::InProgress => Some(refused(
+                    StatusCode::CONFLICT,
+                    ErrorCode::IdempotencyConflict,
+                    "a request with this Idempotency-Key is still in progress, retry once it's done",
+                )),
+                Claim::Mismatch => Some(refused(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    ErrorCode::IdempotencyConflict,
+                    "this Idempotency-Key was used for a different request",
+                )),
+                Claim::Full => Some(refused(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    ErrorCode::Overloaded,
+                    "too many idempotency keys are remembered, try again in a few seconds",
+                )),
+            };
+            if let Some(response) = response {
+                return Ok(req.into_response(response));
+            }
+            let claimed = Claimed {
+                keys,
+                key: Some(key),
+            };
+
+            let (_, mut payload) = actix_http::h1::Payload::create(true);
+            payload.unread_data(body);
+            req.set_payload(payload.into());
+            let res = service.call(req).await?;
+            let code = res
+                .request()
+                .extensions()
+                .get::<FailureCode>()
+                .map(|code| code.0);
+            if !is_final(res.status(), code) {
+                claimed.store(None);
+                return Ok(res.map_into_boxed_body());
+            }
+
+            let (req, res) = res.into_parts();
+            let (res, body) = res.into_parts();
+            let bytes = body::to_bytes(body).await.map_err(|err| {
+                error::ErrorInternalServerError(format!(
+                    "Failed to read the response body for the idempotency key: {}",
+                    err.into()
+                ))
+            })?;
+            claimed.store(Some(StoredResponse {
+                status: res.status(),
+                headers: res.headers().clone(),
+                body: bytes.clone(),
+            }));
+            let res = res.set_body(bytes).map_into_boxed_body();
+            Ok(ServiceResponse::new(req, res))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::App;
+
+    use super::*;
+
+    #[test]
+    fn replays_the_first_response() {
+        let keys = Arc::new(IdempotencyKeys::new(Duration::from_secs(60)));
+        let key = ("api_token:wallet".to_string(), "retry-1".to_string());
+        assert!(matches!(keys.claim(&key, [1; 32]), Claim::First));
+        assert!(matches!(keys.claim(&key, [1; 32]), Claim::InProgress));
+        assert!(matches!(keys.claim(&key, [2; 32]), Claim::Mismatch));
+
+        keys.finish(
+            &key,
+            Some(StoredResponse {
+                status: StatusCode::OK,
+                headers: HeaderMap::new(),
+                body: web::Bytes::from_static(b"{}"),
+            }),
+        );
+        match keys.claim(&key, [1; 32]) {
+            Claim::Replay(stored) => assert_eq!(stored.body, "{}"),
+            claim => panic!("expected a replay, got {:?}", claim),
+        }
+        assert_eq!(keys.purge_expired(), 0);
+
+        // A dropped request frees its key for the retry
+        let other = ("1.2.3.4".to_string(), "retry-1".to_string());
+        assert!(matches!(keys.claim(&other, [1; 32]), Claim::First));
+        drop(Claimed {
+            keys: keys.clone(),
+            key: Some(other.clone()),
+        });
+        assert!(matches!(keys.claim(&other, [1; 32]), Claim::First));
+    }
+
+    #[actix_web::test]
+    async fn replays_only_the_final_failures() {
+        let keys = Arc::new(IdempotencyKeys::new(Duration::from_secs(60)));
+        let app = init_service(
+            App::new()
+                .wrap(IdempotencyMiddleware { keys })
+                .route(
+                    "/widget/create_account",
+                    web::post().to(|req: HttpRequest, body: web::Bytes| async move {
+                        let code = match &body[..] {
+                            b"taken" => ErrorCode::AccountExists,
+                            b"pending" => ErrorCode::Pending,
+                            _ => ErrorCode::RpcUnavailable,
+                        };
+                        report_failure(&req, code);
+                        HttpResponse::Ok().finish()
+                    }),
+                )
+                .route(
+                    "/create_account",
+                    web::post().to(|req: HttpRequest| async move {
+                        report_failure(&req, ErrorCode::Pending);
+                        HttpResponse::Accepted().finish()
+                    }),
+                ),
+        )
+        .await;
+
+        let cases = [
+            ("/widget/create_account", "taken", true),
+            ("/widget/create_account", "pending", false),
+            ("/widget/create_account", "down", false),
+            ("/create_account", "pending", false),
+        ];
+        for (path, body, replayed) in cases {
+            let request = || {
+                TestRequest::post()
+                    .uri(path)
+                    .insert_header((IDEMPOTENCY_KEY_HEADER, format!("{}-{}", path, body)))
+                    .set_payload(body)
+                    .to_request()
+            };
+            call_service(&app, request()).await;
+            let res = call_service(&app, request()).await;
+            assert_eq!(
+                res.headers().contains_key(REPLAYED_HEADER),
+                replayed,
+                "{} {}",
+                path,
+                body
+            );
+        }
+    }
+}
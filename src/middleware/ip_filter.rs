@@ -0,0 +1,196 @@
+use std::future::{ready, Future, Ready};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpResponse};
+use anyhow::Context as _;
+use ipnet::IpNet;
+
+use crate::errors::ErrorCode;
+use crate::middleware::client_ip::client_ip;
+
+/// Path prefix of the admin endpoints, checked against the `admin-*` lists on top of the general ones
+pub(crate) const ADMIN_PATH_PREFIX: &str = "/admin";
+
+/// Allowed and denied CIDR ranges, an empty allowlist allows everyone not denied
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CidrLists {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl CidrLists {
+    fn permits(&self, ip: &IpAddr) -> bool {
+        !self.deny.iter().any(|net| net.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(ip)))
+    }
+}
+
+/// IP filter rules as loaded from the file
+///
+/// One rule per line: `allow <cidr>`, `deny <cidr>`, `admin-allow <cidr>` or `admin-deny <cidr>`,
+/// empty lines and lines starting with `#` are ignored
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IpFilter {
+    general: CidrLists,
+    admin: CidrLists,
+}
+
+impl IpFilter {
+    pub(crate) fn parse(rules: &str) -> anyhow::Result<Self> {
+        let mut filter = Self::default();
+        for (number, line) in rules.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (kind, cidr) = line
+                .split_once(char::is_whitespace)
+                .with_context(|| format!("line {}: expected `<kind> <cidr>`", number + 1))?;
+            let cidr = cidr.trim();
+            // A bare address is a single host range
+            let net: IpNet = match cidr.parse() {
+                Ok(net) => net,
+                Err(_) => cidr
+                    .parse::<IpAddr>()
+                    .map(IpNet::from)
+                    .with_context(|| format!("line {}: invalid CIDR {}", number + 1, cidr))?,
+            };
+            let list = match kind {
+                "allow" => &mut filter.general.allow,
+                "deny" => &mut filter.general.deny,
+                "admin-allow" => &mut filter.admin.allow,
+                "admin-deny" => &mut filter.admin.deny,
+                _ => anyhow::bail!("line {}: unknown rule kind {}", number + 1, kind),
+            };
+            list.push(net);
+        }
+        Ok(filter)
+    }
+
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+        let rules = std::fs::read_to_string(path)
+            .with_context(|| format!("failed reading IP filter file {}", path.display()))?;
+        Self::parse(&rules).with_context(|| format!("failed parsing {}", path.display()))
+    }
+
+    pub(crate) fn permits(&self, ip: &IpAddr, path: &str) -> bool {
+        self.general.permits(ip) && (!path.starts_with(ADMIN_PATH_PREFIX) || self.admin.permits(ip))
+    }
+}
+
+/// IP filter that can be swapped at runtime by reloading its file
+#[derive(Debug, Clone)]
+pub(crate) struct ReloadableIpFilter {
+    path: PathBuf,
+    filter: Arc<RwLock<IpFilter>>,
+}
+
+impl ReloadableIpFilter {
+    pub(crate) fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let filter = IpFilter::load(&path)?;
+        Ok(Self {
+            path,
+            filter: Arc::new(RwLock::new(filter)),
+        })
+    }
+
+    /// Re-reads the file, the current rules stay in effect if it's invalid
+    pub(crate) fn reload(&self) -> anyhow::Result<()> {
+        let filter = IpFilter::load(&self.path)?;
+        *self.filter.write().unwrap() = filter;
+        Ok(())
+    }
+
+    /// Reloads the rules on every SIGHUP
+    pub(crate) fn spawn_reloader(&self) -> anyhow::Result<()> {
+        let mut hangups = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .context("failed listening for SIGHUP")?;
+        let filter = self.clone();
+        tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                match filter.reload() {
+                    Ok(()) => tracing::info!("Reloaded IP filter from {}", filter.path.display()),
+                    Err(err) => tracing::warn!("Failed to reload IP filter: {:?}", err),
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Middleware rejecting the requests from the addresses not permitted by the IP filter with `403`
+/// Uses the client address resolved past the trusted proxies, the address of the peer without them
+pub(crate) struct IpFilterMiddleware {
+    pub(crate) filter: Option<ReloadableIpFilter>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for IpFilterMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = IpFilterService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IpFilterService {
+            service: Rc::new(service),
+            filter: self.filter.clone(),
+        }))
+    }
+}
+
+pub(crate) struct IpFilterService<S> {
+    service: Rc<S>,
+    filter: Option<ReloadableIpFilter>,
+}
+
+impl<S, B> Service<ServiceRequest> for IpFilterService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let client_ip = client_ip(req.request());
+        let permitted = match (&self.filter, client_ip) {
+            (None, _) => true,
+            (Some(filter), Some(ip)) => filter
+                .filter
+                .read()
+                .unwrap()
+                .permits(&ip, crate::api::unversioned(req.path())),
+            // Unknown peer, e.g. a unix socket, can't be matched against the lists
+            (Some(_), None) => false,
+        };
+        if !permitted {
+            tracing::warn!(
+                "Rejected request to {} from {:?} by the IP filter",
+                req.path(),
+                client_ip
+            );
+            let response = HttpResponse::Forbidden().json(serde_json::json!({
+                "result": null,
+                "error": { "code": ErrorCode::Forbidden, "message": "forbidden" },
+            }));
+            return Box::pin(async move { Ok(req.into_response(response)) });
+        }
+
+        let service = self.service.clone();
+        Box::pin(async move { Ok(service.call(req).await?.map_into_boxed_body()) })
+    }
+}
@@ -0,0 +1,12 @@
+pub(crate) mod access_log;
+pub(crate) mod api_tokens;
+pub(crate) mod client_ip;
+pub(crate) mod csrf;
+pub(crate) mod deprecation;
+pub(crate) mod error_pages;
+pub(crate) mod idempotency;
+pub(crate) mod ip_filter;
+pub(crate) mod rate_limit;
+pub(crate) mod replay_guard;
+pub(crate) mod request_id;
+pub(crate) mod response_signing;
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::Method;
+use actix_web::{Error, HttpMessage, HttpResponse};
+
+use crate::errors::ErrorCode;
+use crate::middleware::client_ip::client_ip;
+
+/// Endpoints the per-address limit applies to: the creations, the sign-in links sent by email, the abuse reports
+/// and the challenges of the key proofs and the passkey ceremonies, which take memory until they expire
+const LIMITED_PATHS: &[&str] = &[
+    "/create_account",
+    "/widget/create_account",
+    "/account/create",
+    "/jobs",
+    "/auth/email",
+    "/report",
+    "/key-challenge",
+    "/passkeys/register/options",
+    "/passkeys/login/options",
+];
+
+/// Token bucket refilled with `per_minute` tokens a minute, holding up to `burst` of them
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RateLimitConfig {
+    pub(crate) per_minute: u32,
+    pub(crate) burst: u32,
+}
+
+/// State of a limiter applying to a request, reported in the `X-RateLimit-*` headers of the response
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct RateLimit {
+    pub(crate) limit: u32,
+    /// Requests left after this one
+    pub(crate) remaining: u32,
+    /// Until the requests are back to the limit, or until the next one is allowed if this one was refused
+    pub(crate) reset: Duration,
+    pub(crate) refused: bool,
+}
+
+impl RateLimit {
+    /// Whether the limiter is closer to refusing the requests than the other one
+    fn tighter_than(&self, other: &RateLimit) -> bool {
+        (self.refused, other.remaining, self.reset) > (other.refused, self.remaining, other.reset)
+    }
+
+    fn reset_secs(&self) -> u64 {
+        self.reset.as_secs_f64().ceil() as u64
+    }
+}
+
+/// Records the state of a limiter applying to the request, the tightest of the limiters is reported
+pub(crate) fn report(req: &ServiceRequest, limit: RateLimit) {
+    let mut extensions = req.extensions_mut();
+    if extensions
+        .get::<RateLimit>()
+        .map_or(true, |reported| limit.tighter_than(reported))
+    {
+        extensions.insert(limit);
+    }
+}
+
+/// Adds the `X-RateLimit-*` headers of the reported limiter to the response, and `Retry-After` if it refused the request
+pub(crate) fn insert_headers(res: &mut ServiceResponse<BoxBody>) {
+    let Some(limit) = res.request().extensions().get::<RateLimit>().copied() else {
+        return;
+    };
+    let headers = res.headers_mut();
+    headers.insert(
+        HeaderName::from_static("x-ratelimit-limit"),
+        HeaderValue::from(limit.limit),
+    );
+    headers.insert(
+        HeaderName::from_static("x-ratelimit-remaining"),
+        HeaderValue::from(limit.remaining),
+    );
+    headers.insert(
+        HeaderName::from_static("x-ratelimit-reset"),
+        HeaderValue::from(limit.reset_secs()),
+    );
+    if limit.refused {
+        headers.insert(
+            header::RETRY_AFTER,
+            HeaderValue::from(limit.reset_secs().max(1)),
+        );
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Creation requests of each client address, kept in memory of the process serving them
+/// unless the buckets are shared in Redis
+pub(crate) struct IpRateLimiter {
+    /// Tokens added per second
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    #[cfg(feature = "shared-limits")]
+    shared: Option<Arc<crate::shared_limits::SharedLimits>>,
+}
+
+impl IpRateLimiter {
+    pub(crate) fn new(config: RateLimitConfig) -> Self {
+        Self {
+            rate: config.per_minute.max(1) as f64 / 60.0,
+            burst: config.burst.max(1) as f64,
+            buckets: Mutex::new(HashMap::new()),
+            #[cfg(feature = "shared-limits")]
+            shared: None,
+        }
+    }
+
+    #[cfg(feature = "shared-limits")]
+    /// Keeps the buckets in Redis, shared by all the replicas
+    pub(crate) fn with_shared(mut self, shared: Arc<crate::shared_limits::SharedLimits>) -> Self {
+        self.shared = Some(shared);
+        self
+    }
+
+    /// Takes a token of the address, the returned state tells whether one was left
+    pub(crate) async fn acquire(&self, ip: IpAddr) -> RateLimit {
+        #[cfg(feature = "shared-limits")]
+        if let Some(shared) = &self.shared {
+            let key = format!("ip_rate_limit:{}", ip);
+            if let Some(result) = shared.take_token(&key, self.rate, self.burst).await {
+                return match result {
+                    Ok(tokens) => self.state(tokens, false),
+                    Err(tokens) => self.state(tokens, true),
+                };
+            }
+        }
+        self.acquire_local(ip)
+    }
+
+    /// State of a bucket left with `tokens`
+    fn state(&self, tokens: f64, refused: bool) -> RateLimit {
+        let missing = if refused { 1.0 } else { self.burst } - tokens;
+        RateLimit {
+            limit: self.burst as u32,
+            remaining: tokens.floor() as u32,
+            reset: Duration::from_secs_f64(missing.max(0.0) / self.rate),
+            refused,
+        }
+    }
+
+    fn acquire_local(&self, ip: IpAddr) -> RateLimit {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.updated).as_secs_f64() * self.rate)
+            .min(self.burst);
+        bucket.updated = now;
+        let refused = bucket.tokens < 1.0;
+        if !refused {
+            bucket.tokens -= 1.0;
+        }
+        self.state(bucket.tokens, refused)
+    }
+
+    /// Forgets the addresses whose buckets have refilled, returns how many were forgotten
+    pub(crate) fn purge_full(&self) -> usize {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let before = buckets.len();
+        buckets.retain(|_, bucket| {
+            bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * self.rate
+                < self.burst
+        });
+        before - buckets.len()
+    }
+}
+
+/// Middleware rejecting the creation requests over the per-address limit with `429` and `Retry-After`
+/// Being the outermost limiter, it adds the `X-RateLimit-*` headers of the tightest limiter to all the responses
+/// Uses the client address resolved past the trusted proxies, behind untrusted ones all the clients share their bucket
+pub(crate) struct RateLimitMiddleware {
+    pub(crate) limiter: Option<Arc<IpRateLimiter>>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimitMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = RateLimitService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitService {
+            service: Rc::new(service),
+            limiter: self.limiter.clone(),
+        }))
+    }
+}
+
+pub(crate) struct RateLimitService<S> {
+    service: Rc<S>,
+    limiter: Option<Arc<IpRateLimiter>>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let limited = req.method() == Method::POST
+            && LIMITED_PATHS.contains(&crate::api::unversioned(req.path()));
+        let client_ip = client_ip(req.request());
+        let service = self.service.clone();
+        let (limiter, ip) = match (&self.limiter, client_ip) {
+            (Some(limiter), Some(ip)) if limited => (limiter.clone(), ip),
+            _ => {
+                return Box::pin(async move {
+                    let mut res = service.call(req).await?.map_into_boxed_body();
+                    insert_headers(&mut res);
+                    Ok(res)
+                });
+            }
+        };
+
+        Box::pin(async move {
+            let limit = limiter.acquire(ip).await;
+            report(&req, limit);
+            if !limit.refused {
+                let mut res = service.call(req).await?.map_into_boxed_body();
+                insert_headers(&mut res);
+                return Ok(res);
+            }
+            let retry_after = limit.reset_secs().max(1);
+            tracing::warn!(
+                "Rate limited request to {} from {:?}",
+                req.path(),
+                client_ip
+            );
+            let response = HttpResponse::TooManyRequests().json(serde_json::json!({
+                "result": null,
+                "error": {
+                    "code": ErrorCode::RateLimited,
+                    "message": format!("too many requests from your address, try again in {} seconds", retry_after),
+                },
+            }));
+            let mut res = req.into_response(response);
+            insert_headers(&mut res);
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_the_bucket_state() {
+        let limiter = IpRateLimiter::new(RateLimitConfig {
+            per_minute: 60,
+            burst: 2,
+        });
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let first = limiter.acquire(ip).await;
+        assert_eq!((first.limit, first.remaining, first.refused), (2, 1, false));
+        assert_eq!(first.reset.as_secs_f64().round(), 1.0);
+        limiter.acquire(ip).await;
+        let refused = limiter.acquire(ip).await;
+        assert_eq!((refused.remaining, refused.refused), (0, true));
+        assert!(refused.tighter_than(&first));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, Error, HttpMessage, HttpResponse};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+use crate::errors::ErrorCode;
+
+pub(crate) const API_KEY_HEADER: &str = "x-api-key";
+pub(crate) const TIMESTAMP_HEADER: &str = "x-timestamp";
+pub(crate) const NONCE_HEADER: &str = "x-nonce";
+pub(crate) const REQUEST_SIGNATURE_HEADER: &str = "x-request-signature";
+
+/// Signing secrets of the API clients and the allowed clock skew
+///
+/// A client with a configured secret must sign every request with HMAC-SHA256 over
+/// `{timestamp}\n{nonce}\n{METHOD}\n{path_and_query}\n{hex(sha256(body))}`
+/// and send it hex-encoded in `X-Request-Signature` along with `X-Api-Key`, `X-Timestamp` (unix seconds) and `X-Nonce`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RequestSigningConfig {
+    pub(crate) secrets: HashMap<String, String>,
+    pub(crate) max_skew: Duration,
+}
+
+impl RequestSigningConfig {
+    /// Parses `KEY=SECRET` pairs as given on the command line
+    pub(crate) fn from_pairs(pairs: &[String], max_skew: Duration) -> anyhow::Result<Self> {
+        let mut secrets = HashMap::new();
+        for pair in pairs {
+            let (key, secret) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("expected KEY=SECRET, got {}", pair))?;
+            secrets.insert(key.trim().to_string(), secret.trim().to_string());
+        }
+        Ok(Self { secrets, max_skew })
+    }
+}
+
+/// API key of the client whose request signature was verified
+/// Inserted into the request extensions, so the handlers can attribute the request to the client
+#[derive(Debug, Clone)]
+pub(crate) struct AuthenticatedClient(pub(crate) String);
+
+/// Remembers the nonces seen within the allowed clock skew window to reject replayed requests
+#[derive(Debug, Default)]
+pub(crate) struct ReplayGuard {
+    config: RequestSigningConfig,
+    seen: Mutex<HashMap<(String, String), u64>>,
+}
+
+impl ReplayGuard {
+    pub(crate) fn new(config: RequestSigningConfig) -> Self {
+        Self {
+            config,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns `false` if the nonce was already used by the client within the window
+    fn remember(&self, api_key: &str, nonce: &str, timestamp: u64, now: u64) -> bool {
+        let window = self.config.max_skew.as_secs();
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, seen_at| seen_at.saturating_add(window) >= now);
+        seen.insert((api_key.to_string(), nonce.to_string()), timestamp)
+            .is_none()
+    }
+
+    fn verify(
+        &self,
+        req: &ServiceRequest,
+        secret: &str,
+        api_key: &str,
+        body: &[u8],
+    ) -> Result<(), &'static str> {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let timestamp = header(TIMESTAMP_HEADER).ok_or("missing X-Timestamp header")?;
+        let nonce = header(NONCE_HEADER).ok_or("missing X-Nonce header")?;
+        let signature =
+            header(REQUEST_SIGNATURE_HEADER).ok_or("missing X-Request-Signature header")?;
+
+        let timestamp_secs: u64 = timestamp
+            .parse()
+            .map_err(|_| "invalid X-Timestamp header")?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        if now.abs_diff(timestamp_secs) > self.config.max_skew.as_secs() {
+            return Err("stale request timestamp");
+        }
+
+        let path_and_query = req
+            .uri()
+            .path_and_query()
+            .map(|pq| pq.as_str())
+            .unwrap_or_else(|| req.path());
+        let message = format!(
+            "{}\n{}\n{}\n{}\n{}",
+            timestamp,
+            nonce,
+            req.method(),
+            path_and_query,
+            hex::encode(Sha256::digest(body))
+        );
+        let signature = hex::decode(signature).map_err(|_| "invalid X-Request-Signature header")?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .map_err(|_| "invalid signing secret")?;
+        mac.update(message.as_bytes());
+        mac.verify_slice(&signature)
+            .map_err(|_| "invalid request signature")?;
+
+        if !self.remember(api_key, &nonce, timestamp_secs, now) {
+            return Err("replayed request nonce");
+        }
+        Ok(())
+    }
+}
+
+/// Middleware verifying the signatures of the requests made by API clients with a configured signing secret
+/// Requests without an `X-Api-Key` header, or from clients without a secret, are passed through
+pub(crate) struct ReplayGuardMiddleware {
+    pub(crate) guard: Arc<ReplayGuard>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ReplayGuardMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = ReplayGuardService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ReplayGuardService {
+            service: Rc::new(service),
+            guard: self.guard.clone(),
+        }))
+    }
+}
+
+pub(crate) struct ReplayGuardService<S> {
+    service: Rc<S>,
+    guard: Arc<ReplayGuard>,
+}
+
+impl<S, B> Service<ServiceRequest> for ReplayGuardService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let guard = self.guard.clone();
+        Box::pin(async move {
+            let api_key = req
+                .headers()
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            let secret = api_key
+                .as_ref()
+                .and_then(|api_key| guard.config.secrets.get(api_key).cloned());
+            let (api_key, secret) = match (api_key, secret) {
+                (Some(api_key), Some(secret)) => (api_key, secret),
+                _ => return Ok(service.call(req).await?.map_into_boxed_body()),
+            };
+
+            // The body is part of the signed message, so we read it here and put it back for the handler
+            let body = req.extract::<web::Bytes>().await?;
+            if let Err(reason) = guard.verify(&req, &secret, &api_key, &body) {
+                tracing::warn!("Rejected signed request from {}: {}", api_key, reason);
+                let response = HttpResponse::Unauthorized().json(serde_json::json!({
+                    "result": null,
+                    "error": { "code": ErrorCode::Unauthorized, "message": reason },
+                }));
+                return Ok(req.into_response(response));
+            }
+            let (_, mut payload) = actix_http::h1::Payload::create(true);
+            payload.unread_data(body);
+            req.set_payload(payload.into());
+            req.extensions_mut().insert(AuthenticatedClient(api_key));
+
+            Ok(service.call(req).await?.map_into_boxed_body())
+        })
+    }
+}
@@ -0,0 +1,111 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::{error, web, Error, HttpResponse, Responder};
+use near_crypto::SecretKey;
+
+pub(crate) const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Key used to sign the JSON response bodies, so wallets can verify the responses
+/// really came from the faucet even when they are relayed by third parties
+#[derive(Clone)]
+pub(crate) struct ResponseSigningKey(pub(crate) Option<SecretKey>);
+
+/// Middleware adding a detached ed25519 signature of the JSON response body in the `X-Signature` header
+/// The signature is over the raw body bytes and is formatted as `ed25519:<base58>`
+/// Does nothing if no response-signing key is configured
+pub(crate) struct ResponseSigningMiddleware {
+    pub(crate) key: Option<SecretKey>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ResponseSigningMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Transform = ResponseSigningService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ResponseSigningService {
+            service,
+            key: self.key.clone().map(Rc::new),
+        }))
+    }
+}
+
+pub(crate) struct ResponseSigningService<S> {
+    service: S,
+    key: Option<Rc<SecretKey>>,
+}
+
+fn is_json(res: &ServiceResponse<impl MessageBody>) -> bool {
+    res.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("application/json"))
+        .unwrap_or(false)
+}
+
+impl<S, B> Service<ServiceRequest> for ResponseSigningService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<BoxBody>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let key = self.key.clone();
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            let key = match key {
+                Some(key) if is_json(&res) => key,
+                _ => return Ok(res.map_into_boxed_body()),
+            };
+
+            let (req, res) = res.into_parts();
+            let (res, body) = res.into_parts();
+            let bytes = body::to_bytes(body).await.map_err(|err| {
+                error::ErrorInternalServerError(format!(
+                    "Failed to read the response body for signing: {}",
+                    err.into()
+                ))
+            })?;
+            let signature = key.sign(&bytes);
+
+            let mut res = res.set_body(bytes).map_into_boxed_body();
+            if let Ok(value) = HeaderValue::from_str(&signature.to_string()) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(SIGNATURE_HEADER), value);
+            }
+            Ok(ServiceResponse::new(req, res))
+        })
+    }
+}
+
+/// Endpoint: /.well-known/response-signing-key
+/// Responds with the public key verifying the `X-Signature` headers (JSON), 404 if signing is disabled
+pub(crate) async fn response_signing_key_handler(
+    key: web::Data<ResponseSigningKey>,
+) -> impl Responder {
+    tracing::debug!("GET /.well-known/response-signing-key");
+    match &key.0 {
+        Some(key) => HttpResponse::Ok().json(serde_json::json!({
+            "public_key": key.public_key().to_string(),
+            "signature_header": SIGNATURE_HEADER,
+        })),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
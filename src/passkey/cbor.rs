@@ -0,0 +1,138 @@
+// Just enough CBOR to read the WebAuthn attestation objects and COSE keys
+// Indefinite lengths and floats are never used there, so they are rejected
+
+/// Nesting deeper than this is rejected, WebAuthn structures are at most a few levels deep
+const MAX_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    Integer(i128),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Value>),
+    Map(Vec<(Value, Value)>),
+    Bool(bool),
+    Null,
+}
+
+impl Value {
+    /// Value of the map entry with the given key, `None` for other values and missing keys
+    pub(crate) fn get(&self, key: &Value) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_integer(&self) -> Option<i128> {
+        match self {
+            Value::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes the value at the start of the input, returns it with the number of bytes it took
+pub(crate) fn decode(input: &[u8]) -> anyhow::Result<(Value, usize)> {
+    let mut reader = Reader { input, offset: 0 };
+    let value = reader.value(0)?;
+    Ok((value, reader.offset))
+}
+
+struct Reader<'a> {
+    input: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&[u8]> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.input.len())
+            .ok_or_else(|| anyhow::anyhow!("unexpected end of CBOR input"))?;
+        let bytes = &self.input[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    /// Reads the argument following the initial byte of an item
+    fn argument(&mut self, info: u8) -> anyhow::Result<u64> {
+        let len = match info {
+            0..=23 => return Ok(info as u64),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            _ => anyhow::bail!("unsupported CBOR length encoding {}", info),
+        };
+        Ok(self
+            .take(len)?
+            .iter()
+            .fold(0, |acc, byte| acc << 8 | *byte as u64))
+    }
+
+    fn length(&mut self, info: u8) -> anyhow::Result<usize> {
+        let len = self.argument(info)?;
+        // Every item takes at least a byte, so longer collections can't fit into the rest of the input
+        if len > (self.input.len() - self.offset) as u64 {
+            anyhow::bail!("CBOR length {} exceeds the input", len);
+        }
+        Ok(len as usize)
+    }
+
+    fn value(&mut self, depth: usize) -> anyhow::Result<Value> {
+        if depth > MAX_DEPTH {
+            anyhow::bail!("CBOR nesting is too deep");
+        }
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        Ok(match major {
+            0 => Value::Integer(self.argument(info)? as i128),
+            1 => Value::Integer(-1 - self.argument(info)? as i128),
+            2 => {
+                let len = self.length(info)?;
+                Value::Bytes(self.take(len)?.to_vec())
+            }
+            3 => {
+                let len = self.length(info)?;
+                Value::Text(String::from_utf8(self.take(len)?.to_vec())?)
+            }
+            4 => {
+                let len = self.length(info)?;
+                Value::Array(
+                    (0..len)
+                        .map(|_| self.value(depth + 1))
+                        .collect::<anyhow::Result<_>>()?,
+                )
+            }
+            5 => {
+                let len = self.length(info)?;
+                Value::Map(
+                    (0..len)
+                        .map(|_| Ok((self.value(depth + 1)?, self.value(depth + 1)?)))
+                        .collect::<anyhow::Result<_>>()?,
+                )
+            }
+            // Tags only annotate the value that follows
+            6 => {
+                self.argument(info)?;
+                self.value(depth + 1)?
+            }
+            7 => match info {
+                20 => Value::Bool(false),
+                21 => Value::Bool(true),
+                22 | 23 => Value::Null,
+                _ => anyhow::bail!("unsupported CBOR simple value {}", info),
+            },
+            _ => unreachable!("the major type has 3 bits"),
+        })
+    }
+}
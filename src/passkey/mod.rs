@@ -0,0 +1,613 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use anyhow::Context as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine as _;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+
+use crate::abuse::error_response;
+use crate::errors::{CodedError, ErrorCode};
+use crate::quota::Identity;
+use cbor::Value;
+
+mod cbor;
+
+/// Header carrying the session token issued after a passkey ceremony
+pub(crate) const PASSKEY_SESSION_HEADER: &str = "x-passkey-session";
+
+/// How long the client has to complete a ceremony
+const CHALLENGE_TTL: Duration = Duration::from_secs(300);
+/// Outstanding challenges at most, the options endpoints are unauthenticated
+const MAX_CHALLENGES: usize = 10_000;
+/// COSE identifier of ES256 (ECDSA with P-256 and SHA-256), the only supported algorithm
+const COSE_ES256: i128 = -7;
+
+/// Flags of the authenticator data
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_ATTESTED_CREDENTIAL: u8 = 0x40;
+
+/// Relying party the passkeys are bound to
+#[derive(Debug, Clone)]
+pub(crate) struct PasskeyConfig {
+    /// Domain of the faucet, e.g. `faucet.example.com`
+    pub(crate) rp_id: String,
+    /// Origins the ceremonies may run on, e.g. `https://faucet.example.com`
+    pub(crate) origins: Vec<String>,
+    pub(crate) session_ttl: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ceremony {
+    Registration,
+    Authentication,
+}
+
+impl Ceremony {
+    /// `type` of the client data the browser produces for the ceremony
+    fn client_data_type(&self) -> &'static str {
+        match self {
+            Ceremony::Registration => "webauthn.create",
+            Ceremony::Authentication => "webauthn.get",
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct Credential {
+    /// Uncompressed P-256 point
+    public_key: Vec<u8>,
+    sign_count: i64,
+}
+
+#[derive(Debug)]
+struct Session {
+    credential_id: String,
+    expires_at: Instant,
+}
+
+/// Passkeys registered by the returning users, kept in a SQLite database, and their sessions, kept in memory of the process
+///
+/// Only ES256 credentials are accepted, and attestation isn't requested,
+/// a passkey proves the user came back with the same authenticator, not who made it
+#[derive(Debug)]
+pub(crate) struct Passkeys {
+    config: PasskeyConfig,
+    challenges: Mutex<HashMap<String, (Ceremony, Instant)>>,
+    /// `passkey_credentials` table, by the base64url encoded credential ID
+    credentials: SqlitePool,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+/// Fields of the registration sent along with the credential
+#[derive(Debug, Deserialize)]
+pub(crate) struct RegistrationRequest {
+    #[serde(flatten)]
+    credential: PublicKeyCredential,
+    /// Token of the solved Turnstile widget, only checked if Turnstile is enabled
+    #[serde(default, rename = "cf-turnstile-response")]
+    turnstile_token: Option<String>,
+    /// Token of the reCAPTCHA v3 score, only checked if reCAPTCHA is enabled
+    #[serde(default, rename = "g-recaptcha-response")]
+    recaptcha_token: Option<String>,
+}
+
+/// `AuthenticatorAttestationResponse` and `AuthenticatorAssertionResponse` as sent by the browser,
+/// with the binary fields base64url encoded
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CredentialResponse {
+    client_data_json: String,
+    attestation_object: Option<String>,
+    authenticator_data: Option<String>,
+    signature: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct PublicKeyCredential {
+    /// base64url encoded credential ID
+    id: String,
+    response: CredentialResponse,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+fn decode(field: &str, value: &str) -> anyhow::Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|_| invalid(format!("{} is not valid base64url", field)))
+}
+
+fn invalid(message: impl Into<String>) -> anyhow::Error {
+    CodedError {
+        code: ErrorCode::InvalidRequest,
+        message: message.into(),
+    }
+    .into()
+}
+
+fn unauthorized(message: impl Into<String>) -> anyhow::Error {
+    CodedError {
+        code: ErrorCode::Unauthorized,
+        message: message.into(),
+    }
+    .into()
+}
+
+/// Authenticator data common to both ceremonies
+struct AuthenticatorData<'a> {
+    flags: u8,
+    sign_count: u32,
+    /// Attested credential data and extensions, only present in the registration
+    rest: &'a [u8],
+}
+
+impl Passkeys {
+    /// Keeps the credentials in the `passkey_credentials` table of a SQLite database, both created if missing
+    pub(crate) async fn new(config: PasskeyConfig, database_url: &str) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::from_str(database_url)
+            .context("invalid SQLite database of the passkeys")?
+            .create_if_missing(true);
+        let credentials = SqlitePool::connect_with(options)
+            .await
+            .context("failed opening the passkey database")?;
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS passkey_credentials (
+                id TEXT PRIMARY KEY,
+                public_key BLOB NOT NULL,
+                sign_count BIGINT NOT NULL,
+                registered_at BIGINT NOT NULL
+            )
+            "#,
+        )
+        .execute(&credentials)
+        .await
+        .context("failed creating the passkey_credentials table")?;
+        Ok(Self {
+            config,
+            challenges: Mutex::new(HashMap::new()),
+            credentials,
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Fails with `OVERLOADED` while `MAX_CHALLENGES` unexpired challenges are outstanding
+    fn new_challenge(&self, ceremony: Ceremony) -> anyhow::Result<String> {
+        let challenge = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+        let now = Instant::now();
+        let mut challenges = self.challenges.lock().unwrap();
+        challenges.retain(|_, (_, issued_at)| now.duration_since(*issued_at) < CHALLENGE_TTL);
+        if challenges.len() >= MAX_CHALLENGES {
+            return Err(CodedError {
+                code: ErrorCode::Overloaded,
+                message: "too many passkey ceremonies are in progress, try again later".to_string(),
+            }
+            .into());
+        }
+        challenges.insert(challenge.clone(), (ceremony, now));
+        Ok(challenge)
+    }
+
+    /// Checks the client data against an outstanding challenge of the ceremony, which is used up
+    fn verify_client_data(
+        &self,
+        ceremony: Ceremony,
+        client_data_json: &[u8],
+    ) -> anyhow::Result<()> {
+        let client_data: ClientData = serde_json::from_slice(client_data_json)
+            .map_err(|err| invalid(format!("invalid clientDataJSON: {}", err)))?;
+        if client_data.kind != ceremony.client_data_type() {
+            return Err(invalid(format!(
+                "expected client data of type {}",
+                ceremony.client_data_type()
+            )));
+        }
+        if !self.config.origins.contains(&client_data.origin) {
+            return Err(invalid(format!(
+                "origin {} is not allowed",
+                client_data.origin
+            )));
+        }
+        let issued = self
+            .challenges
+            .lock()
+            .unwrap()
+            .remove(client_data.challenge.trim_end_matches('='));
+        match issued {
+            Some((issued_for, issued_at))
+                if issued_for == ceremony && issued_at.elapsed() < CHALLENGE_TTL =>
+            {
+                Ok(())
+            }
+            _ => Err(invalid("the challenge is unknown or expired")),
+        }
+    }
+
+    fn parse_authenticator_data<'a>(
+        &self,
+        data: &'a [u8],
+    ) -> anyhow::Result<AuthenticatorData<'a>> {
+        if data.len() < 37 {
+            return Err(invalid("authenticator data is too short"));
+        }
+        if data[..32] != Sha256::digest(self.config.rp_id.as_bytes())[..] {
+            return Err(invalid("the passkey belongs to another relying party"));
+        }
+        let flags = data[32];
+        if flags & FLAG_USER_PRESENT == 0 {
+            return Err(invalid("the user was not present"));
+        }
+        Ok(AuthenticatorData {
+            flags,
+            sign_count: u32::from_be_bytes(data[33..37].try_into().unwrap()),
+            rest: &data[37..],
+        })
+    }
+
+    fn new_session(&self, credential_id: String) -> serde_json::Value {
+        let token = hex::encode(rand::random::<[u8; 32]>());
+        let now = Instant::now();
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(
+            token.clone(),
+            Session {
+                credential_id,
+                expires_at: now + self.config.session_ttl,
+            },
+        );
+        let expires_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+            + self.config.session_ttl.as_secs();
+        serde_json::json!({ "session_token": token, "expires_at": expires_at })
+    }
+
+    /// Identity of a live session, the quota of the passkey is charged to it
+    pub(crate) fn identity(&self, token: &str) -> Option<Identity> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(token)
+            .filter(|session| session.expires_at > Instant::now())
+            .map(|session| Identity::passkey(&session.credential_id))
+    }
+
+    /// Verifies the attestation of a new passkey, returns its base64url encoded ID and public key
+    fn verify_registration(
+        &self,
+        credential: &PublicKeyCredential,
+    ) -> anyhow::Result<(String, Credential)> {
+        let response = &credential.response;
+        let client_data_json = decode("clientDataJSON", &response.client_data_json)?;
+        self.verify_client_data(Ceremony::Registration, &client_data_json)?;
+
+        let attestation_object = response
+            .attestation_object
+            .as_deref()
+            .ok_or_else(|| invalid("attestationObject is missing"))?;
+        let (attestation, _) = cbor::decode(&decode("attestationObject", attestation_object)?)
+            .map_err(|err| invalid(format!("invalid attestationObject: {}", err)))?;
+        // The attestation statement isn't verified, none is requested
+        let auth_data = attestation
+            .get(&Value::Text("authData".to_string()))
+            .and_then(Value::as_bytes)
+            .ok_or_else(|| invalid("attestationObject has no authData"))?;
+        let auth_data = self.parse_authenticator_data(auth_data)?;
+        if auth_data.flags & FLAG_ATTESTED_CREDENTIAL == 0 || auth_data.rest.len() < 18 {
+            return Err(invalid("authenticator data has no attested credential"));
+        }
+
+        // AAGUID (16 bytes), credential ID length (2 bytes), credential ID, COSE public key
+        let id_len = u16::from_be_bytes([auth_data.rest[16], auth_data.rest[17]]) as usize;
+        let credential_id = auth_data
+            .rest
+            .get(18..18 + id_len)
+            .ok_or_else(|| invalid("credential ID is truncated"))?;
+        let (cose_key, _) = cbor::decode(&auth_data.rest[18 + id_len..])
+            .map_err(|err| invalid(format!("invalid credential public key: {}", err)))?;
+        let public_key = es256_public_key(&cose_key)?;
+
+        Ok((
+            URL_SAFE_NO_PAD.encode(credential_id),
+            Credential {
+                public_key,
+                sign_count: auth_data.sign_count.into(),
+            },
+        ))
+    }
+
+    /// Stores the public key of a verified passkey, the user signs in with it afterwards
+    async fn store(&self, credential_id: &str, credential: &Credential) -> anyhow::Result<()> {
+        let registered_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let inserted = sqlx::query(
+            "INSERT INTO passkey_credentials (id, public_key, sign_count, registered_at) \
+             VALUES (?, ?, ?, ?) ON CONFLICT (id) DO NOTHING",
+        )
+        .bind(credential_id)
+        .bind(&credential.public_key)
+        .bind(credential.sign_count)
+        .bind(registered_at as i64)
+        .execute(&self.credentials)
+        .await
+        .context("failed storing the passkey")?;
+        if inserted.rows_affected() == 0 {
+            return Err(invalid("the passkey is already registered"));
+        }
+        tracing::info!("Registered passkey {}", credential_id);
+        Ok(())
+    }
+
+    /// Verifies the assertion signed by a registered passkey
+    async fn authenticate(
+        &self,
+        credential: &PublicKeyCredential,
+    ) -> anyhow::Result<serde_json::Value> {
+        let response = &credential.response;
+        let client_data_json = decode("clientDataJSON", &response.client_data_json)?;
+        self.verify_client_data(Ceremony::Authentication, &client_data_json)?;
+
+        let authenticator_data = decode(
+            "authenticatorData",
+            response
+                .authenticator_data
+                .as_deref()
+                .ok_or_else(|| invalid("authenticatorData is missing"))?,
+        )?;
+        let signature = decode(
+            "signature",
+            response
+                .signature
+                .as_deref()
+                .ok_or_else(|| invalid("signature is missing"))?,
+        )?;
+        let auth_data = self.parse_authenticator_data(&authenticator_data)?;
+
+        let credential_id = credential.id.trim_end_matches('=').to_string();
+        let stored: Credential =
+            sqlx::query_as("SELECT public_key, sign_count FROM passkey_credentials WHERE id = ?")
+                .bind(&credential_id)
+                .fetch_optional(&self.credentials)
+                .await
+                .context("failed reading the passkey")?
+                .ok_or_else(|| unauthorized("the passkey is not registered"))?;
+        let mut signed = authenticator_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data_json));
+        ring::signature::UnparsedPublicKey::new(
+            &ring::signature::ECDSA_P256_SHA256_ASN1,
+            &stored.public_key,
+        )
+        .verify(&signed, &signature)
+        .map_err(|_| unauthorized("invalid passkey signature"))?;
+        // Authenticators that count the signatures never repeat a count, unless the key was cloned
+        let sign_count = i64::from(auth_data.sign_count);
+        if (sign_count != 0 || stored.sign_count != 0) && sign_count <= stored.sign_count {
+            tracing::warn!("Passkey {} signature count went backwards", credential_id);
+            return Err(unauthorized("the passkey signature count went backwards"));
+        }
+        sqlx::query("UPDATE passkey_credentials SET sign_count = ? WHERE id = ?")
+            .bind(sign_count)
+            .bind(&credential_id)
+            .execute(&self.credentials)
+            .await
+            .context("failed updating the passkey signature count")?;
+        Ok(self.new_session(credential_id))
+    }
+}
+
+/// Extracts the uncompressed point of an ES256 COSE key
+fn es256_public_key(key: &Value) -> anyhow::Result<Vec<u8>> {
+    let field = |label: i128| key.get(&Value::Integer(label));
+    let (kty, alg, crv) = (
+        field(1).and_then(Value::as_integer),
+        field(3).and_then(Value::as_integer),
+        field(-1).and_then(Value::as_integer),
+    );
+    // EC2 key type on the P-256 curve
+    if kty != Some(2) || alg != Some(COSE_ES256) || crv != Some(1) {
+        return Err(invalid("only ES256 passkeys are supported"));
+    }
+    match (
+        field(-2).and_then(Value::as_bytes),
+        field(-3).and_then(Value::as_bytes),
+    ) {
+        (Some(x), Some(y)) if x.len() == 32 && y.len() == 32 => Ok([&[0x04], x, y].concat()),
+        _ => Err(invalid("invalid ES256 public key coordinates")),
+    }
+}
+
+fn disabled() -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "result": null,
+        "error": { "code": ErrorCode::NotFound, "message": "passkeys are not enabled" },
+    }))
+}
+
+/// Endpoint: /passkeys/register/options
+/// Responds with the `PublicKeyCredentialCreationOptions` for `navigator.credentials.create` (JSON)
+pub(crate) async fn register_options_handler(near: web::Data<crate::NearData>) -> impl Responder {
+    tracing::debug!("POST /passkeys/register/options");
+    let Some(passkeys) = &near.passkeys else {
+        return disabled();
+    };
+    let challenge = match passkeys.new_challenge(Ceremony::Registration) {
+        Ok(challenge) => challenge,
+        Err(err) => return error_response(&err),
+    };
+    HttpResponse::Ok().json(serde_json::json!({
+        "publicKey": {
+            "challenge": challenge,
+            "rp": { "id": passkeys.config.rp_id, "name": passkeys.config.rp_id },
+            // Passkeys aren't tied to any account of the user, every registration is a new user
+            "user": {
+                "id": URL_SAFE_NO_PAD.encode(rand::random::<[u8; 16]>()),
+                "name": "faucet user",
+                "displayName": "faucet user",
+            },
+            "pubKeyCredParams": [{ "type": "public-key", "alg": COSE_ES256 }],
+            "timeout": CHALLENGE_TTL.as_millis() as u64,
+            "attestation": "none",
+            "authenticatorSelection": { "residentKey": "required", "userVerification": "preferred" },
+        },
+    }))
+}
+
+/// Endpoint: /passkeys/register
+/// Verifies the captchas and the credential created by the browser and stores the passkey (JSON)
+/// The registration doesn't start a session, the privileges of the passkeys come with `/passkeys/login`
+pub(crate) async fn register_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+    registration: web::Json<RegistrationRequest>,
+) -> impl Responder {
+    tracing::debug!("POST /passkeys/register");
+    let Some(passkeys) = &near.passkeys else {
+        return disabled();
+    };
+    match register(&req, &near, passkeys, &registration).await {
+        Ok(credential_id) => HttpResponse::Ok().json(serde_json::json!({
+            "credential_id": credential_id,
+        })),
+        Err(err) => {
+            tracing::debug!("Rejected passkey registration: {:?}", err);
+            error_response(&err)
+        }
+    }
+}
+
+/// Registrations go through the captchas of the form and are charged to the regular quota of the requester,
+/// its address if it isn't authenticated, so passkeys can't be minted in bulk
+async fn register(
+    req: &HttpRequest,
+    near: &crate::NearData,
+    passkeys: &Passkeys,
+    registration: &RegistrationRequest,
+) -> anyhow::Result<String> {
+    let remote_ip = crate::middleware::client_ip::client_ip(req);
+    if let Some(turnstile) = &near.turnstile {
+        turnstile
+            .verify(registration.turnstile_token.as_deref(), remote_ip)
+            .await?;
+    }
+    if let Some(recaptcha) = &near.recaptcha {
+        recaptcha
+            .funding_amount(registration.recaptcha_token.as_deref(), remote_ip)
+            .await?;
+    }
+    let (credential_id, credential) = passkeys.verify_registration(&registration.credential)?;
+    let requester = Identity::of(req)
+        .filter(|identity| !identity.is_passkey())
+        .or_else(|| remote_ip.map(Identity::client_address))
+        .into_iter()
+        .collect::<Vec<_>>();
+    near.quotas.acquire_all(&requester).await?;
+    if let Err(err) = passkeys.store(&credential_id, &credential).await {
+        near.quotas.release_all(&requester).await;
+        return Err(err);
+    }
+    Ok(credential_id)
+}
+
+/// Endpoint: /passkeys/login/options
+/// Responds with the `PublicKeyCredentialRequestOptions` for `navigator.credentials.get` (JSON)
+pub(crate) async fn login_options_handler(near: web::Data<crate::NearData>) -> impl Responder {
+    tracing::debug!("POST /passkeys/login/options");
+    let Some(passkeys) = &near.passkeys else {
+        return disabled();
+    };
+    let challenge = match passkeys.new_challenge(Ceremony::Authentication) {
+        Ok(challenge) => challenge,
+        Err(err) => return error_response(&err),
+    };
+    HttpResponse::Ok().json(serde_json::json!({
+        "publicKey": {
+            "challenge": challenge,
+            "rpId": passkeys.config.rp_id,
+            "timeout": CHALLENGE_TTL.as_millis() as u64,
+            "userVerification": "preferred",
+        },
+    }))
+}
+
+/// Endpoint: /passkeys/login
+/// Verifies the assertion of a registered passkey and starts a session (JSON)
+pub(crate) async fn login_handler(
+    near: web::Data<crate::NearData>,
+    credential: web::Json<PublicKeyCredential>,
+) -> impl Responder {
+    tracing::debug!("POST /passkeys/login");
+    let Some(passkeys) = &near.passkeys else {
+        return disabled();
+    };
+    match passkeys.authenticate(&credential).await {
+        Ok(session) => HttpResponse::Ok().json(session),
+        Err(err) => {
+            tracing::debug!("Rejected passkey login: {:?}", err);
+            error_response(&err)
+        }
+    }
+}
+
+/// Passkey identity of the request, if it carries a live session token
+pub(crate) fn identity_of(req: &HttpRequest) -> Option<Identity> {
+    let token = req.headers().get(PASSKEY_SESSION_HEADER)?.to_str().ok()?;
+    req.app_data::<web::Data<crate::NearData>>()?
+        .passkeys
+        .as_ref()?
+        .identity(token)
+}
+
+pub(crate) fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route(
+        "/passkeys/register/options",
+        web::post().to(register_options_handler),
+    )
+    .route("/passkeys/register", web::post().to(register_handler))
+    .route(
+        "/passkeys/login/options",
+        web::post().to(login_options_handler),
+    )
+    .route("/passkeys/login", web::post().to(login_handler));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn bounds_the_outstanding_challenges() {
+        let passkeys = Passkeys::new(
+            PasskeyConfig {
+                rp_id: "faucet.example.com".to_string(),
+                origins: vec!["https://faucet.example.com".to_string()],
+                session_ttl: Duration::from_secs(60),
+            },
+            "sqlite::memory:",
+        )
+        .await
+        .unwrap();
+        for _ in 0..MAX_CHALLENGES {
+            passkeys.new_challenge(Ceremony::Registration).unwrap();
+        }
+        let err = passkeys
+            .new_challenge(Ceremony::Authentication)
+            .unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::Overloaded);
+    }
+}
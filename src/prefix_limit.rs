@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::errors::RetryLater;
+
+/// Limit of the creations sharing the leading characters of the name under the same parent account
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PrefixLimitConfig {
+    pub(crate) limit: u32,
+    /// Leading characters of the normalized name compared
+    pub(crate) length: usize,
+    pub(crate) window: Duration,
+}
+
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    created: u32,
+}
+
+/// Creations per name prefix, kept in memory of the process serving them
+/// Catches the bots registering near-identical subaccounts like `airdrop-bot-001`, `airdrop-bot-002`...
+#[derive(Debug)]
+pub(crate) struct PrefixLimiter {
+    config: PrefixLimitConfig,
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+/// Key the creations of the account are counted under, e.g. `airdropb*.testnet` for `Airdrop-Bot-001.testnet`
+/// Case and separators are ignored, so `a-i-r-d-r-o-p` doesn't get a prefix of its own
+fn prefix_of(account_id: &str, length: usize) -> String {
+    let (name, parent) = account_id.split_once('.').unwrap_or((account_id, ""));
+    let prefix: String = name
+        .chars()
+        .filter(|c| !matches!(c, '-' | '_'))
+        .map(|c| c.to_ascii_lowercase())
+        .take(length.max(1))
+        .collect();
+    format!("{}*.{}", prefix, parent)
+}
+
+impl PrefixLimiter {
+    pub(crate) fn new(config: PrefixLimitConfig) -> Self {
+        Self {
+            config,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts the creation against its prefix, fails with `RATE_LIMITED` over the limit of the current window
+    pub(crate) fn acquire(&self, account_id: &str) -> anyhow::Result<()> {
+        let prefix = prefix_of(account_id, self.config.length);
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        let window = windows.entry(prefix.clone()).or_insert(Window {
+            started: now,
+            created: 0,
+        });
+        if now.duration_since(window.started) >= self.config.window {
+            window.started = now;
+            window.created = 0;
+        }
+        if window.created >= self.config.limit {
+            let retry_after = self
+                .config
+                .window
+                .saturating_sub(now.duration_since(window.started));
+            return Err(RetryLater {
+                message: format!(
+                    "too many accounts named like {} were created recently, try again in {} seconds or pick another name",
+                    prefix,
+                    retry_after.as_secs().max(1)
+                ),
+                retry_after,
+            }
+            .into());
+        }
+        window.created += 1;
+        Ok(())
+    }
+
+    /// Gives back the creation counted by `acquire`, used when the creation failed
+    pub(crate) fn release(&self, account_id: &str) {
+        let prefix = prefix_of(account_id, self.config.length);
+        if let Some(window) = self.windows.lock().unwrap().get_mut(&prefix) {
+            window.created = window.created.saturating_sub(1);
+        }
+    }
+
+    /// Forgets the prefixes whose window has passed, returns how many were forgotten
+    pub(crate) fn purge_expired(&self) -> usize {
+        let mut windows = self.windows.lock().unwrap();
+        let before = windows.len();
+        windows.retain(|_, window| window.started.elapsed() < self.config.window);
+        before - windows.len()
+    }
+}
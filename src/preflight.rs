@@ -0,0 +1,129 @@
+use near_account_id::AccountId;
+use near_crypto::{InMemorySigner, PublicKey};
+use near_jsonrpc_client::methods;
+use near_jsonrpc_primitives::types::query::QueryResponseKind;
+use near_primitives::{
+    types::{Balance, BlockReference, Finality},
+    views::{AccessKeyPermissionView, AccessKeyView, AccountView, QueryRequest},
+};
+
+use crate::utils::rpc_pool::RpcPool;
+
+/// Borsh size of an ed25519 public key and a full access key, stored for the key of the new account
+const ACCESS_KEY_BYTES: u64 = 33 + 9;
+
+/// Checks the signer setup against the RPC node before serving anything,
+/// so misconfigurations fail fast with guidance instead of as failed creations later
+pub(crate) async fn run(
+    rpc: &RpcPool,
+    signer: &InMemorySigner,
+    funding_amount: Balance,
+) -> anyhow::Result<()> {
+    tracing::info!("Running the preflight checks...");
+    let mut problems = vec![];
+
+    match view_account(rpc, &signer.account_id).await {
+        Ok(account) if account.amount < funding_amount => tracing::warn!(
+            "{} holds {} yoctoNEAR, less than the funding amount of {} yoctoNEAR",
+            signer.account_id,
+            account.amount,
+            funding_amount
+        ),
+        Ok(_) => {}
+        Err(err) => problems.push(format!(
+            "the base signer account {} (also the suffix of the created accounts) can't be found: {:#}; \
+             check --base-signer-account-id and that --near-rpc-url points to the right network",
+            signer.account_id, err
+        )),
+    }
+
+    match view_access_key(rpc, &signer.account_id, &signer.public_key).await {
+        Ok(key) => {
+            if let AccessKeyPermissionView::FunctionCall { .. } = key.permission {
+                problems.push(format!(
+                    "{} is a function call access key of {}, creating sub-accounts requires a full access key",
+                    signer.public_key, signer.account_id
+                ));
+            }
+        }
+        Err(err) => problems.push(format!(
+            "{} is not an access key of {}: {:#}; check that the configured secret key or credentials belong to the account",
+            signer.public_key, signer.account_id, err
+        )),
+    }
+
+    match storage_minimum(rpc).await {
+        Ok(minimum) if funding_amount < minimum => problems.push(format!(
+            "the funding amount of {} yoctoNEAR doesn't cover the storage of a new account, \
+             set --funding-amount to at least {} yoctoNEAR",
+            funding_amount, minimum
+        )),
+        Ok(_) => {}
+        Err(err) => problems.push(format!(
+            "failed fetching the protocol config to check the funding amount: {:#}",
+            err
+        )),
+    }
+
+    if !problems.is_empty() {
+        anyhow::bail!(
+            "preflight checks failed (pass --skip-preflight to start anyway):\n- {}",
+            problems.join("\n- ")
+        );
+    }
+    tracing::info!("Preflight checks passed");
+    Ok(())
+}
+
+pub(crate) async fn view_account(
+    rpc: &RpcPool,
+    account_id: &AccountId,
+) -> anyhow::Result<AccountView> {
+    let response = rpc
+        .call(methods::query::RpcQueryRequest {
+            block_reference: BlockReference::Finality(Finality::Final),
+            request: QueryRequest::ViewAccount {
+                account_id: account_id.clone(),
+            },
+        })
+        .await??;
+    match response.kind {
+        QueryResponseKind::ViewAccount(account) => Ok(account),
+        kind => anyhow::bail!("unexpected query response: {:?}", kind),
+    }
+}
+
+async fn view_access_key(
+    rpc: &RpcPool,
+    account_id: &AccountId,
+    public_key: &PublicKey,
+) -> anyhow::Result<AccessKeyView> {
+    let response = rpc
+        .call(methods::query::RpcQueryRequest {
+            block_reference: BlockReference::Finality(Finality::Final),
+            request: QueryRequest::ViewAccessKey {
+                account_id: account_id.clone(),
+                public_key: public_key.clone(),
+            },
+        })
+        .await??;
+    match response.kind {
+        QueryResponseKind::AccessKey(key) => Ok(key),
+        kind => anyhow::bail!("unexpected query response: {:?}", kind),
+    }
+}
+
+/// Balance a new account with a single access key has to hold for its storage
+async fn storage_minimum(rpc: &RpcPool) -> anyhow::Result<Balance> {
+    let config = rpc
+        .call(
+            methods::EXPERIMENTAL_protocol_config::RpcProtocolConfigRequest {
+                block_reference: BlockReference::Finality(Finality::Final),
+            },
+        )
+        .await??
+        .runtime_config;
+    let storage = &config.transaction_costs.storage_usage_config;
+    let bytes = storage.num_bytes_account + storage.num_extra_bytes_record + ACCESS_KEY_BYTES;
+    Ok(bytes as Balance * config.storage_amount_per_byte)
+}
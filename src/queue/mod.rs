@@ -0,0 +1,391 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use near_account_id::AccountId;
+use near_crypto::PublicKey;
+use near_primitives::types::Balance;
+use near_primitives::views::{FinalExecutionOutcomeView, TxExecutionStatus};
+use sqlx::PgPool;
+
+use crate::create_account::RequestOrigin;
+use crate::errors::{user_message, CodedError, ErrorCode};
+use crate::utils::send_tx::{Submitted, WaitLevel};
+
+pub(crate) mod worker;
+
+/// How the process participates in the account creation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum Mode {
+    /// Serves HTTP and signs/broadcasts the transactions itself
+    Standalone,
+    /// Serves HTTP and enqueues the validated requests for the workers, doesn't need the signer key
+    Frontend,
+    /// Consumes the queued requests, signs and broadcasts the transactions and writes back the results
+    Worker,
+}
+
+/// How often the frontends check whether the worker has finished the job
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Postgres-backed queue of account creation jobs shared by the frontends and the workers
+#[derive(Clone)]
+pub(crate) struct Queue {
+    pool: PgPool,
+    wait_timeout: Duration,
+}
+
+/// Job claimed by a worker
+pub(crate) struct Job {
+    pub(crate) id: i64,
+    pub(crate) account_id: String,
+    pub(crate) public_key: String,
+    pub(crate) wait: WaitLevel,
+    pub(crate) origin: RequestOrigin,
+}
+
+impl Queue {
+    /// Connects to the database and creates the jobs table if it doesn't exist yet
+    pub(crate) async fn connect(
+        database_url: &str,
+        wait_timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        let pool = PgPool::connect(database_url)
+            .await
+            .context("failed connecting to the queue database")?;
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS creation_jobs (
+                id BIGSERIAL PRIMARY KEY,
+                account_id TEXT NOT NULL,
+                public_key TEXT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'queued',
+                error TEXT,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .context("failed creating the creation_jobs table")?;
+        sqlx::query(
+            r#"
+            ALTER TABLE creation_jobs
+                ADD COLUMN IF NOT EXISTS wait_level TEXT NOT NULL DEFAULT 'executed',
+                ADD COLUMN IF NOT EXISTS outcome JSONB,
+                ADD COLUMN IF NOT EXISTS entry_point TEXT NOT NULL DEFAULT 'form',
+                ADD COLUMN IF NOT EXISTS tenant TEXT NOT NULL DEFAULT 'public',
+                ADD COLUMN IF NOT EXISTS error_code TEXT,
+                ADD COLUMN IF NOT EXISTS funding_amount TEXT,
+                ADD COLUMN IF NOT EXISTS reached_level TEXT
+            "#,
+        )
+        .execute(&pool)
+        .await
+        .context("failed migrating the creation_jobs table")?;
+        Ok(Self { pool, wait_timeout })
+    }
+
+    /// Connection pool of the queue database, shared with the other state of the frontends
+    pub(crate) fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Validates the request, puts it into the queue and waits until a worker writes back the result
+    pub(crate) async fn enqueue_and_wait(
+        &self,
+        account_id: &str,
+        public_key: &str,
+        wait: WaitLevel,
+        origin: &RequestOrigin,
+    ) -> anyhow::Result<Submitted> {
+        // Workers expect only valid requests, so the parsing errors are reported by the frontend right away
+        AccountId::from_str(account_id)
+            .with_context(|| format!("failed parsing account ID: {}", account_id))?;
+        PublicKey::from_str(public_key)
+            .with_context(|| format!("failed parsing public key: {}", public_key))?;
+
+        let id: i64 = sqlx::query_scalar(
+            r#"
+            INSERT INTO creation_jobs (account_id, public_key, wait_level, entry_point, tenant, funding_amount)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING id
+            "#,
+        )
+        .bind(account_id)
+        .bind(public_key)
+        .bind(wait.to_string())
+        .bind(origin.entry_point.as_str())
+        .bind(&origin.tenant)
+        // The requested amount until the worker writes back the one the account was funded with
+        .bind(origin.funding_amount.map(|amount| amount.to_string()))
+        .fetch_one(&self.pool)
+        .await
+        .context("failed enqueueing the creation job")?;
+        tracing::debug!("Enqueued job {} creating {}", id, account_id);
+
+        let deadline = tokio::time::Instant::now() + self.wait_timeout;
+        loop {
+            #[allow(clippy::type_complexity)]
+            let (status, error, error_code, outcome, reached_level): (
+                String,
+                Option<String>,
+                Option<String>,
+                Option<serde_json::Value>,
+                Option<String>,
+            ) = sqlx::query_as(
+                "SELECT status, error, error_code, outcome, reached_level FROM creation_jobs WHERE id = $1",
+            )
+            .bind(id)
+            .fetch_one(&self.pool)
+            .await
+            .context("failed fetching the creation job status")?;
+            match status.as_str() {
+                "succeeded" => {
+                    // The jobs completed by the older workers reached at least the requested level
+                    let reached = match reached_level {
+                        Some(level) => serde_json::from_value(serde_json::Value::String(level))
+                            .context("failed parsing the reached level of the job")?,
+                        None => TxExecutionStatus::from(wait),
+                    };
+                    let outcome: Option<FinalExecutionOutcomeView> = outcome
+                        .map(serde_json::from_value)
+                        .transpose()
+                        .context("failed parsing the transaction outcome of the job")?;
+                    return Ok(Submitted {
+                        reached,
+                        tx_hash: outcome.as_ref().map(|outcome| outcome.transaction.hash),
+                        outcome,
+                    });
+                }
+                "unknown" => {
+                    return Err(CodedError {
+                        code: ErrorCode::Pending,
+                        message: error
+                            .unwrap_or_else(|| "the result of the job is unknown".to_string()),
+                    }
+                    .into());
+                }
+                "failed" => {
+                    // The worker classified the error, the frontend only has its message left
+                    return Err(CodedError {
+                        code: error_code
+                            .and_then(|code| code.parse().ok())
+                            .unwrap_or(ErrorCode::InternalError),
+                        message: error.unwrap_or_else(|| "unknown error".to_string()),
+                    }
+                    .into());
+                }
+                _ => {}
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(CodedError {
+                    code: ErrorCode::Pending,
+                    message: format!(
+                        "account creation is still processing (job {}), check back later",
+                        id
+                    ),
+                }
+                .into());
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Claims the oldest queued job, `SKIP LOCKED` lets several workers consume the queue concurrently
+    pub(crate) async fn claim_next(&self) -> anyhow::Result<Option<Job>> {
+        #[allow(clippy::type_complexity)]
+        let job: Option<(i64, String, String, String, String, String, Option<String>)> =
+            sqlx::query_as(
+                r#"
+            UPDATE creation_jobs SET status = 'processing', updated_at = now()
+            WHERE id = (
+                SELECT id FROM creation_jobs
+                WHERE status = 'queued'
+                ORDER BY id
+                FOR UPDATE SKIP LOCKED
+                LIMIT 1
+            )
+            RETURNING id, account_id, public_key, wait_level, entry_point, tenant, funding_amount
+            "#,
+            )
+            .fetch_optional(&self.pool)
+            .await
+            .context("failed claiming a creation job")?;
+        let Some((id, account_id, public_key, wait_level, entry_point, tenant, funding_amount)) =
+            job
+        else {
+            return Ok(None);
+        };
+        let parsed = (|| -> anyhow::Result<Job> {
+            Ok(Job {
+                id,
+                account_id,
+                public_key,
+                wait: wait_level.parse()?,
+                origin: RequestOrigin {
+                    entry_point: entry_point.parse()?,
+                    tenant,
+                    // The frontend already admitted the request and charged the quota
+                    identity: None,
+                    proof_of_work: None,
+                    funding_amount: funding_amount.map(|amount| amount.parse()).transpose()?,
+                    invite_code: None,
+                    client_ip: None,
+                    captcha_verified: false,
+                    nonces: None,
+                },
+            })
+        })();
+        match parsed {
+            Ok(job) => Ok(Some(job)),
+            Err(err) => {
+                // Failed right away rather than left `processing` until the janitor marks it `unknown`, nothing was sent
+                let failed = Err(CodedError {
+                    code: ErrorCode::InternalError,
+                    message: format!("the creation job is malformed: {:#}", err),
+                }
+                .into());
+                self.complete(id, &failed, None).await?;
+                Err(err.context(format!("creation job {} is malformed", id)))
+            }
+        }
+    }
+
+    /// Latest successful creation of the account by the workers, in the shape of `GET /account/{account_id}/info`
+    #[cfg(feature = "contract-helper")]
+    pub(crate) async fn creation_record(
+        &self,
+        account_id: &str,
+    ) -> anyhow::Result<Option<serde_json::Value>> {
+        sqlx::query_scalar(
+            r#"
+            SELECT json_build_object(
+                'account_id', account_id,
+                'created_by_faucet', true,
+                'created_at', TO_CHAR(updated_at at time zone 'UTC', 'YYYY-MM-DD HH24:MI:SS.US'),
+                'public_key', public_key,
+                'funding_amount', funding_amount,
+                'transaction_hash', outcome->'transaction'->>'hash',
+                'source', 'faucet'
+            )
+            FROM creation_jobs
+            WHERE account_id = $1 AND status = 'succeeded'
+            ORDER BY id DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(account_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("failed fetching the creation record")
+    }
+
+    /// Fails the queued jobs no worker picked up in time, and marks the jobs whose worker never reported back
+    /// as `unknown`, since their transaction may or may not have been broadcast
+    /// Returns how many queued and processing jobs were expired
+    pub(crate) async fn expire_stale(&self, stale_after: Duration) -> anyhow::Result<(u64, u64)> {
+        let queued = sqlx::query(
+            r#"
+            UPDATE creation_jobs
+            SET status = 'failed', error = 'expired before a worker picked it up', error_code = $2, updated_at = now()
+            WHERE status = 'queued' AND created_at < now() - make_interval(secs => $1)
+            "#,
+        )
+        .bind(stale_after.as_secs_f64())
+        .bind(ErrorCode::InternalError.as_str())
+        .execute(&self.pool)
+        .await
+        .context("failed expiring the stale queued jobs")?
+        .rows_affected();
+        let processing = sqlx::query(
+            r#"
+            UPDATE creation_jobs
+            SET status = 'unknown', error = 'the worker never reported the result, the account may or may not have been created', updated_at = now()
+            WHERE status = 'processing' AND updated_at < now() - make_interval(secs => $1)
+            "#,
+        )
+        .bind(stale_after.as_secs_f64())
+        .execute(&self.pool)
+        .await
+        .context("failed expiring the stale processing jobs")?
+        .rows_affected();
+        Ok((queued, processing))
+    }
+
+    /// Numbers of the queued jobs and of those being processed by the workers
+    pub(crate) async fn depth(&self) -> anyhow::Result<(i64, i64)> {
+        sqlx::query_as(
+            r#"
+            SELECT count(*) FILTER (WHERE status = 'queued'), count(*) FILTER (WHERE status = 'processing')
+            FROM creation_jobs
+            WHERE status IN ('queued', 'processing')
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("failed counting the pending jobs")
+    }
+
+    /// Deletes the finished jobs older than `retention`, returns how many were deleted
+    pub(crate) async fn purge_finished(&self, retention: Duration) -> anyhow::Result<u64> {
+        Ok(sqlx::query(
+            r#"
+            DELETE FROM creation_jobs
+            WHERE status IN ('succeeded', 'failed', 'unknown') AND updated_at < now() - make_interval(secs => $1)
+            "#,
+        )
+        .bind(retention.as_secs_f64())
+        .execute(&self.pool)
+        .await
+        .context("failed purging the finished jobs")?
+        .rows_affected())
+    }
+
+    /// Writes back the result of the job for the frontend to pick up
+    /// The funding amount is kept for the account info, it may differ between the workers and change over time
+    pub(crate) async fn complete(
+        &self,
+        id: i64,
+        result: &anyhow::Result<Submitted>,
+        funding_amount: Option<Balance>,
+    ) -> anyhow::Result<()> {
+        let (status, error, error_code, outcome, reached_level) = match result {
+            Ok(submitted) => (
+                "succeeded",
+                None,
+                None,
+                submitted
+                    .outcome
+                    .as_ref()
+                    .map(serde_json::to_value)
+                    .transpose()?,
+                serde_json::to_value(&submitted.reached)?
+                    .as_str()
+                    .map(str::to_string),
+            ),
+            Err(err) => (
+                "failed",
+                Some(user_message(err)),
+                Some(ErrorCode::classify(err).as_str()),
+                None,
+                None,
+            ),
+        };
+        sqlx::query(
+            "UPDATE creation_jobs SET status = $2, error = $3, error_code = $4, outcome = $5, funding_amount = $6, reached_level = $7, updated_at = now() WHERE id = $1",
+        )
+        .bind(id)
+        .bind(status)
+        .bind(error)
+        .bind(error_code)
+        .bind(outcome)
+        .bind(funding_amount.filter(|_| result.is_ok()).map(|amount| amount.to_string()))
+        .bind(reached_level)
+        .execute(&self.pool)
+        .await
+        .context("failed storing the creation job result")?;
+        Ok(())
+    }
+}
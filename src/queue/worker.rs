@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use crate::queue::Queue;
+use crate::shutdown::Shutdown;
+use crate::NearData;
+
+/// How long the worker sleeps when the queue is empty
+const IDLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Consumes the creation jobs from the queue until the shutdown, signing and broadcasting the transactions
+/// On shutdown no more jobs are claimed, the current one gets the grace period to finish
+pub(crate) async fn run_worker(queue: Queue, near: NearData, shutdown: Shutdown) {
+    tracing::info!("Worker started, waiting for the creation jobs...");
+    while !shutdown.is_requested() {
+        let job = match queue.claim_next().await {
+            Ok(Some(job)) => job,
+            Ok(None) => {
+                tokio::select! {
+                    _ = tokio::time::sleep(IDLE_INTERVAL) => {}
+                    _ = shutdown.requested() => {}
+                }
+                continue;
+            }
+            Err(err) => {
+                tracing::warn!("Failed to claim a creation job: {:?}", err);
+                tokio::time::sleep(IDLE_INTERVAL).await;
+                continue;
+            }
+        };
+
+        tracing::debug!("Processing job {} creating {}", job.id, job.account_id);
+        let result = tokio::select! {
+            result = crate::create_account::submit(
+                &near,
+                &job.account_id,
+                &job.public_key,
+                job.wait,
+                &job.origin,
+            ) => result,
+            // The janitor marks the job as `unknown` later, its transaction may have been sent
+            _ = async {
+                shutdown.requested().await;
+                tokio::time::sleep(shutdown.grace()).await;
+            } => {
+                tracing::warn!("job {}: abandoned on shutdown", job.id);
+                break;
+            }
+        };
+        match &result {
+            Ok(_) => tracing::info!(
+                "job {}: successfully created {} {}",
+                job.id,
+                job.account_id,
+                job.public_key
+            ),
+            Err(err) => tracing::warn!("job {}: failed to create account: {:?}", job.id, err),
+        }
+        let funding_amount = job.origin.funding_amount.or(near
+            .submitter
+            .as_ref()
+            .map(|submitter| submitter.funding_amount()));
+        if let Err(err) = queue.complete(job.id, &result, funding_amount).await {
+            tracing::warn!("Failed to store the result of job {}: {:?}", job.id, err);
+        }
+    }
+    tracing::info!("Worker stopped");
+}
@@ -0,0 +1,472 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+#[cfg(feature = "shared-limits")]
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use serde::Serialize;
+
+use crate::errors::{ErrorCode, RetryLater};
+use crate::middleware::api_tokens::AuthenticatedToken;
+use crate::middleware::replay_guard::AuthenticatedClient;
+#[cfg(feature = "shared-limits")]
+use crate::shared_limits::{Counter, SharedLimits};
+
+const DAY_SECS: u64 = 24 * 60 * 60;
+const WEEK_SECS: u64 = 7 * DAY_SECS;
+/// The unix epoch was a Thursday, weeks start on Monday
+const WEEK_OFFSET_SECS: u64 = 3 * DAY_SECS;
+
+/// Who the quota is charged to, e.g. `api_key:partner`
+/// Each authentication method adds its own provider, so the same name from different providers never collides
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Identity {
+    provider: &'static str,
+    subject: String,
+}
+
+impl Identity {
+    pub(crate) fn api_key(key: &str) -> Self {
+        Self {
+            provider: "api_key",
+            subject: key.to_string(),
+        }
+    }
+
+    /// Programmatic client authenticated with a bearer token, by the name of the token
+    pub(crate) fn api_token(name: &str) -> Self {
+        Self {
+            provider: "api_token",
+            subject: name.to_string(),
+        }
+    }
+
+    #[cfg(feature = "discord")]
+    /// Member of the Discord guild gating the faucet, by the Discord user ID
+    pub(crate) fn discord(user_id: &str) -> Self {
+        Self {
+            provider: "discord",
+            subject: user_id.to_string(),
+        }
+    }
+
+    #[cfg(feature = "email")]
+    /// Address verified through an email link, lowercase and without its `+tag`
+    pub(crate) fn email(address: &str) -> Self {
+        Self {
+            provider: "email",
+            subject: address.to_string(),
+        }
+    }
+
+    #[cfg(feature = "telegram")]
+    /// User of the Telegram bot, by the Telegram user ID
+    pub(crate) fn telegram(user_id: i64) -> Self {
+        Self {
+            provider: "telegram",
+            subject: user_id.to_string(),
+        }
+    }
+
+    pub(crate) fn passkey(credential_id: &str) -> Self {
+        Self {
+            provider: "passkey",
+            subject: credential_id.to_string(),
+        }
+    }
+
+    /// Anonymous client by its address, e.g. charged for the passkeys it registers
+    pub(crate) fn client_address(ip: IpAddr) -> Self {
+        Self {
+            provider: "address",
+            subject: ip.to_string(),
+        }
+    }
+
+    /// Key the created accounts are given, charged on top of whoever requested them
+    pub(crate) fn public_key(public_key: &str) -> Self {
+        Self {
+            provider: "public_key",
+            subject: public_key.to_string(),
+        }
+    }
+
+    fn is_public_key(&self) -> bool {
+        self.provider == "public_key"
+    }
+
+    /// Programmatic client authenticated with an API key or token
+    pub(crate) fn is_client(&self) -> bool {
+        matches!(self.provider, "api_key" | "api_token")
+    }
+
+    #[cfg(feature = "discord")]
+    /// Discord user ID of a Discord session
+    pub(crate) fn discord_user(&self) -> Option<&str> {
+        (self.provider == "discord").then_some(self.subject.as_str())
+    }
+
+    /// Session started by the link sent to an email address
+    pub(crate) fn is_email(&self) -> bool {
+        self.provider == "email"
+    }
+
+    /// Returning user who proved it holds a registered passkey
+    pub(crate) fn is_passkey(&self) -> bool {
+        self.provider == "passkey"
+    }
+
+    /// Identity the request was authenticated as, anonymous requests have none
+    /// A signed API request takes precedence over a bearer token, then come the Discord, the email and the passkey sessions
+    pub(crate) fn of(req: &HttpRequest) -> Option<Self> {
+        // The extensions are released before the sessions are looked up, parsing their cookies writes to them
+        let client = {
+            let extensions = req.extensions();
+            extensions
+                .get::<AuthenticatedClient>()
+                .map(|client| Self::api_key(&client.0))
+                .or_else(|| {
+                    extensions
+                        .get::<AuthenticatedToken>()
+                        .map(|token| Self::api_token(&token.0))
+                })
+        };
+        client.or_else(|| {
+            #[cfg(feature = "discord")]
+            if let Some(identity) = crate::discord::identity_of(req) {
+                return Some(identity);
+            }
+            #[cfg(feature = "email")]
+            if let Some(identity) = crate::email::identity_of(req) {
+                return Some(identity);
+            }
+            crate::passkey::identity_of(req)
+        })
+    }
+}
+
+impl fmt::Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.provider, self.subject)
+    }
+}
+
+/// Creation limits of every identity, unlimited if not set
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct QuotaLimits {
+    pub(crate) daily: Option<u32>,
+    pub(crate) weekly: Option<u32>,
+}
+
+/// Creations made by an identity in the current day and week
+#[derive(Debug, Clone, Copy, Default)]
+struct Usage {
+    day: u64,
+    daily: u32,
+    week: u64,
+    weekly: u32,
+}
+
+impl Usage {
+    /// Starts over the counters of the windows that have passed
+    fn roll(&mut self, now: u64) {
+        let (day, week) = windows(now);
+        if self.day != day {
+            self.day = day;
+            self.daily = 0;
+        }
+        if self.week != week {
+            self.week = week;
+            self.weekly = 0;
+        }
+    }
+}
+
+fn windows(now: u64) -> (u64, u64) {
+    (now / DAY_SECS, (now + WEEK_OFFSET_SECS) / WEEK_SECS)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Allowance of one window as reported by `GET /quota`
+#[derive(Debug, Serialize)]
+pub(crate) struct Allowance {
+    limit: Option<u32>,
+    used: u32,
+    remaining: Option<u32>,
+    /// Unix timestamp the window starts over at
+    resets_at: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct QuotaStatus {
+    identity: String,
+    daily: Allowance,
+    weekly: Allowance,
+}
+
+/// Per-identity creation counters, kept in memory of the process serving the requests unless they are shared in Redis
+#[derive(Default)]
+pub(crate) struct QuotaStore {
+    limits: QuotaLimits,
+    /// Limits of the passkey sessions, usually higher than the API keys'
+    passkey_limits: QuotaLimits,
+    /// Limits of the addresses verified through the email links
+    email_limits: QuotaLimits,
+    /// Limits of the keys given to the created accounts, no matter who requests them
+    public_key_limits: QuotaLimits,
+    /// Limits of single identities overriding the ones of their provider, e.g. of the API tokens
+    identity_limits: HashMap<Identity, QuotaLimits>,
+    usage: Mutex<HashMap<Identity, Usage>>,
+    #[cfg(feature = "shared-limits")]
+    shared: Option<Arc<SharedLimits>>,
+}
+
+impl QuotaStore {
+    pub(crate) fn new(limits: QuotaLimits, passkey_limits: QuotaLimits) -> Self {
+        Self {
+            limits,
+            passkey_limits,
+            email_limits: QuotaLimits::default(),
+            public_key_limits: QuotaLimits::default(),
+            identity_limits: HashMap::new(),
+            usage: Mutex::new(HashMap::new()),
+            #[cfg(feature = "shared-limits")]
+            shared: None,
+        }
+    }
+
+    #[cfg(feature = "shared-limits")]
+    /// Keeps the counters in Redis, shared by all the replicas
+    pub(crate) fn with_shared(mut self, shared: Arc<SharedLimits>) -> Self {
+        self.shared = Some(shared);
+        self
+    }
+
+    /// Limits the accounts created per day with the same public key
+    pub(crate) fn with_public_key_daily_limit(mut self, daily: Option<u32>) -> Self {
+        self.public_key_limits.daily = daily;
+        self
+    }
+
+    #[cfg(feature = "email")]
+    /// Limits the accounts created per day by the same email address
+    pub(crate) fn with_email_daily_limit(mut self, daily: Option<u32>) -> Self {
+        self.email_limits.daily = daily;
+        self
+    }
+
+    /// Gives the identities their own limits, e.g. the daily quotas of the API tokens
+    pub(crate) fn with_identity_limits(mut self, limits: HashMap<Identity, QuotaLimits>) -> Self {
+        self.identity_limits.extend(limits);
+        self
+    }
+
+    fn limits_of(&self, identity: &Identity) -> QuotaLimits {
+        if let Some(limits) = self.identity_limits.get(identity) {
+            *limits
+        } else if identity.is_passkey() {
+            self.passkey_limits
+        } else if identity.is_email() {
+            self.email_limits
+        } else if identity.is_public_key() {
+            self.public_key_limits
+        } else {
+            self.limits
+        }
+    }
+
+    /// Identities a creation is charged to, the requesting identity if any and the public key if the keys are limited
+    pub(crate) fn charged_identities(
+        &self,
+        requester: Option<&Identity>,
+        public_key: &str,
+    ) -> Vec<Identity> {
+        let public_key =
+            (self.public_key_limits.daily.is_some()).then(|| Identity::public_key(public_key));
+        requester.cloned().into_iter().chain(public_key).collect()
+    }
+
+    /// Charges one creation to each of the identities, none of them is charged if any allowance is used up
+    pub(crate) async fn acquire_all(&self, identities: &[Identity]) -> anyhow::Result<()> {
+        for (charged, identity) in identities.iter().enumerate() {
+            if let Err(err) = self.acquire(identity).await {
+                self.release_all(&identities[..charged]).await;
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn release_all(&self, identities: &[Identity]) {
+        for identity in identities {
+            self.release(identity).await;
+        }
+    }
+
+    /// Charges one creation to the identity, fails with `RATE_LIMITED` if the allowance is used up
+    async fn acquire(&self, identity: &Identity) -> anyhow::Result<()> {
+        #[cfg(feature = "shared-limits")]
+        if let Some(shared) = &self.shared {
+            let limits = self.limits_of(identity);
+            match shared.acquire(&shared_counters(identity, limits)).await {
+                Some(Ok(())) => return Ok(()),
+                Some(Err(exhausted)) => {
+                    let limit = match exhausted {
+                        0 => limits.daily,
+                        _ => limits.weekly,
+                    };
+                    return Err(used_up(identity, limit.unwrap_or_default(), exhausted != 0));
+                }
+                None => {}
+            }
+        }
+        let mut usage = self.usage.lock().unwrap();
+        let usage = usage.entry(identity.clone()).or_default();
+        usage.roll(now());
+        let limits = self.limits_of(identity);
+        let exceeded = |limit: Option<u32>, used: u32| limit.is_some_and(|limit| used >= limit);
+        if exceeded(limits.daily, usage.daily) {
+            return Err(used_up(identity, usage.daily, false));
+        }
+        if exceeded(limits.weekly, usage.weekly) {
+            return Err(used_up(identity, usage.daily, true));
+        }
+        usage.daily += 1;
+        usage.weekly += 1;
+        Ok(())
+    }
+
+    /// Gives back the creation charged by `acquire`, used when the creation failed
+    async fn release(&self, identity: &Identity) {
+        #[cfg(feature = "shared-limits")]
+        if let Some(shared) = &self.shared {
+            let counters = shared_counters(identity, self.limits_of(identity));
+            if shared.release(&counters).await.is_some() {
+                return;
+            }
+        }
+        let mut usage = self.usage.lock().unwrap();
+        if let Some(usage) = usage.get_mut(identity) {
+            usage.roll(now());
+            usage.daily = usage.daily.saturating_sub(1);
+            usage.weekly = usage.weekly.saturating_sub(1);
+        }
+    }
+
+    /// Forgets the identities without any creation in the current week, returns how many were dropped
+    pub(crate) fn purge_expired(&self) -> usize {
+        let (_, week) = windows(now());
+        let mut usage = self.usage.lock().unwrap();
+        let before = usage.len();
+        usage.retain(|_, usage| usage.week == week);
+        before - usage.len()
+    }
+
+    pub(crate) async fn status(&self, identity: &Identity) -> QuotaStatus {
+        let now = now();
+        let mut usage = self
+            .usage
+            .lock()
+            .unwrap()
+            .get(identity)
+            .copied()
+            .unwrap_or_default();
+        usage.roll(now);
+        #[cfg(feature = "shared-limits")]
+        if let Some(shared) = &self.shared {
+            let [daily, weekly] = shared_counters(identity, QuotaLimits::default());
+            if let (Some(daily), Some(weekly)) = (
+                shared.count(&daily.key).await,
+                shared.count(&weekly.key).await,
+            ) {
+                usage.daily = daily;
+                usage.weekly = weekly;
+            }
+        }
+        let (day, week) = windows(now);
+        let limits = self.limits_of(identity);
+        let allowance = |limit: Option<u32>, used: u32, resets_at: u64| Allowance {
+            limit,
+            used,
+            remaining: limit.map(|limit| limit.saturating_sub(used)),
+            resets_at,
+        };
+        QuotaStatus {
+            identity: identity.to_string(),
+            daily: allowance(limits.daily, usage.daily, (day + 1) * DAY_SECS),
+            weekly: allowance(
+                limits.weekly,
+                usage.weekly,
+                (week + 1) * WEEK_SECS - WEEK_OFFSET_SECS,
+            ),
+        }
+    }
+}
+
+/// Error of a used up allowance, retried once its day or week is over
+fn used_up(identity: &Identity, used_today: u32, weekly: bool) -> anyhow::Error {
+    let message = match identity.is_public_key() {
+        true => format!(
+            "public key {} was already given {} accounts today, try again after midnight UTC or use another key",
+            identity.subject, used_today
+        ),
+        false => format!("creation quota of {} is used up", identity),
+    };
+    let now = now();
+    let (day, week) = windows(now);
+    let reset = match weekly {
+        true => (week + 1) * WEEK_SECS - WEEK_OFFSET_SECS,
+        false => (day + 1) * DAY_SECS,
+    };
+    RetryLater {
+        message,
+        retry_after: Duration::from_secs(reset.saturating_sub(now)),
+    }
+    .into()
+}
+
+#[cfg(feature = "shared-limits")]
+/// Redis counters of the current day and week of the identity
+fn shared_counters(identity: &Identity, limits: QuotaLimits) -> [Counter; 2] {
+    let (day, week) = windows(now());
+    [
+        Counter {
+            key: format!("quota:{}:day:{}", identity, day),
+            limit: limits.daily,
+            ttl: Duration::from_secs(2 * DAY_SECS),
+        },
+        Counter {
+            key: format!("quota:{}:week:{}", identity, week),
+            limit: limits.weekly,
+            ttl: Duration::from_secs(2 * WEEK_SECS),
+        },
+    ]
+}
+
+/// Endpoint: /quota
+/// Responds with the remaining creation allowance of the authenticated client (JSON)
+pub(crate) async fn quota_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+) -> impl Responder {
+    tracing::debug!("GET /quota");
+    match Identity::of(&req) {
+        Some(identity) => HttpResponse::Ok().json(near.quotas.status(&identity).await),
+        None => HttpResponse::Unauthorized().json(serde_json::json!({
+            "result": null,
+            "error": {
+                "code": ErrorCode::Unauthorized,
+                "message": "quotas are tracked for authenticated clients and passkey sessions only",
+            },
+        })),
+    }
+}
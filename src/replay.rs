@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context as _;
+use near_crypto::{KeyType, SecretKey};
+use serde::{Deserialize, Serialize};
+
+use crate::create_account::{EntryPoint, RequestOrigin};
+use crate::utils::send_tx::WaitLevel;
+
+/// Incoming creation request stripped of everything identifying: the account, the key and the client
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedRequest {
+    /// Milliseconds since the recording started
+    at_ms: u64,
+    entry_point: String,
+    wait: String,
+}
+
+/// Appends the sanitized creation requests to a JSON lines file
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    started: Instant,
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    pub(crate) fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed opening the request recording {}", path.display()))?;
+        tracing::info!("Recording the creation requests to {}", path.display());
+        Ok(Self {
+            started: Instant::now(),
+            file: Mutex::new(file),
+        })
+    }
+
+    pub(crate) fn record(&self, origin: &RequestOrigin, wait: WaitLevel) {
+        let request = RecordedRequest {
+            at_ms: self.started.elapsed().as_millis() as u64,
+            entry_point: origin.entry_point.as_str().to_string(),
+            wait: wait.to_string(),
+        };
+        let line = serde_json::to_string(&request).expect("recorded request is serializable");
+        if let Err(err) = writeln!(self.file.lock().unwrap(), "{}", line) {
+            tracing::warn!("Failed to record the creation request: {:?}", err);
+        }
+    }
+}
+
+/// Re-drives the recorded creation requests against a running service
+#[derive(Debug, clap::Args)]
+pub(crate) struct ReplayArgs {
+    /// Recording made with `--record-requests`
+    file: PathBuf,
+    /// Base URL of the service to send the requests to, e.g. `http://localhost:10000`
+    #[clap(long)]
+    target: String,
+    /// Playback speed, `2.0` sends the requests twice as fast as they were recorded
+    #[clap(long, default_value_t = 1.0)]
+    speed: f64,
+    /// Prefix of the generated account names, `<prefix>-<run>-<n>`
+    #[clap(long, default_value = "replay")]
+    prefix: String,
+}
+
+/// How a replayed request ended, the error code, or the HTTP status if there was none
+type Outcome = String;
+
+/// Sends the recorded requests with the original spacing divided by the speed, without waiting for the responses,
+/// each with a fresh account name and key, then reports the outcomes and latencies
+///
+/// Form requests are sent to the widget endpoint, it takes the same fields but answers with JSON
+pub(crate) async fn run(args: ReplayArgs) -> anyhow::Result<()> {
+    if args.speed <= 0.0 {
+        anyhow::bail!("--speed must be positive");
+    }
+    let file = File::open(&args.file)
+        .with_context(|| format!("failed opening {}", args.file.display()))?;
+    let requests = BufReader::new(file)
+        .lines()
+        .enumerate()
+        .map(|(number, line)| {
+            let line = line?;
+            let request: RecordedRequest = serde_json::from_str(&line)
+                .with_context(|| format!("line {}: invalid recorded request", number + 1))?;
+            Ok((
+                request.at_ms,
+                request.entry_point.parse::<EntryPoint>()?,
+                request.wait.parse::<WaitLevel>()?,
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let run_id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    tracing::info!(
+        "Replaying {} requests against {} at {}x speed",
+        requests.len(),
+        args.target,
+        args.speed
+    );
+
+    let client = reqwest::Client::new();
+    let target = args.target.trim_end_matches('/');
+    let first_at = requests.first().map(|(at, ..)| *at).unwrap_or_default();
+    let started = tokio::time::Instant::now();
+    let mut tasks = vec![];
+    for (number, (at_ms, entry_point, wait)) in requests.into_iter().enumerate() {
+        let offset = Duration::from_millis(at_ms.saturating_sub(first_at)).div_f64(args.speed);
+        tokio::time::sleep_until(started + offset).await;
+        let account_id = format!("{}-{}-{}", args.prefix, run_id, number);
+        let public_key = SecretKey::from_random(KeyType::ED25519)
+            .public_key()
+            .to_string();
+        let request = match entry_point {
+            // The Telegram requests are anonymous too, the widget form is the closest
+            EntryPoint::Form | EntryPoint::Widget | EntryPoint::Telegram => client
+                .post(format!("{}/widget/create_account?wait={}", target, wait))
+                .form(&[("account_id", &account_id), ("public_key", &public_key)]),
+            EntryPoint::Api => client
+                .post(format!("{}/account/create?wait={}", target, wait))
+                .json(&serde_json::json!({ "account_id": account_id, "public_key": public_key })),
+        };
+        tasks.push(tokio::spawn(async move {
+            let sent = Instant::now();
+            let outcome = send(request).await;
+            (outcome, sent.elapsed())
+        }));
+    }
+
+    let mut outcomes: BTreeMap<Outcome, usize> = BTreeMap::new();
+    let mut latencies = vec![];
+    for task in tasks {
+        let (outcome, latency) = task.await?;
+        *outcomes.entry(outcome).or_default() += 1;
+        latencies.push(latency);
+    }
+    latencies.sort();
+    let percentile = |p: usize| {
+        latencies
+            .get((latencies.len() * p / 100).min(latencies.len().saturating_sub(1)))
+            .copied()
+            .unwrap_or_default()
+    };
+    tracing::info!(
+        "Replayed {} requests in {:?}: {:?}, latency p50 {:?}, p95 {:?}, max {:?}",
+        latencies.len(),
+        started.elapsed(),
+        outcomes,
+        percentile(50),
+        percentile(95),
+        latencies.last().copied().unwrap_or_default()
+    );
+    Ok(())
+}
+
+async fn send(request: reqwest::RequestBuilder) -> Outcome {
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(err) => {
+            tracing::debug!("Replayed request failed: {:?}", err);
+            return "CONNECTION_FAILED".to_string();
+        }
+    };
+    let status = response.status();
+    // The widget answers `{"success", "code"}`, the API `{"result", "error": {"code"}}`
+    let body: serde_json::Value = response.json().await.unwrap_or_default();
+    let code = body
+        .get("code")
+        .or_else(|| body.pointer("/error/code"))
+        .and_then(|code| code.as_str());
+    match code {
+        Some(code) => code.to_string(),
+        None if status.is_success() => "OK".to_string(),
+        None => format!("HTTP_{}", status.as_u16()),
+    }
+}
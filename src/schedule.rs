@@ -0,0 +1,135 @@
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context as _;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::errors::{CodedError, ErrorCode};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/// Daily UTC time range the faucet is open in, `HH:MM-HH:MM`, may cross midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Window {
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&minute)
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+fn parse_minute(s: &str) -> anyhow::Result<u32> {
+    let (hours, minutes) = s
+        .trim()
+        .split_once(':')
+        .with_context(|| format!("expected HH:MM, got {}", s))?;
+    let (hours, minutes): (u32, u32) = (hours.parse()?, minutes.parse()?);
+    // 24:00 is allowed as the end of the day
+    if hours > 24 || minutes > 59 || (hours == 24 && minutes > 0) {
+        anyhow::bail!("invalid time of day {}", s);
+    }
+    Ok(hours * 60 + minutes)
+}
+
+impl FromStr for Window {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once('-')
+            .with_context(|| format!("expected HH:MM-HH:MM, got {}", s))?;
+        Ok(Self {
+            start: parse_minute(start)? % MINUTES_PER_DAY,
+            end: parse_minute(end)?,
+        })
+    }
+}
+
+/// Daily windows the faucet is open in, always open if there are none
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Schedule {
+    windows: Vec<Window>,
+}
+
+impl Schedule {
+    pub(crate) fn parse(windows: &[String]) -> anyhow::Result<Self> {
+        let windows = windows
+            .iter()
+            .map(|window| window.parse())
+            .collect::<anyhow::Result<_>>()
+            .context("failed parsing the availability windows")?;
+        Ok(Self { windows })
+    }
+
+    fn minute_of_day() -> u32 {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        ((secs / 60) % MINUTES_PER_DAY as u64) as u32
+    }
+
+    pub(crate) fn is_open(&self) -> bool {
+        let minute = Self::minute_of_day();
+        self.windows.is_empty() || self.windows.iter().any(|w| w.contains(minute))
+    }
+
+    /// When the faucet opens next as `HH:MM UTC`, `None` if it's open now
+    pub(crate) fn next_opening(&self) -> Option<String> {
+        if self.is_open() {
+            return None;
+        }
+        let minute = Self::minute_of_day();
+        self.windows
+            .iter()
+            .min_by_key(|w| (w.start + MINUTES_PER_DAY - minute) % MINUTES_PER_DAY)
+            .map(|w| format!("{:02}:{:02} UTC", w.start / 60, w.start % 60))
+    }
+
+    /// Fails with `FAUCET_CLOSED` outside of the windows
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        match self.next_opening() {
+            None => Ok(()),
+            Some(next_opening) => Err(CodedError {
+                code: ErrorCode::FaucetClosed,
+                message: format!("the faucet is closed, it opens at {}", next_opening),
+            }
+            .into()),
+        }
+    }
+}
+
+/// Spaces out the creations evenly instead of letting them burst
+#[derive(Debug)]
+pub(crate) struct Drip {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl Drip {
+    pub(crate) fn per_minute(rate: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(60) / rate.max(1),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits until the next free slot and takes it
+    pub(crate) async fn wait_turn(&self) {
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + self.interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
@@ -0,0 +1,199 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Context as _;
+
+use crate::utils::redis::{RedisClient, Reply};
+
+/// How long the local limits are used after Redis failed, before it's tried again
+const RETRY_AFTER_FAILURE: Duration = Duration::from_secs(10);
+
+/// Takes a token of the bucket in `KEYS[1]` refilled with `ARGV[1]` tokens per second up to `ARGV[2]`,
+/// returns whether one was taken (1 or 0) and the tokens left, separated by a space
+/// The time is Redis', so the replicas' clocks don't matter
+const TAKE_TOKEN_SCRIPT: &str = r#"
+local rate = tonumber(ARGV[1])
+local burst = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) + tonumber(time[2]) / 1000000
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(bucket[1]) or burst
+local updated = tonumber(bucket[2]) or now
+tokens = math.min(burst, tokens + math.max(0, now - updated) * rate)
+local taken = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    taken = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', tostring(now))
+redis.call('EXPIRE', KEYS[1], math.ceil(burst / rate) + 1)
+return taken .. ' ' .. tostring(tokens)
+"#;
+
+/// Counts one more in each of the `KEYS` unless any of them reached its limit in `ARGV[i]` (-1 if unlimited),
+/// the counters expire after `ARGV[#KEYS + i]` seconds
+/// Returns the 1-based index of the exhausted counter, 0 if all were counted
+const ACQUIRE_SCRIPT: &str = r#"
+local n = #KEYS
+for i = 1, n do
+    local limit = tonumber(ARGV[i])
+    if limit >= 0 and tonumber(redis.call('GET', KEYS[i]) or '0') >= limit then
+        return i
+    end
+end
+for i = 1, n do
+    redis.call('INCR', KEYS[i])
+    redis.call('EXPIRE', KEYS[i], ARGV[n + i])
+end
+return 0
+"#;
+
+/// Gives back one of each of the `KEYS`, never going below zero
+const RELEASE_SCRIPT: &str = r#"
+for i = 1, #KEYS do
+    if tonumber(redis.call('GET', KEYS[i]) or '0') > 0 then
+        redis.call('DECR', KEYS[i])
+    end
+end
+return 0
+"#;
+
+/// Counter of a fixed window shared by the replicas
+pub(crate) struct Counter {
+    pub(crate) key: String,
+    /// Unlimited if not set, the counter still counts
+    pub(crate) limit: Option<u32>,
+    /// Until the window is over
+    pub(crate) ttl: Duration,
+}
+
+/// Rate limit and quota counters kept in Redis, shared by all the replicas of the service
+/// While Redis is unreachable the limiters fall back to their local counters, `None` is returned for that
+pub(crate) struct SharedLimits {
+    client: RedisClient,
+    /// Prepended to all the keys, so several deployments can share a Redis
+    prefix: String,
+    unreachable_until: Mutex<Option<Instant>>,
+}
+
+impl SharedLimits {
+    pub(crate) fn new(redis_url: &str, prefix: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: RedisClient::from_url(redis_url).context("invalid limits Redis URL")?,
+            prefix: prefix.to_string(),
+            unreachable_until: Mutex::new(None),
+        })
+    }
+
+    /// Runs the command unless Redis failed recently, `None` if it can't be reached
+    async fn query(&self, args: &[&str]) -> Option<Reply> {
+        if self
+            .unreachable_until
+            .lock()
+            .unwrap()
+            .is_some_and(|until| until > Instant::now())
+        {
+            return None;
+        }
+        match self.client.query(args).await {
+            Ok(reply) => Some(reply),
+            Err(err) => {
+                tracing::warn!(
+                    "Falling back to the local limits for {}s, Redis failed: {:?}",
+                    RETRY_AFTER_FAILURE.as_secs(),
+                    err
+                );
+                *self.unreachable_until.lock().unwrap() =
+                    Some(Instant::now() + RETRY_AFTER_FAILURE);
+                None
+            }
+        }
+    }
+
+    async fn eval(&self, script: &str, keys: &[String], args: &[String]) -> Option<Reply> {
+        let count = keys.len().to_string();
+        let mut command = vec!["EVAL", script, &count];
+        command.extend(keys.iter().map(String::as_str));
+        command.extend(args.iter().map(String::as_str));
+        self.query(&command).await
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+
+    /// Takes a token of the bucket refilled with `rate` tokens per second up to `burst`,
+    /// returns the tokens left, as an error if none could be taken
+    pub(crate) async fn take_token(
+        &self,
+        key: &str,
+        rate: f64,
+        burst: f64,
+    ) -> Option<Result<f64, f64>> {
+        let reply = self
+            .eval(
+                TAKE_TOKEN_SCRIPT,
+                &[self.key(key)],
+                &[rate.to_string(), burst.to_string()],
+            )
+            .await?;
+        let state = match reply {
+            Reply::Bulk(state) => {
+                let state = String::from_utf8_lossy(&state).into_owned();
+                state
+                    .split_once(' ')
+                    .and_then(|(taken, tokens)| Some((taken == "1", tokens.parse::<f64>().ok()?)))
+            }
+            _ => None,
+        };
+        match state {
+            Some((true, tokens)) => Some(Ok(tokens)),
+            Some((false, tokens)) => Some(Err(tokens)),
+            None => {
+                tracing::warn!("Unexpected Redis reply of the token bucket {}", key);
+                None
+            }
+        }
+    }
+
+    /// Counts one more in each counter unless any of them is exhausted, returns the index of the exhausted one
+    pub(crate) async fn acquire(&self, counters: &[Counter]) -> Option<Result<(), usize>> {
+        let keys: Vec<_> = counters.iter().map(|c| self.key(&c.key)).collect();
+        let args: Vec<_> = counters
+            .iter()
+            .map(|c| c.limit.map_or(-1, i64::from).to_string())
+            .chain(counters.iter().map(|c| c.ttl.as_secs().max(1).to_string()))
+            .collect();
+        match self.eval(ACQUIRE_SCRIPT, &keys, &args).await? {
+            Reply::Integer(0) => Some(Ok(())),
+            Reply::Integer(exhausted) => Some(Err(exhausted as usize - 1)),
+            reply => {
+                tracing::warn!("Unexpected Redis reply of the counters: {:?}", reply);
+                None
+            }
+        }
+    }
+
+    /// Gives back one of each counter counted by `acquire`, used when the creation failed
+    pub(crate) async fn release(&self, counters: &[Counter]) -> Option<()> {
+        let keys: Vec<_> = counters.iter().map(|c| self.key(&c.key)).collect();
+        self.eval(RELEASE_SCRIPT, &keys, &[]).await.map(|_| ())
+    }
+
+    /// Time until the counter expires
+    pub(crate) async fn remaining(&self, key: &str) -> Option<Duration> {
+        match self.query(&["PTTL", &self.key(key)]).await? {
+            Reply::Integer(millis) if millis > 0 => Some(Duration::from_millis(millis as u64)),
+            _ => None,
+        }
+    }
+
+    /// Current value of the counter
+    pub(crate) async fn count(&self, key: &str) -> Option<u32> {
+        match self.query(&["GET", &self.key(key)]).await? {
+            Reply::Nil => Some(0),
+            Reply::Bulk(count) => String::from_utf8_lossy(&count).parse().ok(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+/// Notifies about SIGTERM or Ctrl-C, after which the process stops taking new work and finishes the creations in progress
+#[derive(Debug, Clone)]
+pub(crate) struct Shutdown {
+    requested: watch::Receiver<bool>,
+    grace: Duration,
+}
+
+impl Shutdown {
+    /// Spawns the task listening for the signals, the in-flight creations get `grace` to finish once one arrives
+    pub(crate) fn listen(grace: Duration) -> Self {
+        let (sender, requested) = watch::channel(false);
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            tracing::info!(
+                "Shutting down, waiting up to {}s for the account creations in progress...",
+                grace.as_secs()
+            );
+            sender.send_replace(true);
+            // Keeps the channel open, so the receivers don't see the shutdown as cancelled
+            sender.closed().await;
+        });
+        Self { requested, grace }
+    }
+
+    pub(crate) fn grace(&self) -> Duration {
+        self.grace
+    }
+
+    pub(crate) fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Resolves once the shutdown is requested
+    pub(crate) async fn requested(&self) {
+        let mut requested = self.requested.clone();
+        let _ = requested.wait_for(|requested| *requested).await;
+    }
+}
+
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+                return;
+            }
+            Err(err) => tracing::warn!("failed listening for SIGTERM: {:?}", err),
+        }
+    }
+    if let Err(err) = tokio::signal::ctrl_c().await {
+        tracing::warn!("failed listening for Ctrl-C: {:?}", err);
+        std::future::pending::<()>().await;
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+use near_crypto::{InMemorySigner, KeyType, Signer};
+use near_jsonrpc_client::methods;
+use near_jsonrpc_primitives::types::query::QueryResponseKind;
+use near_primitives::{
+    account::AccessKey,
+    action::{Action, AddKeyAction},
+    transaction::{SignedTransaction, Transaction},
+    types::{BlockReference, Finality},
+    views::{FinalExecutionStatus, QueryRequest},
+};
+
+use crate::errors::TransactionFailed;
+use crate::utils::block_hash::{current_block, BlockSource};
+use crate::utils::nonce::NonceAllocator;
+use crate::utils::rpc_pool::RpcPool;
+use crate::utils::send_tx::{send_tx_request, WaitLevel};
+
+/// Access key of the base account along with the nonces allocated for it
+/// Each lane has its own nonce counter, so the creations signed by different lanes don't wait for each other
+#[derive(Clone)]
+pub(crate) struct Lane {
+    pub(crate) signer: InMemorySigner,
+    pub(crate) nonce: NonceAllocator,
+}
+
+/// Signers of the `count` provisioned lanes, their keys derived from the base signer's one
+/// The derivation is deterministic, so the same keys are used across the restarts and replicas
+pub(crate) fn derive_signers(base: &InMemorySigner, count: u32) -> Vec<InMemorySigner> {
+    (1..=count)
+        .map(|index| {
+            InMemorySigner::from_seed(
+                base.account_id.clone(),
+                KeyType::ED25519,
+                &format!("{}/lane/{}", base.secret_key, index),
+            )
+        })
+        .collect()
+}
+
+/// Adds the keys of the signers missing from the base account as full access keys, in a single transaction
+pub(crate) async fn provision(
+    rpc: &RpcPool,
+    base: &InMemorySigner,
+    nonce: &NonceAllocator,
+    signers: &[InMemorySigner],
+) -> anyhow::Result<()> {
+    let response = rpc
+        .call(methods::query::RpcQueryRequest {
+            block_reference: BlockReference::Finality(Finality::Final),
+            request: QueryRequest::ViewAccessKeyList {
+                account_id: base.account_id.clone(),
+            },
+        })
+        .await??;
+    let existing: HashSet<_> = match response.kind {
+        QueryResponseKind::AccessKeyList(list) => {
+            list.keys.into_iter().map(|key| key.public_key).collect()
+        }
+        kind => anyhow::bail!("unexpected query response: {:?}", kind),
+    };
+    let actions: Vec<Action> = signers
+        .iter()
+        .filter(|signer| !existing.contains(&signer.public_key))
+        .map(|signer| {
+            Action::AddKey(Box::new(AddKeyAction {
+                public_key: signer.public_key.clone(),
+                access_key: AccessKey::full_access(),
+            }))
+        })
+        .collect();
+    if actions.is_empty() {
+        return Ok(());
+    }
+
+    tracing::info!(
+        "Adding {} signer lane key(s) to {}...",
+        actions.len(),
+        base.account_id
+    );
+    let tx = Transaction {
+        signer_id: base.account_id.clone(),
+        public_key: base.public_key.clone(),
+        nonce: nonce.next().await?,
+        receiver_id: base.account_id.clone(),
+        block_hash: current_block(rpc, BlockSource::Final).await?.hash,
+        actions,
+    };
+    let (hash, _size) = tx.get_hash_and_size();
+    let signed_transaction = SignedTransaction::new(base.sign(hash.as_ref()), tx);
+    let outcome = rpc
+        .call(send_tx_request(
+            &signed_transaction,
+            WaitLevel::Final.into(),
+        )?)
+        .await??
+        .final_execution_outcome
+        .context("the lane keys transaction has no outcome")?
+        .into_outcome();
+    match outcome.status {
+        FinalExecutionStatus::SuccessValue(_) => Ok(()),
+        FinalExecutionStatus::Failure(err) => Err(TransactionFailed(err).into()),
+        status => anyhow::bail!("adding the lane keys failed: {:?}", status),
+    }
+}
@@ -0,0 +1,37 @@
+use actix_web::{web, HttpResponse, Responder};
+
+/// Endpoint: /stats
+/// Responds with the current challenge level and the request rate it's based on, the daily cap usage,
+/// the block the transactions reference, `null` in the frontend mode, the totals of the creation history,
+/// `null` if the history can't be read, and the balance of the base account (JSON)
+pub(crate) async fn stats_handler(
+    near: web::Data<crate::NearData>,
+    status_page: web::Data<crate::status::StatusPage>,
+    schedule: web::Data<crate::schedule::Schedule>,
+) -> impl Responder {
+    tracing::debug!("GET /stats");
+    let block = near.submitter.as_ref().map(|submitter| {
+        let block = *submitter.block().borrow();
+        serde_json::json!({
+            "hash": block.hash,
+            "height": block.height,
+            "age_secs": block.age().as_secs(),
+        })
+    });
+    let (totals, status) =
+        tokio::join!(near.history.totals(), status_page.status(&near, &schedule));
+    let totals = match totals {
+        Ok(totals) => Some(totals),
+        Err(err) => {
+            tracing::warn!("{:?}", err);
+            None
+        }
+    };
+    HttpResponse::Ok().json(serde_json::json!({
+        "escalation": near.escalation.status(),
+        "daily_cap": near.daily_cap.as_ref().map(|cap| cap.status()),
+        "block": block,
+        "totals": totals,
+        "balance_yocto": status.balance_yocto(),
+    }))
+}
@@ -0,0 +1,339 @@
+use std::time::{Duration, Instant};
+
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder};
+use futures_util::future::join_all;
+use near_account_id::AccountId;
+use near_jsonrpc_client::methods;
+use near_primitives::types::{Balance, Nonce};
+use serde::{Deserialize, Serialize};
+use tera::Context;
+
+use crate::escalation::ChallengeLevel;
+use crate::tx_submitter::TxSubmitter;
+use crate::utils::circuit_breaker::CircuitState;
+use crate::utils::rpc_pool::{Endpoint, RpcPool};
+
+/// The status is cached for this long, so the page can't be used to hammer the RPC node
+const CACHE_TTL: Duration = Duration::from_secs(10);
+const RPC_TIMEOUT: Duration = Duration::from_secs(5);
+/// The balance is `low` once it covers fewer than this many creations
+const LOW_BALANCE_CREATIONS: Balance = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum BalanceBand {
+    Ok,
+    Low,
+    Empty,
+    /// The balance couldn't be fetched
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct RpcHealth {
+    /// Host of the endpoint only, the URLs may carry API keys
+    endpoint: String,
+    healthy: bool,
+    /// Whether the creations are sent to it, endpoints failing repeatedly are skipped for a while
+    in_rotation: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latest_block_height: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Last nonce allocated for one of the signer's access keys
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct LaneNonce {
+    public_key: String,
+    nonce: Option<Nonce>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct QueueDepth {
+    queued: i64,
+    processing: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ServiceStatus {
+    /// `operational` when an RPC endpoint is healthy, the balance is fine and nothing pauses the creations
+    overall: &'static str,
+    rpc: Vec<RpcHealth>,
+    /// State of the circuit breaker of the RPC calls
+    rpc_circuit: Option<CircuitState>,
+    /// Block hash the transactions reference and its age, `None` in the frontend mode
+    block_hash: Option<String>,
+    block_hash_age_secs: Option<u64>,
+    /// Nonces of the signer's access keys, empty in the frontend mode
+    nonces: Vec<LaneNonce>,
+    /// Jobs waiting for the workers, `None` unless in the frontend mode
+    queue: Option<QueueDepth>,
+    /// Creations waiting for the submission workers, `None` unless they're enabled
+    submission_queue: Option<usize>,
+    balance: BalanceBand,
+    /// Balance of the base account in yoctoNEAR, `None` if it couldn't be fetched
+    balance_yocto: Option<String>,
+    paused: bool,
+    /// Why the creations are paused, empty if they aren't
+    pause_reasons: Vec<String>,
+    /// NEAR transferred out by this process in the last 24 hours in yoctoNEAR, `None` unless the disbursement is capped
+    disbursed_last_day_yocto: Option<String>,
+    /// Unix timestamp the status was gathered at
+    checked_at: u64,
+}
+
+impl ServiceStatus {
+    pub(crate) fn balance_yocto(&self) -> Option<&str> {
+        self.balance_yocto.as_deref()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct StatusQuery {
+    format: Option<String>,
+}
+
+/// Gathers the service state for the status page
+pub(crate) struct StatusPage {
+    rpc: RpcPool,
+    base_account_id: AccountId,
+    funding_amount: Balance,
+    cached: tokio::sync::Mutex<Option<(Instant, ServiceStatus)>>,
+}
+
+impl StatusPage {
+    pub(crate) fn new(rpc: RpcPool, base_account_id: AccountId, funding_amount: Balance) -> Self {
+        Self {
+            rpc,
+            base_account_id,
+            funding_amount,
+            cached: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Probes each endpoint directly, bypassing the failover
+    async fn rpc_health(endpoint: &Endpoint) -> RpcHealth {
+        let started = Instant::now();
+        let result = tokio::time::timeout(
+            RPC_TIMEOUT,
+            endpoint.client().call(methods::status::RpcStatusRequest),
+        )
+        .await;
+        let (latest_block_height, error) = match result {
+            Ok(Ok(status)) => (Some(status.sync_info.latest_block_height), None),
+            Ok(Err(err)) => (None, Some(err.to_string())),
+            Err(_) => (None, Some("timed out".to_string())),
+        };
+        RpcHealth {
+            endpoint: endpoint.host().to_string(),
+            healthy: error.is_none(),
+            in_rotation: endpoint.is_up(),
+            latency_ms: error
+                .is_none()
+                .then(|| started.elapsed().as_millis() as u64),
+            latest_block_height,
+            error,
+        }
+    }
+
+    async fn balance(&self) -> Option<Balance> {
+        match tokio::time::timeout(
+            RPC_TIMEOUT,
+            crate::preflight::view_account(&self.rpc, &self.base_account_id),
+        )
+        .await
+        {
+            Ok(Ok(account)) => Some(account.amount),
+            Ok(Err(err)) => {
+                tracing::warn!("Failed fetching the faucet balance: {:?}", err);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    fn balance_band(&self, balance: Option<Balance>) -> BalanceBand {
+        match balance {
+            Some(amount) if amount < self.funding_amount => BalanceBand::Empty,
+            Some(amount) if amount < self.funding_amount.saturating_mul(LOW_BALANCE_CREATIONS) => {
+                BalanceBand::Low
+            }
+            Some(_) => BalanceBand::Ok,
+            None => BalanceBand::Unknown,
+        }
+    }
+
+    async fn lane_nonces(submitter: &TxSubmitter) -> Vec<LaneNonce> {
+        join_all(submitter.lanes().iter().map(|lane| async {
+            let nonce = match lane.nonce.current().await {
+                Ok(nonce) => Some(nonce),
+                Err(err) => {
+                    tracing::warn!("Failed fetching the current nonce: {:?}", err);
+                    None
+                }
+            };
+            LaneNonce {
+                public_key: lane.signer.public_key.to_string(),
+                nonce,
+            }
+        }))
+        .await
+    }
+
+    async fn gather(
+        &self,
+        near: &crate::NearData,
+        schedule: &crate::schedule::Schedule,
+    ) -> ServiceStatus {
+        let (rpc, balance_yocto, nonces) = tokio::join!(
+            join_all(self.rpc.endpoints().iter().map(Self::rpc_health)),
+            self.balance(),
+            async {
+                match &near.submitter {
+                    Some(submitter) => Self::lane_nonces(submitter).await,
+                    None => vec![],
+                }
+            }
+        );
+        let balance = self.balance_band(balance_yocto);
+        let block = near
+            .submitter
+            .as_ref()
+            .map(|submitter| *submitter.block().borrow());
+
+        #[allow(unused_mut)]
+        let mut queue = None;
+        #[cfg(feature = "queue")]
+        if let Some(jobs) = &near.queue {
+            match jobs.depth().await {
+                Ok((queued, processing)) => queue = Some(QueueDepth { queued, processing }),
+                Err(err) => tracing::warn!("Failed fetching the queue depth: {:?}", err),
+            }
+        }
+
+        let mut pause_reasons = vec![];
+        if let Some(pause) = near.maintenance.current() {
+            pause_reasons.push(match pause.reason {
+                _ if pause.paused_by == crate::disbursement::PAUSED_BY => {
+                    "the daily disbursement cap is reached".to_string()
+                }
+                Some(reason) => format!("paused by the admins: {}", reason),
+                None => "paused by the admins".to_string(),
+            });
+        }
+        if let Some(next_opening) = schedule.next_opening() {
+            pause_reasons.push(format!(
+                "outside of the availability windows, opens at {}",
+                next_opening
+            ));
+        }
+        if near.escalation.level() == ChallengeLevel::Deny {
+            pause_reasons.push("denying all requests under heavy load".to_string());
+        }
+        if near
+            .daily_cap
+            .as_ref()
+            .is_some_and(|cap| cap.is_exhausted())
+        {
+            pause_reasons.push("the daily account cap is reached".to_string());
+        }
+        if balance == BalanceBand::Empty {
+            pause_reasons.push("the faucet balance can't cover another account".to_string());
+        }
+
+        let operational = rpc.iter().any(|endpoint| endpoint.healthy)
+            && balance == BalanceBand::Ok
+            && pause_reasons.is_empty();
+        ServiceStatus {
+            overall: if operational {
+                "operational"
+            } else {
+                "degraded"
+            },
+            rpc,
+            rpc_circuit: self.rpc.circuit_state(),
+            block_hash: block.map(|block| block.hash.to_string()),
+            block_hash_age_secs: block.map(|block| block.age().as_secs()),
+            nonces,
+            queue,
+            submission_queue: near.submitter.as_ref().and_then(TxSubmitter::queued),
+            balance,
+            balance_yocto: balance_yocto.map(|amount| amount.to_string()),
+            paused: !pause_reasons.is_empty(),
+            pause_reasons,
+            disbursed_last_day_yocto: near
+                .disbursement
+                .as_ref()
+                .map(|cap| cap.current().to_string()),
+            checked_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub(crate) async fn status(
+        &self,
+        near: &crate::NearData,
+        schedule: &crate::schedule::Schedule,
+    ) -> ServiceStatus {
+        let mut cached = self.cached.lock().await;
+        match &*cached {
+            Some((at, status)) if at.elapsed() < CACHE_TTL => status.clone(),
+            _ => {
+                let status = self.gather(near, schedule).await;
+                *cached = Some((Instant::now(), status.clone()));
+                status
+            }
+        }
+    }
+}
+
+/// Whether the client accepts JSON and not HTML, e.g. a programmatic client rather than a browser
+pub(crate) fn prefers_json(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json") && !accept.contains("text/html"))
+}
+
+/// JSON is served to `?format=json` and to clients preferring it over HTML
+fn wants_json(req: &HttpRequest, query: &StatusQuery) -> bool {
+    match query.format.as_deref() {
+        Some(format) => format == "json",
+        None => prefers_json(req),
+    }
+}
+
+/// Endpoint: /status
+/// Responds with the RPC health, cached block hash and its age, nonces, queue depths, balance and whether the creations are paused
+/// (HTML, or JSON with `?format=json` or `Accept: application/json`)
+pub(crate) async fn status_handler(
+    req: HttpRequest,
+    query: web::Query<StatusQuery>,
+    near: web::Data<crate::NearData>,
+    schedule: web::Data<crate::schedule::Schedule>,
+    page: web::Data<StatusPage>,
+    templates: web::Data<crate::templates::Templates>,
+) -> actix_web::Result<impl Responder> {
+    tracing::debug!("GET /status");
+    let status = page.status(&near, &schedule).await;
+    if wants_json(&req, &query) {
+        return Ok(HttpResponse::Ok().json(status));
+    }
+
+    let mut context = Context::new();
+    context.insert("status", &status);
+    let rendered = templates
+        .render("status.html.tera", &context)
+        .map_err(|err| {
+            actix_web::error::ErrorInternalServerError(format!(
+                "Failed to render template: {:?}",
+                err
+            ))
+        })?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(rendered))
+}
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use near_primitives::types::Balance;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tracing::Instrument;
+
+use crate::errors::{CodedError, ErrorCode};
+use crate::metrics::SUBMISSION_QUEUE_DEPTH;
+use crate::tx_submitter::TxSubmitter;
+use crate::utils::nonce::NonceBlock;
+use crate::utils::send_tx::{Submitted, WaitLevel};
+
+type SubmissionResult = anyhow::Result<Submitted>;
+
+/// Creation waiting for a worker, answered through `reply`
+struct Job {
+    account_id: String,
+    public_key: String,
+    wait: WaitLevel,
+    funding_amount: Balance,
+    nonces: Option<Arc<NonceBlock>>,
+    /// Span of the request, so the transaction hashes are still recorded on its access log line
+    span: tracing::Span,
+    reply: oneshot::Sender<SubmissionResult>,
+}
+
+/// Bounded queue of the creations in front of a fixed number of worker tasks submitting them
+/// The handlers only enqueue and wait, the requests over the capacity are rejected with `RATE_LIMITED`
+#[derive(Clone)]
+pub(crate) struct SubmissionPool {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl SubmissionPool {
+    /// Spawns `workers` tasks submitting the queued creations through the given submitter
+    pub(crate) fn spawn(submitter: TxSubmitter, workers: usize, capacity: usize) -> Self {
+        let (jobs, receiver) = mpsc::channel(capacity.max(1));
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..workers.max(1) {
+            tokio::spawn(run_worker(index, submitter.clone(), receiver.clone()));
+        }
+        Self { jobs }
+    }
+
+    /// Creations waiting for a worker
+    pub(crate) fn depth(&self) -> usize {
+        self.jobs.max_capacity() - self.jobs.capacity()
+    }
+
+    /// Queues the creation and waits for a worker to submit it
+    pub(crate) async fn submit(
+        &self,
+        account_id: &str,
+        public_key: &str,
+        wait: WaitLevel,
+        funding_amount: Balance,
+        nonces: Option<Arc<NonceBlock>>,
+    ) -> SubmissionResult {
+        let (reply, result) = oneshot::channel();
+        let job = Job {
+            account_id: account_id.to_string(),
+            public_key: public_key.to_string(),
+            wait,
+            funding_amount,
+            nonces,
+            span: tracing::Span::current(),
+            reply,
+        };
+        match self.jobs.try_send(job) {
+            Ok(()) => SUBMISSION_QUEUE_DEPTH.inc(),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                return Err(CodedError {
+                    code: ErrorCode::RateLimited,
+                    message: "too many accounts are waiting to be created, try again later"
+                        .to_string(),
+                }
+                .into())
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                anyhow::bail!("the submission workers stopped")
+            }
+        }
+        result
+            .await
+            .context("the submission worker dropped the request")?
+    }
+}
+
+async fn run_worker(index: usize, submitter: TxSubmitter, jobs: Arc<Mutex<mpsc::Receiver<Job>>>) {
+    tracing::debug!("Submission worker {} started", index);
+    loop {
+        let Some(job) = jobs.lock().await.recv().await else {
+            return;
+        };
+        SUBMISSION_QUEUE_DEPTH.dec();
+        let result = submitter
+            .submit(
+                &job.account_id,
+                &job.public_key,
+                job.wait,
+                job.funding_amount,
+                job.nonces.as_deref(),
+            )
+            .instrument(job.span)
+            .await;
+        // The client may have given up waiting, the account is created regardless
+        let _ = job.reply.send(result);
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Context as _;
+use serde::Deserialize;
+
+use crate::create_account::{EntryPoint, RequestOrigin};
+use crate::errors::ErrorCode;
+use crate::quota::Identity;
+use crate::tx_tracker::CreationTx;
+
+const API_URL: &str = "https://api.telegram.org";
+/// How long `getUpdates` waits for new messages before answering empty
+const POLL_TIMEOUT_SECS: u64 = 30;
+/// Delay before polling again after a failed poll
+const POLL_RETRY_DELAY: Duration = Duration::from_secs(5);
+const USAGE: &str =
+    "Send /create <name> <public key> to create an account, e.g. /create alice ed25519:...
+Add the invite code at the end if the faucet asks for one.";
+
+/// Bot token and the time between the creations of the same Telegram user
+#[derive(Debug, Clone)]
+pub(crate) struct TelegramConfig {
+    pub(crate) bot_token: String,
+    pub(crate) cooldown: Duration,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<IncomingMessage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IncomingMessage {
+    message_id: i64,
+    from: Option<User>,
+    chat: Chat,
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct User {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+/// Command of a message addressed to the bot
+#[derive(Debug, PartialEq, Eq)]
+enum Command<'a> {
+    Help,
+    Create {
+        name: &'a str,
+        public_key: &'a str,
+        invite_code: Option<&'a str>,
+    },
+}
+
+/// Parses `/create <name> <public key> [invite code]`, the commands sent in groups carry the bot's `@username`
+fn parse(text: &str) -> Option<Command<'_>> {
+    let mut words = text.split_whitespace();
+    let command = words.next()?;
+    let command = command
+        .split_once('@')
+        .map_or(command, |(command, _)| command);
+    match command {
+        "/start" | "/help" => Some(Command::Help),
+        "/create" => match (words.next(), words.next(), words.next(), words.next()) {
+            (Some(name), Some(public_key), invite_code, None) => Some(Command::Create {
+                name,
+                public_key,
+                invite_code,
+            }),
+            _ => Some(Command::Help),
+        },
+        _ => None,
+    }
+}
+
+/// Telegram bot creating accounts through the long-polled Bot API
+/// The creations go through the same admission as the other entry points, charged to the Telegram user
+struct TelegramBot {
+    config: TelegramConfig,
+    http: reqwest::Client,
+    /// Last creation of each Telegram user
+    creations: Mutex<HashMap<i64, Instant>>,
+}
+
+impl TelegramBot {
+    fn new(config: TelegramConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            config,
+            http: reqwest::Client::builder()
+                // Longer than the long poll, which is answered once it times out
+                .timeout(Duration::from_secs(POLL_TIMEOUT_SECS + 10))
+                .build()
+                .context("failed building the Telegram HTTP client")?,
+            creations: Mutex::new(HashMap::new()),
+        })
+    }
+
+    async fn call<T: serde::de::DeserializeOwned>(
+        &self,
+        method: &str,
+        body: serde_json::Value,
+    ) -> anyhow::Result<T> {
+        let response: ApiResponse<T> = self
+            .http
+            .post(format!(
+                "{}/bot{}/{}",
+                API_URL, self.config.bot_token, method
+            ))
+            .json(&body)
+            .send()
+            .await
+            .with_context(|| format!("failed calling the Telegram {} method", method))?
+            .json()
+            .await
+            .with_context(|| format!("failed parsing the Telegram {} response", method))?;
+        match (response.ok, response.result) {
+            (true, Some(result)) => Ok(result),
+            _ => anyhow::bail!(
+                "Telegram {} failed: {}",
+                method,
+                response.description.unwrap_or_default()
+            ),
+        }
+    }
+
+    async fn updates(&self, offset: i64) -> anyhow::Result<Vec<Update>> {
+        self.call(
+            "getUpdates",
+            serde_json::json!({
+                "offset": offset,
+                "timeout": POLL_TIMEOUT_SECS,
+                "allowed_updates": ["message"],
+            }),
+        )
+        .await
+    }
+
+    async fn reply(&self, message: &IncomingMessage, text: &str) {
+        let sent = self
+            .call::<serde_json::Value>(
+                "sendMessage",
+                serde_json::json!({
+                    "chat_id": message.chat.id,
+                    "text": text,
+                    "reply_to_message_id": message.message_id,
+                }),
+            )
+            .await;
+        if let Err(err) = sent {
+            tracing::warn!("Failed to reply on Telegram: {:?}", err);
+        }
+    }
+
+    /// Starts the cooldown of the user, fails with the seconds left if it's still running
+    fn admit(&self, user_id: i64) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut creations = self.creations.lock().unwrap();
+        if let Some(last) = creations.get(&user_id) {
+            let elapsed = now.duration_since(*last);
+            if elapsed < self.config.cooldown {
+                return Err((self.config.cooldown - elapsed).as_secs().max(1));
+            }
+        }
+        creations.insert(user_id, now);
+        Ok(())
+    }
+
+    /// Ends the cooldown started by `admit`, used when the creation failed
+    fn release(&self, user_id: i64) {
+        self.creations.lock().unwrap().remove(&user_id);
+    }
+
+    fn purge_expired(&self) {
+        let now = Instant::now();
+        self.creations
+            .lock()
+            .unwrap()
+            .retain(|_, last| now.duration_since(*last) < self.config.cooldown);
+    }
+
+    async fn handle(&self, near: &crate::NearData, message: IncomingMessage) {
+        let (Some(user), Some(text)) = (&message.from, &message.text) else {
+            return;
+        };
+        let (name, public_key, invite_code) = match parse(text) {
+            Some(Command::Create {
+                name,
+                public_key,
+                invite_code,
+            }) => (name, public_key, invite_code),
+            Some(Command::Help) => return self.reply(&message, USAGE).await,
+            None => return,
+        };
+        // Nothing can sign the challenges from a chat
+        if near.key_proofs.is_some() {
+            return self
+                .reply(
+                    &message,
+                    "This faucet requires proving the ownership of the key, use the website instead.",
+                )
+                .await;
+        }
+        let data = match near.validation.validate(name, public_key) {
+            Ok(data) => data,
+            Err(errors) => return self.reply(&message, &errors.to_string()).await,
+        };
+        if let Err(retry_after) = self.admit(user.id) {
+            return self
+                .reply(
+                    &message,
+                    &format!(
+                        "You created an account recently, try again in {} seconds.",
+                        retry_after
+                    ),
+                )
+                .await;
+        }
+
+        let origin = RequestOrigin {
+            entry_point: EntryPoint::Telegram,
+            tenant: RequestOrigin::PUBLIC_TENANT.to_string(),
+            identity: Some(Identity::telegram(user.id)),
+            proof_of_work: None,
+            funding_amount: None,
+            invite_code: invite_code.map(str::to_string),
+            client_ip: None,
+            captcha_verified: false,
+            nonces: None,
+        };
+        let result = crate::create_account::create_account(
+            near,
+            &data.account_id,
+            &data.public_key,
+            near.default_wait,
+            &origin,
+        )
+        .await;
+        let text = match result {
+            Ok(submitted) => {
+                tracing::info!(
+                    "Telegram user {} created {} {}",
+                    user.id,
+                    data.account_id,
+                    data.public_key
+                );
+                match CreationTx::of(&submitted, near.explorer_tx_url.as_deref()) {
+                    Some(CreationTx {
+                        explorer_url: Some(explorer_url),
+                        ..
+                    }) => format!("Account {} created: {}", data.account_id, explorer_url),
+                    Some(tx) => format!(
+                        "Account {} created, transaction {}",
+                        data.account_id, tx.tx_hash
+                    ),
+                    None => format!("Account {} created", data.account_id),
+                }
+            }
+            Err(err) => {
+                tracing::warn!(
+                    "Failed to create {} for Telegram user {}: {:?}",
+                    data.account_id,
+                    user.id,
+                    err
+                );
+                // A creation still in progress may succeed yet, so its cooldown stays
+                if ErrorCode::classify(&err) != ErrorCode::Pending {
+                    self.release(user.id);
+                }
+                crate::errors::user_message(&err)
+            }
+        };
+        self.reply(&message, &text).await;
+    }
+}
+
+/// Long-polls the Bot API for the `/create` commands until the shutdown, each message is handled in its own task
+/// Only one process may poll with the same bot token, run the bot on a single replica
+pub(crate) async fn run_bot(
+    near: crate::NearData,
+    config: TelegramConfig,
+    shutdown: crate::shutdown::Shutdown,
+) {
+    let bot = match TelegramBot::new(config) {
+        Ok(bot) => std::sync::Arc::new(bot),
+        Err(err) => {
+            tracing::error!("Failed to start the Telegram bot: {:?}", err);
+            return;
+        }
+    };
+    tracing::info!("Starting the Telegram bot...");
+    let mut offset = 0;
+    loop {
+        let updates = tokio::select! {
+            updates = bot.updates(offset) => updates,
+            _ = shutdown.requested() => return,
+        };
+        match updates {
+            Ok(updates) => {
+                for update in updates {
+                    // Confirmed by the next poll, so each update is handled once
+                    offset = offset.max(update.update_id + 1);
+                    if let Some(message) = update.message {
+                        let (bot, near) = (bot.clone(), near.clone());
+                        tokio::spawn(async move { bot.handle(&near, message).await });
+                    }
+                }
+                bot.purge_expired();
+            }
+            Err(err) => {
+                tracing::warn!("Failed to poll the Telegram updates: {:?}", err);
+                tokio::time::sleep(POLL_RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_commands() {
+        assert_eq!(
+            parse("/create alice ed25519:abc"),
+            Some(Command::Create {
+                name: "alice",
+                public_key: "ed25519:abc",
+                invite_code: None,
+            })
+        );
+        assert_eq!(
+            parse("/create@faucet_bot alice ed25519:abc code"),
+            Some(Command::Create {
+                name: "alice",
+                public_key: "ed25519:abc",
+                invite_code: Some("code"),
+            })
+        );
+        assert_eq!(parse("/create alice"), Some(Command::Help));
+        assert_eq!(parse("/start"), Some(Command::Help));
+        assert_eq!(parse("hello"), None);
+    }
+}
@@ -0,0 +1,71 @@
+use near_primitives::types::Balance;
+use serde::Serialize;
+use tera::{Context, Tera};
+
+const NEAR_DECIMALS: usize = 24;
+/// Digits of the fractional NEAR amounts shown on the pages, the rest is cut off
+const SHOWN_DECIMALS: usize = 5;
+
+/// Values available to every template, on top of what the handlers insert
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct GlobalContext {
+    pub(crate) network: String,
+    pub(crate) account_suffix: String,
+    /// Formatted in NEAR, e.g. `100 NEAR`
+    pub(crate) funding_amount: String,
+    pub(crate) version: &'static str,
+    /// Shown on top of the pages, e.g. to announce a maintenance
+    pub(crate) maintenance_banner: Option<String>,
+    /// Whether the public key may be left empty to get a generated one
+    pub(crate) generated_keys: bool,
+    /// Site key of the Turnstile widget rendered in the form, `None` if Turnstile is disabled
+    pub(crate) turnstile_site_key: Option<String>,
+    /// Site key of the reCAPTCHA v3 script scoring the form, `None` if reCAPTCHA is disabled
+    pub(crate) recaptcha_site_key: Option<String>,
+    /// Whether the forms ask for an invite code
+    pub(crate) invite_required: bool,
+    /// Whether the creations are restricted to the members of a Discord server
+    pub(crate) discord_required: bool,
+    /// Whether the creations require an email address verified through a link
+    pub(crate) email_required: bool,
+    /// Whether the forms ask for a signed key challenge
+    pub(crate) key_proof_required: bool,
+    /// Lengths and characters of the allowed account names, e.g. `2 to 56 lowercase letters and digits`
+    pub(crate) name_policy: String,
+}
+
+/// Tera templates rendered with the global context
+/// The handlers only insert their own values, the globals are merged in by `render`
+#[derive(Clone)]
+pub(crate) struct Templates {
+    tera: Tera,
+    globals: Context,
+}
+
+impl Templates {
+    pub(crate) fn new(tera: Tera, globals: &GlobalContext) -> anyhow::Result<Self> {
+        Ok(Self {
+            tera,
+            globals: Context::from_serialize(globals)?,
+        })
+    }
+
+    /// Renders the template, the values of the context take precedence over the globals
+    pub(crate) fn render(&self, template: &str, context: &Context) -> tera::Result<String> {
+        let mut merged = self.globals.clone();
+        merged.extend(context.clone());
+        self.tera.render(template, &merged)
+    }
+}
+
+/// Formats a yoctoNEAR amount in NEAR, e.g. `100 NEAR` or `0.5 NEAR`
+pub(crate) fn format_near(amount: Balance) -> String {
+    let unit = 10u128.pow(NEAR_DECIMALS as u32);
+    let fraction = format!("{:0width$}", amount % unit, width = NEAR_DECIMALS);
+    let fraction = fraction[..SHOWN_DECIMALS].trim_end_matches('0');
+    if fraction.is_empty() {
+        format!("{} NEAR", amount / unit)
+    } else {
+        format!("{}.{} NEAR", amount / unit, fraction)
+    }
+}
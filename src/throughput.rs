@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::errors::RetryLater;
+
+pub(crate) const MINUTE: Duration = Duration::from_secs(60);
+pub(crate) const HOUR: Duration = Duration::from_secs(60 * 60);
+/// Longest a request waits in the queue for a slot, those further away are rejected right away
+const MAX_WAIT: Duration = MINUTE;
+
+/// Most creations in any sliding window of the given length
+#[derive(Debug, Clone, Copy)]
+struct Cap {
+    window: Duration,
+    limit: usize,
+}
+
+/// Caps the total creations per minute and per hour regardless of who requests them,
+/// protecting the access key of the base account, its balance and the RPC node
+#[derive(Debug)]
+pub(crate) struct ThroughputLimiter {
+    caps: Vec<Cap>,
+    /// How many requests may wait for a free slot, the rest are rejected right away
+    max_queued: usize,
+    queued: AtomicUsize,
+    /// Creations within the longest window, oldest first
+    submitted: Mutex<VecDeque<Instant>>,
+}
+
+/// Counts the request as queued until it gets a slot or is dropped
+struct QueuedGuard<'a>(&'a AtomicUsize);
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn at_capacity(retry_after: Duration) -> anyhow::Error {
+    let seconds = retry_after.as_secs().max(1);
+    RetryLater {
+        message: format!(
+            "the faucet is at its creation capacity, try again in {} seconds",
+            seconds
+        ),
+        retry_after: Duration::from_secs(seconds),
+    }
+    .into()
+}
+
+impl ThroughputLimiter {
+    /// Limiter without any cap, add them with `with_limit`
+    pub(crate) fn new(max_queued: usize) -> Self {
+        Self {
+            caps: Vec::new(),
+            max_queued,
+            queued: AtomicUsize::new(0),
+            submitted: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Allows at most `limit` creations in any sliding `window`
+    pub(crate) fn with_limit(mut self, window: Duration, limit: u32) -> Self {
+        self.caps.push(Cap {
+            window,
+            limit: limit.max(1) as usize,
+        });
+        self
+    }
+
+    /// Takes a slot in every window, or returns the earliest instant one frees up in all of them
+    fn try_take(&self) -> Result<(), Instant> {
+        let now = Instant::now();
+        let longest = self
+            .caps
+            .iter()
+            .map(|cap| cap.window)
+            .max()
+            .unwrap_or_default();
+        let mut submitted = self.submitted.lock().unwrap();
+        while submitted
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= longest)
+        {
+            submitted.pop_front();
+        }
+        let free_at = self
+            .caps
+            .iter()
+            .filter_map(|cap| {
+                let in_window = submitted
+                    .iter()
+                    .rev()
+                    .take_while(|at| now.duration_since(**at) < cap.window)
+                    .count();
+                (in_window >= cap.limit)
+                    .then(|| submitted[submitted.len() - cap.limit] + cap.window)
+            })
+            .max();
+        match free_at {
+            Some(free_at) => Err(free_at),
+            None => {
+                submitted.push_back(now);
+                Ok(())
+            }
+        }
+    }
+
+    /// Waits for a slot, fails with `RATE_LIMITED` and the time until the next slot if too many requests are waiting
+    /// already or the next slot is over a minute away, e.g. when the hourly cap is reached
+    pub(crate) async fn acquire(&self) -> anyhow::Result<()> {
+        let free_at = match self.try_take() {
+            Ok(()) => return Ok(()),
+            Err(free_at) => free_at,
+        };
+        let wait = free_at.saturating_duration_since(Instant::now());
+        if wait > MAX_WAIT {
+            return Err(at_capacity(wait));
+        }
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return Err(at_capacity(wait));
+        }
+        let _queued = QueuedGuard(&self.queued);
+        loop {
+            match self.try_take() {
+                Ok(()) => return Ok(()),
+                Err(free_at) => tokio::time::sleep_until(free_at).await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{retry_after, ErrorCode};
+
+    #[tokio::test]
+    async fn rejects_the_requests_past_the_hourly_cap() {
+        let limiter = ThroughputLimiter::new(10)
+            .with_limit(MINUTE, 5)
+            .with_limit(HOUR, 2);
+        limiter.acquire().await.unwrap();
+        limiter.acquire().await.unwrap();
+        // The next slot is an hour away, too long to wait for
+        let err = limiter.acquire().await.unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::RateLimited);
+        assert!(retry_after(&err).unwrap() > MINUTE);
+    }
+}
@@ -0,0 +1,941 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use near_account_id::AccountId;
+use near_crypto::{PublicKey, Signer};
+use near_jsonrpc_client::{
+    errors::{JsonRpcError, JsonRpcServerError},
+    methods::tx::RpcTransactionError,
+};
+use near_primitives::{
+    account::AccessKey,
+    action::{Action, AddKeyAction, CreateAccountAction, TransferAction},
+    errors::{InvalidTxError, TxExecutionError},
+    hash::CryptoHash,
+    transaction::{SignedTransaction, Transaction},
+    types::{Balance, Nonce},
+    views::{FinalExecutionOutcomeView, FinalExecutionStatus, TxExecutionStatus},
+};
+use tokio::sync::watch;
+
+use crate::errors::{CodedError, ErrorCode, NonceRetriesExhausted, TransactionFailed};
+use crate::metrics;
+use crate::middleware::access_log::TX_HASHES_FIELD;
+use crate::schedule::{Drip, Schedule};
+use crate::signer_lanes::Lane;
+use crate::submission_pool::SubmissionPool;
+use crate::throughput::{ThroughputLimiter, HOUR, MINUTE};
+use crate::tx_tracker::TxTracker;
+use crate::utils::block_hash::{
+    current_block, update_block_hash, BlockInfo, BlockRefresh, BlockSource,
+};
+use crate::utils::nonce::NonceBlock;
+use crate::utils::retry::{nonce_retry_delay, CongestionPolicy, RetryPolicy};
+use crate::utils::rpc_client::{AccountCreatorRpc, SubmitterRpc};
+use crate::utils::rpc_pool::{is_congestion, is_endpoint_failure, RpcTimeout};
+use crate::utils::send_tx::{SendTxResponse, Submitted, WaitLevel};
+
+/// A fresh block hash is fetched at most this many times per creation, a node still rejecting it is broken
+const MAX_EXPIRED_RETRIES: u32 = 2;
+/// Nonces tried at most per creation, the node keeps rejecting them when other transactions race for the access key
+const MAX_NONCE_RETRIES: u32 = 5;
+
+/// How long the status of a timed out transaction is polled before giving up
+const STATUS_POLL_TIMEOUT: Duration = Duration::from_secs(60);
+const STATUS_POLL_MAX_DELAY: Duration = Duration::from_secs(8);
+/// How long the outcome of an asynchronously broadcast transaction is polled for
+const BACKGROUND_POLL_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Signs and broadcasts the account creation transactions of the base signer
+/// The only place transactions are submitted from, shared by all the entry points and the queue workers
+/// Generic over the RPC client so the tests can script the node's answers
+#[derive(Clone)]
+pub(crate) struct TxSubmitter<R = SubmitterRpc> {
+    rpc: R,
+    /// Access keys of the base account, the creations are spread over them round-robin
+    lanes: Arc<Vec<Lane>>,
+    next_lane: Arc<AtomicUsize>,
+    /// Latest block published by the updater, its hash is the reference of the transactions
+    /// Also refreshed right away when a transaction is rejected as expired
+    block: Arc<watch::Sender<BlockInfo>>,
+    block_source: BlockSource,
+    funding_amount: Balance,
+    schedule: Schedule,
+    drip: Option<Arc<Drip>>,
+    throughput: Option<Arc<ThroughputLimiter>>,
+    /// Set in the asynchronous broadcast mode, the outcomes are polled in the background
+    tracker: Option<TxTracker>,
+    retry: RetryPolicy,
+    congestion: CongestionPolicy,
+    /// Set when the creations are submitted by the worker tasks rather than by the handlers
+    pool: Option<SubmissionPool>,
+    /// Number of the creations in progress, drained on shutdown
+    in_flight: Arc<watch::Sender<usize>>,
+    /// Hash of the latest transaction sent for each account being created
+    sent: Arc<std::sync::Mutex<HashMap<String, CryptoHash>>>,
+}
+
+/// Counts a creation as in progress until dropped, including when its request is dropped
+struct InFlightGuard<'a>(&'a watch::Sender<usize>);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.send_modify(|in_flight| *in_flight -= 1);
+    }
+}
+
+impl<R: AccountCreatorRpc> TxSubmitter<R> {
+    /// Fetches the current block and spawns the updater keeping it fresh
+    pub(crate) async fn new(
+        rpc: R,
+        lanes: Vec<Lane>,
+        funding_amount: Balance,
+        refresh: BlockRefresh,
+    ) -> anyhow::Result<Self> {
+        let block = Arc::new(watch::Sender::new(
+            current_block(&rpc, refresh.source)
+                .await
+                .context("failed fetching latest block hash")?,
+        ));
+        tracing::debug!("Spawning the block hash updater...");
+        tokio::spawn(update_block_hash(rpc.clone(), block.clone(), refresh));
+        Ok(Self {
+            rpc,
+            lanes: Arc::new(lanes),
+            next_lane: Arc::new(AtomicUsize::new(0)),
+            block,
+            block_source: refresh.source,
+            funding_amount,
+            schedule: Schedule::default(),
+            drip: None,
+            throughput: None,
+            tracker: None,
+            retry: RetryPolicy::default(),
+            congestion: CongestionPolicy::default(),
+            pool: None,
+            in_flight: Arc::new(watch::Sender::new(0)),
+            sent: Arc::default(),
+        })
+    }
+
+    /// Rejects the creations outside of the availability windows
+    pub(crate) fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedule = schedule;
+        self
+    }
+
+    /// Submits at most `per_minute` creations a minute, evenly spaced
+    pub(crate) fn with_drip(mut self, per_minute: u32) -> Self {
+        self.drip = Some(Arc::new(Drip::per_minute(per_minute)));
+        self
+    }
+
+    /// Submits at most `per_minute` creations in any minute and `per_hour` in any hour,
+    /// up to `max_queued` requests wait for a slot
+    pub(crate) fn with_throughput_limit(
+        mut self,
+        per_minute: Option<u32>,
+        per_hour: Option<u32>,
+        max_queued: usize,
+    ) -> Self {
+        let mut limiter = ThroughputLimiter::new(max_queued);
+        if let Some(per_minute) = per_minute {
+            limiter = limiter.with_limit(MINUTE, per_minute);
+        }
+        if let Some(per_hour) = per_hour {
+            limiter = limiter.with_limit(HOUR, per_hour);
+        }
+        self.throughput = Some(Arc::new(limiter));
+        self
+    }
+
+    /// Retries sending the transaction on transient RPC failures, once all the endpoints failed
+    pub(crate) fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sends the transactions rejected for a congested shard again after a delay, until the total wait runs out
+    pub(crate) fn with_congestion_policy(mut self, congestion: CongestionPolicy) -> Self {
+        self.congestion = congestion;
+        self
+    }
+
+    /// Returns as soon as the node accepts the transaction, its outcome is tracked in the background
+    pub(crate) fn with_async_broadcast(mut self) -> Self {
+        self.tracker = Some(TxTracker::default());
+        self
+    }
+
+    /// Outcomes of the transactions broadcast asynchronously, `None` unless in that mode
+    pub(crate) fn tracker(&self) -> Option<&TxTracker> {
+        self.tracker.as_ref()
+    }
+
+    /// Access keys the creations are signed with
+    pub(crate) fn lanes(&self) -> &[Lane] {
+        &self.lanes
+    }
+
+    /// Creations waiting for the workers, `None` unless there are any
+    pub(crate) fn queued(&self) -> Option<usize> {
+        self.pool.as_ref().map(SubmissionPool::depth)
+    }
+
+    /// Waits until the creations in progress finish or the deadline passes, returns how many are still unfinished
+    pub(crate) async fn drain(&self, deadline: tokio::time::Instant) -> usize {
+        let mut in_flight = self.in_flight.subscribe();
+        let _ = tokio::time::timeout_at(deadline, in_flight.wait_for(|in_flight| *in_flight == 0))
+            .await;
+        let unfinished = *in_flight.borrow();
+        unfinished
+    }
+
+    /// The base account all the lanes sign for
+    fn account_id(&self) -> &AccountId {
+        &self.lanes[0].signer.account_id
+    }
+
+    /// Picks the access key of the next creation, so concurrent creations don't contend on one nonce counter
+    fn next_lane(&self) -> &Lane {
+        &self.lanes[self.next_lane.fetch_add(1, Ordering::Relaxed) % self.lanes.len()]
+    }
+
+    /// Configured funding of the new accounts, the requests may ask for a reduced one, see `RequestOrigin`
+    pub(crate) fn funding_amount(&self) -> Balance {
+        self.funding_amount
+    }
+
+    /// Latest block known to the submitter, `changed()` on the receiver notifies about the updates
+    pub(crate) fn block(&self) -> watch::Receiver<BlockInfo> {
+        self.block.subscribe()
+    }
+
+    /// Polls the status of a sent transaction with backoff until the node knows its outcome at the `wait` level
+    /// Gives up with `PENDING` after `timeout`, the transaction may still land
+    async fn poll_status(
+        &self,
+        hash: &CryptoHash,
+        wait: WaitLevel,
+        timeout: Duration,
+    ) -> anyhow::Result<Result<SendTxResponse, JsonRpcError<RpcTransactionError>>> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut delay = Duration::from_secs(1);
+        loop {
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(STATUS_POLL_MAX_DELAY);
+            match self
+                .rpc
+                .tx_status(hash, self.account_id(), wait.into())
+                .await
+            {
+                Err(err) => tracing::warn!("failed polling the status of {}: {}", hash, err),
+                // Not seen by the node yet, or still not at the wait level
+                Ok(Err(JsonRpcError::ServerError(JsonRpcServerError::HandlerError(
+                    RpcTransactionError::UnknownTransaction { .. }
+                    | RpcTransactionError::TimeoutError,
+                )))) => tracing::debug!("transaction {} is still pending", hash),
+                Ok(Err(JsonRpcError::TransportError(err))) => {
+                    tracing::warn!("failed polling the status of {}: {:?}", hash, err)
+                }
+                Ok(response) => return Ok(response),
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(CodedError {
+                    code: ErrorCode::Pending,
+                    message: format!(
+                        "transaction {} was sent but its outcome is still unknown, check it in the explorer before retrying",
+                        hash
+                    ),
+                }
+                .into());
+            }
+        }
+    }
+
+    /// Polls the final outcome of an asynchronously broadcast transaction and records it in the tracker
+    async fn track_outcome(self, hash: CryptoHash, tracker: TxTracker) {
+        let result = match self
+            .poll_status(&hash, WaitLevel::Final, BACKGROUND_POLL_TIMEOUT)
+            .await
+        {
+            Ok(Ok(response)) => match response
+                .final_execution_outcome
+                .map(|outcome| outcome.into_outcome().status)
+            {
+                Some(FinalExecutionStatus::SuccessValue(_)) => Ok(()),
+                Some(FinalExecutionStatus::Failure(err)) => Err(TransactionFailed(err).into()),
+                status => Err(anyhow::anyhow!(
+                    "transaction execution failed: {:?}",
+                    status
+                )),
+            },
+            Ok(Err(err)) => Err(err.into()),
+            Err(err) => Err(err),
+        };
+        match &result {
+            Ok(()) => tracing::info!("asynchronously broadcast transaction {} succeeded", hash),
+            Err(err) => tracing::warn!(
+                "asynchronously broadcast transaction {} failed: {:?}",
+                hash,
+                err
+            ),
+        }
+        tracker.finish(&hash, &result);
+    }
+
+    /// Fetches the latest block and publishes it, used when the cached block hash turned out to be too old
+    async fn refresh_block_hash(&self) -> anyhow::Result<CryptoHash> {
+        let block = current_block(&self.rpc, self.block_source)
+            .await
+            .context("failed fetching latest block hash")?;
+        self.block.send_replace(block);
+        Ok(block.hash)
+    }
+
+    /// Creates a Transaction with actions:
+    /// - CreateAccount
+    /// - AddKey
+    /// - Transfer (funding the account with `funding_amount`)
+    /// Signs the transaction by the base signer and sends it to the NEAR RPC node
+    /// Waits for the transaction to reach the given `wait` level before returning
+    /// Returns the outcome of the transaction, unless the wait level was reached before the execution
+    /// Goes through the queue of the workers if there are any
+    /// Takes the nonce from `nonces` while it has some left, see `reserve_nonces`
+    pub(crate) async fn create_account(
+        &self,
+        account_id: &str,
+        public_key: &str,
+        wait: WaitLevel,
+        funding_amount: Balance,
+        nonces: Option<Arc<NonceBlock>>,
+    ) -> anyhow::Result<Submitted> {
+        self.in_flight.send_modify(|in_flight| *in_flight += 1);
+        let _in_flight = InFlightGuard(&self.in_flight);
+        let result = match &self.pool {
+            Some(pool) => {
+                pool.submit(account_id, public_key, wait, funding_amount, nonces)
+                    .await
+            }
+            None => {
+                self.submit(
+                    account_id,
+                    public_key,
+                    wait,
+                    funding_amount,
+                    nonces.as_deref(),
+                )
+                .await
+            }
+        };
+        self.sent.lock().unwrap().remove(account_id);
+        result
+    }
+
+    /// Reserves `count` consecutive nonces of the next lane for the creations of a batch
+    pub(crate) async fn reserve_nonces(&self, count: u64) -> anyhow::Result<NonceBlock> {
+        let lane = self.next_lane.fetch_add(1, Ordering::Relaxed) % self.lanes.len();
+        NonceBlock::reserve(lane, self.lanes[lane].nonce.clone(), count).await
+    }
+
+    /// Hash of the latest transaction sent to create the account, `None` if nothing was sent yet
+    pub(crate) fn sent_hash(&self, account_id: &str) -> Option<CryptoHash> {
+        self.sent.lock().unwrap().get(account_id).copied()
+    }
+
+    /// Same as `create_account`, run by the workers or by the handlers themselves without them
+    pub(crate) async fn submit(
+        &self,
+        account_id: &str,
+        public_key: &str,
+        wait: WaitLevel,
+        funding_amount: Balance,
+        nonces: Option<&NonceBlock>,
+    ) -> anyhow::Result<Submitted> {
+        tracing::debug!(
+            "Creating account {} with public key {}",
+            account_id,
+            public_key
+        );
+        let new_account = AccountId::from_str(account_id)
+            .with_context(|| format!("failed parsing account ID: {}", account_id))?;
+        let pkey = PublicKey::from_str(public_key)
+            .with_context(|| format!("failed parsing public key: {}", public_key))?;
+
+        self.schedule.check()?;
+        self.rpc.check_available()?;
+        if let Some(throughput) = &self.throughput {
+            throughput.acquire().await?;
+        }
+        if let Some(drip) = &self.drip {
+            drip.wait_turn().await;
+        }
+
+        let result = self
+            .sign_and_send(new_account, pkey, account_id, wait, funding_amount, nonces)
+            .await;
+        metrics::record_transaction(&result);
+        result
+    }
+
+    /// Sends the creation transaction until it reaches the `wait` level, retrying the rejected nonces,
+    /// the expired block hashes, the congested shards and the transient RPC failures
+    async fn sign_and_send(
+        &self,
+        new_account: AccountId,
+        pkey: PublicKey,
+        account_id: &str,
+        wait: WaitLevel,
+        funding_amount: Balance,
+        nonces: Option<&NonceBlock>,
+    ) -> anyhow::Result<Submitted> {
+        let actions = vec![
+            Action::CreateAccount(CreateAccountAction {}),
+            Action::AddKey(Box::new(AddKeyAction {
+                public_key: pkey,
+                access_key: AccessKey::full_access(),
+            })),
+            Action::Transfer(TransferAction {
+                deposit: funding_amount,
+            }),
+        ];
+        // The transactions of a batch stay on the lane of its block, even once the block is used up
+        let lane = match nonces {
+            Some(block) => &self.lanes[block.lane],
+            None => self.next_lane(),
+        };
+        let mut next_nonce = match nonces.and_then(NonceBlock::take) {
+            Some(nonce) => nonce,
+            None => lane.nonce.next().await?,
+        };
+        let mut block_hash = self.block.borrow().hash;
+        let mut expired_retries = 0;
+        let mut nonce_retries = 0;
+        let mut failed_attempts = 0;
+        let mut congestion_waited = Duration::ZERO;
+        // Hashes of all the attempts, recorded on the request span to correlate requests and transactions
+        let mut tx_hashes: Vec<String> = vec![];
+
+        loop {
+            let tx = Transaction {
+                signer_id: lane.signer.account_id.clone(),
+                public_key: lane.signer.public_key.clone(),
+                nonce: next_nonce,
+                receiver_id: new_account.clone(),
+                block_hash,
+                actions: actions.clone(),
+            };
+            let (hash, _size) = tx.get_hash_and_size();
+            tx_hashes.push(hash.to_string());
+            tracing::Span::current().record(TX_HASHES_FIELD, tx_hashes.join(",").as_str());
+            let sig = lane.signer.sign(hash.as_ref());
+            let signed_transaction = SignedTransaction::new(sig, tx.clone());
+            self.sent
+                .lock()
+                .unwrap()
+                .insert(account_id.to_string(), hash);
+
+            tracing::debug!(
+                "Sending transaction {} creating {} with nonce {} to NEAR RPC node...",
+                hash,
+                account_id,
+                next_nonce
+            );
+            // In the asynchronous mode the node only validates the transaction before answering
+            let wait_until = match self.tracker {
+                Some(_) => TxExecutionStatus::None,
+                None => wait.into(),
+            };
+            let response = match self.rpc.broadcast(&signed_transaction, wait_until).await? {
+                // The node gave up waiting, or we gave up on the node, but the transaction was most likely accepted,
+                // failing here would make the user retry and pay for the account twice
+                Ok(Err(JsonRpcError::ServerError(JsonRpcServerError::HandlerError(
+                    RpcTransactionError::TimeoutError,
+                ))))
+                | Err(RpcTimeout { .. }) => {
+                    tracing::warn!("sending transaction {} timed out, polling its status", hash);
+                    self.poll_status(&hash, wait, STATUS_POLL_TIMEOUT).await?
+                }
+                Ok(response) => response,
+            };
+            match response {
+                Ok(r) => match r
+                    .final_execution_outcome
+                    .map(|outcome| outcome.into_outcome())
+                {
+                    // the requested wait level was reached before the execution, e.g. `included`
+                    None => {
+                        lane.nonce.record_used(next_nonce);
+                        tracing::info!(
+                            "transaction for {} reached {:?}",
+                            account_id,
+                            r.final_execution_status
+                        );
+                        if let Some(tracker) = &self.tracker {
+                            tracker.track(hash, account_id);
+                            tokio::spawn(self.clone().track_outcome(hash, tracker.clone()));
+                        }
+                        return Ok(Submitted {
+                            reached: r.final_execution_status,
+                            outcome: None,
+                            tx_hash: Some(hash),
+                        });
+                    }
+                    Some(
+                        outcome @ FinalExecutionOutcomeView {
+                            status: FinalExecutionStatus::SuccessValue(_),
+                            ..
+                        },
+                    ) => {
+                        lane.nonce.record_used(next_nonce);
+                        tracing::info!(
+                            "transaction execution succeeded for {}: {:?}",
+                            account_id,
+                            &outcome.status
+                        );
+                        return Ok(Submitted {
+                            reached: r.final_execution_status,
+                            outcome: Some(outcome),
+                            tx_hash: Some(hash),
+                        });
+                    }
+                    // looks like this one doesn't show up, and instead we get an Err(JsonRpcError) in this case,
+                    // but might as well handle this case here too
+                    Some(FinalExecutionOutcomeView {
+                        status:
+                            FinalExecutionStatus::Failure(TxExecutionError::InvalidTxError(
+                                InvalidTxError::InvalidNonce { tx_nonce, ak_nonce },
+                            )),
+                        ..
+                    }) => {
+                        nonce_retries = check_nonce_retries(nonce_retries)?;
+                        next_nonce = retry_nonce(
+                            lane,
+                            nonces,
+                            nonce_retries,
+                            next_nonce,
+                            tx_nonce,
+                            ak_nonce,
+                        )
+                        .await?;
+                        tracing::debug!(
+                            "retrying creating {} with nonce {} after nonce {} was rejected with current access key nonce {}",
+                            account_id,
+                            next_nonce,
+                            tx_nonce,
+                            ak_nonce,
+                        );
+                    }
+                    Some(FinalExecutionOutcomeView {
+                        status:
+                            FinalExecutionStatus::Failure(TxExecutionError::InvalidTxError(
+                                InvalidTxError::Expired,
+                            )),
+                        ..
+                    }) if expired_retries < MAX_EXPIRED_RETRIES => {
+                        expired_retries += 1;
+                        metrics::EXPIRED_RETRIES.inc();
+                        block_hash = self.refresh_block_hash().await?;
+                        tracing::warn!(
+                            "retrying creating {} with block hash {} after the transaction expired",
+                            account_id,
+                            block_hash
+                        );
+                    }
+                    Some(outcome) => {
+                        // A transaction failing on its actions was still included and used up the nonce
+                        if let FinalExecutionStatus::Failure(TxExecutionError::ActionError(_)) =
+                            &outcome.status
+                        {
+                            lane.nonce.record_used(next_nonce);
+                        }
+                        tracing::warn!("transaction execution failed: {:?}", &outcome.status);
+                        return Err(match outcome.status {
+                            FinalExecutionStatus::Failure(err) => TransactionFailed(err).into(),
+                            status => anyhow::anyhow!("transaction execution failed: {:?}", status),
+                        });
+                    }
+                },
+                Err(JsonRpcError::ServerError(JsonRpcServerError::HandlerError(
+                    RpcTransactionError::InvalidTransaction {
+                        context: InvalidTxError::InvalidNonce { tx_nonce, ak_nonce },
+                    },
+                ))) => {
+                    nonce_retries = check_nonce_retries(nonce_retries)?;
+                    next_nonce =
+                        retry_nonce(lane, nonces, nonce_retries, next_nonce, tx_nonce, ak_nonce)
+                            .await?;
+                    tracing::debug!(
+                        "retrying creating {} with nonce {} after nonce {} was rejected with current access key nonce {}",
+                        account_id,
+                        next_nonce,
+                        tx_nonce,
+                        ak_nonce,
+                    );
+                }
+                // The nonce of an expired transaction wasn't used, so it's sent again with the same one
+                Err(JsonRpcError::ServerError(JsonRpcServerError::HandlerError(
+                    RpcTransactionError::InvalidTransaction {
+                        context: InvalidTxError::Expired,
+                    },
+                ))) if expired_retries < MAX_EXPIRED_RETRIES => {
+                    expired_retries += 1;
+                    metrics::EXPIRED_RETRIES.inc();
+                    block_hash = self.refresh_block_hash().await?;
+                    tracing::warn!(
+                        "retrying creating {} with block hash {} after the transaction expired",
+                        account_id,
+                        block_hash
+                    );
+                }
+                // The transaction wasn't accepted, its nonce is still free; expiring meanwhile is handled above
+                Err(e) if is_congestion(&e) => {
+                    if !self.congestion.should_wait(congestion_waited) {
+                        tracing::warn!(
+                            "giving up creating {} after waiting {:?} for the congestion to clear: {:?}",
+                            account_id,
+                            congestion_waited,
+                            e
+                        );
+                        return Err(anyhow::Error::new(e).context(CodedError {
+                            code: ErrorCode::RpcUnavailable,
+                            message: "the network is congested, please try again in a few minutes"
+                                .to_string(),
+                        }));
+                    }
+                    congestion_waited += self.congestion.delay;
+                    tracing::warn!(
+                        "retrying creating {} in {:?} as the shard is congested",
+                        account_id,
+                        self.congestion.delay
+                    );
+                    tokio::time::sleep(self.congestion.delay).await;
+                }
+                // The same signed transaction is sent again, so if the failed attempt landed after all,
+                // the retry is rejected for its nonce rather than funding the account twice
+                Err(e) if is_transient(&e) && self.retry.should_retry(failed_attempts + 1) => {
+                    self.rpc.check_available()?;
+                    failed_attempts += 1;
+                    let delay = self.retry.delay(failed_attempts);
+                    tracing::warn!(
+                        "retrying creating {} in {:?} after attempt {} failed: {:?}",
+                        account_id,
+                        delay,
+                        failed_attempts,
+                        e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e.into()),
+            };
+        }
+    }
+}
+
+impl TxSubmitter {
+    /// Hands the creations over to `workers` tasks through a queue of `capacity`, rejecting them once it's full
+    /// The workers submit with the settings made so far, so this has to be the last one
+    pub(crate) fn with_workers(mut self, workers: usize, capacity: usize) -> Self {
+        self.pool = Some(SubmissionPool::spawn(self.clone(), workers, capacity));
+        self
+    }
+}
+
+/// Counts another nonce retry, failing once they're used up rather than racing for the access key forever
+fn check_nonce_retries(nonce_retries: u32) -> Result<u32, NonceRetriesExhausted> {
+    if nonce_retries >= MAX_NONCE_RETRIES {
+        tracing::warn!("giving up after {} rejected nonces", nonce_retries + 1);
+        return Err(NonceRetriesExhausted {
+            attempts: nonce_retries + 1,
+        });
+    }
+    Ok(nonce_retries + 1)
+}
+
+/// Allocates the nonce to sign again with after `old_nonce` was rejected, following a jittered delay
+/// The counter is read after the delay, so the nonce accounts for the creations that retried meanwhile
+async fn retry_nonce(
+    lane: &Lane,
+    nonces: Option<&NonceBlock>,
+    nonce_retries: u32,
+    old_nonce: Nonce,
+    tx_nonce: Nonce,
+    ak_nonce: Nonce,
+) -> anyhow::Result<Nonce> {
+    metrics::NONCE_RETRIES.inc();
+    tokio::time::sleep(nonce_retry_delay(nonce_retries)).await;
+    match nonces {
+        Some(block) => block.retry(old_nonce, tx_nonce, ak_nonce).await,
+        None => lane.nonce.retry(old_nonce, tx_nonce, ak_nonce).await,
+    }
+}
+
+/// Failures of the RPC endpoints rather than of the transaction, worth retrying after a while
+fn is_transient(err: &JsonRpcError<RpcTransactionError>) -> bool {
+    is_endpoint_failure(err)
+        || matches!(
+            err.handler_error(),
+            Some(RpcTransactionError::InternalError { .. })
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    use near_crypto::{InMemorySigner, KeyType, SecretKey};
+    use near_primitives::types::BlockHeight;
+    use near_primitives::views::{AccessKeyPermissionView, AccessKeyView};
+
+    use super::*;
+    use crate::utils::nonce::NonceAllocator;
+    use crate::utils::rpc_client::TxResponse;
+
+    const BASE_NONCE: Nonce = 100;
+
+    /// Answers the broadcasts and the status polls with the scripted responses in order and records the sent transactions
+    #[derive(Clone, Default)]
+    struct MockRpc {
+        responses: Arc<Mutex<VecDeque<TxResponse>>>,
+        statuses: Arc<Mutex<VecDeque<TxResponse>>>,
+        sent: Arc<Mutex<Vec<Transaction>>>,
+    }
+
+    impl MockRpc {
+        fn with_responses(responses: impl IntoIterator<Item = TxResponse>) -> Self {
+            Self {
+                responses: Arc::new(Mutex::new(responses.into_iter().collect())),
+                ..Default::default()
+            }
+        }
+
+        fn with_statuses(self, statuses: impl IntoIterator<Item = TxResponse>) -> Self {
+            *self.statuses.lock().unwrap() = statuses.into_iter().collect();
+            self
+        }
+
+        fn sent_nonces(&self) -> Vec<Nonce> {
+            self.sent
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|tx| tx.nonce)
+                .collect()
+        }
+    }
+
+    impl AccountCreatorRpc for MockRpc {
+        async fn broadcast(
+            &self,
+            signed_transaction: &SignedTransaction,
+            _wait_until: TxExecutionStatus,
+        ) -> anyhow::Result<TxResponse> {
+            self.sent
+                .lock()
+                .unwrap()
+                .push(signed_transaction.transaction.clone());
+            Ok(self
+                .responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("no response scripted for the broadcast"))
+        }
+
+        async fn tx_status(
+            &self,
+            _tx_hash: &CryptoHash,
+            _sender_account_id: &AccountId,
+            _wait_until: TxExecutionStatus,
+        ) -> TxResponse {
+            self.statuses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("no response scripted for the status poll")
+        }
+
+        async fn view_access_key(
+            &self,
+            _account_id: &AccountId,
+            _public_key: &PublicKey,
+        ) -> anyhow::Result<AccessKeyView> {
+            Ok(AccessKeyView {
+                nonce: BASE_NONCE,
+                permission: AccessKeyPermissionView::FullAccess,
+            })
+        }
+
+        async fn latest_block(
+            &self,
+            _source: BlockSource,
+        ) -> anyhow::Result<(CryptoHash, BlockHeight)> {
+            Ok((CryptoHash::default(), 1))
+        }
+    }
+
+    fn included() -> TxResponse {
+        Ok(Ok(SendTxResponse {
+            final_execution_outcome: None,
+            final_execution_status: TxExecutionStatus::Included,
+        }))
+    }
+
+    fn handler_error(err: RpcTransactionError) -> TxResponse {
+        Ok(Err(JsonRpcError::ServerError(
+            JsonRpcServerError::HandlerError(err),
+        )))
+    }
+
+    fn unknown() -> TxResponse {
+        handler_error(RpcTransactionError::UnknownTransaction {
+            requested_transaction_hash: CryptoHash::default(),
+        })
+    }
+
+    fn rejected(context: InvalidTxError) -> TxResponse {
+        Ok(Err(JsonRpcError::ServerError(
+            JsonRpcServerError::HandlerError(RpcTransactionError::InvalidTransaction { context }),
+        )))
+    }
+
+    fn invalid_nonce(tx_nonce: Nonce, ak_nonce: Nonce) -> TxResponse {
+        rejected(InvalidTxError::InvalidNonce { tx_nonce, ak_nonce })
+    }
+
+    async fn submitter(rpc: MockRpc) -> TxSubmitter<MockRpc> {
+        let signer = InMemorySigner::from_seed(
+            "faucet.test.near".parse().unwrap(),
+            KeyType::ED25519,
+            "faucet",
+        );
+        let lane = Lane {
+            signer,
+            nonce: NonceAllocator::local(BASE_NONCE),
+        };
+        let refresh = BlockRefresh {
+            interval: Duration::from_secs(3600),
+            stale_after: Duration::from_secs(3600),
+            source: BlockSource::Final,
+        };
+        TxSubmitter::new(rpc, vec![lane], 1, refresh).await.unwrap()
+    }
+
+    async fn create(rpc: &MockRpc) -> anyhow::Result<Submitted> {
+        let public_key = SecretKey::from_seed(KeyType::ED25519, "alice").public_key();
+        submitter(rpc.clone())
+            .await
+            .submit(
+                "alice.test.near",
+                &public_key.to_string(),
+                WaitLevel::Included,
+                1,
+                None,
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn retries_rejected_nonces_above_the_access_key_nonce() {
+        let rpc = MockRpc::with_responses([invalid_nonce(101, 150), included()]);
+        let submitted = create(&rpc).await.unwrap();
+        assert_eq!(submitted.reached, TxExecutionStatus::Included);
+        assert_eq!(rpc.sent_nonces(), [101, 151]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_contended_nonces() {
+        let rpc = MockRpc::with_responses(
+            (0..=MAX_NONCE_RETRIES as u64).map(|i| invalid_nonce(101 + i, 101 + i)),
+        );
+        let err = create(&rpc).await.unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::NonceConflict);
+        assert_eq!(rpc.sent_nonces().len(), MAX_NONCE_RETRIES as usize + 1);
+    }
+
+    #[tokio::test]
+    async fn resends_expired_transactions_with_the_same_nonce() {
+        let rpc = MockRpc::with_responses([rejected(InvalidTxError::Expired), included()]);
+        create(&rpc).await.unwrap();
+        assert_eq!(rpc.sent_nonces(), [101, 101]);
+    }
+
+    #[tokio::test]
+    async fn maps_rejections_to_error_codes() {
+        let cases = [
+            (
+                InvalidTxError::NotEnoughBalance {
+                    signer_id: "faucet.test.near".parse().unwrap(),
+                    balance: 0,
+                    cost: 1,
+                },
+                ErrorCode::FaucetEmpty,
+            ),
+            (
+                InvalidTxError::InvalidReceiverId {
+                    receiver_id: "alice.test.near".to_string(),
+                },
+                ErrorCode::InvalidAccountId,
+            ),
+            (
+                InvalidTxError::InvalidSignature,
+                ErrorCode::TransactionFailed,
+            ),
+        ];
+        for (context, code) in cases {
+            let rpc = MockRpc::with_responses([rejected(context)]);
+            let err = create(&rpc).await.unwrap_err();
+            assert_eq!(ErrorCode::classify(&err), code);
+            assert_eq!(rpc.sent_nonces().len(), 1);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn polls_the_status_of_the_timed_out_broadcasts() {
+        let rpc = MockRpc::with_responses([handler_error(RpcTransactionError::TimeoutError)])
+            .with_statuses([unknown(), included()]);
+        let submitted = create(&rpc).await.unwrap();
+        assert_eq!(submitted.reached, TxExecutionStatus::Included);
+        assert_eq!(rpc.sent_nonces(), [101]);
+        assert!(rpc.statuses.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reports_the_transactions_still_unknown_as_pending() {
+        let rpc = MockRpc::with_responses([handler_error(RpcTransactionError::TimeoutError)])
+            .with_statuses((0..20).map(|_| unknown()));
+        let err = create(&rpc).await.unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::Pending);
+        assert_eq!(rpc.sent_nonces(), [101]);
+    }
+
+    #[tokio::test]
+    async fn takes_the_reserved_nonces_and_resyncs_them_when_rejected() {
+        let rpc = MockRpc::with_responses([invalid_nonce(101, 150), included(), included()]);
+        let submitter = submitter(rpc.clone()).await;
+        let block = submitter.reserve_nonces(3).await.unwrap();
+        let public_key = SecretKey::from_seed(KeyType::ED25519, "alice")
+            .public_key()
+            .to_string();
+        for account_id in ["alice.test.near", "bob.test.near"] {
+            submitter
+                .submit(
+                    account_id,
+                    &public_key,
+                    WaitLevel::Included,
+                    1,
+                    Some(&block),
+                )
+                .await
+                .unwrap();
+        }
+        // The rejected nonce moved the two left in the block above the access key nonce, the retry took the first
+        assert_eq!(rpc.sent_nonces(), [101, 151, 152]);
+        assert_eq!(block.take(), None);
+    }
+}
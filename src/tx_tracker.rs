@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_web::{web, HttpResponse, Responder};
+use near_primitives::hash::CryptoHash;
+use near_primitives::types::{Balance, Gas};
+use serde::Serialize;
+
+use crate::errors::{user_message, ErrorCode};
+use crate::utils::send_tx::Submitted;
+
+/// Finished transactions are kept for this long for the clients to look them up
+const RETENTION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub(crate) enum TxState {
+    Pending,
+    Succeeded,
+    Failed { code: ErrorCode, message: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct TrackedTx {
+    tx_hash: CryptoHash,
+    account_id: String,
+    #[serde(flatten)]
+    state: TxState,
+    /// Unix timestamp
+    submitted_at: u64,
+    /// Unix timestamp the outcome became known at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finished_at: Option<u64>,
+}
+
+/// Outcomes of the transactions broadcast without waiting, resolved by the background polling
+#[derive(Debug, Default, Clone)]
+pub(crate) struct TxTracker {
+    txs: Arc<Mutex<HashMap<CryptoHash, TrackedTx>>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl TxTracker {
+    pub(crate) fn track(&self, tx_hash: CryptoHash, account_id: &str) {
+        self.txs.lock().unwrap().insert(
+            tx_hash,
+            TrackedTx {
+                tx_hash,
+                account_id: account_id.to_string(),
+                state: TxState::Pending,
+                submitted_at: now(),
+                finished_at: None,
+            },
+        );
+    }
+
+    pub(crate) fn finish(&self, tx_hash: &CryptoHash, result: &anyhow::Result<()>) {
+        if let Some(tx) = self.txs.lock().unwrap().get_mut(tx_hash) {
+            tx.state = match result {
+                Ok(()) => TxState::Succeeded,
+                Err(err) => TxState::Failed {
+                    code: ErrorCode::classify(err),
+                    message: user_message(err),
+                },
+            };
+            tx.finished_at = Some(now());
+        }
+    }
+
+    fn get(&self, tx_hash: &CryptoHash) -> Option<TrackedTx> {
+        self.txs.lock().unwrap().get(tx_hash).cloned()
+    }
+
+    /// Forgets the transactions finished longer than the retention ago, returns how many were forgotten
+    pub(crate) fn purge_finished(&self) -> usize {
+        let cutoff = now().saturating_sub(RETENTION.as_secs());
+        let mut txs = self.txs.lock().unwrap();
+        let before = txs.len();
+        txs.retain(|_, tx| {
+            tx.finished_at
+                .map_or(true, |finished_at| finished_at > cutoff)
+        });
+        before - txs.len()
+    }
+}
+
+/// Creation transaction as reported by the responses, with what its execution burnt once executed
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct CreationTx {
+    pub(crate) tx_hash: CryptoHash,
+    /// Gas burnt by the transaction and its receipts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) gas_burnt: Option<Gas>,
+    /// yoctoNEAR paid for the burnt gas, as a string
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) tokens_burnt: Option<String>,
+    /// Page of the transaction on the explorer, see `EXPLORER_TX_URL`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) explorer_url: Option<String>,
+}
+
+impl CreationTx {
+    /// The transaction of a creation, `None` if its hash is unknown
+    /// The burnt gas and tokens are only known once it was executed
+    pub(crate) fn of(submitted: &Submitted, explorer_tx_url: Option<&str>) -> Option<Self> {
+        let tx_hash = submitted.tx_hash?;
+        let burnt = submitted.outcome.as_ref().map(|outcome| {
+            std::iter::once(&outcome.transaction_outcome)
+                .chain(&outcome.receipts_outcome)
+                .fold((0, 0), |(gas, tokens): (Gas, Balance), execution| {
+                    (
+                        gas + execution.outcome.gas_burnt,
+                        tokens + execution.outcome.tokens_burnt,
+                    )
+                })
+        });
+        Some(Self {
+            gas_burnt: burnt.map(|(gas, _)| gas),
+            tokens_burnt: burnt.map(|(_, tokens)| tokens.to_string()),
+            ..Self::pending(tx_hash, explorer_tx_url)
+        })
+    }
+
+    /// Transaction of a creation still processing, nothing was burnt yet as far as the faucet knows
+    pub(crate) fn pending(tx_hash: CryptoHash, explorer_tx_url: Option<&str>) -> Self {
+        Self {
+            tx_hash,
+            gas_burnt: None,
+            tokens_burnt: None,
+            explorer_url: explorer_tx_url
+                .map(|template| template.replace("{tx_hash}", &tx_hash.to_string())),
+        }
+    }
+}
+
+/// Endpoint: /tx/{tx_hash}
+/// Responds with the state of a transaction broadcast without waiting: `pending`, `succeeded` or `failed` (JSON)
+pub(crate) async fn tx_status_handler(
+    near: web::Data<crate::NearData>,
+    tx_hash: web::Path<String>,
+) -> impl Responder {
+    tracing::debug!("GET /tx/{}", tx_hash);
+    let tracked = tx_hash.parse::<CryptoHash>().ok().and_then(|tx_hash| {
+        near.submitter
+            .as_ref()
+            .and_then(|submitter| submitter.tracker())
+            .and_then(|tracker| tracker.get(&tx_hash))
+    });
+    match tracked {
+        Some(tx) => HttpResponse::Ok().json(tx),
+        None => HttpResponse::NotFound().json(serde_json::json!({
+            "result": null,
+            "error": {
+                "code": ErrorCode::NotFound,
+                "message": format!("transaction {} is not tracked by this faucet", tx_hash),
+            },
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_primitives::views::TxExecutionStatus;
+
+    #[test]
+    fn links_the_creation_tx() {
+        let submitted = Submitted {
+            reached: TxExecutionStatus::Included,
+            outcome: None,
+            tx_hash: Some(CryptoHash::default()),
+        };
+        let tx = CreationTx::of(
+            &submitted,
+            Some("https://testnet.nearblocks.io/txns/{tx_hash}"),
+        )
+        .unwrap();
+        assert_eq!(
+            tx.explorer_url.unwrap(),
+            format!(
+                "https://testnet.nearblocks.io/txns/{}",
+                CryptoHash::default()
+            )
+        );
+        assert!(tx.gas_burnt.is_none() && tx.tokens_burnt.is_none());
+
+        let unknown = Submitted {
+            tx_hash: None,
+            ..submitted
+        };
+        assert!(CreationTx::of(&unknown, None).is_none());
+    }
+}
@@ -1,44 +1,109 @@
-use std::{
-    sync::{Arc, RwLock},
-    time::Duration,
-};
-
-use near_jsonrpc_client::{
-    errors::JsonRpcError,
-    methods::status::{RpcStatusError, RpcStatusRequest},
-    JsonRpcClient,
-};
-use near_primitives::hash::CryptoHash;
-
-/// Fetches the current block hash from the NEAR RPC node
-pub(crate) async fn current_block_hash(
-    near_rpc: &JsonRpcClient,
-) -> Result<CryptoHash, JsonRpcError<RpcStatusError>> {
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use near_primitives::{hash::CryptoHash, types::BlockHeight};
+use rand::Rng;
+use tokio::sync::watch;
+
+use crate::metrics;
+use crate::utils::rpc_client::AccountCreatorRpc;
+
+/// Latest block known to this process, as published by the updater
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BlockInfo {
+    pub(crate) hash: CryptoHash,
+    pub(crate) height: BlockHeight,
+    /// When the block was fetched from the NEAR RPC node
+    pub(crate) fetched_at: SystemTime,
+}
+
+impl BlockInfo {
+    /// Time since the block was fetched
+    pub(crate) fn age(&self) -> Duration {
+        self.fetched_at.elapsed().unwrap_or_default()
+    }
+}
+
+/// Where the block the transactions reference is taken from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum BlockSource {
+    /// Latest final block from the `block` method, it can't be orphaned by a fork
+    #[default]
+    Final,
+    /// Latest block from the `status` method, a couple of blocks fresher, but it may be orphaned
+    /// and the transactions referencing it rejected as expired
+    Status,
+}
+
+/// Fetches the latest block from the NEAR RPC node
+pub(crate) async fn current_block<R: AccountCreatorRpc>(
+    near_rpc: &R,
+    source: BlockSource,
+) -> anyhow::Result<BlockInfo> {
     tracing::debug!("Fetching current block hash from NEAR RPC node...");
-    near_rpc
-        .call(RpcStatusRequest)
-        .await
-        .map(|status| status.sync_info.latest_block_hash)
+    let (hash, height) = near_rpc.latest_block(source).await?;
+    let block = BlockInfo {
+        hash,
+        height,
+        fetched_at: SystemTime::now(),
+    };
+    record_metrics(&block);
+    Ok(block)
+}
+
+fn record_metrics(block: &BlockInfo) {
+    metrics::LATEST_BLOCK_HEIGHT.set(block.height as i64);
+    metrics::BLOCK_FETCHED_AT.set(
+        block
+            .fetched_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64(),
+    );
+}
+
+/// How often the updater refreshes the block hash
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BlockRefresh {
+    /// Delay between the refreshes, up to a tenth more is added at random
+    /// so the replicas started together don't poll the node in lockstep
+    pub(crate) interval: Duration,
+    /// Age past which the cached block is reported as stale
+    pub(crate) stale_after: Duration,
+    pub(crate) source: BlockSource,
+}
+
+impl BlockRefresh {
+    fn next_delay(&self) -> Duration {
+        self.interval
+            .mul_f64(1.0 + rand::thread_rng().gen_range(0.0..=0.1))
+    }
 }
 
-/// Constantly updates the block hash in the given `Arc<RwLock<CryptoHash>>` every 30 seconds
-/// by fetching the latest block hash from the NEAR RPC node
+/// Publishes the latest block to the given channel at the refresh interval
 /// This is used to ensure that the block hash used in the transaction is always up to date
-pub(crate) async fn update_block_hash(
-    near_rpc: JsonRpcClient,
-    block_hash: Arc<RwLock<CryptoHash>>,
+pub(crate) async fn update_block_hash<R: AccountCreatorRpc>(
+    near_rpc: R,
+    block: Arc<watch::Sender<BlockInfo>>,
+    refresh: BlockRefresh,
 ) {
     loop {
-        tokio::time::sleep(Duration::from_secs(30)).await;
+        tokio::time::sleep(refresh.next_delay()).await;
         tracing::debug!("Updating block hash...");
-        let current = match current_block_hash(&near_rpc).await {
-            Ok(b) => b,
-            Err(e) => {
-                tracing::warn!("failed to fetch current block hash: {:?}", e);
-                continue;
+        match current_block(&near_rpc, refresh.source).await {
+            Ok(current) => {
+                block.send_replace(current);
             }
-        };
-        let mut b = block_hash.write().unwrap();
-        *b = current;
+            Err(e) => tracing::warn!("failed to fetch current block hash: {:?}", e),
+        }
+        let age = block.borrow().age();
+        let stale = age > refresh.stale_after;
+        if stale {
+            tracing::warn!(
+                "the cached block hash is {:?} old, the updater can't refresh it",
+                age
+            );
+        }
+        metrics::BLOCK_HASH_STALE.set(stale as i64);
     }
 }
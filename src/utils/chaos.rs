@@ -0,0 +1,169 @@
+use near_account_id::AccountId;
+use near_crypto::PublicKey;
+use near_jsonrpc_client::errors::{
+    JsonRpcError, JsonRpcServerError, JsonRpcServerResponseStatusError,
+};
+use near_jsonrpc_client::methods::tx::RpcTransactionError;
+use near_primitives::errors::InvalidTxError;
+use near_primitives::hash::CryptoHash;
+use near_primitives::transaction::SignedTransaction;
+use near_primitives::types::BlockHeight;
+use near_primitives::views::{AccessKeyView, TxExecutionStatus};
+use rand::Rng;
+
+use crate::utils::block_hash::BlockSource;
+use crate::utils::rpc_client::{AccountCreatorRpc, TxResponse};
+use crate::utils::rpc_pool::RpcTimeout;
+
+/// Probabilities of the faults injected into the transaction calls, each between 0 and 1
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ChaosConfig {
+    /// The transaction is rejected with `InvalidNonce` before it's sent
+    pub(crate) invalid_nonce: f64,
+    /// The transaction is rejected as expired before it's sent
+    pub(crate) expired: f64,
+    /// The call times out, after the transaction was sent, so it may still land
+    pub(crate) timeout: f64,
+    /// The endpoint answers `502 Bad Gateway` instead of making the call
+    pub(crate) server_error: f64,
+}
+
+enum Fault {
+    InvalidNonce,
+    Expired,
+    Timeout,
+    ServerError,
+}
+
+impl ChaosConfig {
+    /// Draws the fault of a call, `None` if it goes through untouched
+    /// Only the broadcasts can be rejected, the status polls only time out or fail
+    fn draw(&self, broadcast: bool) -> Option<Fault> {
+        let (invalid_nonce, expired) = match broadcast {
+            true => (self.invalid_nonce, self.expired),
+            false => (0.0, 0.0),
+        };
+        let mut roll = rand::thread_rng().gen_range(0.0..1.0);
+        let faults = [
+            (Fault::InvalidNonce, invalid_nonce),
+            (Fault::Expired, expired),
+            (Fault::Timeout, self.timeout),
+            (Fault::ServerError, self.server_error),
+        ];
+        for (fault, probability) in faults {
+            if roll < probability {
+                return Some(fault);
+            }
+            roll -= probability;
+        }
+        None
+    }
+}
+
+/// Wraps the RPC client and fails its transaction calls at random, to exercise the retries without a flaky network
+/// Only built with the `chaos` feature, never enable it against a network with real funds at stake
+#[derive(Clone)]
+pub(crate) struct ChaosRpc<R> {
+    inner: R,
+    config: ChaosConfig,
+}
+
+impl<R: AccountCreatorRpc> ChaosRpc<R> {
+    pub(crate) fn new(inner: R, config: ChaosConfig) -> Self {
+        Self { inner, config }
+    }
+}
+
+fn rejected(context: InvalidTxError) -> TxResponse {
+    Ok(Err(JsonRpcError::ServerError(
+        JsonRpcServerError::HandlerError(RpcTransactionError::InvalidTransaction { context }),
+    )))
+}
+
+fn bad_gateway() -> TxResponse {
+    Ok(Err(JsonRpcError::ServerError(
+        JsonRpcServerError::ResponseStatusError(JsonRpcServerResponseStatusError::Unexpected {
+            status: reqwest::StatusCode::BAD_GATEWAY,
+        }),
+    )))
+}
+
+fn timed_out(method: &str) -> TxResponse {
+    Err(RpcTimeout {
+        method: method.to_string(),
+        after: std::time::Duration::ZERO,
+    })
+}
+
+impl<R: AccountCreatorRpc> AccountCreatorRpc for ChaosRpc<R> {
+    async fn broadcast(
+        &self,
+        signed_transaction: &SignedTransaction,
+        wait_until: TxExecutionStatus,
+    ) -> anyhow::Result<TxResponse> {
+        let nonce = signed_transaction.transaction.nonce;
+        let fault = self.config.draw(true);
+        Ok(match fault {
+            Some(Fault::InvalidNonce) => {
+                tracing::warn!("chaos: rejecting nonce {}", nonce);
+                rejected(InvalidTxError::InvalidNonce {
+                    tx_nonce: nonce,
+                    ak_nonce: nonce,
+                })
+            }
+            Some(Fault::Expired) => {
+                tracing::warn!("chaos: rejecting the transaction as expired");
+                rejected(InvalidTxError::Expired)
+            }
+            Some(Fault::Timeout) => {
+                let response = self.inner.broadcast(signed_transaction, wait_until).await?;
+                tracing::warn!("chaos: dropping the response to send_tx: {:?}", response);
+                timed_out("send_tx")
+            }
+            Some(Fault::ServerError) => {
+                tracing::warn!("chaos: failing send_tx with 502");
+                bad_gateway()
+            }
+            None => self.inner.broadcast(signed_transaction, wait_until).await?,
+        })
+    }
+
+    async fn tx_status(
+        &self,
+        tx_hash: &CryptoHash,
+        sender_account_id: &AccountId,
+        wait_until: TxExecutionStatus,
+    ) -> TxResponse {
+        match self.config.draw(false) {
+            Some(Fault::Timeout) => {
+                tracing::warn!("chaos: timing out the status of {}", tx_hash);
+                timed_out("tx")
+            }
+            Some(Fault::ServerError) => {
+                tracing::warn!("chaos: failing the status of {} with 502", tx_hash);
+                bad_gateway()
+            }
+            _ => {
+                self.inner
+                    .tx_status(tx_hash, sender_account_id, wait_until)
+                    .await
+            }
+        }
+    }
+
+    async fn view_access_key(
+        &self,
+        account_id: &AccountId,
+        public_key: &PublicKey,
+    ) -> anyhow::Result<AccessKeyView> {
+        self.inner.view_access_key(account_id, public_key).await
+    }
+
+    async fn latest_block(&self, source: BlockSource) -> anyhow::Result<(CryptoHash, BlockHeight)> {
+        self.inner.latest_block(source).await
+    }
+
+    fn check_available(&self) -> anyhow::Result<()> {
+        self.inner.check_available()
+    }
+}
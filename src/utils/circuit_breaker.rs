@@ -0,0 +1,127 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::metrics::RPC_CIRCUIT_OPEN;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CircuitState {
+    /// The calls go through
+    Closed,
+    /// The calls fail right away until the cooldown is over
+    Open,
+    /// A single probe call is let through to find out whether the RPC recovered
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed {
+        consecutive_failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// The probe is abandoned if it doesn't report back within the cooldown, e.g. when its request was dropped
+    HalfOpen {
+        probe_started: Instant,
+    },
+}
+
+/// Stops calling the NEAR RPC after `threshold` consecutive failures, so the requests fail fast while it's down
+/// After the cooldown a single probe call is let through, closing the circuit again if it succeeds
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(threshold: u32, cooldown: Duration) -> Self {
+        RPC_CIRCUIT_OPEN.set(0);
+        Self {
+            threshold: threshold.max(1),
+            cooldown,
+            state: Mutex::new(State::Closed {
+                consecutive_failures: 0,
+            }),
+        }
+    }
+
+    /// Whether a call may go through, letting the probe through once the cooldown is over
+    /// Fails with the time left until the next probe otherwise
+    pub(crate) fn admit(&self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            State::Closed { .. } => Ok(()),
+            State::Open { until } if until > now => Err(until - now),
+            State::HalfOpen { probe_started } if probe_started + self.cooldown > now => {
+                Err(probe_started + self.cooldown - now)
+            }
+            _ => {
+                tracing::info!("Probing the NEAR RPC after its circuit was open");
+                *state = State::HalfOpen { probe_started: now };
+                Ok(())
+            }
+        }
+    }
+
+    /// Time left until the next probe, `None` while the calls go through
+    pub(crate) fn retry_in(&self) -> Option<Duration> {
+        let now = Instant::now();
+        match *self.state.lock().unwrap() {
+            State::Open { until } if until > now => Some(until - now),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn state(&self) -> CircuitState {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { .. } => CircuitState::Open,
+            State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    pub(crate) fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(*state, State::Closed { .. }) {
+            tracing::info!("NEAR RPC recovered, closing its circuit");
+            RPC_CIRCUIT_OPEN.set(0);
+        }
+        *state = State::Closed {
+            consecutive_failures: 0,
+        };
+    }
+
+    pub(crate) fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let failures = match *state {
+            State::Closed {
+                consecutive_failures,
+            } => consecutive_failures + 1,
+            // A failed probe opens the circuit again right away
+            State::HalfOpen { .. } => self.threshold,
+            State::Open { .. } => return,
+        };
+        if failures >= self.threshold {
+            tracing::warn!(
+                "NEAR RPC failed {} times in a row, failing the calls fast for {:?}",
+                failures,
+                self.cooldown
+            );
+            *state = State::Open {
+                until: Instant::now() + self.cooldown,
+            };
+            RPC_CIRCUIT_OPEN.set(1);
+        } else {
+            *state = State::Closed {
+                consecutive_failures: failures,
+            };
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use near_account_id::AccountId;
+use near_crypto::{InMemorySigner, KeyFile};
+
+/// Loads a signer from a near-cli credentials file, `{"account_id", "public_key", "private_key"}`
+pub(crate) fn load_file(path: &Path) -> anyhow::Result<InMemorySigner> {
+    let key_file = KeyFile::from_file(path)
+        .with_context(|| format!("failed reading credentials file {}", path.display()))?;
+    if key_file.secret_key.public_key() != key_file.public_key {
+        anyhow::bail!(
+            "the private key in {} doesn't match its public key {}",
+            path.display(),
+            key_file.public_key
+        );
+    }
+    Ok(InMemorySigner::from(key_file))
+}
+
+/// Finds the credentials of the account in a near-cli credentials directory, e.g. `~/.near-credentials/testnet`
+///
+/// Looks for `<account_id>.json` as written by the JS near-cli, then for the only key in
+/// `<account_id>/<public_key>.json` as written by near-cli-rs
+pub(crate) fn find_in_dir(dir: &Path, account_id: &AccountId) -> anyhow::Result<PathBuf> {
+    let file = dir.join(format!("{}.json", account_id));
+    if file.is_file() {
+        return Ok(file);
+    }
+    let account_dir = dir.join(account_id.as_str());
+    if !account_dir.is_dir() {
+        anyhow::bail!(
+            "no credentials of {} in {}, expected {} or {}",
+            account_id,
+            dir.display(),
+            file.display(),
+            account_dir.display()
+        );
+    }
+    let mut keys = std::fs::read_dir(&account_dir)
+        .with_context(|| format!("failed listing {}", account_dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .filter(|path| {
+            path.as_ref().map_or(true, |path| {
+                path.extension().is_some_and(|ext| ext == "json")
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("failed listing {}", account_dir.display()))?;
+    match keys.len() {
+        1 => Ok(keys.remove(0)),
+        0 => anyhow::bail!("no credentials files in {}", account_dir.display()),
+        _ => anyhow::bail!(
+            "{} holds several keys, pick one with --credentials-file: {}",
+            account_dir.display(),
+            keys.iter()
+                .map(|key| key.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
@@ -1,2 +1,13 @@
 pub(crate) mod block_hash;
+#[cfg(feature = "chaos")]
+pub(crate) mod chaos;
+pub(crate) mod circuit_breaker;
+pub(crate) mod credentials;
 pub(crate) mod nonce;
+pub(crate) mod preflight;
+#[cfg(any(feature = "shared-nonce", feature = "shared-limits"))]
+pub(crate) mod redis;
+pub(crate) mod retry;
+pub(crate) mod rpc_client;
+pub(crate) mod rpc_pool;
+pub(crate) mod send_tx;
@@ -1,19 +1,93 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use anyhow::Context as _;
+use tokio::sync::Notify;
+
+use near_account_id::AccountId;
+use near_crypto::PublicKey;
 use near_primitives::types::Nonce;
 
+#[cfg(feature = "shared-nonce")]
+use crate::utils::redis::{RedisClient, Reply};
+use crate::utils::rpc_client::AccountCreatorRpc;
+
+/// Sets the counter to the on-chain nonce if it's behind, returns the previous value, -1 if it wasn't raised
+/// Lua compares the nonces as doubles, exact below 2^53, while the stored strings and INCR keep them exact
+#[cfg(feature = "shared-nonce")]
+const REDIS_RAISE_SCRIPT: &str = r#"
+local n = tonumber(redis.call('GET', KEYS[1]) or '0')
+if n < tonumber(ARGV[1]) then
+    redis.call('SET', KEYS[1], ARGV[1])
+    return n
+end
+return -1
+"#;
+
+/// Raises the counter to the access key nonce if it's behind, then allocates the next nonce
+#[cfg(feature = "shared-nonce")]
+const REDIS_RETRY_SCRIPT: &str = r#"
+local n = tonumber(redis.call('GET', KEYS[1]) or '0')
+if n < tonumber(ARGV[1]) then
+    redis.call('SET', KEYS[1], ARGV[1])
+end
+return redis.call('INCR', KEYS[1])
+"#;
+
+/// Fetches the current nonce of the given access key from the NEAR RPC node
+pub(crate) async fn current_nonce<R: AccountCreatorRpc>(
+    near_rpc: &R,
+    account_id: &AccountId,
+    public_key: &PublicKey,
+) -> anyhow::Result<Nonce> {
+    Ok(near_rpc
+        .view_access_key(account_id, public_key)
+        .await?
+        .nonce)
+}
+
+/// Raises the nonce counter to the on-chain nonce of the access key at the given interval
+/// Keeps the counter from drifting behind when the key is also used elsewhere, which would make every transaction retry
+pub(crate) async fn reconcile_nonce<R: AccountCreatorRpc>(
+    near_rpc: R,
+    nonce: NonceAllocator,
+    account_id: AccountId,
+    public_key: PublicKey,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        tracing::debug!("Reconciling the nonce with the access key...");
+        let raised = match current_nonce(&near_rpc, &account_id, &public_key).await {
+            Ok(chain_nonce) => nonce
+                .raise_to(chain_nonce)
+                .await
+                .map(|prev_nonce| prev_nonce.map(|prev_nonce| (prev_nonce, chain_nonce))),
+            Err(err) => Err(err),
+        };
+        match raised {
+            Ok(Some((prev_nonce, chain_nonce))) => tracing::warn!(
+                "nonce {} fell behind the access key nonce {}, raised it",
+                prev_nonce,
+                chain_nonce
+            ),
+            Ok(None) => {}
+            Err(err) => tracing::warn!("failed reconciling the nonce: {:?}", err),
+        }
+    }
+}
+
 /// Returns a nonce greater than both the nonces we know are too small.
 fn new_nonce(nonce1: Nonce, nonce2: Nonce) -> Nonce {
     std::cmp::max(nonce1, nonce2) + 1
 }
 
 /// Returns and stores in `nonce` a new nonce to try with after getting an InvalidNonce{ tx_nonce, ak_nonce } error
-pub(crate) fn retry_nonce(
-    nonce: &AtomicU64,
-    old_nonce: Nonce,
-    tx_nonce: Nonce,
-    ak_nonce: Nonce,
-) -> Nonce {
+fn retry_nonce(nonce: &AtomicU64, old_nonce: Nonce, tx_nonce: Nonce, ak_nonce: Nonce) -> Nonce {
     if tx_nonce != old_nonce {
         tracing::warn!(
             "NEAR RPC node reported that our transaction's nonce was {}, when we remember sending {}",
@@ -28,3 +102,465 @@ pub(crate) fn retry_nonce(
     // now we call new_nonce() again because fetch_update() returns the old value
     new_nonce(prev_nonce, ak_nonce)
 }
+
+/// Shortest time between two writes of the nonce state file
+const NONCE_STATE_WRITE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Highest nonces used by the included transactions of each access key, kept in a small JSON file
+/// The on-chain nonce read on startup may lag behind the transactions sent just before a restart,
+/// starting from the persisted one avoids a burst of nonce retries
+pub(crate) struct NonceStateFile {
+    path: PathBuf,
+    nonces: Mutex<BTreeMap<String, Nonce>>,
+    /// Wakes the writer task when a higher nonce is recorded
+    changed: Notify,
+}
+
+impl NonceStateFile {
+    /// Reads the state file, a missing file is an empty state, and starts the task writing it back
+    pub(crate) fn open(path: PathBuf) -> anyhow::Result<Arc<Self>> {
+        let nonces = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).with_context(|| {
+                format!("failed parsing the nonce state file {}", path.display())
+            })?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => {
+                return Err(err).with_context(|| {
+                    format!("failed reading the nonce state file {}", path.display())
+                })
+            }
+        };
+        let file = Arc::new(Self {
+            path,
+            nonces: Mutex::new(nonces),
+            changed: Notify::new(),
+        });
+        tokio::spawn(file.clone().write_changes());
+        Ok(file)
+    }
+
+    fn key(account_id: &AccountId, public_key: &PublicKey) -> String {
+        format!("{}:{}", account_id, public_key)
+    }
+
+    /// Highest persisted nonce of the access key
+    pub(crate) fn get(&self, account_id: &AccountId, public_key: &PublicKey) -> Option<Nonce> {
+        self.nonces
+            .lock()
+            .unwrap()
+            .get(&Self::key(account_id, public_key))
+            .copied()
+    }
+
+    /// Keeps the nonce if it's higher than the recorded one, the writer task persists it shortly after
+    fn record(&self, key: &str, nonce: Nonce) {
+        let mut nonces = self.nonces.lock().unwrap();
+        match nonces.get(key) {
+            Some(&recorded) if recorded >= nonce => return,
+            _ => nonces.insert(key.to_string(), nonce),
+        };
+        self.changed.notify_one();
+    }
+
+    /// Writes the recorded nonces on the blocking threads, at most once per `NONCE_STATE_WRITE_INTERVAL`,
+    /// so the submitters never wait for the disk
+    /// The nonces recorded in the last interval before a crash are lost, the access key nonce covers most of them
+    async fn write_changes(self: Arc<Self>) {
+        loop {
+            self.changed.notified().await;
+            let file = self.clone();
+            let written = tokio::task::spawn_blocking(move || file.write()).await;
+            if let Err(err) = written
+                .map_err(anyhow::Error::from)
+                .and_then(|written| written)
+            {
+                tracing::warn!("failed persisting the nonces: {:?}", err);
+            }
+            tokio::time::sleep(NONCE_STATE_WRITE_INTERVAL).await;
+        }
+    }
+
+    /// Replaces the file by a rename so a crash never leaves it half-written
+    fn write(&self) -> anyhow::Result<()> {
+        let data = serde_json::to_vec_pretty(&*self.nonces.lock().unwrap())?;
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, data)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Persisted nonce of the access key of a local counter
+#[derive(Clone)]
+pub(crate) struct PersistedNonce {
+    file: Arc<NonceStateFile>,
+    key: String,
+}
+
+/// Where the nonces of the base signer's access key are allocated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum NonceBackend {
+    /// In-process counter, only correct when a single process uses the access key
+    Local,
+    /// Counter in a Postgres row shared by all the replicas using the access key
+    #[cfg(feature = "shared-nonce")]
+    Postgres,
+    /// Counter in a Redis key shared by all the replicas using the access key
+    #[cfg(feature = "shared-nonce")]
+    Redis,
+}
+
+/// Hands out the nonces for the transactions signed by the base signer
+#[derive(Clone)]
+pub(crate) enum NonceAllocator {
+    Local(Arc<AtomicU64>, Option<PersistedNonce>),
+    #[cfg(feature = "shared-nonce")]
+    Postgres {
+        pool: sqlx::PgPool,
+        key: String,
+    },
+    #[cfg(feature = "shared-nonce")]
+    Redis {
+        client: Arc<RedisClient>,
+        key: String,
+    },
+}
+
+impl NonceAllocator {
+    pub(crate) fn local(nonce: Nonce) -> Self {
+        Self::Local(Arc::new(AtomicU64::new(nonce)), None)
+    }
+
+    /// Local counter starting from the higher of the on-chain and the persisted nonce, recording the used nonces
+    pub(crate) fn persisted(
+        file: Arc<NonceStateFile>,
+        account_id: &AccountId,
+        public_key: &PublicKey,
+        chain_nonce: Nonce,
+    ) -> Self {
+        let nonce = match file.get(account_id, public_key) {
+            Some(persisted) if persisted > chain_nonce => {
+                tracing::info!(
+                    "starting from the persisted nonce {} of {}, the access key nonce is {}",
+                    persisted,
+                    public_key,
+                    chain_nonce
+                );
+                persisted
+            }
+            _ => chain_nonce,
+        };
+        let persisted = PersistedNonce {
+            key: NonceStateFile::key(account_id, public_key),
+            file,
+        };
+        Self::Local(Arc::new(AtomicU64::new(nonce)), Some(persisted))
+    }
+
+    /// Records the nonce of a transaction included in a block, only persisted local counters keep it
+    pub(crate) fn record_used(&self, used_nonce: Nonce) {
+        if let Self::Local(_, Some(persisted)) = self {
+            persisted.file.record(&persisted.key, used_nonce);
+        }
+    }
+
+    /// Connects to the shared counter of the access key and seeds it with the on-chain nonce
+    /// The counter is never moved backwards, so replicas starting later don't reuse the allocated nonces
+    #[cfg(feature = "shared-nonce")]
+    pub(crate) async fn postgres(
+        database_url: &str,
+        account_id: &AccountId,
+        public_key: &PublicKey,
+        chain_nonce: Nonce,
+    ) -> anyhow::Result<Self> {
+        let pool = sqlx::PgPool::connect(database_url)
+            .await
+            .context("failed connecting to the nonce database")?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS access_key_nonces (key TEXT PRIMARY KEY, nonce BIGINT NOT NULL)",
+        )
+        .execute(&pool)
+        .await
+        .context("failed creating the access_key_nonces table")?;
+
+        let key = format!("{}:{}", account_id, public_key);
+        sqlx::query(
+            r#"
+            INSERT INTO access_key_nonces (key, nonce) VALUES ($1, $2)
+            ON CONFLICT (key) DO UPDATE SET nonce = GREATEST(access_key_nonces.nonce, EXCLUDED.nonce)
+            "#,
+        )
+        .bind(&key)
+        .bind(to_db_nonce(chain_nonce)?)
+        .execute(&pool)
+        .await
+        .context("failed seeding the shared nonce")?;
+        Ok(Self::Postgres { pool, key })
+    }
+
+    /// Connects to the shared counter of the access key in Redis and seeds it with the on-chain nonce
+    /// The counter is never moved backwards, so replicas starting later don't reuse the allocated nonces
+    #[cfg(feature = "shared-nonce")]
+    pub(crate) async fn redis(
+        redis_url: &str,
+        account_id: &AccountId,
+        public_key: &PublicKey,
+        chain_nonce: Nonce,
+    ) -> anyhow::Result<Self> {
+        let client = RedisClient::from_url(redis_url).context("invalid nonce Redis URL")?;
+        let allocator = Self::Redis {
+            client: Arc::new(client),
+            key: format!("access_key_nonce:{}:{}", account_id, public_key),
+        };
+        allocator
+            .raise_to(chain_nonce)
+            .await
+            .context("failed seeding the shared nonce")?;
+        Ok(allocator)
+    }
+
+    /// Allocates the nonce for a new transaction
+    pub(crate) async fn next(&self) -> anyhow::Result<Nonce> {
+        match self {
+            Self::Local(nonce, _) => Ok(nonce.fetch_add(1, Ordering::SeqCst) + 1),
+            #[cfg(feature = "shared-nonce")]
+            Self::Postgres { pool, key } => {
+                let nonce: i64 = sqlx::query_scalar(
+                    "UPDATE access_key_nonces SET nonce = nonce + 1 WHERE key = $1 RETURNING nonce",
+                )
+                .bind(key)
+                .fetch_one(pool)
+                .await?;
+                Ok(nonce as Nonce)
+            }
+            #[cfg(feature = "shared-nonce")]
+            Self::Redis { client, key } => Ok(client.query_integer(&["INCR", key]).await? as Nonce),
+        }
+    }
+
+    /// Allocates `count` consecutive nonces with a single bump of the counter
+    pub(crate) async fn reserve(&self, count: u64) -> anyhow::Result<Range<Nonce>> {
+        let last = match self {
+            Self::Local(nonce, _) => nonce.fetch_add(count, Ordering::SeqCst) + count,
+            #[cfg(feature = "shared-nonce")]
+            Self::Postgres { pool, key } => {
+                let nonce: i64 = sqlx::query_scalar(
+                    "UPDATE access_key_nonces SET nonce = nonce + $2 WHERE key = $1 RETURNING nonce",
+                )
+                .bind(key)
+                .bind(to_db_nonce(count)?)
+                .fetch_one(pool)
+                .await?;
+                nonce as Nonce
+            }
+            #[cfg(feature = "shared-nonce")]
+            Self::Redis { client, key } => {
+                client
+                    .query_integer(&["INCRBY", key, &count.to_string()])
+                    .await? as Nonce
+            }
+        };
+        Ok(last + 1 - count..last + 1)
+    }
+
+    /// Last allocated nonce, for monitoring
+    pub(crate) async fn current(&self) -> anyhow::Result<Nonce> {
+        match self {
+            Self::Local(nonce, _) => Ok(nonce.load(Ordering::SeqCst)),
+            #[cfg(feature = "shared-nonce")]
+            Self::Postgres { pool, key } => {
+                let nonce: i64 =
+                    sqlx::query_scalar("SELECT nonce FROM access_key_nonces WHERE key = $1")
+                        .bind(key)
+                        .fetch_one(pool)
+                        .await?;
+                Ok(nonce as Nonce)
+            }
+            #[cfg(feature = "shared-nonce")]
+            Self::Redis { client, key } => match client.query(&["GET", key]).await? {
+                Reply::Bulk(nonce) => Ok(String::from_utf8(nonce)?.parse()?),
+                reply => anyhow::bail!("unexpected Redis reply for the nonce: {:?}", reply),
+            },
+        }
+    }
+
+    /// Raises the counter to the on-chain nonce if it fell behind, returns the nonce it was raised from
+    pub(crate) async fn raise_to(&self, chain_nonce: Nonce) -> anyhow::Result<Option<Nonce>> {
+        match self {
+            Self::Local(nonce, _) => {
+                let prev_nonce = nonce.fetch_max(chain_nonce, Ordering::SeqCst);
+                Ok((prev_nonce < chain_nonce).then_some(prev_nonce))
+            }
+            #[cfg(feature = "shared-nonce")]
+            Self::Postgres { pool, key } => {
+                let prev_nonce: Option<i64> = sqlx::query_scalar(
+                    r#"
+                    UPDATE access_key_nonces SET nonce = $2
+                    FROM (SELECT nonce AS prev_nonce FROM access_key_nonces WHERE key = $1 FOR UPDATE) prev
+                    WHERE key = $1 AND nonce < $2
+                    RETURNING prev.prev_nonce
+                    "#,
+                )
+                .bind(key)
+                .bind(to_db_nonce(chain_nonce)?)
+                .fetch_optional(pool)
+                .await?;
+                Ok(prev_nonce.map(|nonce| nonce as Nonce))
+            }
+            #[cfg(feature = "shared-nonce")]
+            Self::Redis { client, key } => {
+                let prev_nonce = client
+                    .query_integer(&[
+                        "EVAL",
+                        REDIS_RAISE_SCRIPT,
+                        "1",
+                        key,
+                        &chain_nonce.to_string(),
+                    ])
+                    .await?;
+                Ok((prev_nonce >= 0).then_some(prev_nonce as Nonce))
+            }
+        }
+    }
+
+    /// Returns and stores a new nonce to try with after getting an InvalidNonce{ tx_nonce, ak_nonce } error
+    pub(crate) async fn retry(
+        &self,
+        old_nonce: Nonce,
+        tx_nonce: Nonce,
+        ak_nonce: Nonce,
+    ) -> anyhow::Result<Nonce> {
+        match self {
+            Self::Local(nonce, _) => Ok(retry_nonce(nonce, old_nonce, tx_nonce, ak_nonce)),
+            #[cfg(feature = "shared-nonce")]
+            Self::Postgres { pool, key } => {
+                let nonce: i64 = sqlx::query_scalar(
+                    "UPDATE access_key_nonces SET nonce = GREATEST(nonce, $2) + 1 WHERE key = $1 RETURNING nonce",
+                )
+                .bind(key)
+                .bind(to_db_nonce(ak_nonce)?)
+                .fetch_one(pool)
+                .await?;
+                Ok(nonce as Nonce)
+            }
+            #[cfg(feature = "shared-nonce")]
+            Self::Redis { client, key } => Ok(client
+                .query_integer(&["EVAL", REDIS_RETRY_SCRIPT, "1", key, &ak_nonce.to_string()])
+                .await? as Nonce),
+        }
+    }
+}
+
+/// Consecutive nonces of an access key reserved at once for the transactions of a batch, see `NonceAllocator::reserve`
+/// The transactions take them in order and fall back to the allocator once they run out
+pub(crate) struct NonceBlock {
+    /// Index of the submitter's lane the access key belongs to
+    pub(crate) lane: usize,
+    allocator: NonceAllocator,
+    nonces: Mutex<Range<Nonce>>,
+}
+
+impl std::fmt::Debug for NonceBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NonceBlock")
+            .field("lane", &self.lane)
+            .field("nonces", &self.nonces)
+            .finish()
+    }
+}
+
+impl NonceBlock {
+    pub(crate) async fn reserve(
+        lane: usize,
+        allocator: NonceAllocator,
+        count: u64,
+    ) -> anyhow::Result<Self> {
+        let nonces = allocator.reserve(count).await?;
+        Ok(Self {
+            lane,
+            allocator,
+            nonces: Mutex::new(nonces),
+        })
+    }
+
+    /// Next reserved nonce, `None` once they are all taken
+    pub(crate) fn take(&self) -> Option<Nonce> {
+        self.nonces.lock().unwrap().next()
+    }
+
+    /// Returns a new nonce to try with after one of the block was rejected with an InvalidNonce{ tx_nonce, ak_nonce } error,
+    /// taken from the block once resynced, or from the allocator when the block is used up
+    pub(crate) async fn retry(
+        &self,
+        old_nonce: Nonce,
+        tx_nonce: Nonce,
+        ak_nonce: Nonce,
+    ) -> anyhow::Result<Nonce> {
+        self.resync(ak_nonce).await?;
+        match self.take() {
+            Some(nonce) => Ok(nonce),
+            None => self.allocator.retry(old_nonce, tx_nonce, ak_nonce).await,
+        }
+    }
+
+    /// Replaces the nonces left in the block by a fresh range above the access key nonce
+    /// The other transactions moved the access key past the block, its nonces would be rejected as well
+    async fn resync(&self, ak_nonce: Nonce) -> anyhow::Result<()> {
+        let left = std::mem::take(&mut *self.nonces.lock().unwrap());
+        if left.is_empty() || left.start > ak_nonce {
+            *self.nonces.lock().unwrap() = left;
+            return Ok(());
+        }
+        self.allocator.raise_to(ak_nonce).await?;
+        let nonces = self.allocator.reserve(left.end - left.start).await?;
+        tracing::debug!(
+            "resynced the reserved nonces {:?} to {:?} above the access key nonce {}",
+            left,
+            nonces,
+            ak_nonce
+        );
+        *self.nonces.lock().unwrap() = nonces;
+        Ok(())
+    }
+}
+
+/// Postgres has no unsigned integers, nonces are stored as BIGINT
+#[cfg(feature = "shared-nonce")]
+fn to_db_nonce(nonce: Nonce) -> anyhow::Result<i64> {
+    i64::try_from(nonce).map_err(|_| anyhow::anyhow!("nonce {} doesn't fit into BIGINT", nonce))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn retries_from_the_resynced_block() {
+        let allocator = NonceAllocator::local(100);
+        let block = NonceBlock::reserve(0, allocator.clone(), 3).await.unwrap();
+        assert_eq!(allocator.next().await.unwrap(), 104);
+        assert_eq!(block.take(), Some(101));
+
+        // The nonces left are moved above the access key nonce, the retry takes the first one
+        assert_eq!(block.retry(101, 101, 150).await.unwrap(), 151);
+        assert_eq!(block.take(), Some(152));
+        assert_eq!(block.take(), None);
+        // A used up block falls back to the allocator
+        assert_eq!(block.retry(152, 152, 160).await.unwrap(), 161);
+        assert_eq!(allocator.next().await.unwrap(), 162);
+    }
+
+    #[tokio::test]
+    async fn persists_the_highest_recorded_nonce() {
+        let path = std::env::temp_dir().join(format!("nonces-{}.json", rand::random::<u64>()));
+        let file = NonceStateFile::open(path.clone()).unwrap();
+        file.record("signer.testnet:ed25519:a", 7);
+        file.record("signer.testnet:ed25519:a", 5);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let data = std::fs::read(&path).unwrap();
+        let nonces: BTreeMap<String, Nonce> = serde_json::from_slice(&data).unwrap();
+        assert_eq!(nonces.get("signer.testnet:ed25519:a"), Some(&7));
+        let _ = std::fs::remove_file(path);
+    }
+}
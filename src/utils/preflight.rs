@@ -0,0 +1,49 @@
+use near_jsonrpc_client::methods;
+use near_jsonrpc_primitives::types::query::RpcQueryError;
+use near_primitives::types::{BlockReference, Finality};
+use near_primitives::views::QueryRequest;
+
+use crate::errors::{CodedError, ErrorCode};
+use crate::utils::rpc_pool::RpcPool;
+
+/// Fails with `ACCOUNT_EXISTS` if the account is already on chain, so taken names are rejected without spending gas
+/// Lookups that fail for another reason let the creation go ahead, the transaction fails on its own if the name is taken
+pub(crate) async fn ensure_account_available(
+    rpc: &RpcPool,
+    account_id: &str,
+) -> anyhow::Result<()> {
+    let response = rpc
+        .call(methods::query::RpcQueryRequest {
+            block_reference: BlockReference::Finality(Finality::Final),
+            request: QueryRequest::ViewAccount {
+                account_id: account_id.parse()?,
+            },
+        })
+        .await;
+    match response {
+        Ok(Ok(_)) => Err(CodedError {
+            code: ErrorCode::AccountExists,
+            message: format!(
+                "the name {} is already taken, please choose another one",
+                account_id
+            ),
+        }
+        .into()),
+        Ok(Err(err))
+            if matches!(
+                err.handler_error(),
+                Some(RpcQueryError::UnknownAccount { .. })
+            ) =>
+        {
+            Ok(())
+        }
+        Ok(Err(err)) => {
+            tracing::warn!("Failed checking whether {} exists: {:?}", account_id, err);
+            Ok(())
+        }
+        Err(err) => {
+            tracing::warn!("Failed checking whether {} exists: {}", account_id, err);
+            Ok(())
+        }
+    }
+}
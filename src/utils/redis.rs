@@ -0,0 +1,224 @@
+use std::time::Duration;
+
+use anyhow::Context as _;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+
+/// Longest a command may take, including the wait for the connection and opening it
+/// An unresponsive Redis fails the commands then, so the limiters fall back to their local counters
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Reply of a Redis command, error replies are returned as errors
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Reply {
+    Nil,
+    Status(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+}
+
+/// Minimal Redis client speaking RESP over a single connection, enough for the shared nonce and limit counters
+/// The commands are serialized on the connection, which is opened again after a failed command
+pub(crate) struct RedisClient {
+    addr: String,
+    username: Option<String>,
+    password: Option<String>,
+    db: Option<u32>,
+    timeout: Duration,
+    conn: Mutex<Option<BufStream<TcpStream>>>,
+}
+
+impl RedisClient {
+    /// Parses `redis://[[username]:password@]host[:port][/db]`, the connection is opened by the first command
+    pub(crate) fn from_url(url: &str) -> anyhow::Result<Self> {
+        let rest = url
+            .strip_prefix("redis://")
+            .context("only redis:// URLs are supported, TLS isn't")?;
+        let (credentials, rest) = match rest.rsplit_once('@') {
+            Some((credentials, rest)) => (Some(credentials), rest),
+            None => (None, rest),
+        };
+        let (username, password) = match credentials.map(|c| c.split_once(':')) {
+            Some(Some((username, password))) => (
+                Some(username).filter(|u| !u.is_empty()).map(str::to_string),
+                Some(password.to_string()),
+            ),
+            Some(None) => (None, credentials.map(str::to_string)),
+            None => (None, None),
+        };
+        let (host, db) = match rest.split_once('/') {
+            Some((host, "")) => (host, None),
+            Some((host, db)) => (
+                host,
+                Some(
+                    db.parse()
+                        .with_context(|| format!("invalid Redis database: {}", db))?,
+                ),
+            ),
+            None => (rest, None),
+        };
+        anyhow::ensure!(!host.is_empty(), "the Redis URL has no host");
+        let addr = match host.contains(':') {
+            true => host.to_string(),
+            false => format!("{}:6379", host),
+        };
+        Ok(Self {
+            addr,
+            username,
+            password,
+            db,
+            timeout: COMMAND_TIMEOUT,
+            conn: Mutex::new(None),
+        })
+    }
+
+    async fn connect(&self) -> anyhow::Result<BufStream<TcpStream>> {
+        let stream = TcpStream::connect(&self.addr)
+            .await
+            .with_context(|| format!("failed connecting to Redis at {}", self.addr))?;
+        let mut conn = BufStream::new(stream);
+        if let Some(password) = &self.password {
+            let mut args = vec!["AUTH"];
+            args.extend(self.username.as_deref());
+            args.push(password);
+            execute(&mut conn, &args)
+                .await
+                .context("Redis authentication failed")?;
+        }
+        if let Some(db) = self.db {
+            execute(&mut conn, &["SELECT", &db.to_string()])
+                .await
+                .context("failed selecting the Redis database")?;
+        }
+        Ok(conn)
+    }
+
+    /// Runs the command, e.g. `["INCR", key]`, failing after `COMMAND_TIMEOUT`
+    pub(crate) async fn query(&self, args: &[&str]) -> anyhow::Result<Reply> {
+        let command = async {
+            let mut conn = self.conn.lock().await;
+            if conn.is_none() {
+                *conn = Some(self.connect().await?);
+            }
+            let result = execute(conn.as_mut().unwrap(), args).await;
+            // The connection may be left mid-reply, it's opened again rather than reused
+            if result.is_err() {
+                *conn = None;
+            }
+            result
+        };
+        match tokio::time::timeout(self.timeout, command).await {
+            Ok(result) => result,
+            // Dropping the command released the lock, the connection it was left in is dropped too
+            Err(_) => {
+                if let Ok(mut conn) = self.conn.try_lock() {
+                    *conn = None;
+                }
+                anyhow::bail!(
+                    "Redis at {} didn't answer within {:?}",
+                    self.addr,
+                    self.timeout
+                )
+            }
+        }
+    }
+
+    #[cfg(feature = "shared-nonce")]
+    /// Runs the command expecting an integer reply
+    pub(crate) async fn query_integer(&self, args: &[&str]) -> anyhow::Result<i64> {
+        match self.query(args).await? {
+            Reply::Integer(value) => Ok(value),
+            reply => anyhow::bail!("expected an integer reply from Redis, got {:?}", reply),
+        }
+    }
+}
+
+async fn execute(conn: &mut BufStream<TcpStream>, args: &[&str]) -> anyhow::Result<Reply> {
+    conn.write_all(&encode(args)).await?;
+    conn.flush().await?;
+    read_reply(conn).await
+}
+
+/// Command as a RESP array of bulk strings
+fn encode(args: &[&str]) -> Vec<u8> {
+    let mut command = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        command.extend(format!("${}\r\n", arg.len()).as_bytes());
+        command.extend(arg.as_bytes());
+        command.extend(b"\r\n");
+    }
+    command
+}
+
+async fn read_reply(conn: &mut (impl AsyncBufRead + Unpin)) -> anyhow::Result<Reply> {
+    let mut line = String::new();
+    if conn.read_line(&mut line).await? == 0 {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    let line = line.trim_end_matches("\r\n");
+    let (kind, value) = line.split_at(line.len().min(1));
+    match kind {
+        "+" => Ok(Reply::Status(value.to_string())),
+        "-" => anyhow::bail!("Redis error: {}", value),
+        ":" => Ok(Reply::Integer(value.parse()?)),
+        "$" => {
+            let len: i64 = value.parse()?;
+            if len < 0 {
+                return Ok(Reply::Nil);
+            }
+            let mut data = vec![0; len as usize + 2];
+            conn.read_exact(&mut data).await?;
+            data.truncate(len as usize);
+            Ok(Reply::Bulk(data))
+        }
+        _ => anyhow::bail!("unsupported Redis reply: {}", line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(mut reply: &[u8]) -> anyhow::Result<Reply> {
+        read_reply(&mut reply).await
+    }
+
+    #[test]
+    fn encodes_the_commands() {
+        assert_eq!(
+            encode(&["INCRBY", "nonce", "10"]),
+            b"*3\r\n$6\r\nINCRBY\r\n$5\r\nnonce\r\n$2\r\n10\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn parses_the_replies() {
+        assert_eq!(
+            parse(b"+OK\r\n").await.unwrap(),
+            Reply::Status("OK".to_string())
+        );
+        assert_eq!(parse(b":-42\r\n").await.unwrap(), Reply::Integer(-42));
+        assert_eq!(parse(b"$-1\r\n").await.unwrap(), Reply::Nil);
+        assert_eq!(
+            parse(b"$4\r\na\r\nb\r\n").await.unwrap(),
+            Reply::Bulk(b"a\r\nb".to_vec())
+        );
+        assert!(parse(b"-ERR wrong type\r\n").await.is_err());
+        assert!(parse(b"*1\r\n").await.is_err());
+        assert!(parse(b"$5\r\nabc").await.is_err());
+        assert!(parse(b"").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn times_out_on_an_unresponsive_server() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Accepts the connection but never answers
+        let server = tokio::spawn(async move { listener.accept().await });
+        let mut client = RedisClient::from_url(&format!("redis://{}", addr)).unwrap();
+        client.timeout = Duration::from_millis(100);
+        assert!(client.query(&["PING"]).await.is_err());
+        server.abort();
+    }
+}
@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// The backoff never grows past this, however many attempts are allowed
+const MAX_DELAY: Duration = Duration::from_secs(10);
+/// Delay before the first retry of a rejected nonce, doubled for each of the next ones
+const NONCE_RETRY_BASE_DELAY: Duration = Duration::from_millis(50);
+
+/// How often and how patiently the transient RPC failures are retried
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RetryPolicy {
+    /// Attempts in total, including the first one
+    pub(crate) max_attempts: u32,
+    /// Delay before the first retry, doubled for each of the next ones
+    pub(crate) base_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    /// Fails on the first transient error
+    fn default() -> Self {
+        Self {
+            max_attempts: 1,
+            base_delay: Duration::ZERO,
+        }
+    }
+}
+
+/// How the transactions rejected for a congested shard are held back and sent again
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CongestionPolicy {
+    /// Delay before each resend
+    pub(crate) delay: Duration,
+    /// Total time a creation may spend waiting, it fails once the next delay would exceed it
+    pub(crate) max_wait: Duration,
+}
+
+impl Default for CongestionPolicy {
+    /// Fails on the first congestion error
+    fn default() -> Self {
+        Self {
+            delay: Duration::ZERO,
+            max_wait: Duration::ZERO,
+        }
+    }
+}
+
+impl CongestionPolicy {
+    /// Whether another delay fits in the total wait, given the time already waited
+    pub(crate) fn should_wait(&self, waited: Duration) -> bool {
+        !self.delay.is_zero() && waited + self.delay <= self.max_wait
+    }
+}
+
+/// Delay before retrying the given number of rejected nonces, with full jitter
+/// The creations rejected together would otherwise all retry at once and collide on the nonces again
+pub(crate) fn nonce_retry_delay(nonce_retries: u32) -> Duration {
+    let exponent = nonce_retries.saturating_sub(1).min(16);
+    let cap = NONCE_RETRY_BASE_DELAY.saturating_mul(1 << exponent);
+    cap.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+}
+
+impl RetryPolicy {
+    /// Whether another attempt may follow the given number of failed ones
+    pub(crate) fn should_retry(&self, failed_attempts: u32) -> bool {
+        failed_attempts < self.max_attempts
+    }
+
+    /// Delay before the retry following the given number of failed attempts,
+    /// with full jitter so the replicas retrying at once don't hit the node in lockstep
+    pub(crate) fn delay(&self, failed_attempts: u32) -> Duration {
+        let exponent = failed_attempts.saturating_sub(1).min(16);
+        let cap = self.base_delay.saturating_mul(1 << exponent).min(MAX_DELAY);
+        cap.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+    }
+}
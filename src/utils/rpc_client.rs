@@ -0,0 +1,140 @@
+use std::future::Future;
+
+use anyhow::Context as _;
+use near_account_id::AccountId;
+use near_crypto::PublicKey;
+use near_jsonrpc_client::errors::JsonRpcError;
+use near_jsonrpc_client::methods::{
+    self, block::RpcBlockRequest, status::RpcStatusRequest, tx::RpcTransactionError,
+};
+use near_jsonrpc_primitives::types::query::QueryResponseKind;
+use near_primitives::hash::CryptoHash;
+use near_primitives::transaction::SignedTransaction;
+use near_primitives::types::{BlockHeight, BlockReference, Finality};
+use near_primitives::views::{AccessKeyView, TxExecutionStatus};
+
+use crate::utils::block_hash::BlockSource;
+use crate::utils::rpc_pool::{RpcPool, RpcTimeout};
+use crate::utils::send_tx::{send_tx_request, tx_status_request, SendTxResponse};
+
+/// RPC client of the submitter, wrapped to inject the faults with the `chaos` feature
+#[cfg(not(feature = "chaos"))]
+pub(crate) type SubmitterRpc = RpcPool;
+#[cfg(feature = "chaos")]
+pub(crate) type SubmitterRpc = crate::utils::chaos::ChaosRpc<RpcPool>;
+
+/// Answer of the `send_tx` and `tx` methods, `RpcTimeout` if no endpoint answered in time
+pub(crate) type TxResponse =
+    Result<Result<SendTxResponse, JsonRpcError<RpcTransactionError>>, RpcTimeout>;
+
+/// The NEAR RPC methods the account creation relies on
+/// Implemented by `RpcPool`, the tests substitute a mock to script the node's answers
+pub(crate) trait AccountCreatorRpc: Clone + Send + Sync + 'static {
+    /// Sends the signed transaction with `send_tx` and waits until it reaches `wait_until`
+    fn broadcast(
+        &self,
+        signed_transaction: &SignedTransaction,
+        wait_until: TxExecutionStatus,
+    ) -> impl Future<Output = anyhow::Result<TxResponse>> + Send;
+
+    /// Polls the status of a sent transaction with the `tx` method
+    fn tx_status(
+        &self,
+        tx_hash: &CryptoHash,
+        sender_account_id: &AccountId,
+        wait_until: TxExecutionStatus,
+    ) -> impl Future<Output = TxResponse> + Send;
+
+    /// Fetches the access key as of the latest block
+    fn view_access_key(
+        &self,
+        account_id: &AccountId,
+        public_key: &PublicKey,
+    ) -> impl Future<Output = anyhow::Result<AccessKeyView>> + Send;
+
+    /// Fetches the hash and the height of the latest block of the given source
+    fn latest_block(
+        &self,
+        source: BlockSource,
+    ) -> impl Future<Output = anyhow::Result<(CryptoHash, BlockHeight)>> + Send;
+
+    /// Fails fast while the RPC is known to be down
+    fn check_available(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+impl AccountCreatorRpc for RpcPool {
+    async fn broadcast(
+        &self,
+        signed_transaction: &SignedTransaction,
+        wait_until: TxExecutionStatus,
+    ) -> anyhow::Result<TxResponse> {
+        Ok(self
+            .call(send_tx_request(signed_transaction, wait_until)?)
+            .await)
+    }
+
+    async fn tx_status(
+        &self,
+        tx_hash: &CryptoHash,
+        sender_account_id: &AccountId,
+        wait_until: TxExecutionStatus,
+    ) -> TxResponse {
+        self.call(tx_status_request(tx_hash, sender_account_id, wait_until))
+            .await
+    }
+
+    async fn view_access_key(
+        &self,
+        account_id: &AccountId,
+        public_key: &PublicKey,
+    ) -> anyhow::Result<AccessKeyView> {
+        let response = self
+            .call(methods::query::RpcQueryRequest {
+                block_reference: BlockReference::Finality(Finality::None),
+                request: near_primitives::views::QueryRequest::ViewAccessKey {
+                    account_id: account_id.clone(),
+                    public_key: public_key.clone(),
+                },
+            })
+            .await?
+            .with_context(|| {
+                format!(
+                    "failed fetching access key info for {} {}",
+                    account_id, public_key
+                )
+            })?;
+        match response.kind {
+            QueryResponseKind::AccessKey(access_key) => Ok(access_key),
+            kind => anyhow::bail!(
+                "received unexpected query response when getting access key info: {:?}",
+                kind
+            ),
+        }
+    }
+
+    async fn latest_block(&self, source: BlockSource) -> anyhow::Result<(CryptoHash, BlockHeight)> {
+        match source {
+            BlockSource::Final => {
+                let block = self
+                    .call(RpcBlockRequest {
+                        block_reference: BlockReference::Finality(Finality::Final),
+                    })
+                    .await??;
+                Ok((block.header.hash, block.header.height))
+            }
+            BlockSource::Status => {
+                let status = self.call(RpcStatusRequest).await??;
+                Ok((
+                    status.sync_info.latest_block_hash,
+                    status.sync_info.latest_block_height,
+                ))
+            }
+        }
+    }
+
+    fn check_available(&self) -> anyhow::Result<()> {
+        RpcPool::check_available(self)
+    }
+}
@@ -0,0 +1,308 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context as _;
+use near_jsonrpc_client::{
+    errors::{
+        JsonRpcError, JsonRpcServerError, JsonRpcTransportHandlerResponseError,
+        JsonRpcTransportRecvError, JsonRpcTransportSendError, RpcTransportError,
+    },
+    methods::RpcMethod,
+    JsonRpcClient, MethodCallResult,
+};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::errors::{CodedError, ErrorCode};
+use crate::metrics::RPC_ENDPOINT_UP;
+use crate::utils::circuit_breaker::{CircuitBreaker, CircuitState};
+
+/// Consecutive failures after which an endpoint is taken out of the rotation
+const FAILURES_TO_MARK_DOWN: u32 = 3;
+/// How long an endpoint stays out of the rotation before it's tried again
+const MARKED_DOWN_FOR: Duration = Duration::from_secs(30);
+
+/// One of the configured NEAR RPC nodes along with its health
+#[derive(Debug)]
+pub(crate) struct Endpoint {
+    /// Host of the endpoint only, the URLs may carry API keys
+    host: String,
+    client: JsonRpcClient,
+    consecutive_failures: AtomicU32,
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl Endpoint {
+    pub(crate) fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub(crate) fn client(&self) -> &JsonRpcClient {
+        &self.client
+    }
+
+    /// Whether the endpoint is in the rotation, i.e. it wasn't marked down recently
+    pub(crate) fn is_up(&self) -> bool {
+        self.down_until
+            .lock()
+            .unwrap()
+            .map_or(true, |until| until <= Instant::now())
+    }
+
+    fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        *self.down_until.lock().unwrap() = None;
+        RPC_ENDPOINT_UP.with_label_values(&[&self.host]).set(1);
+    }
+
+    fn record_failure(&self) {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= FAILURES_TO_MARK_DOWN {
+            tracing::warn!(
+                "NEAR RPC endpoint {} failed {} times in a row, taking it out of the rotation for {:?}",
+                self.host,
+                failures,
+                MARKED_DOWN_FOR
+            );
+            *self.down_until.lock().unwrap() = Some(Instant::now() + MARKED_DOWN_FOR);
+            RPC_ENDPOINT_UP.with_label_values(&[&self.host]).set(0);
+        }
+    }
+}
+
+/// Clients of the configured NEAR RPC endpoints, in the order of preference
+/// Each call goes to the first endpoint in the rotation and fails over to the next one on server errors and timeouts
+#[derive(Debug, Clone)]
+pub(crate) struct RpcPool {
+    endpoints: Arc<Vec<Endpoint>>,
+    /// Budget of a whole call, failing over between the endpoints included
+    call_timeout: Duration,
+    /// Fails the calls fast while all the endpoints are down
+    breaker: Option<Arc<CircuitBreaker>>,
+}
+
+impl RpcPool {
+    /// Requests to an endpoint taking longer than `attempt_timeout` fail over to the next endpoint,
+    /// calls taking longer than `call_timeout` in total fail with `RpcTimeout`
+    pub(crate) fn new(
+        urls: &[String],
+        headers: HeaderMap,
+        attempt_timeout: Duration,
+        call_timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        anyhow::ensure!(!urls.is_empty(), "at least one NEAR RPC URL is required");
+        let http = reqwest::Client::builder()
+            .timeout(attempt_timeout)
+            .default_headers(headers)
+            .build()?;
+        let endpoints = urls
+            .iter()
+            .map(|url| {
+                let host = endpoint_host(url);
+                RPC_ENDPOINT_UP.with_label_values(&[&host]).set(1);
+                Endpoint {
+                    host,
+                    client: JsonRpcClient::with(http.clone()).connect(url),
+                    consecutive_failures: AtomicU32::new(0),
+                    down_until: Mutex::new(None),
+                }
+            })
+            .collect();
+        Ok(Self {
+            endpoints: Arc::new(endpoints),
+            call_timeout,
+            breaker: None,
+        })
+    }
+
+    /// Opens the circuit after `threshold` consecutive calls failed on all the endpoints, probing again after `cooldown`
+    pub(crate) fn with_circuit_breaker(mut self, threshold: u32, cooldown: Duration) -> Self {
+        self.breaker = Some(Arc::new(CircuitBreaker::new(threshold, cooldown)));
+        self
+    }
+
+    /// `None` without a circuit breaker
+    pub(crate) fn circuit_state(&self) -> Option<CircuitState> {
+        self.breaker.as_ref().map(|breaker| breaker.state())
+    }
+
+    /// Fails with `RPC_UNAVAILABLE` while the circuit is open, so the callers don't queue up behind a dead RPC
+    pub(crate) fn check_available(&self) -> anyhow::Result<()> {
+        match self.breaker.as_ref().and_then(|breaker| breaker.retry_in()) {
+            Some(retry_in) => Err(CodedError {
+                code: ErrorCode::RpcUnavailable,
+                message: format!(
+                    "the NEAR RPC is unavailable, try again in {} seconds",
+                    retry_in.as_secs().max(1)
+                ),
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    /// Calls the method on the endpoints in the rotation until one of them answers, within the budget of a call
+    /// Handler errors are answers too, only the failures of the endpoint itself are failed over
+    /// When all the endpoints are marked down, all of them are tried rather than failing right away
+    pub(crate) async fn call<M>(
+        &self,
+        method: M,
+    ) -> Result<MethodCallResult<M::Response, M::Error>, RpcTimeout>
+    where
+        M: RpcMethod,
+        M::Error: std::fmt::Debug,
+    {
+        let method_name = method.method_name().to_string();
+        tokio::time::timeout(self.call_timeout, self.call_endpoints(method))
+            .await
+            .map_err(|_| RpcTimeout {
+                method: method_name,
+                after: self.call_timeout,
+            })
+    }
+
+    async fn call_endpoints<M>(&self, method: M) -> MethodCallResult<M::Response, M::Error>
+    where
+        M: RpcMethod,
+        M::Error: std::fmt::Debug,
+    {
+        if let Some(breaker) = &self.breaker {
+            if let Err(retry_in) = breaker.admit() {
+                return Err(JsonRpcError::ServerError(
+                    JsonRpcServerError::InternalError {
+                        info: Some(format!(
+                            "circuit breaker is open, the NEAR RPC isn't called for another {:?}",
+                            retry_in
+                        )),
+                    },
+                ));
+            }
+        }
+        let up = self.endpoints.iter().filter(|endpoint| endpoint.is_up());
+        let candidates: Vec<&Endpoint> = if self.endpoints.iter().any(Endpoint::is_up) {
+            up.collect()
+        } else {
+            self.endpoints.iter().collect()
+        };
+        let mut last_error = None;
+        for endpoint in candidates {
+            match endpoint.client.call(&method).await {
+                Err(err) if is_endpoint_failure(&err) => {
+                    tracing::warn!(
+                        "NEAR RPC endpoint {} failed {}: {:?}",
+                        endpoint.host,
+                        method.method_name(),
+                        err
+                    );
+                    endpoint.record_failure();
+                    last_error = Some(err);
+                }
+                result => {
+                    endpoint.record_success();
+                    if let Some(breaker) = &self.breaker {
+                        breaker.record_success();
+                    }
+                    return result;
+                }
+            }
+        }
+        if let Some(breaker) = &self.breaker {
+            breaker.record_failure();
+        }
+        Err(last_error.expect("the pool has at least one endpoint"))
+    }
+}
+
+/// A NEAR RPC call didn't finish within its budget, whether the node got the request is unknown
+#[derive(Debug)]
+pub(crate) struct RpcTimeout {
+    pub(crate) method: String,
+    pub(crate) after: Duration,
+}
+
+impl fmt::Display for RpcTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "NEAR RPC call {} timed out after {:?}",
+            self.method, self.after
+        )
+    }
+}
+
+impl std::error::Error for RpcTimeout {}
+
+/// Whether the error is the fault of the endpoint rather than of the request, so another endpoint may do better
+pub(crate) fn is_endpoint_failure<E>(err: &JsonRpcError<E>) -> bool {
+    match err {
+        JsonRpcError::TransportError(RpcTransportError::SendError(
+            JsonRpcTransportSendError::PayloadSerializeError(_),
+        )) => false,
+        err if is_congestion(err) => false,
+        JsonRpcError::TransportError(_) => true,
+        JsonRpcError::ServerError(
+            JsonRpcServerError::InternalError { .. }
+            | JsonRpcServerError::ResponseStatusError(_)
+            | JsonRpcServerError::NonContextualError(_),
+        ) => true,
+        JsonRpcError::ServerError(
+            JsonRpcServerError::RequestValidationError(_) | JsonRpcServerError::HandlerError(_),
+        ) => false,
+    }
+}
+
+/// Transaction rejected because its shard is congested or stuck
+/// The client predates these errors and fails parsing them, so they're recognized by the variant named in the parse error
+pub(crate) fn is_congestion<E>(err: &JsonRpcError<E>) -> bool {
+    match err {
+        JsonRpcError::TransportError(RpcTransportError::RecvError(
+            JsonRpcTransportRecvError::ResponseParseError(
+                JsonRpcTransportHandlerResponseError::ErrorMessageParseError(err),
+            ),
+        )) => {
+            let err = err.to_string();
+            err.contains("`ShardCongested`") || err.contains("`ShardStuck`")
+        }
+        _ => false,
+    }
+}
+
+/// Headers sent with every RPC request: the API key, as `x-api-key`, and `KEY=VALUE` pairs as given on the command line
+/// The values are marked sensitive, so they're kept out of the debug output
+pub(crate) fn rpc_headers(api_key: Option<&str>, pairs: &[String]) -> anyhow::Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let pairs = pairs.iter().map(|pair| {
+        pair.split_once('=')
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .ok_or_else(|| anyhow::anyhow!("expected KEY=VALUE, got {}", pair))
+    });
+    for pair in api_key
+        .map(|key| Ok(("x-api-key", key)))
+        .into_iter()
+        .chain(pairs)
+    {
+        let (name, value) = pair?;
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("invalid RPC header name: {}", name))?;
+        let mut value = HeaderValue::from_str(value)
+            .with_context(|| format!("invalid value of the RPC header {}", name))?;
+        value.set_sensitive(true);
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+/// Strips the scheme, the credentials, the path and the query from the URL
+pub(crate) fn endpoint_host(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host)
+        .to_string()
+}
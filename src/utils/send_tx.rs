@@ -0,0 +1,142 @@
+use anyhow::Context as _;
+use near_account_id::AccountId;
+use near_jsonrpc_client::methods::{
+    self, tx::RpcTransactionError, RpcAnyRequest, RpcHandlerResponse,
+};
+use near_primitives::hash::CryptoHash;
+use near_primitives::transaction::SignedTransaction;
+use near_primitives::views::{
+    FinalExecutionOutcomeView, FinalExecutionOutcomeViewEnum, TxExecutionStatus,
+};
+use serde::Deserialize;
+
+/// How long the creation endpoints wait for the transaction before responding
+/// Passed as `?wait=included|executed|final`, trading latency for certainty
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum WaitLevel {
+    /// The transaction is included into a block, its execution result is unknown
+    Included,
+    /// The transaction and its receipts are executed (the same as `broadcast_tx_commit`)
+    #[default]
+    Executed,
+    /// The execution of the transaction and its receipts is finalised
+    Final,
+}
+
+impl std::fmt::Display for WaitLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            WaitLevel::Included => "included",
+            WaitLevel::Executed => "executed",
+            WaitLevel::Final => "final",
+        })
+    }
+}
+
+impl std::str::FromStr for WaitLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "included" => Ok(WaitLevel::Included),
+            "executed" => Ok(WaitLevel::Executed),
+            "final" => Ok(WaitLevel::Final),
+            _ => anyhow::bail!("unknown wait level: {}", s),
+        }
+    }
+}
+
+impl From<WaitLevel> for TxExecutionStatus {
+    fn from(level: WaitLevel) -> Self {
+        match level {
+            WaitLevel::Included => TxExecutionStatus::Included,
+            WaitLevel::Executed => TxExecutionStatus::Executed,
+            WaitLevel::Final => TxExecutionStatus::Final,
+        }
+    }
+}
+
+/// Default wait level of the requests not passing one, set with `--tx-wait-until`
+/// `none` doesn't wait for the transaction at all, same as `--async-broadcast`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum TxWaitUntil {
+    None,
+    Included,
+    /// Called `EXECUTED_OPTIMISTIC` by the newer nodes
+    #[value(alias = "executed-optimistic")]
+    Executed,
+    Final,
+}
+
+impl TxWaitUntil {
+    /// The wait level of the requests, `None` if they don't wait
+    pub(crate) fn wait_level(self) -> Option<WaitLevel> {
+        match self {
+            TxWaitUntil::None => None,
+            TxWaitUntil::Included => Some(WaitLevel::Included),
+            TxWaitUntil::Executed => Some(WaitLevel::Executed),
+            TxWaitUntil::Final => Some(WaitLevel::Final),
+        }
+    }
+}
+
+/// A sent creation transaction: the level it actually reached, maybe past the requested one,
+/// and its outcome unless that was before the execution
+#[derive(Debug, Clone)]
+pub(crate) struct Submitted {
+    pub(crate) reached: TxExecutionStatus,
+    pub(crate) outcome: Option<FinalExecutionOutcomeView>,
+    /// Hash of the sent transaction, unknown for the jobs of the queue completed without an outcome
+    pub(crate) tx_hash: Option<CryptoHash>,
+}
+
+/// Query parameters accepted by the creation endpoints
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct WaitQuery {
+    pub(crate) wait: Option<WaitLevel>,
+}
+
+/// Response of the `send_tx` RPC method
+/// The outcome is missing if the requested wait level is reached before the execution
+#[derive(Debug, Deserialize)]
+pub(crate) struct SendTxResponse {
+    #[serde(flatten)]
+    pub(crate) final_execution_outcome: Option<FinalExecutionOutcomeViewEnum>,
+    pub(crate) final_execution_status: TxExecutionStatus,
+}
+
+impl RpcHandlerResponse for SendTxResponse {}
+
+/// Builds the `tx` RPC request polling the status of a sent transaction
+/// Answers like `send_tx`, so both go through the same outcome handling
+pub(crate) fn tx_status_request(
+    tx_hash: &CryptoHash,
+    sender_account_id: &AccountId,
+    wait_until: TxExecutionStatus,
+) -> RpcAnyRequest<SendTxResponse, RpcTransactionError> {
+    methods::any::<Result<SendTxResponse, RpcTransactionError>>(
+        "tx",
+        serde_json::json!({
+            "tx_hash": tx_hash,
+            "sender_account_id": sender_account_id,
+            "wait_until": wait_until,
+        }),
+    )
+}
+
+/// Builds the `send_tx` RPC request
+/// `near-jsonrpc-client` doesn't support this method yet, so it goes through `methods::any`
+pub(crate) fn send_tx_request(
+    signed_transaction: &SignedTransaction,
+    wait_until: TxExecutionStatus,
+) -> anyhow::Result<RpcAnyRequest<SendTxResponse, RpcTransactionError>> {
+    let signed_tx = borsh::to_vec(signed_transaction).context("failed serializing transaction")?;
+    Ok(methods::any::<Result<SendTxResponse, RpcTransactionError>>(
+        "send_tx",
+        serde_json::json!({
+            "signed_tx_base64": near_primitives::serialize::to_base64(&signed_tx),
+            "wait_until": wait_until,
+        }),
+    ))
+}
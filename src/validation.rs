@@ -0,0 +1,347 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::Context as _;
+use near_account_id::AccountId;
+use near_crypto::PublicKey;
+use serde::Serialize;
+
+use crate::account_lists::ReloadableAccountLists;
+use crate::errors::ErrorCode;
+
+/// Characters the account names may consist of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum NameCharset {
+    /// Everything NEAR allows: lowercase letters, digits and single `-` or `_` between them
+    Near,
+    /// Lowercase letters and digits
+    Alphanumeric,
+    /// Lowercase letters only
+    Letters,
+}
+
+impl NameCharset {
+    /// Human-readable description, as shown next to the form and in the errors
+    pub(crate) fn describe(&self) -> &'static str {
+        match self {
+            NameCharset::Near => "lowercase letters, digits and single `-` or `_` between them",
+            NameCharset::Alphanumeric => "lowercase letters and digits",
+            NameCharset::Letters => "lowercase letters",
+        }
+    }
+
+    fn allows(&self, c: char) -> bool {
+        match self {
+            NameCharset::Near => {
+                c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_'
+            }
+            NameCharset::Alphanumeric => c.is_ascii_lowercase() || c.is_ascii_digit(),
+            NameCharset::Letters => c.is_ascii_lowercase(),
+        }
+    }
+
+    /// What's wrong with the characters of the name, if anything
+    fn violation(&self, name: &str) -> Option<String> {
+        if let Some(c) = name.chars().find(|c| !self.allows(*c)) {
+            return Some(format!(
+                "account name can't contain `{}`, only {}",
+                c,
+                self.describe()
+            ));
+        }
+        let separator = |c: char| c == '-' || c == '_';
+        if name.starts_with(separator) || name.ends_with(separator) {
+            return Some("account name can't start or end with `-` or `_`".to_string());
+        }
+        if name
+            .as_bytes()
+            .windows(2)
+            .any(|pair| separator(pair[0] as char) && separator(pair[1] as char))
+        {
+            return Some("account name can't contain `-` or `_` twice in a row".to_string());
+        }
+        None
+    }
+}
+
+/// Rules the account input of every entry point is checked against
+#[derive(Debug, Clone)]
+pub(crate) struct ValidationRules {
+    /// Parent account, the created accounts are `<name>.<suffix>`
+    pub(crate) suffix: AccountId,
+    pub(crate) min_name_length: usize,
+    pub(crate) max_name_length: usize,
+    pub(crate) charset: NameCharset,
+    /// Lowercase words the names may not contain, e.g. profanity, matched ignoring the separators
+    blocked_words: Vec<String>,
+    /// Names and keys the operators allow or refuse
+    pub(crate) lists: Option<ReloadableAccountLists>,
+}
+
+/// Account input that passed the validation, with the suffix appended
+#[derive(Debug, Clone)]
+pub(crate) struct AccountInput {
+    pub(crate) account_id: String,
+    pub(crate) public_key: String,
+}
+
+/// Input field a validation error refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Field {
+    AccountId,
+    PublicKey,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct FieldError {
+    pub(crate) field: Field,
+    pub(crate) code: ErrorCode,
+    pub(crate) message: String,
+}
+
+/// All the problems found in the input, so the user can fix them at once
+#[derive(Debug, Clone)]
+pub(crate) struct ValidationErrors(pub(crate) Vec<FieldError>);
+
+impl ValidationErrors {
+    /// Code of the first problem, used as the code of the whole response
+    pub(crate) fn code(&self) -> ErrorCode {
+        self.0
+            .first()
+            .map(|err| err.code)
+            .unwrap_or(ErrorCode::InvalidRequest)
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let messages: Vec<&str> = self.0.iter().map(|err| err.message.as_str()).collect();
+        f.write_str(&messages.join("; "))
+    }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+impl ValidationRules {
+    /// Rules allowing any name that fits into the NEAR account ID limits under the given suffix
+    pub(crate) fn new(suffix: AccountId) -> Self {
+        // The full account id is `<name>.<suffix>` and has to fit into the NEAR account id limit
+        let max_name_length = AccountId::MAX_LEN.saturating_sub(suffix.len() + 1);
+        Self {
+            suffix,
+            min_name_length: AccountId::MIN_LEN,
+            max_name_length,
+            charset: NameCharset::Near,
+            blocked_words: Vec::new(),
+            lists: None,
+        }
+    }
+
+    /// Narrows the allowed name length, the NEAR limits still apply
+    pub(crate) fn with_name_length(mut self, min: Option<usize>, max: Option<usize>) -> Self {
+        if let Some(min) = min {
+            self.min_name_length = min.max(AccountId::MIN_LEN);
+        }
+        if let Some(max) = max {
+            self.max_name_length = max.min(self.max_name_length);
+        }
+        self
+    }
+
+    pub(crate) fn with_charset(mut self, charset: NameCharset) -> Self {
+        self.charset = charset;
+        self
+    }
+
+    /// Rejects the names containing any of the words
+    pub(crate) fn with_blocked_words(mut self, words: Vec<String>) -> Self {
+        self.blocked_words = words.into_iter().map(|word| word.to_lowercase()).collect();
+        self
+    }
+
+    /// Reads the blocked words, one per line, empty lines and lines starting with `#` are ignored
+    pub(crate) fn load_blocked_words(path: &Path) -> anyhow::Result<Vec<String>> {
+        let words = std::fs::read_to_string(path)
+            .with_context(|| format!("failed reading blocked words file {}", path.display()))?;
+        Ok(words
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect())
+    }
+
+    /// Summary of the name rules, e.g. `2 to 56 lowercase letters and digits`
+    pub(crate) fn describe_names(&self) -> String {
+        format!(
+            "{} to {} {}",
+            self.min_name_length,
+            self.max_name_length,
+            self.charset.describe()
+        )
+    }
+
+    /// Whether the name contains a blocked word, `-` and `_` are ignored so they can't split the word
+    fn contains_blocked_word(&self, name: &str) -> bool {
+        let joined: String = name.chars().filter(|c| *c != '-' && *c != '_').collect();
+        self.blocked_words
+            .iter()
+            .any(|word| !word.is_empty() && joined.contains(word.as_str()))
+    }
+
+    /// Checks the names and keys against the operators' lists as well
+    pub(crate) fn with_lists(mut self, lists: ReloadableAccountLists) -> Self {
+        self.lists = Some(lists);
+        self
+    }
+
+    /// Name part of the input, i.e. the first label if the rest is exactly the suffix, the whole input otherwise
+    ///
+    /// Matching whole labels rather than the string end keeps `alice.evilstatelessnet` or
+    /// `alice.other.statelessnet` from passing as sub-accounts of `statelessnet`,
+    /// they end up with dots in the name and are rejected
+    fn name_of<'a>(&self, account_id: &'a str) -> &'a str {
+        match account_id.split_once('.') {
+            Some((name, parent)) if parent == self.suffix.as_str() => name,
+            _ => account_id,
+        }
+    }
+
+    /// Trims and lowercases the input, appends the suffix unless the user already did and checks the result
+    pub(crate) fn validate(
+        &self,
+        account_id: &str,
+        public_key: &str,
+    ) -> Result<AccountInput, ValidationErrors> {
+        let mut errors = vec![];
+        let account_id = account_id.trim().to_lowercase();
+        let public_key = public_key.trim();
+
+        let name = self.name_of(&account_id);
+        let account_id = format!("{}.{}", name, self.suffix);
+        let mut account_error = |message: String| {
+            errors.push(FieldError {
+                field: Field::AccountId,
+                code: ErrorCode::InvalidAccountId,
+                message,
+            })
+        };
+        if name.len() < self.min_name_length {
+            account_error(format!(
+                "account name must be at least {} characters long",
+                self.min_name_length
+            ));
+        } else if name.len() > self.max_name_length {
+            account_error(format!(
+                "account name must be at most {} characters long",
+                self.max_name_length
+            ));
+        } else if name.contains('.') {
+            // Only direct sub-accounts of the base signer can be created
+            account_error(format!(
+                "{} is not a direct sub-account of {}",
+                account_id, self.suffix
+            ));
+        } else if let Some(message) = self.charset.violation(name) {
+            account_error(message);
+        } else if let Err(err) = AccountId::from_str(&account_id) {
+            account_error(format!("invalid account ID {}: {}", account_id, err));
+        } else if self.contains_blocked_word(name) {
+            errors.push(FieldError {
+                field: Field::AccountId,
+                code: ErrorCode::ReservedName,
+                message: "account name contains a word that isn't allowed".to_string(),
+            });
+        } else if let Some(message) = self
+            .lists
+            .as_ref()
+            .and_then(|lists| lists.current().refuses_name(name))
+        {
+            errors.push(FieldError {
+                field: Field::AccountId,
+                code: ErrorCode::ReservedName,
+                message,
+            });
+        }
+
+        match PublicKey::from_str(public_key) {
+            Err(err) => errors.push(FieldError {
+                field: Field::PublicKey,
+                code: ErrorCode::InvalidPublicKey,
+                message: format!("invalid public key {}: {}", public_key, err),
+            }),
+            Ok(key) => {
+                if let Some(message) = self
+                    .lists
+                    .as_ref()
+                    .and_then(|lists| lists.current().refuses_key(&key))
+                {
+                    errors.push(FieldError {
+                        field: Field::PublicKey,
+                        code: ErrorCode::BlockedKey,
+                        message,
+                    });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(AccountInput {
+                account_id,
+                public_key: public_key.to_string(),
+            })
+        } else {
+            Err(ValidationErrors(errors))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "ed25519:6E8sCci9badyRkXb3JoRpBj5p8C6Tw41ELDZoiihKEtp";
+
+    #[test]
+    fn enforces_the_name_policy() {
+        let rules = ValidationRules::new("testnet".parse().unwrap())
+            .with_charset(NameCharset::Alphanumeric)
+            .with_blocked_words(vec!["Darn".to_string()]);
+        assert!(rules.validate("alice42", KEY).is_ok());
+
+        let errors = rules.validate("alice-42", KEY).unwrap_err();
+        assert_eq!(errors.code(), ErrorCode::InvalidAccountId);
+        assert!(errors.to_string().contains("`-`"));
+
+        let errors = rules.validate("xdarnx.testnet", KEY).unwrap_err();
+        assert_eq!(errors.code(), ErrorCode::ReservedName);
+
+        let rules = ValidationRules::new("testnet".parse().unwrap());
+        assert!(rules.validate("alice-42", KEY).is_ok());
+        assert!(rules.validate("alice--42", KEY).is_err());
+        assert!(rules.validate("_alice", KEY).is_err());
+    }
+
+    #[test]
+    fn strips_only_the_whole_suffix() {
+        let rules = ValidationRules::new("statelessnet".parse().unwrap());
+        for account_id in ["alice.evilstatelessnet", "alice.other.statelessnet"] {
+            let errors = rules.validate(account_id, KEY).unwrap_err();
+            assert_eq!(errors.code(), ErrorCode::InvalidAccountId);
+        }
+        assert_eq!(
+            rules
+                .validate("ALICE.statelessnet", KEY)
+                .unwrap()
+                .account_id,
+            "alice.statelessnet"
+        );
+        assert_eq!(
+            rules.validate("statelessnet", KEY).unwrap().account_id,
+            "statelessnet.statelessnet"
+        );
+    }
+}
@@ -0,0 +1,186 @@
+use actix_web::http::header;
+use actix_web::{error, web, HttpRequest, HttpResponse, Responder, Result};
+use near_primitives::views::TxExecutionStatus;
+use serde::Serialize;
+use tera::Context;
+
+use crate::errors::{retry_after, user_message, ErrorCode};
+use crate::tx_tracker::CreationTx;
+use crate::utils::send_tx::WaitQuery;
+use crate::{CheckMode, FormCreated, FormData, FormFailure, NearData};
+
+/// Origins the Turnstile script and challenge frame are loaded from
+const TURNSTILE_SOURCES: &[&str] = &["https://challenges.cloudflare.com"];
+/// Origins the reCAPTCHA script and its frame are loaded from
+const RECAPTCHA_SOURCES: &[&str] = &["https://www.google.com", "https://www.gstatic.com"];
+
+/// Origins allowed to embed the `/widget` page in an iframe
+/// Used both for the `frame-ancestors` CSP directive and as `postMessage` targets
+#[derive(Debug, Clone, Default)]
+pub(crate) struct WidgetConfig {
+    pub(crate) allowed_origins: Vec<String>,
+    /// Whether the page loads the Turnstile widget, whose script and frame the policy allows then
+    pub(crate) turnstile: bool,
+    /// Whether the page loads reCAPTCHA, whose script and frame the policy allows then
+    pub(crate) recaptcha: bool,
+}
+
+impl WidgetConfig {
+    /// Content-Security-Policy of the widget page
+    /// No inline scripts or styles are used so the page works under a strict policy
+    fn content_security_policy(&self) -> String {
+        let mut frame_ancestors = vec!["'self'".to_string()];
+        frame_ancestors.extend(self.allowed_origins.iter().cloned());
+        let mut sources = vec!["'self'"];
+        if self.turnstile {
+            sources.extend(TURNSTILE_SOURCES);
+        }
+        if self.recaptcha {
+            sources.extend(RECAPTCHA_SOURCES);
+        }
+        format!(
+            "default-src 'self'; script-src {0}; frame-src {0}; style-src 'self'; frame-ancestors {1}",
+            sources.join(" "),
+            frame_ancestors.join(" ")
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct WidgetResponse {
+    success: bool,
+    account_id: String,
+    public_key: String,
+    code: Option<ErrorCode>,
+    error: Option<String>,
+    /// Level the creation transaction reached before responding
+    #[serde(skip_serializing_if = "Option::is_none")]
+    final_execution_status: Option<TxExecutionStatus>,
+    /// Hash of the creation transaction, the gas and tokens it burnt once executed and its explorer page
+    #[serde(flatten)]
+    transaction: Option<CreationTx>,
+    /// Seconds until the client may retry, e.g. the remaining cooldown of the public key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after_secs: Option<u64>,
+}
+
+/// Endpoint: /widget
+/// Minimal version of the index page meant to be embedded in an iframe by partner sites
+/// The results of the submission are reported to the parent window via `postMessage`
+pub(crate) async fn widget(
+    near: web::Data<NearData>,
+    templates: web::Data<crate::templates::Templates>,
+    widget_config: web::Data<WidgetConfig>,
+) -> Result<impl Responder> {
+    tracing::debug!("GET /widget");
+    let mut context = Context::new();
+    context.insert("allowed_origins", &widget_config.allowed_origins.join(" "));
+    context.insert("pause", &near.maintenance.current());
+    if let Some(guard) = &near.form_guard {
+        context.insert("form_stamp", &guard.stamp());
+    }
+
+    let rendered = templates
+        .render("widget.html.tera", &context)
+        .map_err(|err| {
+            error::ErrorInternalServerError(format!("Failed to render template: {:?}", err))
+        })?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .insert_header((
+            header::CONTENT_SECURITY_POLICY,
+            widget_config.content_security_policy(),
+        ))
+        .body(rendered))
+}
+
+impl From<(&FormData, Result<FormCreated, FormFailure>)> for WidgetResponse {
+    /// Answer to a submission of the form, the failures name the account and key as they were submitted
+    fn from((form, result): (&FormData, Result<FormCreated, FormFailure>)) -> Self {
+        match result {
+            Ok(created) => Self {
+                success: true,
+                account_id: created.account_id,
+                public_key: created.public_key,
+                code: None,
+                error: None,
+                final_execution_status: Some(created.reached),
+                transaction: created.transaction,
+                retry_after_secs: None,
+            },
+            Err(failure) => Self {
+                success: false,
+                account_id: form.account_id.trim().to_string(),
+                public_key: form.public_key.trim().to_string(),
+                code: Some(ErrorCode::classify(&failure.err)),
+                error: Some(user_message(&failure.err)),
+                final_execution_status: None,
+                transaction: failure.transaction,
+                retry_after_secs: retry_after(&failure.err)
+                    .map(|retry_after| retry_after.as_secs().max(1)),
+            },
+        }
+    }
+}
+
+/// Endpoint: /widget/create_account
+/// Same as `/create_account` but responds with JSON the widget script can forward to the parent window
+/// The form guard and the captchas are checked as for the form, the CSRF cookie isn't sent to the embedded page
+pub(crate) async fn widget_create_account(
+    req: HttpRequest,
+    near: web::Data<NearData>,
+    query: web::Query<WaitQuery>,
+    form: web::Form<FormData>,
+) -> impl Responder {
+    tracing::debug!("POST /widget/create_account");
+    let wait = query.wait.unwrap_or(near.default_wait);
+    let result = crate::submit_form(&req, &near, &form, CheckMode::Widget, wait).await;
+    let response = WidgetResponse::from((&*form, result));
+    if let Some(code) = response.code {
+        crate::middleware::idempotency::report_failure(&req, code);
+    }
+    let mut builder = HttpResponse::Ok();
+    if let Some(retry_after) = response.retry_after_secs {
+        builder.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+    }
+    builder.json(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_the_enabled_captchas() {
+        let config = WidgetConfig {
+            allowed_origins: vec!["https://wallet.example.com".to_string()],
+            turnstile: true,
+            recaptcha: false,
+        };
+        assert_eq!(
+            config.content_security_policy(),
+            "default-src 'self'; script-src 'self' https://challenges.cloudflare.com; \
+             frame-src 'self' https://challenges.cloudflare.com; style-src 'self'; \
+             frame-ancestors 'self' https://wallet.example.com"
+        );
+    }
+
+    #[test]
+    fn names_the_submitted_account_of_the_failures() {
+        let form: FormData = serde_json::from_value(serde_json::json!({
+            "account_id": " alice ",
+            "public_key": "ed25519:a",
+        }))
+        .unwrap();
+        let err = anyhow::Error::new(crate::errors::RetryLater {
+            message: "the public key is cooling down".to_string(),
+            retry_after: std::time::Duration::from_secs(30),
+        });
+        let response = WidgetResponse::from((&form, Err(err.into())));
+        assert!(!response.success);
+        assert_eq!(response.account_id, "alice");
+        assert_eq!(response.code, Some(ErrorCode::RateLimited));
+        assert_eq!(response.retry_after_secs, Some(30));
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_ws::{Message, MessageStream, Session};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::errors::ErrorCode;
+use crate::jobs::Job;
+use crate::maintenance::Pause;
+
+/// Ping sent to idle sockets so the proxies in between don't close them
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+/// Jobs a socket may follow at once
+const MAX_SUBSCRIPTIONS: usize = 100;
+
+/// Message of the clients, e.g. `{"type": "subscribe", "job_id": "..."}`
+#[derive(Debug, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe { job_id: String },
+    Unsubscribe { job_id: String },
+}
+
+/// Message pushed to the clients
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    /// State of a followed job as served by `GET /jobs/{id}`, sent on subscribing and whenever it changes
+    Job {
+        job: Box<Job>,
+    },
+    /// Faucet-wide notice, `paused` with the pause or `resumed`
+    Announcement {
+        kind: &'static str,
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pause: Option<Pause>,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+impl ServerMessage {
+    fn announcement(pause: Option<Pause>) -> Self {
+        match pause {
+            Some(pause) => Self::Announcement {
+                kind: "paused",
+                message: match &pause.reason {
+                    Some(reason) => format!("The faucet is paused for maintenance: {}", reason),
+                    None => "The faucet is paused for maintenance".to_string(),
+                },
+                pause: Some(pause),
+            },
+            None => Self::Announcement {
+                kind: "resumed",
+                message: "The faucet is creating accounts again".to_string(),
+                pause: None,
+            },
+        }
+    }
+
+    fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self::Error {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+async fn send(session: &mut Session, message: &ServerMessage) -> Result<(), actix_ws::Closed> {
+    let text = serde_json::to_string(message).expect("server message is serializable");
+    session.text(text).await
+}
+
+/// Endpoint: /ws
+/// WebSocket pushing the state changes of the subscribed creation jobs and the pauses of the faucet (JSON text messages)
+pub(crate) async fn ws_handler(
+    req: HttpRequest,
+    near: web::Data<crate::NearData>,
+    body: web::Payload,
+) -> actix_web::Result<HttpResponse> {
+    tracing::debug!("GET /ws");
+    let (response, session, messages) = actix_ws::handle(&req, body)?;
+    actix_web::rt::spawn(serve(near.get_ref().clone(), session, messages));
+    Ok(response)
+}
+
+/// Serves a socket until either side closes it
+async fn serve(near: crate::NearData, mut session: Session, mut messages: MessageStream) {
+    let mut jobs = near.jobs.subscribe();
+    let mut pauses = near.maintenance.subscribe();
+    let mut subscriptions = HashSet::new();
+    let mut keep_alive = tokio::time::interval(KEEP_ALIVE);
+
+    let pause = pauses.borrow_and_update().clone();
+    if pause.is_some()
+        && send(&mut session, &ServerMessage::announcement(pause))
+            .await
+            .is_err()
+    {
+        return;
+    }
+    loop {
+        let sent = tokio::select! {
+            message = messages.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = handle(&near, &mut subscriptions, &text);
+                    send(&mut session, &reply).await
+                }
+                Some(Ok(Message::Ping(bytes))) => session.pong(&bytes).await,
+                Some(Ok(Message::Close(reason))) => {
+                    let _ = session.close(reason).await;
+                    return;
+                }
+                Some(Ok(_)) => Ok(()),
+                Some(Err(err)) => {
+                    tracing::debug!("WebSocket protocol error: {:?}", err);
+                    break;
+                }
+                None => return,
+            },
+            job = jobs.recv() => match job {
+                Ok(job) if subscriptions.contains(job.id()) => {
+                    send(&mut session, &ServerMessage::Job { job: Box::new(job) }).await
+                }
+                Ok(_) => Ok(()),
+                Err(RecvError::Lagged(missed)) => {
+                    let message = format!("missed {} job updates, poll /jobs/{{id}} for their state", missed);
+                    send(&mut session, &ServerMessage::error(ErrorCode::Overloaded, message)).await
+                }
+                Err(RecvError::Closed) => break,
+            },
+            changed = pauses.changed() => match changed {
+                Ok(()) => {
+                    let pause = pauses.borrow_and_update().clone();
+                    send(&mut session, &ServerMessage::announcement(pause)).await
+                }
+                Err(_) => break,
+            },
+            _ = keep_alive.tick() => session.ping(b"").await,
+        };
+        if sent.is_err() {
+            return;
+        }
+    }
+    let _ = session.close(None).await;
+}
+
+/// Applies a client message, answering with the current state of a newly followed job
+fn handle(
+    near: &crate::NearData,
+    subscriptions: &mut HashSet<String>,
+    text: &str,
+) -> ServerMessage {
+    let message = match serde_json::from_str::<ClientMessage>(text) {
+        Ok(message) => message,
+        Err(err) => {
+            return ServerMessage::error(
+                ErrorCode::InvalidRequest,
+                format!("expected a subscribe or unsubscribe message: {}", err),
+            )
+        }
+    };
+    match message {
+        ClientMessage::Subscribe { job_id } => {
+            let Some(job) = near.jobs.get(&job_id) else {
+                return ServerMessage::error(
+                    ErrorCode::NotFound,
+                    format!("job {} is not known to this faucet", job_id),
+                );
+            };
+            if subscriptions.len() >= MAX_SUBSCRIPTIONS && !subscriptions.contains(&job_id) {
+                return ServerMessage::error(
+                    ErrorCode::InvalidRequest,
+                    format!("a socket may follow up to {} jobs", MAX_SUBSCRIPTIONS),
+                );
+            }
+            subscriptions.insert(job_id);
+            ServerMessage::Job { job: Box::new(job) }
+        }
+        ClientMessage::Unsubscribe { job_id } => {
+            subscriptions.remove(&job_id);
+            match near.jobs.get(&job_id) {
+                Some(job) => ServerMessage::Job { job: Box::new(job) },
+                None => ServerMessage::error(
+                    ErrorCode::NotFound,
+                    format!("job {} is not known to this faucet", job_id),
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_client_messages() {
+        assert_eq!(
+            serde_json::from_str::<ClientMessage>(r#"{"type": "subscribe", "job_id": "abc"}"#)
+                .unwrap(),
+            ClientMessage::Subscribe {
+                job_id: "abc".to_string()
+            }
+        );
+        assert!(serde_json::from_str::<ClientMessage>(r#"{"type": "publish"}"#).is_err());
+
+        let resumed = serde_json::to_value(ServerMessage::announcement(None)).unwrap();
+        assert_eq!(resumed["type"], "announcement");
+        assert_eq!(resumed["kind"], "resumed");
+    }
+}